@@ -2,8 +2,10 @@ set -xe
 
 REPO_ROOT=$(pwd)
 
-# Build the revaultd binary
+# Build the revaultd binary, along with the ZMQ notifications (which need the system libzmq)
 cargo build --release
+sudo apt update && sudo apt install libzmq3-dev
+cargo build --features zmq
 
 # Download the bitcoind binary
 BITCOIND_VERSION="0.21.0"
@@ -48,6 +48,20 @@ where
         .map(NoisePubkey)
 }
 
+fn deserialize_noisepubkeys<'de, D>(deserializer: D) -> Result<Vec<NoisePubkey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let keys = Vec::<String>::deserialize(deserializer)?;
+    keys.iter()
+        .map(|data| {
+            FromHex::from_hex(data)
+                .map_err(de::Error::custom)
+                .map(NoisePubkey)
+        })
+        .collect()
+}
+
 fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
     D: Deserializer<'de>,
@@ -95,12 +109,36 @@ pub struct BitcoindConfig {
         default = "default_poll_interval"
     )]
     pub poll_interval_secs: Duration,
+    /// bitcoind's 'zmqpubhashblock' endpoint, to sync as soon as a block is connected
+    pub zmqpubhashblock: Option<String>,
+    /// bitcoind's 'zmqpubrawtx' endpoint, to sync as soon as a transaction of ours is seen
+    pub zmqpubrawtx: Option<String>,
+}
+
+/// To use an Electrum server in place of bitcoind, for managers not running a full node
+#[derive(Debug, Clone, Deserialize)]
+pub struct ElectrumConfig {
+    /// The host:port of the Electrum server, over plain TCP. It must be on this machine: a
+    /// remote server is reached through a local TLS tunnel.
+    pub addr: String,
+}
+
+impl ElectrumConfig {
+    /// Whether the server is on this machine, as we only talk plaintext to it
+    pub fn is_local(&self) -> bool {
+        match self.addr.parse::<SocketAddr>() {
+            Ok(addr) => addr.ip().is_loopback(),
+            Err(_) => matches!(self.addr.rsplit_once(':'), Some(("localhost", _))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct WatchtowerConfig {
-    pub host: String,
-    pub noise_key: String,
+    // TODO: Tor
+    pub host: SocketAddr,
+    #[serde(deserialize_with = "deserialize_noisepubkey")]
+    pub noise_key: NoisePubkey,
 }
 
 /// If we are a stakeholder, we need to connect to our watchtower(s)
@@ -109,6 +147,10 @@ pub struct StakeholderConfig {
     pub xpub: bip32::ExtendedPubKey,
     pub watchtowers: Vec<WatchtowerConfig>,
     pub emergency_address: EmergencyAddress,
+    /// Cancel by ourselves any Unvault for which no Spend transaction was announced to the
+    /// Coordinator (default: false)
+    #[serde(default)]
+    pub local_watchtower: bool,
 }
 
 // Same fields as the WatchtowerConfig struct for now, but leave them separate.
@@ -127,15 +169,83 @@ pub struct ManagerConfig {
     pub cosigners: Vec<CosignerConfig>,
 }
 
+/// To serve the JSONRPC API over TCP as well, for clients on another host
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcTcpConfig {
+    /// The ip:port to listen on
+    pub addr: SocketAddr,
+    /// The Noise static public keys of the clients allowed to connect. If empty, clients
+    /// authenticate with the token from the 'rpc_cookie' file in our data directory instead.
+    #[serde(deserialize_with = "deserialize_noisepubkeys", default)]
+    pub noise_keys: Vec<NoisePubkey>,
+}
+
+/// To serve the JSONRPC commands as REST routes over HTTP, for HTTP-native clients. They
+/// authenticate with the token from the 'rpc_cookie' file in our data directory.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcHttpConfig {
+    /// The ip:port to listen on
+    pub addr: SocketAddr,
+}
+
+/// The JSONRPC commands a user other than ours may call on the Unix sockets. Once an ACL is set,
+/// the users it doesn't list may not call anything.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcAclEntry {
+    pub uid: u32,
+    pub methods: Vec<String>,
+}
+
+/// Shell commands to run when something happens to a vault. In each of them `%o` is replaced by
+/// the deposit outpoint of the vault, `%t` by the txid involved (empty if none) and `%s` by the
+/// new status of the vault.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NotifyConfig {
+    /// We detected a new deposit
+    pub deposit: Option<String>,
+    /// A vault's revocation transactions are all signed
+    pub secured: Option<String>,
+    /// A vault's Unvault transaction is signed
+    pub active: Option<String>,
+    /// We saw a vault's Unvault transaction onchain
+    pub unvault: Option<String>,
+    /// We broadcast a vault's Cancel transaction
+    pub cancel: Option<String>,
+    /// A reorg made a vault go back to a previous status
+    pub rewind: Option<String>,
+}
+
 /// Static informations we require to operate
 #[derive(Debug, Deserialize)]
 pub struct Config {
     /// Everything we need to know to talk to bitcoind
     pub bitcoind_config: BitcoindConfig,
-    /// Some() if we are a stakeholder
+    /// Some() if we use an Electrum server instead of bitcoind. We still use the network and
+    /// the poll interval of the bitcoind config.
+    pub electrum_config: Option<ElectrumConfig>,
+    /// Some() if we are a stakeholder. If we are neither a stakeholder nor a manager, we only
+    /// watch the vaults and serve the read-only commands.
     pub stakeholder_config: Option<StakeholderConfig>,
     /// Some() if we are a manager
     pub manager_config: Option<ManagerConfig>,
+    /// Some() if we want to run commands on events
+    pub notify_config: Option<NotifyConfig>,
+    /// Some() if we want to serve the JSONRPC API over TCP in addition to the Unix socket
+    pub rpc_tcp_config: Option<RpcTcpConfig>,
+    /// Some() if we want to serve the JSONRPC API over HTTP too
+    pub rpc_http_config: Option<RpcHttpConfig>,
+    /// Whether to serve the read-only commands on a second 'revaultd_observer_rpc' socket, for
+    /// the clients which should not be able to do anything else (default: false)
+    #[serde(default)]
+    pub observer_socket: bool,
+    /// Who may call what on the Unix sockets, checked against the credentials of the
+    /// connecting process. Only supported on Linux.
+    #[serde(default)]
+    pub rpc_acl: Vec<RpcAclEntry>,
+    /// Whether the users of our group may connect to the Unix sockets, to call what the ACL lets
+    /// them call (default: false)
+    #[serde(default)]
+    pub rpc_group_access: bool,
     /// The stakeholders' xpubs
     #[serde(deserialize_with = "deserialize_xpubs")]
     pub stakeholders_xpubs: Vec<DescriptorPublicKey>,
@@ -238,10 +348,33 @@ impl Config {
                     .map_err(|e| ConfigError(format!("Parsing configuration file: {}", e)))
             })?;
 
-        if config.stakeholder_config.is_none() && config.manager_config.is_none() {
-            return Err(ConfigError(format!(
-                r#"At least one "stakeholder_config" or "manager_config" must be present"#
-            )));
+        if config.electrum_config.is_some() && config.stakeholder_config.is_some() {
+            return Err(ConfigError(
+                r#"An "electrum_config" is only supported for managers, stakeholders need bitcoind's wallet to fee-bump the revocation transactions"#
+                    .to_owned(),
+            ));
+        }
+
+        if let Some(ref electrum_config) = config.electrum_config {
+            if !electrum_config.is_local() {
+                return Err(ConfigError(format!(
+                    "The Electrum server at '{}' is not on this machine. We don't support TLS, use a local TLS tunnel to reach it.",
+                    electrum_config.addr
+                )));
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        if !config.rpc_acl.is_empty() {
+            return Err(ConfigError(
+                r#"An "rpc_acl" is only supported on Linux"#.to_owned(),
+            ));
+        }
+        if config.rpc_group_access && config.rpc_acl.is_empty() {
+            return Err(ConfigError(
+                r#"An "rpc_acl" is needed to tell what the users of our group may call with "rpc_group_access""#
+                    .to_owned(),
+            ));
         }
 
         if config.stakeholders_xpubs.len() != config.cosigners_keys.len() {
@@ -303,7 +436,7 @@ impl Config {
 
 #[cfg(test)]
 mod tests {
-    use super::{config_file_path, Config};
+    use super::{config_file_path, Config, ElectrumConfig};
 
     // Test the format of the configuration file
     #[test]
@@ -340,6 +473,8 @@ mod tests {
             cookie_path = "/home/user/.bitcoin/.cookie"
             addr = "127.0.0.1:8332"
             poll_interval_secs = 18
+            zmqpubhashblock = "tcp://127.0.0.1:28332"
+            zmqpubrawtx = "tcp://127.0.0.1:28333"
 
             # We are one of the above stakeholders
             [stakeholder_config]
@@ -347,7 +482,16 @@ mod tests {
             watchtowers = [ { host = "127.0.0.1:1", noise_key = "46084f8a7da40ef7ffc38efa5af8a33a742b90f920885d17c533bb2a0b680cb3" } ]
             emergency_address = "bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej"
         "#;
-        toml::from_str::<Config>(toml_str).expect("Deserializing stakeholder toml_str");
+        let config =
+            toml::from_str::<Config>(toml_str).expect("Deserializing stakeholder toml_str");
+        assert_eq!(
+            config.bitcoind_config.zmqpubhashblock.as_deref(),
+            Some("tcp://127.0.0.1:28332")
+        );
+        assert_eq!(
+            config.bitcoind_config.zmqpubrawtx.as_deref(),
+            Some("tcp://127.0.0.1:28333")
+        );
 
         // A valid manager config
         let toml_str = r#"
@@ -382,12 +526,31 @@ mod tests {
             addr = "127.0.0.1:8332"
             poll_interval_secs = 4
 
+            # We don't run a full node
+            [electrum_config]
+            addr = "electrum.example.com:50001"
+
             # We are one of the above managers
             [manager_config]
             xpub = "xpub6AtVcKWPpZ9t3Aa3VvzWid1dzJFeXPfNntPbkGsYjNrp7uhXpzSL5QVMCmaHqUzbVUGENEwbBbzF9E8emTxQeP3AzbMjfzvwSDkwUrxg2G4"
             cosigners = [ { host = "127.0.0.1:1", noise_key = "087629614d227ff2b9ed5f2ce2eb7cd527d2d18f866b24009647251fce58de38" } ]
+
+            # Authenticated with the cookie file
+            [rpc_tcp_config]
+            addr = "127.0.0.1:8432"
         "#;
-        toml::from_str::<Config>(toml_str).expect("Deserializing manager toml_str");
+        let config = toml::from_str::<Config>(toml_str).expect("Deserializing manager toml_str");
+        let electrum_config = config.electrum_config.unwrap();
+        assert_eq!(electrum_config.addr, "electrum.example.com:50001");
+        // We would refuse it, we don't support TLS
+        assert!(!electrum_config.is_local());
+        for addr in &["127.0.0.1:50001", "[::1]:50001", "localhost:50001"] {
+            let electrum_config = ElectrumConfig {
+                addr: addr.to_string(),
+            };
+            assert!(electrum_config.is_local());
+        }
+        assert!(config.rpc_tcp_config.unwrap().noise_keys.is_empty());
 
         // A valid sakeholder-manager config
         let toml_str = r#"
@@ -431,8 +594,42 @@ mod tests {
             xpub = "xpub6AP3nZhB34Zoan3KCL9bAdnwNHdzMbskLudpbchwTfkHwnNDXYf1769gzozjgzDNUF7iwa5nCdhE5byrcx5PDKFCUDByeuqiHa382EKhcay"
             watchtowers = [ { host = "127.0.0.1:1", noise_key = "46084f8a7da40ef7ffc38efa5af8a33a742b90f920885d17c533bb2a0b680cb3" } ]
             emergency_address = "bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej"
+
+            [notify_config]
+            unvault = "page-ops 'Unvault %t of vault %o'"
+            rewind = "echo '%o is now %s' >> /tmp/reorgs.log"
+
+            [rpc_tcp_config]
+            addr = "0.0.0.0:8432"
+            noise_keys = ["3a21c42ed7b3c3ae1c11b4b18a4e5a8f0b4c5d25b5ed92f2c1bb5dae5a2e4f11"]
+
+            [rpc_http_config]
+            addr = "127.0.0.1:8433"
+
+            [[rpc_acl]]
+            uid = 1001
+            methods = ["revault", "setspendtx"]
+
+            [[rpc_acl]]
+            uid = 1002
+            methods = ["listvaults"]
         "#;
-        toml::from_str::<Config>(toml_str).expect("Deserializing stakeholder-manager toml_str");
+        let config =
+            toml::from_str::<Config>(toml_str).expect("Deserializing stakeholder-manager toml_str");
+        let notify_config = config.notify_config.unwrap();
+        assert_eq!(
+            notify_config.unvault.as_deref(),
+            Some("page-ops 'Unvault %t of vault %o'")
+        );
+        assert!(notify_config.deposit.is_none());
+        let rpc_tcp_config = config.rpc_tcp_config.unwrap();
+        assert_eq!(rpc_tcp_config.addr.port(), 8432);
+        assert_eq!(rpc_tcp_config.noise_keys.len(), 1);
+        assert_eq!(config.rpc_http_config.unwrap().addr.port(), 8433);
+        assert_eq!(config.rpc_acl.len(), 2);
+        assert_eq!(config.rpc_acl[0].uid, 1001);
+        assert_eq!(config.rpc_acl[1].methods, vec!["listvaults".to_string()]);
+        assert!(!config.rpc_group_access);
 
         // Not enough parameters
         let toml_str = r#"
@@ -1,31 +1,50 @@
 use crate::{
     bitcoind::{
+        backend::ChainBackend,
+        electrum::ElectrumClient,
+        feebump::{feebump_reserve, feebump_transaction},
         interface::{BitcoinD, OnchainDescriptorState, SyncInfo, UtxoInfo},
+        notifications::{is_relevant, ChainNotification, ZmqSubscriber},
+        spenders::{index_spenders, spender_txid},
         BitcoindError,
     },
     database::{
         actions::{
-            db_cancel_unvault, db_confirm_deposit, db_confirm_unvault,
+            db_cancel_unvault, db_clear_spenders_dbtx, db_confirm_deposit, db_confirm_unvault,
+            db_emer_deposit, db_emer_unvault, db_insert_feebumped_tx,
             db_insert_new_unconfirmed_vault, db_mark_broadcasted_spend, db_mark_canceled_unvault,
-            db_mark_rebroadcastable_spend, db_mark_spent_unvault, db_spend_unvault,
-            db_unconfirm_cancel_dbtx, db_unconfirm_deposit_dbtx, db_unconfirm_spend_dbtx,
-            db_unconfirm_unvault_dbtx, db_unvault_deposit, db_update_deposit_index, db_update_tip,
-            db_update_tip_dbtx,
+            db_mark_emergencied_unvault, db_mark_emergencied_vault, db_mark_rebroadcastable_spend,
+            db_mark_spent_unvault, db_spend_unvault, db_unconfirm_cancel_dbtx,
+            db_unconfirm_deposit_dbtx, db_unconfirm_emer_dbtx, db_unconfirm_spend_dbtx,
+            db_unconfirm_unemer_dbtx, db_unconfirm_unvault_dbtx, db_unemer_deposit,
+            db_unvault_deposit, db_update_deposit_index, db_update_tip, db_update_tip_dbtx,
         },
         interface::{
             db_broadcastable_spend_transactions, db_cancel_dbtx, db_cancel_transaction,
-            db_canceling_vaults, db_deposits, db_exec, db_spending_vaults, db_tip, db_unvault_dbtx,
-            db_unvault_from_deposit, db_unvault_transaction, db_unvaulted_vaults,
-            db_vault_by_deposit, db_vault_by_unvault_txid, db_vaults_dbtx, db_wallet,
+            db_canceling_vaults, db_deposits, db_emer_dbtx, db_emer_transaction, db_emering_vaults,
+            db_events, db_exec, db_feebumped_txids, db_last_event_id, db_spending_vaults, db_tip,
+            db_transactions_sig_missing, db_unemering_vaults, db_unvault_dbtx,
+            db_unvault_emer_dbtx, db_unvault_emer_transaction, db_unvault_from_deposit,
+            db_unvault_transaction, db_unvaulted_vaults, db_vault_by_deposit,
+            db_vault_by_unvault_txid, db_vaults_dbtx, db_wallet, EventsFilter,
         },
-        schema::DbVault,
+        schema::{DbVault, EventKind},
     },
+    emergency_kit::refresh_emergency_kit,
+    hooks::run_event_hook,
     revaultd::{BlockchainTip, RevaultD, VaultStatus},
-    threadmessages::{BitcoindMessageOut, WalletTransaction},
+    sigfetcher::fetch_all_signatures,
+    threadmessages::{BitcoindMessageOut, Notification, WalletTransaction},
+};
+use common::{
+    assume_ok,
+    config::{BitcoindConfig, ElectrumConfig},
 };
-use common::{assume_ok, config::BitcoindConfig};
 use revault_tx::{
-    bitcoin::{Amount, Network, OutPoint, TxOut, Txid},
+    bitcoin::{
+        blockdata::constants::genesis_block, consensus::encode, hashes::hex::FromHex, Amount,
+        Network, OutPoint, Transaction, TxOut, Txid,
+    },
     miniscript::DescriptorTrait,
     transactions::{
         transaction_chain, transaction_chain_manager, CancelTransaction, EmergencyTransaction,
@@ -36,12 +55,12 @@ use revault_tx::{
 };
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::PathBuf,
     process,
     sync::{
         atomic::{AtomicBool, Ordering},
-        mpsc::Receiver,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
         Arc, RwLock,
     },
     thread,
@@ -97,7 +116,7 @@ fn roundup_progress(progress: f64) -> f64 {
 /// between calls.
 /// If sync_progress == 1.0, we are done.
 fn bitcoind_sync_status(
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     bitcoind_config: &BitcoindConfig,
     sleep_duration: &mut Option<Duration>,
     sync_progress: &mut f64,
@@ -189,7 +208,7 @@ fn maybe_create_wallet(revaultd: &mut RevaultD, bitcoind: &BitcoinD) -> Result<(
             }
         }
 
-        bitcoind.createwallet_startup(bitcoind_wallet_path)?;
+        bitcoind.createwallet_startup(bitcoind_wallet_path, true)?;
         log::info!("Importing descriptors to bitcoind watchonly wallet.");
 
         // Now, import descriptors.
@@ -218,6 +237,64 @@ fn maybe_create_wallet(revaultd: &mut RevaultD, bitcoind: &BitcoinD) -> Result<(
         }
         log::trace!("Importing unvault descriptors '{:?}'", &addresses);
         bitcoind.startup_import_unvault_descriptors(addresses, wallet.timestamp, fresh_wallet)?;
+
+        // Track the CPFP outputs of the Unvault and Spend transactions, too, so that we can spend
+        // them to bump their feerate.
+        let mut addresses = revaultd.all_cpfp_addresses();
+        for i in 0..addresses.len() {
+            addresses[i] = bitcoind.addr_descriptor(&addresses[i])?;
+        }
+        log::trace!("Importing CPFP descriptors '{:?}'", &addresses);
+        bitcoind.startup_import_cpfp_descriptors(addresses, wallet.timestamp, fresh_wallet)?;
+    }
+
+    Ok(())
+}
+
+// Wallets created before we tracked the CPFP outputs of the Unvault and Spend transactions don't
+// have their descriptors, import them. This needs the watchonly wallet to be loaded.
+fn maybe_import_cpfp_descriptors(
+    revaultd: &RevaultD,
+    bitcoind: &BitcoinD,
+) -> Result<(), BitcoindError> {
+    if bitcoind.has_cpfp_descriptors()? {
+        return Ok(());
+    }
+
+    log::info!("Importing CPFP descriptors to bitcoind watchonly wallet.");
+    let wallet = db_wallet(&revaultd.db_file())?;
+    let mut addresses = revaultd.all_cpfp_addresses();
+    for i in 0..addresses.len() {
+        addresses[i] = bitcoind.addr_descriptor(&addresses[i])?;
+    }
+    log::trace!("Importing CPFP descriptors '{:?}'", &addresses);
+    // The wallet was not just created, bitcoind needs to rescan for them.
+    bitcoind.startup_import_cpfp_descriptors(addresses, wallet.timestamp, false)
+}
+
+// This creates the wallet holding the coins used to bump the feerate of the revocation
+// transactions. Only stakeholders have one.
+fn maybe_create_feebump_wallet(
+    revaultd: &RevaultD,
+    bitcoind: &BitcoinD,
+) -> Result<(), BitcoindError> {
+    if !revaultd.is_stakeholder() {
+        return Ok(());
+    }
+    let feebump_wallet_path = revaultd
+        .feebump_wallet_file()
+        .expect("Wallet id is set at startup in setup_db()");
+
+    if !PathBuf::from(feebump_wallet_path.clone()).exists() {
+        while bitcoind.listwallets()?.contains(&feebump_wallet_path) {
+            log::info!("Found a leftover fee-bumping wallet loaded on bitcoind. Removing it.");
+            if let Err(e) = bitcoind.unloadwallet(feebump_wallet_path.clone()) {
+                log::error!("Unloading wallet '{}': '{}'", &feebump_wallet_path, e);
+            }
+        }
+
+        log::info!("Creating a new fee-bumping wallet on bitcoind.");
+        bitcoind.createwallet_startup(feebump_wallet_path, false)?;
     }
 
     Ok(())
@@ -228,6 +305,16 @@ fn maybe_load_wallet(revaultd: &RevaultD, bitcoind: &BitcoinD) -> Result<(), Bit
         .watchonly_wallet_file()
         .expect("Wallet id is set at startup in setup_db()");
 
+    if revaultd.is_stakeholder() {
+        let feebump_wallet_path = revaultd
+            .feebump_wallet_file()
+            .expect("Wallet id is set at startup in setup_db()");
+        if !bitcoind.listwallets()?.contains(&feebump_wallet_path) {
+            log::info!("Loading our fee-bumping wallet '{}'.", feebump_wallet_path);
+            bitcoind.loadwallet_startup(feebump_wallet_path)?;
+        }
+    }
+
     match bitcoind
         .listwallets()?
         .into_iter()
@@ -260,6 +347,9 @@ pub fn start_bitcoind(revaultd: &mut RevaultD) -> Result<BitcoinD, BitcoindError
         revaultd
             .watchonly_wallet_file()
             .expect("Wallet id is set at startup in setup_db()"),
+        revaultd
+            .feebump_wallet_file()
+            .expect("Wallet id is set at startup in setup_db()"),
     )
     .map_err(|e| {
         BitcoindError::Custom(format!("Could not connect to bitcoind: {}", e.to_string()))
@@ -277,10 +367,38 @@ pub fn start_bitcoind(revaultd: &mut RevaultD) -> Result<BitcoinD, BitcoindError
     Ok(bitcoind)
 }
 
+/// Connects to and sanity checks the Electrum server we use in place of bitcoind.
+pub fn start_electrum(
+    revaultd: &mut RevaultD,
+    electrum_config: &ElectrumConfig,
+) -> Result<ElectrumClient, BitcoindError> {
+    let electrum = ElectrumClient::new(
+        electrum_config,
+        revaultd.all_deposit_addresses(),
+        revaultd.all_unvault_addresses(),
+    )
+    .map_err(|e| {
+        BitcoindError::Custom(format!(
+            "Could not connect to the Electrum server: {}",
+            e.to_string()
+        ))
+    })?;
+
+    let network = revaultd.bitcoind_config.network;
+    if electrum.getblockhash(0)? != genesis_block(network).block_hash() {
+        return Err(BitcoindError::Custom(format!(
+            "Wrong network, the Electrum server is not on '{}'",
+            network
+        )));
+    }
+
+    Ok(electrum)
+}
+
 // Try to broadcast fully signed spend transactions, only mature ones will get through
 fn maybe_broadcast_spend_transactions(
     revaultd: &Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
 ) -> Result<(), BitcoindError> {
     let db_path = revaultd.read().unwrap().db_file();
 
@@ -317,7 +435,7 @@ fn maybe_broadcast_spend_transactions(
 
 fn maybe_confirm_spend(
     db_path: &PathBuf,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     db_vault: &DbVault,
     spend_txid: &Txid,
 ) -> Result<bool, BitcoindError> {
@@ -340,7 +458,7 @@ fn maybe_confirm_spend(
 // the vault state to 'spent'.
 fn mark_confirmed_spends(
     revaultd: &Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
 ) -> Result<(), BitcoindError> {
     let db_path = revaultd.read().unwrap().db_file();
@@ -399,17 +517,96 @@ fn mark_confirmed_spends(
     Ok(())
 }
 
+// A revocation transaction may have been broadcast as presigned, or with a fee-bumping input
+// attached (hence under another txid). Get the version of it that made it to the wallet, if
+// any, along with its blockheight if it's confirmed. Confirmed ones are preferred, then the
+// ones in mempool.
+fn wallet_revocation_tx(
+    db_path: &PathBuf,
+    bitcoind: &dyn ChainBackend,
+    presigned_txid: &Txid,
+) -> Result<Option<(Txid, Option<u32>)>, BitcoindError> {
+    let mut txids = db_feebumped_txids(db_path, presigned_txid)?;
+    txids.push(*presigned_txid);
+
+    let mut wallet_tx = None;
+    for txid in txids {
+        if let Ok((_, blockheight, _)) = bitcoind.get_wallet_transaction(&txid) {
+            if blockheight.is_some() {
+                return Ok(Some((txid, blockheight)));
+            }
+            if wallet_tx.is_none() || bitcoind.is_in_mempool(&txid)? {
+                wallet_tx = Some((txid, None));
+            }
+        }
+    }
+
+    Ok(wallet_tx)
+}
+
+// Is any version of this revocation transaction in mempool?
+fn revocation_tx_in_mempool(
+    db_path: &PathBuf,
+    bitcoind: &dyn ChainBackend,
+    presigned_txid: &Txid,
+) -> Result<bool, BitcoindError> {
+    if bitcoind.is_in_mempool(presigned_txid)? {
+        return Ok(true);
+    }
+    for txid in db_feebumped_txids(db_path, presigned_txid)? {
+        if bitcoind.is_in_mempool(&txid)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Another stakeholder may have broadcast a revocation transaction with a fee-bumping input
+// attached, hence under a txid we don't know about. As they are signed with ALL|ANYONECANPAY,
+// all the versions of a revocation transaction have the very same outputs though. If this
+// (spending) transaction is one of them, record it so we recognize it from now on.
+fn is_feebumped_version(
+    db_path: &PathBuf,
+    bitcoind: &dyn ChainBackend,
+    txid: &Txid,
+    presigned_tx: &impl RevaultTransaction,
+) -> Result<bool, BitcoindError> {
+    let presigned_tx = &presigned_tx.inner_tx().global.unsigned_tx;
+    let (tx_hex, _, _) = bitcoind.get_wallet_transaction(txid)?;
+    let tx: Transaction = Vec::from_hex(&tx_hex)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| encode::deserialize(&bytes).map_err(|e| e.to_string()))
+        .map_err(|e| {
+            BitcoindError::Custom(format!(
+                "Invalid transaction '{}' in 'gettransaction': {}",
+                txid, e
+            ))
+        })?;
+    if tx.output != presigned_tx.output {
+        return Ok(false);
+    }
+
+    log::debug!(
+        "Transaction '{}' is a fee-bumped version of '{}'",
+        txid,
+        presigned_tx.txid()
+    );
+    db_insert_feebumped_tx(db_path, &presigned_tx.txid(), txid)?;
+    Ok(true)
+}
+
 fn maybe_confirm_cancel(
     db_path: &PathBuf,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     db_vault: &DbVault,
     cancel_txid: &Txid,
 ) -> Result<bool, BitcoindError> {
-    if let (_, Some(height), _) = bitcoind.get_wallet_transaction(cancel_txid)? {
+    if let Some((txid, Some(height))) = wallet_revocation_tx(db_path, bitcoind, cancel_txid)? {
         db_mark_canceled_unvault(&db_path, db_vault.id)?;
         log::debug!(
             "Cancel tx '{}', spending vault {:x?} was confirmed at height '{}'",
-            &cancel_txid,
+            &txid,
             db_vault,
             height
         );
@@ -422,7 +619,7 @@ fn maybe_confirm_cancel(
 
 fn mark_confirmed_cancels(
     revaultd: &Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
 ) -> Result<(), BitcoindError> {
     let db_path = revaultd.read().unwrap().db_file();
@@ -442,7 +639,7 @@ fn mark_confirmed_cancels(
             }
         };
 
-        if !bitcoind.is_in_mempool(&cancel_tx.txid())? {
+        if !revocation_tx_in_mempool(&db_path, bitcoind, &cancel_txid)? {
             // At least, is this transaction still in mempool?
             // If it was evicted, downgrade it to `unvaulted`, the listunspent polling loop will
             // take care of checking its new state immediately.
@@ -478,11 +675,176 @@ fn mark_confirmed_cancels(
     Ok(())
 }
 
+fn maybe_confirm_emer(
+    db_path: &PathBuf,
+    bitcoind: &dyn ChainBackend,
+    db_vault: &DbVault,
+    emer_txid: &Txid,
+) -> Result<bool, BitcoindError> {
+    if let Some((txid, Some(height))) = wallet_revocation_tx(db_path, bitcoind, emer_txid)? {
+        db_mark_emergencied_vault(&db_path, db_vault.id)?;
+        log::debug!(
+            "Emergency tx '{}', spending vault {:x?} was confirmed at height '{}'",
+            &txid,
+            db_vault,
+            height
+        );
+
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+// Check if some Emergency transactions were confirmed, if so upgrade the vault state to
+// 'emergencyvaulted'.
+fn mark_confirmed_emers(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &dyn ChainBackend,
+    deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.read().unwrap().db_file();
+
+    for (db_vault, emer_tx) in db_emering_vaults(&db_path)? {
+        let emer_txid = emer_tx.txid();
+        match maybe_confirm_emer(&db_path, bitcoind, &db_vault, &emer_txid) {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                log::error!(
+                    "Error checking if Emergency '{}' is confirmed: '{}'",
+                    &emer_txid,
+                    e
+                );
+                continue;
+            }
+        };
+
+        if !revocation_tx_in_mempool(&db_path, bitcoind, &emer_txid)? {
+            // At least, is this transaction still in mempool?
+            // If it was evicted, downgrade it to its previous state and track the deposit
+            // again. The listunspent polling loop will take care of checking its new state
+            // immediately.
+            db_unemer_deposit(&db_path, db_vault.id)?;
+
+            let deposit_descriptor = revaultd
+                .read()
+                .unwrap()
+                .derived_deposit_descriptor(db_vault.derivation_index);
+            let txo = TxOut {
+                script_pubkey: deposit_descriptor.inner().script_pubkey(),
+                value: db_vault.amount.as_sat(),
+            };
+            deposits_cache.insert(
+                db_vault.deposit_outpoint,
+                UtxoInfo {
+                    txo,
+                    is_confirmed: true,
+                },
+            );
+
+            log::debug!(
+                "Emergency tx '{}', spending deposit '{}' was evicted from mempool.",
+                emer_txid,
+                db_vault.deposit_outpoint
+            );
+        } else {
+            log::trace!("Emergency tx '{}' is still unconfirmed", emer_txid);
+        }
+    }
+
+    Ok(())
+}
+
+fn maybe_confirm_unemer(
+    db_path: &PathBuf,
+    bitcoind: &dyn ChainBackend,
+    db_vault: &DbVault,
+    unemer_txid: &Txid,
+) -> Result<bool, BitcoindError> {
+    if let Some((txid, Some(height))) = wallet_revocation_tx(db_path, bitcoind, unemer_txid)? {
+        db_mark_emergencied_unvault(&db_path, db_vault.id)?;
+        log::debug!(
+            "UnvaultEmergency tx '{}', spending vault {:x?} was confirmed at height '{}'",
+            &txid,
+            db_vault,
+            height
+        );
+
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+// Check if some UnvaultEmergency transactions were confirmed, if so upgrade the vault state to
+// 'unvaultemergencyvaulted'.
+fn mark_confirmed_unemers(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &dyn ChainBackend,
+    unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.read().unwrap().db_file();
+
+    for (db_vault, unemer_tx) in db_unemering_vaults(&db_path)? {
+        let unemer_txid = unemer_tx.txid();
+        match maybe_confirm_unemer(&db_path, bitcoind, &db_vault, &unemer_txid) {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                log::error!(
+                    "Error checking if UnvaultEmergency '{}' is confirmed: '{}'",
+                    &unemer_txid,
+                    e
+                );
+                continue;
+            }
+        };
+
+        if !revocation_tx_in_mempool(&db_path, bitcoind, &unemer_txid)? {
+            // At least, is this transaction still in mempool?
+            // If it was evicted, downgrade it to `unvaulted` or `unvaulting` depending on whether
+            // the Unvault is mined as we may have broadcast it beforehand. The listunspent polling
+            // loop will take care of checking its new state immediately.
+            let (_, unvault_tx) = db_unvault_transaction(&db_path, db_vault.id)?;
+            let unvault_txid = unvault_tx.txid();
+            let unvault_descriptor = revaultd
+                .read()
+                .unwrap()
+                .derived_unvault_descriptor(db_vault.derivation_index);
+            let unvault_txin = unvault_tx.revault_unvault_txin(&unvault_descriptor);
+            let unvault_outpoint = unvault_txin.outpoint();
+
+            let (_, unvault_height, _) = bitcoind.get_wallet_transaction(&unvault_txid)?;
+            let is_confirmed = unvault_height.is_some();
+            if is_confirmed {
+                db_confirm_unvault(&db_path, &unvault_txid)?;
+            } else {
+                db_unvault_deposit(&db_path, &unvault_txid)?;
+            }
+
+            let txo = unvault_txin.into_txout().into_txout();
+            unvaults_cache.insert(unvault_outpoint, UtxoInfo { txo, is_confirmed });
+
+            log::debug!(
+                "UnvaultEmergency tx '{}', spending Unvault '{}' was evicted from mempool.",
+                unemer_txid,
+                unvault_outpoint
+            );
+        } else {
+            log::trace!("UnvaultEmergency tx '{}' is still unconfirmed", unemer_txid);
+        }
+    }
+
+    Ok(())
+}
+
 // Everything we do when the chain moves forward
 fn new_tip_event(
     revaultd: &Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     new_tip: &BlockchainTip,
+    deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
 ) -> Result<(), BitcoindError> {
     let db_path = revaultd.read().unwrap().db_file();
@@ -499,13 +861,17 @@ fn new_tip_event(
     // Did some Cancel transaction get confirmed?
     mark_confirmed_cancels(revaultd, bitcoind, unvaults_cache)?;
 
+    // Did some Emergency or UnvaultEmergency transaction get confirmed?
+    mark_confirmed_emers(revaultd, bitcoind, deposits_cache)?;
+    mark_confirmed_unemers(revaultd, bitcoind, unvaults_cache)?;
+
     Ok(())
 }
 
 // Rewind the state of a vault for which the Unvault transaction was already broadcast
 fn unconfirm_unvault(
     revaultd: &Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     db_tx: &rusqlite::Transaction,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
     vault: &DbVault,
@@ -580,6 +946,40 @@ fn unconfirm_unvault(
             Err(e) => log::debug!("Error re-broadcasting Cancel tx '{}': '{}'", cancel_txid, e),
         }
 
+        // Still re-insert it, even if it'll be removed at the next `listunspent` poll
+        unvaults_cache.insert(
+            unvault_outpoint,
+            UtxoInfo {
+                txo,
+                is_confirmed: false,
+            },
+        );
+    } else if matches!(
+        vault.status,
+        VaultStatus::UnvaultEmergencyVaulted | VaultStatus::UnvaultEmergencyVaulting
+    ) {
+        // Just in case, rebroadcast it.
+        let unemer_tx = match db_unvault_emer_dbtx(&db_tx, vault.id)? {
+            Some(tx) => tx,
+            None => {
+                log::error!(
+                    "Vault '{}' has status '{}' but no UnvaultEmergency in database!",
+                    vault.deposit_outpoint,
+                    vault.status
+                );
+                return Ok(());
+            }
+        };
+        let unemer_txid = unemer_tx.txid();
+        match bitcoind.rebroadcast_wallet_tx(&unemer_txid) {
+            Ok(()) => {}
+            Err(e) => log::debug!(
+                "Error re-broadcasting UnvaultEmergency tx '{}': '{}'",
+                unemer_txid,
+                e
+            ),
+        }
+
         // Still re-insert it, even if it'll be removed at the next `listunspent` poll
         unvaults_cache.insert(
             unvault_outpoint,
@@ -596,7 +996,7 @@ fn unconfirm_unvault(
 // Rewind the state of a vault for which the Unvault transaction was never broadcast
 fn unconfirm_vault(
     revaultd: &Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     db_tx: &rusqlite::Transaction,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
@@ -656,9 +1056,28 @@ fn unconfirm_vault(
             Ok(())
         }
         VaultStatus::EmergencyVaulting | VaultStatus::EmergencyVaulted => {
-            // TODO
-            log::error!("Emergency unimplemented");
-            return Ok(());
+            // The deposit was already spent by the Emergency transaction. Same as for the
+            // 'second layer', just rewind the state to the broadcast of the Emergency and
+            // make sure it gets mined again.
+            db_unconfirm_emer_dbtx(db_tx, vault.id)?;
+
+            let emer_tx = db_emer_dbtx(db_tx, vault.id)?.ok_or_else(|| {
+                BitcoindError::Custom(format!(
+                    "Vault '{}' has status '{}' but no Emergency in database!",
+                    vault.deposit_outpoint, vault.status
+                ))
+            })?;
+            let emer_txid = emer_tx.txid();
+            match bitcoind.rebroadcast_wallet_tx(&emer_txid) {
+                Ok(()) => {}
+                Err(e) => log::debug!(
+                    "Error re-broadcasting Emergency tx '{}': '{}'",
+                    emer_txid,
+                    e
+                ),
+            }
+
+            Ok(())
         }
     }
 }
@@ -673,11 +1092,14 @@ fn unconfirm_vault(
 fn comprehensive_rescan(
     revaultd: &Arc<RwLock<RevaultD>>,
     db_tx: &rusqlite::Transaction,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
 ) -> Result<(), BitcoindError> {
     log::info!("Starting rescan of all vaults in db..");
+    let db_path = revaultd.read().unwrap().db_file();
+    // The blocks the spenders were found in may not be part of the chain anymore
+    db_clear_spenders_dbtx(db_tx)?;
     let mut vaults = db_vaults_dbtx(&db_tx)?;
     let mut tip = bitcoind.get_tip()?;
 
@@ -754,6 +1176,41 @@ fn comprehensive_rescan(
             min_conf
         );
 
+        // If the Emergency transaction *only* was confirmed and becomes unconfirmed, we just
+        // mark it as such. The bitcoind wallet will take care of the rebroadcast.
+        if matches!(vault.status, VaultStatus::EmergencyVaulted) {
+            let emer_tx = match db_emer_dbtx(db_tx, vault.id)? {
+                Some(tx) => tx,
+                None => {
+                    log::error!(
+                        "Vault '{}' has status '{}' but no Emergency in database!",
+                        vault.deposit_outpoint,
+                        vault.status
+                    );
+                    continue;
+                }
+            };
+            let emer_txid = emer_tx.txid();
+            let blockheight = wallet_revocation_tx(&db_path, bitcoind, &emer_txid)?
+                .map(|(_, blockheight)| blockheight)
+                .flatten();
+            if let Some(height) = blockheight {
+                log::debug!(
+                    "Vault {}'s Emergency transaction is still confirmed (height '{}')",
+                    vault.deposit_outpoint,
+                    height
+                );
+            } else {
+                db_unconfirm_emer_dbtx(db_tx, vault.id)?;
+                log::debug!(
+                    "Vault {}'s Emergency transaction {} got unconfirmed.",
+                    vault.deposit_outpoint,
+                    emer_txid
+                );
+            }
+            continue;
+        }
+
         // Second layer: if the Unvault was confirmed and becomes unconfirmed, we mark it as such
         // and make sure to rebroadcast the transaction that were depending on it.
         if matches!(
@@ -831,8 +1288,10 @@ fn comprehensive_rescan(
 
             // Same for the Cancel transaction
             if matches!(vault.status, VaultStatus::Canceled) {
-                let cancel_txid = cancel_txid(revaultd, &vault)?;
-                let (_, blockheight, _) = bitcoind.get_wallet_transaction(&cancel_txid)?;
+                let cancel_txid = vault_cancel_tx(revaultd, &vault)?.txid();
+                let blockheight = wallet_revocation_tx(&db_path, bitcoind, &cancel_txid)?
+                    .map(|(_, blockheight)| blockheight)
+                    .flatten();
                 if let Some(height) = blockheight {
                     log::debug!(
                         "Vault {}'s Cancel transaction is still confirmed (height '{}')",
@@ -850,7 +1309,39 @@ fn comprehensive_rescan(
                 }
             }
 
-            // TODO: First Emergency
+            // Same for the UnvaultEmergency transaction
+            if matches!(vault.status, VaultStatus::UnvaultEmergencyVaulted) {
+                let unemer_tx = match db_unvault_emer_dbtx(db_tx, vault.id)? {
+                    Some(tx) => tx,
+                    None => {
+                        log::error!(
+                            "Vault '{}' has status '{}' but no UnvaultEmergency in database!",
+                            vault.deposit_outpoint,
+                            vault.status
+                        );
+                        continue;
+                    }
+                };
+                let unemer_txid = unemer_tx.txid();
+                let blockheight = wallet_revocation_tx(&db_path, bitcoind, &unemer_txid)?
+                    .map(|(_, blockheight)| blockheight)
+                    .flatten();
+                if let Some(height) = blockheight {
+                    log::debug!(
+                        "Vault {}'s UnvaultEmergency transaction is still confirmed (height '{}')",
+                        vault.deposit_outpoint,
+                        height
+                    );
+                } else {
+                    db_unconfirm_unemer_dbtx(db_tx, vault.id)?;
+                    log::debug!(
+                        "Vault {}'s UnvaultEmergency transaction {} got unconfirmed.",
+                        vault.deposit_outpoint,
+                        unemer_txid
+                    );
+                    continue;
+                }
+            }
         }
     }
 
@@ -865,7 +1356,7 @@ fn comprehensive_rescan(
 // Returns the previous tip.
 fn update_tip(
     revaultd: &mut Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
 ) -> Result<BlockchainTip, BitcoindError> {
@@ -882,7 +1373,7 @@ fn update_tip(
         let bit_curr_hash = bitcoind.getblockhash(current_tip.height)?;
         if bit_curr_hash == current_tip.hash || current_tip.height == 0 {
             // We moved forward, everything is fine.
-            new_tip_event(&revaultd, bitcoind, &tip, unvaults_cache)?;
+            new_tip_event(&revaultd, bitcoind, &tip, deposits_cache, unvaults_cache)?;
             return Ok(current_tip);
         }
     }
@@ -961,7 +1452,19 @@ fn presigned_transactions(
     }
 }
 
-// Fill up the deposit UTXOs cache from db vaults
+// The coins of ours we need to know the spender of
+fn watched_outpoints(
+    deposits_cache: &HashMap<OutPoint, UtxoInfo>,
+    unvaults_cache: &HashMap<OutPoint, UtxoInfo>,
+) -> HashSet<OutPoint> {
+    deposits_cache
+        .keys()
+        .chain(unvaults_cache.keys())
+        .copied()
+        .collect()
+}
+
+// Fill up the deposit UTXOs cache from db vaults
 fn populate_deposit_cache(
     revaultd: &RevaultD,
 ) -> Result<HashMap<OutPoint, UtxoInfo>, BitcoindError> {
@@ -1051,14 +1554,14 @@ fn unvault_txin_from_deposit(
     Ok(unvault_tx.revault_unvault_txin(&unvault_descriptor))
 }
 
-// Get the Cancel txid of a give vault, trying first to fetch the transaction from the DB and
-// falling back to generating it.
+// Get the Cancel transaction of a give vault, trying first to fetch it from the DB and falling
+// back to generating it.
 // Assumes the given deposit outpoint actually corresponds to an existing vaults, will panic
 // otherwise.
-fn cancel_txid(
+fn vault_cancel_tx(
     revaultd: &Arc<RwLock<RevaultD>>,
     db_vault: &DbVault,
-) -> Result<Txid, BitcoindError> {
+) -> Result<CancelTransaction, BitcoindError> {
     let revaultd = revaultd.read().unwrap();
     let db_path = revaultd.db_file();
 
@@ -1078,7 +1581,32 @@ fn cancel_txid(
         cancel_tx
     };
 
-    Ok(cancel_tx.txid())
+    Ok(cancel_tx)
+}
+
+// Get the Emergency transaction of the vault at this deposit outpoint from the DB. Returns None
+// if we are not a stakeholder, or if the vault has no presigned transactions stored
+// (unconfirmed).
+// Assumes the given deposit outpoint actually corresponds to an existing vaults, will panic
+// otherwise.
+fn deposit_emer_tx(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    deposit_outpoint: &OutPoint,
+) -> Result<Option<(DbVault, EmergencyTransaction)>, BitcoindError> {
+    let revaultd = revaultd.read().unwrap();
+    if !revaultd.is_stakeholder() {
+        return Ok(None);
+    }
+
+    let db_path = revaultd.db_file();
+    let db_vault = db_vault_by_deposit(&db_path, &deposit_outpoint)?
+        .expect("Checking Emergency txid for an unknow deposit");
+    if matches!(db_vault.status, VaultStatus::Unconfirmed) {
+        return Ok(None);
+    }
+    let (_, emer_tx) = db_emer_transaction(&db_path, db_vault.id)?;
+
+    Ok(Some((db_vault, emer_tx)))
 }
 
 // Which kind of transaction may spend the Unvault transaction.
@@ -1088,13 +1616,14 @@ enum UnvaultSpender {
     Cancel(Txid),
     // The Spend, any transaction spending via the managers path
     Spend(Txid),
-    // TODO: UnvaultEmergency
+    // The UnvaultEmergency, spending via the stakeholders path to the Emergency Deep Vault
+    UnvaultEmergency(Txid),
 }
 
 // Retrieve the transaction kind (and its txid) that spent an Unvault
 fn unvault_spender(
     revaultd: &mut Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     previous_tip: &BlockchainTip,
     unvault_outpoint: &OutPoint,
 ) -> Result<Option<UnvaultSpender>, BitcoindError> {
@@ -1109,36 +1638,211 @@ fn unvault_spender(
         })?;
 
     // First, check if it was spent by a Cancel, it's cheaper.
-    let cancel_txid = cancel_txid(revaultd, &vault)?;
-    if let Ok((_, blockheight, _)) = bitcoind.get_wallet_transaction(&cancel_txid) {
+    let cancel_tx = vault_cancel_tx(revaultd, &vault)?;
+    if let Some((cancel_txid, blockheight)) =
+        wallet_revocation_tx(&db_path, bitcoind, &cancel_tx.txid())?
+    {
         // If it's not in the block chain nor in mempool, it's not really what spent it.
         if blockheight.is_some() || bitcoind.is_in_mempool(&cancel_txid)? {
             return Ok(Some(UnvaultSpender::Cancel(cancel_txid)));
-        } else {
-            return Ok(None);
         }
     }
 
-    // Second, check if it was spent by an UnvaultEmergency
-    // TODO
+    // Second, check if it was spent by an UnvaultEmergency. Only stakeholders have it.
+    let unemer_tx = if revaultd.read().unwrap().is_stakeholder() {
+        let (_, unemer_tx) = db_unvault_emer_transaction(&db_path, vault.id)?;
+        if let Some((unemer_txid, blockheight)) =
+            wallet_revocation_tx(&db_path, bitcoind, &unemer_tx.txid())?
+        {
+            if blockheight.is_some() || bitcoind.is_in_mempool(&unemer_txid)? {
+                return Ok(Some(UnvaultSpender::UnvaultEmergency(unemer_txid)));
+            }
+        }
+        Some(unemer_tx)
+    } else {
+        None
+    };
 
-    // Finally, fetch the spending transaction and assume it's a Spend.
-    if let Some(spend_txid) = bitcoind.get_spender_txid(&unvault_outpoint, &previous_tip.hash)? {
+    // Finally, fetch the spending transaction and assume it's a Spend, unless it's a fee-bumped
+    // version of one of the revocation transactions.
+    if let Some(spender_txid) = spender_txid(&db_path, bitcoind, &unvault_outpoint, previous_tip)? {
         // FIXME: be smarter, all the information are in the previous call, no need for a
         // second one.
-        let (_, blockheight, _) = bitcoind.get_wallet_transaction(&spend_txid)?;
-        if blockheight.is_some() || bitcoind.is_in_mempool(&spend_txid)? {
-            return Ok(Some(UnvaultSpender::Spend(spend_txid)));
+        let (_, blockheight, _) = bitcoind.get_wallet_transaction(&spender_txid)?;
+        if blockheight.is_some() || bitcoind.is_in_mempool(&spender_txid)? {
+            if is_feebumped_version(&db_path, bitcoind, &spender_txid, &cancel_tx)? {
+                return Ok(Some(UnvaultSpender::Cancel(spender_txid)));
+            }
+            if let Some(ref unemer_tx) = unemer_tx {
+                if is_feebumped_version(&db_path, bitcoind, &spender_txid, unemer_tx)? {
+                    return Ok(Some(UnvaultSpender::UnvaultEmergency(spender_txid)));
+                }
+            }
+            return Ok(Some(UnvaultSpender::Spend(spender_txid)));
         }
     }
 
     Ok(None)
 }
 
+// Move our first unused deposit derivation index forward, and import the addresses at the new
+// end of the gap. If we are recovering, have the backend look for their past transactions, too.
+fn increment_deposit_index(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &dyn ChainBackend,
+    rescan_since: Option<u32>,
+) -> Result<(), BitcoindError> {
+    let current_first_index = revaultd.read().unwrap().current_unused_index;
+    let new_index = current_first_index.increment().map_err(|e| {
+        // FIXME: we should probably go back to 0 at this point.
+        BitcoindError::Custom(format!("Deriving next index: {}", e))
+    })?;
+    db_update_deposit_index(&revaultd.read().unwrap().db_file(), new_index)?;
+    revaultd.write().unwrap().current_unused_index = new_index;
+    let (deposit_address, unvault_address, cpfp_address) = {
+        let revaultd = revaultd.read().unwrap();
+        (
+            revaultd.last_deposit_address(),
+            revaultd.last_unvault_address(),
+            revaultd.last_cpfp_address(),
+        )
+    };
+    if let Some(timestamp) = rescan_since {
+        bitcoind.watch_past_addresses(
+            &deposit_address,
+            &unvault_address,
+            &cpfp_address,
+            timestamp,
+        )?;
+    } else {
+        bitcoind.watch_fresh_addresses(&deposit_address, &unvault_address, &cpfp_address)?;
+    }
+
+    log::debug!(
+        "Incremented deposit derivation index from {}",
+        current_first_index
+    );
+
+    Ok(())
+}
+
+// Rebuild the vaults of a lost database out of all the deposits bitcoind's watchonly wallet ever
+// saw, once it rescanned the block chain. We register them as confirmed and fetch all their
+// signatures from the Coordinator, so they get back to 'secured' or 'active'. Then we make our
+// tip go back to before the first deposit, for the poller to replay what happened to them
+// onchain since (Unvault, Spend, Cancel, ..).
+// A deposit moves the gap forward, and the addresses at its new end may have received deposits
+// too. So we rescan for them and query the deposits again, until the last gap has none.
+fn recover_vaults(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind: &dyn ChainBackend,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.read().unwrap().db_file();
+    let min_conf = revaultd.read().unwrap().min_conf;
+    let since = revaultd
+        .read()
+        .unwrap()
+        .recovery_timestamp
+        .expect("We only recover if the timestamp was given");
+    let mut first_height: Option<u32> = None;
+    let mut recovered = HashSet::new();
+
+    loop {
+        let deposits = bitcoind.confirmed_deposits(min_conf)?;
+        let mut found_new = false;
+
+        for (outpoint, utxo) in deposits {
+            if recovered.contains(&outpoint) {
+                continue;
+            }
+            let derivation_index = match revaultd
+                .read()
+                .unwrap()
+                .derivation_index_map
+                .get(&utxo.txo.script_pubkey)
+            {
+                Some(index) => *index,
+                None => {
+                    log::warn!(
+                        "Deposit at '{}' is for an address we don't know, ignoring it",
+                        outpoint
+                    );
+                    continue;
+                }
+            };
+            let blockheight = match bitcoind.get_wallet_transaction(&outpoint.txid)? {
+                (_, Some(height), received_at) => {
+                    db_insert_new_unconfirmed_vault(
+                        &db_path,
+                        revaultd
+                            .read()
+                            .unwrap()
+                            .wallet_id
+                            .expect("Wallet id is set at startup in setup_db()"),
+                        &outpoint,
+                        &Amount::from_sat(utxo.txo.value),
+                        derivation_index,
+                        received_at,
+                    )?;
+                    height
+                }
+                // It got reorged out in the meantime, the poller will pick it up again.
+                (_, None, _) => continue,
+            };
+            recovered.insert(outpoint);
+            found_new = true;
+            while derivation_index >= revaultd.read().unwrap().current_unused_index {
+                increment_deposit_index(revaultd, bitcoind, Some(since))?;
+            }
+
+            let (unvault_tx, cancel_tx, emer_tx, unemer_tx) =
+                presigned_transactions(&revaultd.read().unwrap(), outpoint, utxo)?;
+            db_confirm_deposit(
+                &db_path,
+                &outpoint,
+                blockheight,
+                &unvault_tx,
+                &cancel_tx,
+                emer_tx.as_ref(),
+                unemer_tx.as_ref(),
+            )?;
+            first_height = Some(first_height.map_or(blockheight, |h| h.min(blockheight)));
+            log::debug!("Recovered vault at '{}'", outpoint);
+        }
+
+        if !found_new {
+            break;
+        }
+    }
+    log::info!("Recovered '{}' vaults", recovered.len());
+
+    // This needs to happen before the poller moves them further, as it would set them back to
+    // 'secured' or 'active'.
+    let txs = db_transactions_sig_missing(&db_path)?;
+    fetch_all_signatures(&revaultd.read().unwrap(), txs).map_err(|e| {
+        BitcoindError::Custom(format!(
+            "Fetching signatures from the Coordinator: {}",
+            e.to_string()
+        ))
+    })?;
+
+    if let Some(height) = first_height {
+        let height = height.saturating_sub(1);
+        let tip = BlockchainTip {
+            height,
+            hash: bitcoind.getblockhash(height)?,
+        };
+        db_update_tip(&db_path, &tip)?;
+    }
+    log::info!("Vaults recovered, replaying what happened to them since.");
+
+    Ok(())
+}
+
 // This syncs with bitcoind our onchain utxos. We track the deposits and unvaults ones.
 fn update_utxos(
     revaultd: &mut Arc<RwLock<RevaultD>>,
-    bitcoind: &BitcoinD,
+    bitcoind: &dyn ChainBackend,
     deposits_cache: &mut HashMap<OutPoint, UtxoInfo>,
     unvaults_cache: &mut HashMap<OutPoint, UtxoInfo>,
     previous_tip: &BlockchainTip,
@@ -1154,6 +1858,17 @@ fn update_utxos(
         new_spent: spent_unvaults,
     } = bitcoind.sync_unvaults(&unvaults_cache)?;
 
+    // Before looking up what spent our coins, index the spenders in the new blocks.
+    let mut indexed_height = previous_tip.height;
+    if !spent_unvaults.is_empty() {
+        indexed_height = index_spenders(
+            &db_path,
+            bitcoind,
+            indexed_height,
+            watched_outpoints(deposits_cache, unvaults_cache),
+        )?;
+    }
+
     for (outpoint, utxo) in new_unvaults {
         // Note that it *might* have actually been confirmed in-between the last poll, but we keep
         // single transitions, and it's no big deal to mark it confirmed during the next poll.
@@ -1223,6 +1938,35 @@ fn update_utxos(
                     }
                 }
             }
+            Some(UnvaultSpender::UnvaultEmergency(txid)) => {
+                db_emer_unvault(&db_path, &unvault_outpoint.txid)?;
+                unvaults_cache.remove(&unvault_outpoint).ok_or_else(|| {
+                    BitcoindError::Custom("An unknown unvault got spent?".to_string())
+                })?;
+                log::debug!(
+                    "Unvault transaction at {} is now being emergencied",
+                    &unvault_outpoint
+                );
+
+                // Immediately check if it was confirmed, just in case
+                let (db_vault, _) = db_vault_by_unvault_txid(&db_path, &unvault_outpoint.txid)?
+                    .ok_or_else(|| {
+                        BitcoindError::Custom(format!(
+                            "No vault for Unvault '{}'",
+                            &unvault_outpoint.txid
+                        ))
+                    })?;
+                match maybe_confirm_unemer(&db_path, bitcoind, &db_vault, &txid) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!(
+                            "Error checking if UnvaultEmergency '{}' is confirmed: '{}'",
+                            &txid,
+                            e
+                        );
+                    }
+                }
+            }
             None => {}
         }
     }
@@ -1280,30 +2024,8 @@ fn update_utxos(
 
         // Mind the gap! https://www.youtube.com/watch?v=UOPyGKDQuRk
         // FIXME: of course, that's rudimentary
-        let current_first_index = revaultd.read().unwrap().current_unused_index;
-        if derivation_index >= current_first_index {
-            let new_index = revaultd
-                .read()
-                .unwrap()
-                .current_unused_index
-                .increment()
-                .map_err(|e| {
-                    // FIXME: we should probably go back to 0 at this point.
-                    BitcoindError::Custom(format!("Deriving next index: {}", e))
-                })?;
-            db_update_deposit_index(&revaultd.read().unwrap().db_file(), new_index)?;
-            revaultd.write().unwrap().current_unused_index = new_index;
-            let next_addr = bitcoind
-                .addr_descriptor(&revaultd.read().unwrap().last_deposit_address().to_string())?;
-            bitcoind.import_fresh_deposit_descriptor(next_addr)?;
-            let next_addr = bitcoind
-                .addr_descriptor(&revaultd.read().unwrap().last_unvault_address().to_string())?;
-            bitcoind.import_fresh_unvault_descriptor(next_addr)?;
-
-            log::debug!(
-                "Incremented deposit derivation index from {}",
-                current_first_index
-            );
+        if derivation_index >= revaultd.read().unwrap().current_unused_index {
+            increment_deposit_index(revaultd, bitcoind, None)?;
         }
     }
 
@@ -1355,6 +2077,15 @@ fn update_utxos(
         log::debug!("Vault at {} is now confirmed", &outpoint);
     }
 
+    if !spent_deposits.is_empty() {
+        index_spenders(
+            &db_path,
+            bitcoind,
+            indexed_height,
+            watched_outpoints(deposits_cache, unvaults_cache),
+        )?;
+    }
+
     for (deposit_outpoint, utxo) in spent_deposits {
         let unvault_txin = match unvault_txin_from_deposit(&revaultd, &deposit_outpoint, utxo.txo) {
             Ok(txin) => txin,
@@ -1399,7 +2130,12 @@ fn update_utxos(
                 // Can we retrieve the transaction that spent the Unvault too?
                 match unvault_spender(revaultd, bitcoind, previous_tip, &unvault_outpoint)? {
                     Some(UnvaultSpender::Cancel(txid)) => {
-                        // We check directly wether it was confirmed
+                        // Mark it as being canceled first in any case, so that confirming it is
+                        // a single transition.
+                        db_cancel_unvault(&db_path, &unvault_outpoint.txid)?;
+                        log::debug!("Unvault transaction at {} was canceled", &unvault_outpoint);
+
+                        // Now, was it confirmed?
                         let (db_vault, _) =
                             db_vault_by_unvault_txid(&db_path, &unvault_outpoint.txid)?
                                 .ok_or_else(|| {
@@ -1409,14 +2145,7 @@ fn update_utxos(
                                     ))
                                 })?;
                         match maybe_confirm_cancel(&db_path, bitcoind, &db_vault, &txid) {
-                            Ok(true) => {}
-                            Ok(false) => {
-                                db_cancel_unvault(&db_path, &unvault_outpoint.txid)?;
-                                log::debug!(
-                                    "Unvault transaction at {} was canceled",
-                                    &unvault_outpoint
-                                );
-                            }
+                            Ok(_) => {}
                             Err(e) => {
                                 log::error!(
                                     "Error checking if Cancel '{}' is confirmed: '{}'",
@@ -1452,6 +2181,33 @@ fn update_utxos(
                             }
                         }
                     }
+                    Some(UnvaultSpender::UnvaultEmergency(txid)) => {
+                        // Same as for the Cancel
+                        db_emer_unvault(&db_path, &unvault_outpoint.txid)?;
+                        log::debug!(
+                            "Unvault transaction at {} was emergencied",
+                            &unvault_outpoint
+                        );
+
+                        let (db_vault, _) =
+                            db_vault_by_unvault_txid(&db_path, &unvault_outpoint.txid)?
+                                .ok_or_else(|| {
+                                    BitcoindError::Custom(format!(
+                                        "No vault for Unvault '{}'",
+                                        &unvault_outpoint.txid
+                                    ))
+                                })?;
+                        match maybe_confirm_unemer(&db_path, bitcoind, &db_vault, &txid) {
+                            Ok(_) => {}
+                            Err(e) => {
+                                log::error!(
+                                    "Error checking if UnvaultEmergency '{}' is confirmed: '{}'",
+                                    &txid,
+                                    e
+                                );
+                            }
+                        }
+                    }
                     None => {
                         // If we couldn't find the transaction spending the Unvault, just mark it
                         // as being unvaulted. We'll try again later (maybe the Spend was just
@@ -1506,12 +2262,64 @@ fn update_utxos(
                 );
                 log::debug!(
                     "Spender txid: '{:?}'",
-                    bitcoind.get_spender_txid(&unvault_outpoint, &previous_tip.hash)?
+                    spender_txid(&db_path, bitcoind, &unvault_outpoint, previous_tip)?
+                );
+            }
+        }
+
+        // Was it spent by the Emergency transaction? Only stakeholders have it.
+        match deposit_emer_tx(revaultd, &deposit_outpoint) {
+            Ok(Some((db_vault, emer_tx))) => {
+                let emer_txid = emer_tx.txid();
+                // Another stakeholder may have broadcast it with a fee-bumping input attached.
+                if wallet_revocation_tx(&db_path, bitcoind, &emer_txid)?.is_none() {
+                    if let Some(spender_txid) =
+                        spender_txid(&db_path, bitcoind, &deposit_outpoint, previous_tip)?
+                    {
+                        is_feebumped_version(&db_path, bitcoind, &spender_txid, &emer_tx)?;
+                    }
+                }
+                if let Some((bumped_txid, blockheight)) =
+                    wallet_revocation_tx(&db_path, bitcoind, &emer_txid)?
+                {
+                    if blockheight.is_some() || bitcoind.is_in_mempool(&bumped_txid)? {
+                        // Mark it as being emergencied first in any case, so that confirming
+                        // it is a single transition.
+                        db_emer_deposit(&db_path, db_vault.id)?;
+                        log::debug!(
+                            "The deposit utxo created via '{}' was emergencied via '{}'",
+                            &deposit_outpoint,
+                            &emer_txid
+                        );
+                        match maybe_confirm_emer(&db_path, bitcoind, &db_vault, &emer_txid) {
+                            Ok(_) => {}
+                            Err(e) => {
+                                log::error!(
+                                    "Error checking if Emergency '{}' is confirmed: '{}'",
+                                    &emer_txid,
+                                    e
+                                );
+                            }
+                        }
+
+                        deposits_cache
+                            .remove(&deposit_outpoint)
+                            .expect("It was in spent_deposits, it must still be here.");
+                        continue;
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!(
+                    "Error while getting Emergency txid for deposit '{}': '{}'",
+                    &deposit_outpoint,
+                    e
                 );
             }
         }
 
-        // TODO: handle bypass and emergency
+        // TODO: handle bypass
 
         // Only remove the deposit from the cache if it's not in mempool nor in block chain.
         if let (_, Some(height), _) = bitcoind.get_wallet_transaction(&deposit_outpoint.txid)? {
@@ -1543,11 +2351,121 @@ fn update_utxos(
     Ok(())
 }
 
+// Go through what was recorded in the vaults history since the last event we handled, to run the
+// user's hooks and stream it to the JSONRPC server. This includes the status changes and
+// broadcasts triggered by the other threads.
+fn handle_new_events(
+    revaultd: &RevaultD,
+    last_event_id: &mut i64,
+    notif_tx: &Sender<Notification>,
+) -> Result<(), BitcoindError> {
+    let db_path = revaultd.db_file();
+    let filter = EventsFilter {
+        kinds: Some(vec![
+            EventKind::StatusChange,
+            EventKind::Rewind,
+            EventKind::Broadcast,
+        ]),
+        after_id: Some(*last_event_id),
+        ..EventsFilter::default()
+    };
+
+    let mut new_secured = false;
+    for event in db_events(&db_path, &filter, 0, u32::MAX)? {
+        *last_event_id = event.id;
+        if let Err(e) = run_event_hook(revaultd, &event) {
+            log::error!("Error running the hook for event '{}': '{}'", event.id, e);
+        }
+        new_secured |=
+            event.kind == EventKind::StatusChange && event.status == VaultStatus::Secured;
+
+        let outpoint = event.deposit_outpoint;
+        let notif = match (event.kind, event.status, event.txid) {
+            (EventKind::Broadcast, _, Some(txid)) => Notification::Broadcast { outpoint, txid },
+            // Vaults are only ever inserted as unconfirmed, afterward it's a rewind
+            (EventKind::StatusChange, VaultStatus::Unconfirmed, _) => {
+                match db_vault_by_deposit(&db_path, &outpoint) {
+                    Ok(Some(db_vault)) => Notification::Deposit {
+                        outpoint,
+                        amount: db_vault.amount,
+                    },
+                    Ok(None) => {
+                        log::error!("No vault for event at '{}'", outpoint);
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("Error fetching vault at '{}': '{}'", outpoint, e);
+                        continue;
+                    }
+                }
+            }
+            (_, status, _) => Notification::StatusChange { outpoint, status },
+        };
+
+        // The JSONRPC server may be gone if we are shutting down
+        if let Err(e) = notif_tx.send(notif) {
+            log::debug!("Sending notification to the JSONRPC server: '{}'", e);
+        }
+    }
+
+    // Keep the emergency kit in sync with the vaults we can emergency
+    if new_secured {
+        if let Err(e) = refresh_emergency_kit(revaultd) {
+            log::error!("Error refreshing the emergency kit: '{}'", e);
+        }
+    }
+
+    Ok(())
+}
+
+// Wait a bit for bitcoind to notify us of something happening on chain. Returns true if we
+// should sync right away.
+fn wait_chain_notification(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    chain_notifs: Option<&Receiver<ChainNotification>>,
+    deposits_cache: &HashMap<OutPoint, UtxoInfo>,
+    unvaults_cache: &HashMap<OutPoint, UtxoInfo>,
+) -> bool {
+    let wait_time = Duration::from_millis(500);
+    let chain_notifs = match chain_notifs {
+        Some(chain_notifs) => chain_notifs,
+        None => {
+            thread::sleep(wait_time);
+            return false;
+        }
+    };
+
+    let first_notif = match chain_notifs.recv_timeout(wait_time) {
+        Ok(notif) => notif,
+        Err(RecvTimeoutError::Timeout) => return false,
+        // The listener failed, we are back to polling
+        Err(RecvTimeoutError::Disconnected) => {
+            thread::sleep(wait_time);
+            return false;
+        }
+    };
+
+    // Process all the pending ones at once, a single sync will cover them all
+    let revaultd = revaultd.read().unwrap();
+    std::iter::once(first_notif)
+        .chain(chain_notifs.try_iter())
+        .fold(false, |sync, notif| {
+            sync || match notif {
+                ChainNotification::NewBlock => true,
+                ChainNotification::NewTx(tx) => {
+                    is_relevant(&revaultd, &tx, deposits_cache, unvaults_cache)
+                }
+            }
+        })
+}
+
 fn poller_main(
     mut revaultd: Arc<RwLock<RevaultD>>,
-    bitcoind: Arc<RwLock<BitcoinD>>,
+    bitcoind: Arc<RwLock<dyn ChainBackend>>,
     sync_progress: Arc<RwLock<f64>>,
     shutdown: Arc<AtomicBool>,
+    notif_tx: Sender<Notification>,
+    chain_notifs: Option<Receiver<ChainNotification>>,
 ) -> Result<(), BitcoindError> {
     let mut last_poll = None;
     let mut sync_waittime = None;
@@ -1555,8 +2473,11 @@ fn poller_main(
     let mut deposits_cache = populate_deposit_cache(&revaultd.read().unwrap())?;
     // Same for the unvaults
     let mut unvaults_cache = populate_unvaults_cache(&revaultd.read().unwrap())?;
-    // When bitcoind is synced, we poll each 30s. On regtest we speed it up for testing.
+    // When bitcoind is synced, we poll each 30s. On regtest we speed it up for testing. If
+    // bitcoind notifies us of new blocks and transactions, we also sync as soon as it does.
     let poll_interval = revaultd.read().unwrap().bitcoind_config.poll_interval_secs;
+    // Only notify what happened from now on
+    let mut last_event_id = db_last_event_id(&revaultd.read().unwrap().db_file())?;
 
     while !shutdown.load(Ordering::Relaxed) {
         let now = Instant::now();
@@ -1580,28 +2501,67 @@ fn poller_main(
             )?;
 
             // Ok. Sync, done. Now just be sure the watchonly wallet is properly loaded, and
-            // to create it if it's first run.
+            // to create it if it's first run. Only bitcoind has wallets.
             if *sync_progress.read().unwrap() as u32 >= 1 {
-                let mut revaultd = revaultd.write().unwrap();
-                let bitcoind = bitcoind.read().unwrap();
-                maybe_create_wallet(&mut revaultd, &bitcoind).map_err(|e| {
-                    BitcoindError::Custom(format!("Error while creating wallet: {}", e.to_string()))
-                })?;
-                maybe_load_wallet(&revaultd, &bitcoind).map_err(|e| {
-                    BitcoindError::Custom(format!("Error while loading wallet: {}", e.to_string()))
-                })?;
+                let backend = bitcoind.read().unwrap();
+                if let Some(bitcoind) = backend.as_bitcoind() {
+                    let mut revaultd = revaultd.write().unwrap();
+                    maybe_create_wallet(&mut revaultd, bitcoind).map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Error while creating wallet: {}",
+                            e.to_string()
+                        ))
+                    })?;
+                    maybe_create_feebump_wallet(&revaultd, bitcoind).map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Error while creating fee-bumping wallet: {}",
+                            e.to_string()
+                        ))
+                    })?;
+                    maybe_load_wallet(&revaultd, bitcoind).map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Error while loading wallet: {}",
+                            e.to_string()
+                        ))
+                    })?;
+                    maybe_import_cpfp_descriptors(&revaultd, bitcoind).map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Error while importing CPFP descriptors: {}",
+                            e.to_string()
+                        ))
+                    })?;
+                }
 
                 log::info!("bitcoind now synced.");
             }
 
+            // Now that bitcoind rescanned the block chain, rebuild our vaults if asked to.
+            if *sync_progress.read().unwrap() as u32 >= 1
+                && revaultd.read().unwrap().recovery_timestamp.is_some()
+            {
+                recover_vaults(&revaultd, &bitcoind.read().unwrap())?;
+                revaultd.write().unwrap().recovery_timestamp = None;
+                deposits_cache = populate_deposit_cache(&revaultd.read().unwrap())?;
+                unvaults_cache = populate_unvaults_cache(&revaultd.read().unwrap())?;
+                // Don't run the hooks for the recovered vaults, the user knows about them
+                last_event_id = db_last_event_id(&revaultd.read().unwrap().db_file())?;
+            }
+
             last_poll = Some(now);
             continue;
         }
 
         if let Some(last_poll) = last_poll {
             if now.duration_since(last_poll) < poll_interval {
-                thread::sleep(Duration::from_millis(500));
-                continue;
+                if !wait_chain_notification(
+                    &revaultd,
+                    chain_notifs.as_ref(),
+                    &deposits_cache,
+                    &unvaults_cache,
+                ) {
+                    continue;
+                }
+                log::debug!("Got notified of a chain update by bitcoind, syncing now");
             }
         }
 
@@ -1619,12 +2579,17 @@ fn poller_main(
             &mut unvaults_cache,
             &previous_tip,
         )?;
+        // We'll retry the ones we could not fetch on the next round
+        if let Err(e) = handle_new_events(&revaultd.read().unwrap(), &mut last_event_id, &notif_tx)
+        {
+            log::error!("Error handling new events: '{}'", e);
+        }
     }
 
     Ok(())
 }
 
-fn wallet_transaction(bitcoind: &BitcoinD, txid: Txid) -> Option<WalletTransaction> {
+fn wallet_transaction(bitcoind: &dyn ChainBackend, txid: Txid) -> Option<WalletTransaction> {
     let res = bitcoind.get_wallet_transaction(&txid);
     if let Ok((hex, blockheight, received_time)) = res {
         Some(WalletTransaction {
@@ -1642,13 +2607,19 @@ fn wallet_transaction(bitcoind: &BitcoinD, txid: Txid) -> Option<WalletTransacti
     }
 }
 
+// Stakeholders can't use an Electrum server, but better safe than sorry
+fn no_feebump_wallet() -> BitcoindError {
+    BitcoindError::Custom("The fee-bumping wallet is only available with bitcoind".to_string())
+}
+
 /// The bitcoind event loop.
-/// Listens for bitcoind requests (wallet / chain) and poll bitcoind every 30 seconds,
-/// updating our state accordingly.
+/// Listens for bitcoind requests (wallet / chain) and poll bitcoind every 30 seconds (or as soon
+/// as it notifies us of a new block or transaction), updating our state accordingly.
 pub fn bitcoind_main_loop(
     rx: Receiver<BitcoindMessageOut>,
     revaultd: Arc<RwLock<RevaultD>>,
-    bitcoind: Arc<RwLock<BitcoinD>>,
+    bitcoind: Arc<RwLock<dyn ChainBackend>>,
+    notif_tx: Sender<Notification>,
 ) -> Result<(), BitcoindError> {
     // The verification progress announced by bitcoind *at startup* thus won't be updated
     // after startup check. Should be *exactly* 1.0 when synced, but hey, floats so we are
@@ -1657,13 +2628,46 @@ pub fn bitcoind_main_loop(
     // Used to shutdown the poller thread
     let shutdown = Arc::new(AtomicBool::new(false));
 
+    // If bitcoind publishes ZMQ notifications, we use a thread to listen to them and wake up
+    // the poller. Only bitcoind does, Electrum servers are always polled.
+    let zmq_subscriber = if bitcoind.read().unwrap().as_bitcoind().is_some() {
+        ZmqSubscriber::new(&revaultd.read().unwrap().bitcoind_config)?
+    } else {
+        None
+    };
+    let (chain_notifs, zmq_thread) = match zmq_subscriber {
+        Some(subscriber) => {
+            let (chain_notif_tx, chain_notif_rx) = mpsc::channel();
+            let _shutdown = shutdown.clone();
+            let zmq_thread = std::thread::spawn(move || {
+                if let Err(e) = subscriber.listen(chain_notif_tx, _shutdown) {
+                    log::error!(
+                        "Error listening to bitcoind's notifications, falling back to polling: '{}'",
+                        e
+                    );
+                }
+            });
+            (Some(chain_notif_rx), Some(zmq_thread))
+        }
+        None => (None, None),
+    };
+
     // We use a thread to 1) wait for bitcoind to be synced 2) poll listunspent
     let poller_thread = std::thread::spawn({
         let _revaultd = revaultd.clone();
         let _bitcoind = bitcoind.clone();
         let _sync_progress = sync_progress.clone();
         let _shutdown = shutdown.clone();
-        move || poller_main(_revaultd, _bitcoind, _sync_progress, _shutdown)
+        move || {
+            poller_main(
+                _revaultd,
+                _bitcoind,
+                _sync_progress,
+                _shutdown,
+                notif_tx,
+                chain_notifs,
+            )
+        }
     });
 
     for msg in rx {
@@ -1675,6 +2679,9 @@ pub fn bitcoind_main_loop(
                     assume_ok!(poller_thread.join(), "Joining bitcoind poller thread"),
                     "Error in bitcoind poller thread"
                 );
+                if let Some(zmq_thread) = zmq_thread {
+                    assume_ok!(zmq_thread.join(), "Joining bitcoind notifications thread");
+                }
                 return Ok(());
             }
             BitcoindMessageOut::SyncProgress(resp_tx) => {
@@ -1707,8 +2714,391 @@ pub fn bitcoind_main_loop(
                         ))
                     })?;
             }
+            BitcoindMessageOut::FeebumpTransaction(tx, presigned_fees, feerate_vb, resp_tx) => {
+                log::trace!("Received 'feebumptransaction' from main thread");
+                let backend = bitcoind.read().unwrap();
+                resp_tx
+                    .send(
+                        backend
+                            .as_bitcoind()
+                            .ok_or_else(no_feebump_wallet)
+                            .and_then(|bitcoind| {
+                                feebump_transaction(bitcoind, tx, presigned_fees, feerate_vb)
+                            }),
+                    )
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending fee-bumped transaction to main thread: {}",
+                            e
+                        ))
+                    })?;
+            }
+            BitcoindMessageOut::FeebumpReserve(revocation_weight, feerate_vb, resp_tx) => {
+                log::trace!("Received 'feebumpreserve' from main thread");
+                let backend = bitcoind.read().unwrap();
+                resp_tx
+                    .send(
+                        backend
+                            .as_bitcoind()
+                            .ok_or_else(no_feebump_wallet)
+                            .and_then(|bitcoind| {
+                                feebump_reserve(bitcoind, revocation_weight, feerate_vb)
+                            }),
+                    )
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending fee-bumping reserve to main thread: {}",
+                            e
+                        ))
+                    })?;
+            }
+            BitcoindMessageOut::EstimateFeerate(conf_target, estimate_mode, resp_tx) => {
+                log::trace!("Received 'estimatefeerate' from main thread");
+                resp_tx
+                    .send(
+                        bitcoind
+                            .read()
+                            .unwrap()
+                            .estimate_feerate(conf_target, estimate_mode),
+                    )
+                    .map_err(|e| {
+                        BitcoindError::Custom(format!(
+                            "Sending feerate estimate to main thread: {}",
+                            e
+                        ))
+                    })?;
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitcoind::mock::MockBitcoind, database::interface::db_spender, test_utils::dummy_revaultd,
+    };
+    use revault_tx::bitcoin::{util::bip32::ChildNumber, Address, Script, TxIn};
+
+    use std::{fs, str::FromStr};
+
+    const DEPOSIT_VALUE: u64 = 50_000_000;
+
+    // A manager's state on a fresh scratch data directory, and a mock bitcoind tracking its
+    // addresses up to the gap limit.
+    fn dummy_poller_state(datadir_name: &str) -> (RevaultD, MockBitcoind) {
+        let revaultd = dummy_revaultd(datadir_name);
+        let bitcoind = MockBitcoind::new(revaultd.bitcoind_config.network, &revaultd.data_dir);
+        for i in 0..revaultd.gap_limit() {
+            let index = ChildNumber::from(i);
+            bitcoind.watch(&revaultd.vault_address(index), "revault-deposit");
+            bitcoind.watch(&revaultd.unvault_address(index), "revault-unvault");
+            bitcoind.watch(&revaultd.cpfp_address(index), "revault-cpfp");
+        }
+        // Don't start from the genesis
+        bitcoind.mine(10);
+
+        (revaultd, bitcoind)
+    }
+
+    // The poller's state, to drive it one poll at a time
+    struct Poller {
+        revaultd: Arc<RwLock<RevaultD>>,
+        bitcoind: BitcoinD,
+        deposits_cache: HashMap<OutPoint, UtxoInfo>,
+        unvaults_cache: HashMap<OutPoint, UtxoInfo>,
+    }
+
+    impl Poller {
+        fn new(revaultd: RevaultD, bitcoind: &MockBitcoind) -> Self {
+            Poller {
+                deposits_cache: populate_deposit_cache(&revaultd).unwrap(),
+                unvaults_cache: populate_unvaults_cache(&revaultd).unwrap(),
+                revaultd: Arc::new(RwLock::new(revaultd)),
+                bitcoind: bitcoind.client(),
+            }
+        }
+
+        fn poll(&mut self) {
+            let previous_tip = update_tip(
+                &mut self.revaultd,
+                &self.bitcoind,
+                &mut self.deposits_cache,
+                &mut self.unvaults_cache,
+            )
+            .expect("Updating the tip");
+            update_utxos(
+                &mut self.revaultd,
+                &self.bitcoind,
+                &mut self.deposits_cache,
+                &mut self.unvaults_cache,
+                &previous_tip,
+            )
+            .expect("Updating the utxos");
+        }
+
+        fn db_path(&self) -> PathBuf {
+            self.revaultd.read().unwrap().db_file()
+        }
+
+        fn vault(&self, deposit_outpoint: &OutPoint) -> DbVault {
+            db_vault_by_deposit(&self.db_path(), deposit_outpoint)
+                .unwrap()
+                .expect("Vault must exist")
+        }
+
+        fn unvault_outpoint(&self, vault: &DbVault) -> OutPoint {
+            let revaultd = self.revaultd.read().unwrap();
+            let (_, unvault_tx) = db_unvault_transaction(&revaultd.db_file(), vault.id).unwrap();
+            unvault_tx
+                .revault_unvault_txin(&revaultd.derived_unvault_descriptor(vault.derivation_index))
+                .outpoint()
+        }
+
+        fn cleanup(self) {
+            let datadir = self.revaultd.read().unwrap().data_dir.clone();
+            fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+        }
+    }
+
+    #[test]
+    fn poller_deposit_reorgs() {
+        let (revaultd, bitcoind) = dummy_poller_state("scratch_datadir_poller_reorgs");
+        let min_conf = revaultd.min_conf;
+        let deposit_address = revaultd.deposit_address();
+        let mut poller = Poller::new(revaultd, &bitcoind);
+        poller.poll();
+        assert_eq!(
+            db_tip(&poller.db_path()).unwrap(),
+            bitcoind.tip(),
+            "We must have caught up with the tip"
+        );
+
+        // A new deposit is detected as unconfirmed, then as funded once deep enough
+        let deposit_tx = bitcoind.pay_to(&deposit_address, Amount::from_sat(DEPOSIT_VALUE));
+        let deposit_outpoint = OutPoint::new(deposit_tx.txid(), 0);
+        poller.poll();
+        assert_eq!(
+            poller.vault(&deposit_outpoint).status,
+            VaultStatus::Unconfirmed
+        );
+        bitcoind.mine(min_conf - 1);
+        poller.poll();
+        assert_eq!(
+            poller.vault(&deposit_outpoint).status,
+            VaultStatus::Unconfirmed
+        );
+        bitcoind.mine(1);
+        poller.poll();
+        assert_eq!(poller.vault(&deposit_outpoint).status, VaultStatus::Funded);
+        assert!(poller.deposits_cache[&deposit_outpoint].is_confirmed);
+
+        // A reorg including it again in the new chain, still deep enough
+        bitcoind.reorg(min_conf);
+        bitcoind.mine(min_conf + 1);
+        poller.poll();
+        assert_eq!(db_tip(&poller.db_path()).unwrap(), bitcoind.tip());
+        assert_eq!(poller.vault(&deposit_outpoint).status, VaultStatus::Funded);
+
+        // A reorg not including it, it's unconfirmed (and rebroadcast) again
+        bitcoind.reorg(min_conf + 1);
+        bitcoind.evict(&deposit_outpoint.txid);
+        bitcoind.mine(min_conf + 2);
+        poller.poll();
+        assert_eq!(db_tip(&poller.db_path()).unwrap(), bitcoind.tip());
+        assert_eq!(
+            poller.vault(&deposit_outpoint).status,
+            VaultStatus::Unconfirmed
+        );
+        assert!(!poller.deposits_cache[&deposit_outpoint].is_confirmed);
+        assert!(
+            db_unvault_from_deposit(&poller.db_path(), &deposit_outpoint)
+                .unwrap()
+                .is_none()
+        );
+
+        // Once it gets confirmed again, it's back to funded
+        bitcoind.mine(min_conf);
+        poller.poll();
+        assert_eq!(poller.vault(&deposit_outpoint).status, VaultStatus::Funded);
+        assert!(
+            db_unvault_from_deposit(&poller.db_path(), &deposit_outpoint)
+                .unwrap()
+                .is_some()
+        );
+
+        poller.cleanup();
+    }
+
+    #[test]
+    fn poller_unvault_spenders() {
+        let (revaultd, bitcoind) = dummy_poller_state("scratch_datadir_poller_spenders");
+        let (min_conf, unvault_csv) = (revaultd.min_conf, revaultd.unvault_csv);
+        let (first_address, second_address) = (
+            revaultd.vault_address(ChildNumber::from(0)),
+            revaultd.vault_address(ChildNumber::from(1)),
+        );
+        let mut poller = Poller::new(revaultd, &bitcoind);
+
+        // Two funded vaults
+        let deposits: Vec<OutPoint> = [first_address, second_address]
+            .iter()
+            .map(|address| {
+                let tx = bitcoind.pay_to(address, Amount::from_sat(DEPOSIT_VALUE));
+                OutPoint::new(tx.txid(), 0)
+            })
+            .collect();
+        poller.poll();
+        bitcoind.mine(min_conf);
+        poller.poll();
+        let vaults: Vec<DbVault> = deposits.iter().map(|d| poller.vault(d)).collect();
+        for vault in vaults.iter() {
+            assert_eq!(vault.status, VaultStatus::Funded);
+        }
+
+        // Both get unvaulted, we notice it in mempool then once confirmed
+        for vault in vaults.iter() {
+            let (_, unvault_tx) = db_unvault_transaction(&poller.db_path(), vault.id).unwrap();
+            bitcoind.broadcast(unvault_tx.into_psbt().extract_tx());
+        }
+        poller.poll();
+        for deposit in deposits.iter() {
+            assert_eq!(poller.vault(deposit).status, VaultStatus::Unvaulting);
+        }
+        bitcoind.mine(1);
+        poller.poll();
+        for deposit in deposits.iter() {
+            assert_eq!(poller.vault(deposit).status, VaultStatus::Unvaulted);
+        }
+
+        // The first one gets canceled and the second one spent, both confirmed in between two
+        // polls. We find them in the blocks.
+        let first_unvault = poller.unvault_outpoint(&vaults[0]);
+        let second_unvault = poller.unvault_outpoint(&vaults[1]);
+        let (_, cancel_tx) = db_cancel_transaction(&poller.db_path(), vaults[0].id)
+            .unwrap()
+            .expect("Funded vault");
+        let cancel_txid = bitcoind.broadcast(cancel_tx.into_psbt().extract_tx());
+        let spend_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: second_unvault,
+                script_sig: Script::new(),
+                sequence: unvault_csv,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: DEPOSIT_VALUE / 2,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let spend_txid = bitcoind.broadcast(spend_tx);
+        bitcoind.mine(1);
+        poller.poll();
+        assert_eq!(poller.vault(&deposits[0]).status, VaultStatus::Canceled);
+        let spent_vault = poller.vault(&deposits[1]);
+        assert_eq!(spent_vault.status, VaultStatus::Spent);
+        assert_eq!(spent_vault.spend_txid, Some(spend_txid));
+        assert!(poller.unvaults_cache.is_empty());
+        let db_path = poller.db_path();
+        assert_eq!(
+            db_spender(&db_path, &first_unvault).unwrap(),
+            Some(cancel_txid)
+        );
+        assert_eq!(
+            db_spender(&db_path, &second_unvault).unwrap(),
+            Some(spend_txid)
+        );
+
+        // A reorg drops the index. As the spenders made it to the new chain, the vaults are
+        // left untouched.
+        bitcoind.reorg(1);
+        bitcoind.mine(2);
+        poller.poll();
+        assert_eq!(poller.vault(&deposits[0]).status, VaultStatus::Canceled);
+        assert_eq!(poller.vault(&deposits[1]).status, VaultStatus::Spent);
+        assert_eq!(db_spender(&db_path, &first_unvault).unwrap(), None);
+        assert_eq!(db_spender(&db_path, &second_unvault).unwrap(), None);
+
+        poller.cleanup();
+    }
+
+    #[test]
+    fn poller_main_chain_notifications() {
+        let (mut revaultd, bitcoind) = dummy_poller_state("scratch_datadir_poller_notifs");
+        // Never poll on our own, only when notified
+        revaultd.bitcoind_config.poll_interval_secs = Duration::from_secs(3600);
+        let min_conf = revaultd.min_conf;
+        let deposit_address = revaultd.deposit_address();
+        let db_path = revaultd.db_file();
+        let datadir = revaultd.data_dir.clone();
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (notif_tx, notif_rx) = mpsc::channel();
+        let (chain_notif_tx, chain_notif_rx) = mpsc::channel();
+        let poller_thread = thread::spawn({
+            let revaultd = Arc::new(RwLock::new(revaultd));
+            let backend: Arc<RwLock<dyn ChainBackend>> = Arc::new(RwLock::new(bitcoind.client()));
+            // bitcoind is synced and the wallet loaded
+            let sync_progress = Arc::new(RwLock::new(1.0));
+            let shutdown = shutdown.clone();
+            move || {
+                poller_main(
+                    revaultd,
+                    backend,
+                    sync_progress,
+                    shutdown,
+                    notif_tx,
+                    Some(chain_notif_rx),
+                )
+            }
+        });
+
+        // Wait for the first poll, they'll all be triggered by a notification afterward
+        let start = Instant::now();
+        while db_tip(&db_path).unwrap() != bitcoind.tip() {
+            assert!(start.elapsed() < Duration::from_secs(10), "First poll");
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        // An unrelated transaction doesn't trigger a sync, but one paying to us does
+        let unrelated_tx = bitcoind.pay_to(
+            &Address::from_str("bc1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxswvvzej")
+                .unwrap(),
+            Amount::from_sat(DEPOSIT_VALUE),
+        );
+        chain_notif_tx
+            .send(ChainNotification::NewTx(unrelated_tx))
+            .unwrap();
+        let deposit_tx = bitcoind.pay_to(&deposit_address, Amount::from_sat(DEPOSIT_VALUE));
+        let deposit_outpoint = OutPoint::new(deposit_tx.txid(), 0);
+        chain_notif_tx
+            .send(ChainNotification::NewTx(deposit_tx))
+            .unwrap();
+        match notif_rx.recv_timeout(Duration::from_secs(10)).unwrap() {
+            Notification::Deposit { outpoint, amount } => {
+                assert_eq!(outpoint, deposit_outpoint);
+                assert_eq!(amount, Amount::from_sat(DEPOSIT_VALUE));
+            }
+            notif => panic!("Unexpected notification: '{:?}'", notif),
+        }
+
+        // A new block triggers a sync
+        bitcoind.mine(min_conf);
+        chain_notif_tx.send(ChainNotification::NewBlock).unwrap();
+        match notif_rx.recv_timeout(Duration::from_secs(10)).unwrap() {
+            Notification::StatusChange { outpoint, status } => {
+                assert_eq!(outpoint, deposit_outpoint);
+                assert_eq!(status, VaultStatus::Funded);
+            }
+            notif => panic!("Unexpected notification: '{:?}'", notif),
+        }
+
+        shutdown.store(true, Ordering::Relaxed);
+        poller_thread.join().unwrap().unwrap();
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
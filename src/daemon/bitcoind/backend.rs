@@ -0,0 +1,108 @@
+//! What our poller needs from the block chain. It's implemented by bitcoind's watchonly wallet,
+//! and by an Electrum server for those who don't run a full node.
+
+use crate::{
+    bitcoind::{
+        interface::{BitcoinD, EstimateMode, OnchainDescriptorState, SyncInfo, UtxoInfo},
+        BitcoindError,
+    },
+    revaultd::BlockchainTip,
+};
+use revault_tx::bitcoin::{Address, BlockHash, OutPoint, Transaction, Txid};
+
+use std::collections::HashMap;
+
+pub trait ChainBackend: Send + Sync {
+    /// The current best block
+    fn get_tip(&self) -> Result<BlockchainTip, BitcoindError>;
+
+    /// The hash of the block at this height in the best chain
+    fn getblockhash(&self, height: u32) -> Result<BlockHash, BitcoindError>;
+
+    /// How far the backend is from being synced with the network
+    fn synchronization_info(&self) -> Result<SyncInfo, BitcoindError>;
+
+    /// Start tracking the addresses derived at a newly used index
+    fn watch_fresh_addresses(
+        &self,
+        deposit_address: &Address,
+        unvault_address: &Address,
+        cpfp_address: &Address,
+    ) -> Result<(), BitcoindError>;
+
+    /// Start tracking the addresses derived at a newly used index, along with the transactions
+    /// they got since this timestamp. Used when recovering the vaults of a lost database.
+    fn watch_past_addresses(
+        &self,
+        deposit_address: &Address,
+        unvault_address: &Address,
+        cpfp_address: &Address,
+        since: u32,
+    ) -> Result<(), BitcoindError>;
+
+    /// Get the new, newly confirmed and spent deposit utxos compared to the ones we know about
+    fn sync_deposits(
+        &self,
+        deposits_utxos: &HashMap<OutPoint, UtxoInfo>,
+        min_conf: u32,
+    ) -> Result<OnchainDescriptorState, BitcoindError>;
+
+    /// Get the new, newly confirmed and spent Unvault utxos compared to the ones we know about
+    fn sync_unvaults(
+        &self,
+        unvault_utxos: &HashMap<OutPoint, UtxoInfo>,
+    ) -> Result<OnchainDescriptorState, BitcoindError>;
+
+    /// Get all the deposits ever made to our deposit addresses that have at least `min_conf`
+    /// confirmations, be they spent or not. Used to rebuild the vaults of a lost database.
+    fn confirmed_deposits(
+        &self,
+        min_conf: u32,
+    ) -> Result<HashMap<OutPoint, UtxoInfo>, BitcoindError>;
+
+    /// Get the raw transaction as hex, the blockheight it was included in if
+    /// it's confirmed, as well as the reception time.
+    fn get_wallet_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<(String, Option<u32>, u32), BitcoindError>;
+
+    fn is_in_mempool(&self, txid: &Txid) -> Result<bool, BitcoindError>;
+
+    /// Get the txid of the transaction spending this outpoint, if it was spent since this block.
+    /// Backends providing the transactions of the blocks may only find it among the unconfirmed
+    /// ones, as we index the confirmed spenders of our coins ourselves.
+    fn get_spender_txid(
+        &self,
+        spent_outpoint: &OutPoint,
+        block_hash: &BlockHash,
+    ) -> Result<Option<Txid>, BitcoindError>;
+
+    /// Get the transactions of this block, to index those spending our coins. None if the
+    /// backend can't provide them, in which case it must find confirmed spenders on its own.
+    fn get_block_transactions(
+        &self,
+        _block_hash: &BlockHash,
+    ) -> Result<Option<Vec<Transaction>>, BitcoindError> {
+        Ok(None)
+    }
+
+    fn broadcast_transaction(&self, tx: &Transaction) -> Result<(), BitcoindError>;
+
+    /// Broadcast a transaction we already know about
+    fn rebroadcast_wallet_tx(&self, txid: &Txid) -> Result<(), BitcoindError>;
+
+    /// Get the feerate, in sat/vbyte, for a transaction to be confirmed within `conf_target`
+    /// blocks. None if there isn't enough data to estimate it.
+    fn estimate_feerate(
+        &self,
+        conf_target: u16,
+        estimate_mode: EstimateMode,
+    ) -> Result<Option<u64>, BitcoindError>;
+
+    /// The bitcoind we are talking to, if any. Only it provides us with wallets (to create
+    /// the watchonly one at startup, or to fee-bump the revocation transactions).
+    fn as_bitcoind(&self) -> Option<&BitcoinD> {
+        None
+    }
+}
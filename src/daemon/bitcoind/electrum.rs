@@ -0,0 +1,946 @@
+//! An Electrum server as a chain backend, for managers who don't run a full node.
+//!
+//! We talk the Electrum protocol (JSONRPC over a TCP stream, one message per line). There is no
+//! wallet on the server side: we query the utxos and the history of each of our deposit and
+//! Unvault scripts at every poll. We don't support TLS, the server must be on this machine.
+
+use crate::{
+    bitcoind::{
+        backend::ChainBackend,
+        interface::{EstimateMode, OnchainDescriptorState, SyncInfo, UtxoInfo, MIN_DEPOSIT_VALUE},
+        BitcoindError,
+    },
+    revaultd::BlockchainTip,
+};
+use common::config::ElectrumConfig;
+use revault_tx::bitcoin::{
+    consensus::encode,
+    hashes::{
+        hex::{FromHex, ToHex},
+        sha256, Hash,
+    },
+    Address, BlockHash, BlockHeader, OutPoint, Script, Transaction, TxOut, Txid,
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    str::FromStr,
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value as Json};
+
+/// An error while talking to the Electrum server
+#[derive(Debug)]
+pub enum ElectrumError {
+    /// The connection to the server
+    Io(io::Error),
+    /// The server returned an error
+    Server(Json),
+    /// The server returned something we did not expect
+    Api(String),
+}
+
+impl fmt::Display for ElectrumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: '{}'", e),
+            Self::Server(e) => write!(f, "server error: '{}'", e),
+            Self::Api(s) => write!(f, "API break: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for ElectrumError {}
+
+fn api_break(msg: impl Into<String>) -> BitcoindError {
+    BitcoindError::Electrum(ElectrumError::Api(msg.into()))
+}
+
+fn io_error(e: io::Error) -> BitcoindError {
+    BitcoindError::Electrum(ElectrumError::Io(e))
+}
+
+// The Electrum protocol identifies scripts by their SHA256, reversed
+fn script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).into_inner();
+    hash.reverse();
+    hash[..].to_hex()
+}
+
+// Unconfirmed transactions have a height of 0 (or -1 if they have unconfirmed parents)
+fn confirmations(tip_height: u32, height: i64) -> u32 {
+    if height > 0 {
+        (tip_height as i64 - height + 1).max(0) as u32
+    } else {
+        0
+    }
+}
+
+fn from_hex<T: encode::Decodable>(hex: Option<&Json>, what: &str) -> Result<T, BitcoindError> {
+    hex.and_then(|h| h.as_str())
+        .and_then(|h| Vec::from_hex(h).ok())
+        .and_then(|bytes| encode::deserialize(&bytes).ok())
+        .ok_or_else(|| api_break(format!("invalid {} hex", what)))
+}
+
+// Get the (txid, height) pairs of a 'get_history' result
+fn parse_history(history: &Json) -> Result<Vec<(Txid, i64)>, BitcoindError> {
+    history
+        .as_array()
+        .ok_or_else(|| api_break("'get_history' didn't return an array"))?
+        .iter()
+        .map(|entry| {
+            let txid = entry
+                .get("tx_hash")
+                .and_then(|t| t.as_str())
+                .and_then(|t| Txid::from_str(t).ok())
+                .ok_or_else(|| api_break("no valid 'tx_hash' in 'get_history' entry"))?;
+            let height = entry
+                .get("height")
+                .and_then(|h| h.as_i64())
+                .ok_or_else(|| api_break("no valid 'height' in 'get_history' entry"))?;
+            Ok((txid, height))
+        })
+        .collect()
+}
+
+// If the connection to the server breaks, how many times we reconnect before giving up on a
+// request. We wait this long before the first attempt, and twice as long at each retry.
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+}
+
+impl Connection {
+    fn new(addr: &str) -> Result<Connection, BitcoindError> {
+        let stream = TcpStream::connect(addr).map_err(io_error)?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .map_err(io_error)?;
+        stream
+            .set_write_timeout(Some(Duration::from_secs(30)))
+            .map_err(io_error)?;
+        let reader = BufReader::new(stream.try_clone().map_err(io_error)?);
+
+        let mut connection = Connection {
+            reader,
+            writer: stream,
+            next_id: 0,
+        };
+        // This must be the first message, and the server would close the connection if it
+        // doesn't support the protocol version.
+        connection.batch_call("server.version", &[json!(["revaultd", "1.4"])])?;
+
+        Ok(connection)
+    }
+
+    // Send these requests for the same method all at once, and get their results in order
+    fn batch_call(&mut self, method: &str, params: &[Json]) -> Result<Vec<Json>, BitcoindError> {
+        let n_requests = params.len();
+        let first_id = self.next_id;
+        self.next_id += n_requests as u64;
+
+        let mut requests = String::new();
+        for (i, params) in params.iter().enumerate() {
+            let req = json!({
+                "jsonrpc": "2.0",
+                "id": first_id + i as u64,
+                "method": method,
+                "params": params,
+            });
+            log::trace!("Sending to Electrum server: {}", req);
+            requests.push_str(&req.to_string());
+            requests.push('\n');
+        }
+        self.writer
+            .write_all(requests.as_bytes())
+            .map_err(io_error)?;
+
+        let mut results = vec![None; n_requests];
+        let mut n_results = 0;
+        while n_results < n_requests {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).map_err(io_error)? == 0 {
+                return Err(io_error(io::ErrorKind::UnexpectedEof.into()));
+            }
+            let resp: Json = serde_json::from_str(&line)
+                .map_err(|e| api_break(format!("invalid JSON response: {}", e)))?;
+            log::trace!("Got from Electrum server: {}", resp);
+
+            // Notifications don't have an id, and we may get the responses to a previous batch
+            // we bailed out of.
+            let id = match resp.get("id").and_then(|id| id.as_u64()) {
+                Some(id) if id >= first_id && id < first_id + n_requests as u64 => id,
+                _ => continue,
+            };
+            if let Some(error) = resp.get("error").filter(|e| !e.is_null()) {
+                return Err(BitcoindError::Electrum(ElectrumError::Server(
+                    error.clone(),
+                )));
+            }
+            let result = resp
+                .get("result")
+                .cloned()
+                .ok_or_else(|| api_break("no 'result' in response"))?;
+            if results[(id - first_id) as usize].replace(result).is_none() {
+                n_results += 1;
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|r| r.expect("We got all the results"))
+            .collect())
+    }
+}
+
+pub struct ElectrumClient {
+    addr: String,
+    // None if the connection broke, we'll reconnect at the next request
+    connection: Mutex<Option<Connection>>,
+    deposit_scripts: Mutex<Vec<Script>>,
+    unvault_scripts: Mutex<Vec<Script>>,
+}
+
+impl ElectrumClient {
+    pub fn new(
+        config: &ElectrumConfig,
+        deposit_addresses: Vec<String>,
+        unvault_addresses: Vec<String>,
+    ) -> Result<ElectrumClient, BitcoindError> {
+        let connection = Connection::new(&config.addr)?;
+
+        let scripts = |addresses: Vec<String>| -> Result<Vec<Script>, BitcoindError> {
+            addresses
+                .iter()
+                .map(|addr| {
+                    Address::from_str(addr)
+                        .map(|addr| addr.script_pubkey())
+                        .map_err(|e| {
+                            BitcoindError::Custom(format!("Invalid address '{}': {}", addr, e))
+                        })
+                })
+                .collect()
+        };
+
+        Ok(ElectrumClient {
+            addr: config.addr.clone(),
+            connection: Mutex::new(Some(connection)),
+            deposit_scripts: Mutex::new(scripts(deposit_addresses)?),
+            unvault_scripts: Mutex::new(scripts(unvault_addresses)?),
+        })
+    }
+
+    // Send these requests for the same method all at once, and get their results in order.
+    // If the connection breaks, reconnect and send them again.
+    fn batch_call(&self, method: &str, params: Vec<Json>) -> Result<Vec<Json>, BitcoindError> {
+        let mut connection = self.connection.lock().unwrap();
+        let mut attempts = 0;
+
+        loop {
+            let res = match connection.take() {
+                Some(conn) => Ok(conn),
+                None => Connection::new(&self.addr),
+            }
+            .and_then(|mut conn| {
+                let res = conn.batch_call(method, &params);
+                // Only keep the connection if it's not broken
+                if !matches!(res, Err(BitcoindError::Electrum(ElectrumError::Io(_)))) {
+                    *connection = Some(conn);
+                }
+                res
+            });
+
+            match res {
+                Err(BitcoindError::Electrum(ElectrumError::Io(e)))
+                    if attempts < RECONNECT_ATTEMPTS =>
+                {
+                    let backoff = RECONNECT_BACKOFF * 2u32.pow(attempts);
+                    log::warn!(
+                        "Error talking to the Electrum server at '{}': '{}'. Reconnecting in {:?}.",
+                        self.addr,
+                        e,
+                        backoff
+                    );
+                    thread::sleep(backoff);
+                    attempts += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn call(&self, method: &str, params: Json) -> Result<Json, BitcoindError> {
+        Ok(self
+            .batch_call(method, vec![params])?
+            .pop()
+            .expect("One request, one result"))
+    }
+
+    fn header(&self, height: u32) -> Result<BlockHeader, BitcoindError> {
+        let res = self.call("blockchain.block.header", json!([height]))?;
+        from_hex(Some(&res), "header")
+    }
+
+    fn raw_transaction(&self, txid: &Txid) -> Result<Transaction, BitcoindError> {
+        let res = self.call("blockchain.transaction.get", json!([txid.to_string()]))?;
+        from_hex(Some(&res), "transaction")
+    }
+
+    fn history(&self, script: &Script) -> Result<Vec<(Txid, i64)>, BitcoindError> {
+        parse_history(&self.call(
+            "blockchain.scripthash.get_history",
+            json!([script_hash(script)]),
+        )?)
+    }
+
+    // The height of this transaction according to the history of its first output's script.
+    // None if the server doesn't know about it, 0 or less if it's unconfirmed.
+    fn tx_height(&self, tx: &Transaction) -> Result<Option<i64>, BitcoindError> {
+        let txid = tx.txid();
+        let script = match tx.output.first() {
+            Some(txo) => &txo.script_pubkey,
+            None => return Ok(None),
+        };
+
+        Ok(self
+            .history(script)?
+            .into_iter()
+            .find(|(hist_txid, _)| hist_txid == &txid)
+            .map(|(_, height)| height))
+    }
+
+    // Same as bitcoind's `sync_chainstate`, out of the `listunspent` of each of these scripts
+    fn sync_chainstate(
+        &self,
+        current_utxos: &HashMap<OutPoint, UtxoInfo>,
+        scripts: Vec<Script>,
+        min_conf: u32,
+        min_amount: Option<u64>,
+    ) -> Result<OnchainDescriptorState, BitcoindError> {
+        let (mut new_utxos, mut confirmed_utxos) = (HashMap::new(), HashMap::new());
+        // All seen utxos, if an utxo remains unseen by listunspent then it's spent.
+        let mut spent_utxos = current_utxos.clone();
+        let tip_height = self.get_tip()?.height;
+
+        let params = scripts.iter().map(|s| json!([script_hash(s)])).collect();
+        let results = self.batch_call("blockchain.scripthash.listunspent", params)?;
+        for (script, utxos) in scripts.into_iter().zip(results) {
+            for utxo in utxos
+                .as_array()
+                .ok_or_else(|| api_break("'listunspent' didn't return an array"))?
+            {
+                let txid = utxo
+                    .get("tx_hash")
+                    .and_then(|t| t.as_str())
+                    .and_then(|t| Txid::from_str(t).ok())
+                    .ok_or_else(|| api_break("no valid 'tx_hash' in 'listunspent' entry"))?;
+                let vout = utxo
+                    .get("tx_pos")
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(|| api_break("no valid 'tx_pos' in 'listunspent' entry"))?;
+                let height = utxo
+                    .get("height")
+                    .and_then(|h| h.as_i64())
+                    .ok_or_else(|| api_break("no valid 'height' in 'listunspent' entry"))?;
+                let value = utxo
+                    .get("value")
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(|| api_break("no valid 'value' in 'listunspent' entry"))?;
+                let outpoint = OutPoint {
+                    txid,
+                    vout: vout as u32,
+                };
+
+                if let Some(utxo) = spent_utxos.remove(&outpoint) {
+                    // It may be known but still unconfirmed, though.
+                    if !utxo.is_confirmed && confirmations(tip_height, height) >= min_conf {
+                        confirmed_utxos.insert(outpoint, utxo);
+                    }
+                    continue;
+                }
+
+                if matches!(min_amount, Some(min_amount) if value < min_amount) {
+                    continue;
+                }
+                new_utxos.insert(
+                    outpoint,
+                    UtxoInfo {
+                        txo: TxOut {
+                            value,
+                            script_pubkey: script.clone(),
+                        },
+                        // All new utxos are marked as unconfirmed. This allows for a proper state
+                        // transition.
+                        is_confirmed: false,
+                    },
+                );
+            }
+        }
+
+        Ok(OnchainDescriptorState {
+            new_unconf: new_utxos,
+            new_conf: confirmed_utxos,
+            new_spent: spent_utxos,
+        })
+    }
+}
+
+impl ChainBackend for ElectrumClient {
+    fn get_tip(&self) -> Result<BlockchainTip, BitcoindError> {
+        let res = self.call("blockchain.headers.subscribe", json!([]))?;
+        let height = res
+            .get("height")
+            .and_then(|h| h.as_u64())
+            .ok_or_else(|| api_break("no valid 'height' in 'headers.subscribe' result"))?;
+        let header: BlockHeader = from_hex(res.get("hex"), "header")?;
+
+        Ok(BlockchainTip {
+            height: height as u32,
+            hash: header.block_hash(),
+        })
+    }
+
+    fn getblockhash(&self, height: u32) -> Result<BlockHash, BitcoindError> {
+        Ok(self.header(height)?.block_hash())
+    }
+
+    fn synchronization_info(&self) -> Result<SyncInfo, BitcoindError> {
+        // The server only serves what its own node validated
+        let height = self.get_tip()?.height as u64;
+        Ok(SyncInfo {
+            headers: height,
+            blocks: height,
+            ibd: false,
+            progress: 1.0,
+        })
+    }
+
+    fn watch_fresh_addresses(
+        &self,
+        deposit_address: &Address,
+        unvault_address: &Address,
+        _cpfp_address: &Address,
+    ) -> Result<(), BitcoindError> {
+        // We don't track the CPFP outputs, there is no wallet to spend them with
+        self.deposit_scripts
+            .lock()
+            .unwrap()
+            .push(deposit_address.script_pubkey());
+        self.unvault_scripts
+            .lock()
+            .unwrap()
+            .push(unvault_address.script_pubkey());
+        Ok(())
+    }
+
+    fn watch_past_addresses(
+        &self,
+        deposit_address: &Address,
+        unvault_address: &Address,
+        cpfp_address: &Address,
+        _since: u32,
+    ) -> Result<(), BitcoindError> {
+        // The server gives us the whole history of a script, there is nothing to rescan
+        self.watch_fresh_addresses(deposit_address, unvault_address, cpfp_address)
+    }
+
+    fn sync_deposits(
+        &self,
+        deposits_utxos: &HashMap<OutPoint, UtxoInfo>,
+        min_conf: u32,
+    ) -> Result<OnchainDescriptorState, BitcoindError> {
+        let scripts = self.deposit_scripts.lock().unwrap().clone();
+        self.sync_chainstate(deposits_utxos, scripts, min_conf, Some(MIN_DEPOSIT_VALUE))
+    }
+
+    fn sync_unvaults(
+        &self,
+        unvault_utxos: &HashMap<OutPoint, UtxoInfo>,
+    ) -> Result<OnchainDescriptorState, BitcoindError> {
+        let scripts = self.unvault_scripts.lock().unwrap().clone();
+        self.sync_chainstate(unvault_utxos, scripts, 1, None)
+    }
+
+    fn confirmed_deposits(
+        &self,
+        min_conf: u32,
+    ) -> Result<HashMap<OutPoint, UtxoInfo>, BitcoindError> {
+        let mut deposits = HashMap::new();
+        let tip_height = self.get_tip()?.height;
+        let scripts = self.deposit_scripts.lock().unwrap().clone();
+
+        let params = scripts.iter().map(|s| json!([script_hash(s)])).collect();
+        let histories = self.batch_call("blockchain.scripthash.get_history", params)?;
+        for (script, history) in scripts.iter().zip(histories) {
+            for (txid, height) in parse_history(&history)? {
+                if confirmations(tip_height, height) < min_conf {
+                    continue;
+                }
+
+                let tx = self.raw_transaction(&txid)?;
+                for (vout, txo) in tx.output.into_iter().enumerate() {
+                    if &txo.script_pubkey == script && txo.value >= MIN_DEPOSIT_VALUE {
+                        deposits.insert(
+                            OutPoint {
+                                txid,
+                                vout: vout as u32,
+                            },
+                            UtxoInfo {
+                                txo,
+                                is_confirmed: true,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(deposits)
+    }
+
+    fn get_wallet_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<(String, Option<u32>, u32), BitcoindError> {
+        let tx = self.raw_transaction(txid)?;
+        let blockheight = self
+            .tx_height(&tx)?
+            .filter(|height| *height > 0)
+            .map(|height| height as u32);
+        // There is no reception time without a wallet, use the block's one
+        let received = match blockheight {
+            Some(height) => self.header(height)?.time,
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|dur| dur.as_secs() as u32)
+                .unwrap_or(0),
+        };
+
+        Ok((encode::serialize_hex(&tx), blockheight, received))
+    }
+
+    fn is_in_mempool(&self, txid: &Txid) -> Result<bool, BitcoindError> {
+        let tx = match self.raw_transaction(txid) {
+            Ok(tx) => tx,
+            // It doesn't know about this transaction
+            Err(BitcoindError::Electrum(ElectrumError::Server(_))) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        Ok(matches!(self.tx_height(&tx)?, Some(height) if height <= 0))
+    }
+
+    fn get_spender_txid(
+        &self,
+        spent_outpoint: &OutPoint,
+        _block_hash: &BlockHash,
+    ) -> Result<Option<Txid>, BitcoindError> {
+        // The server indexes the whole history of the script, there is no need to restrict the
+        // search to what happened since the block.
+        let prev_tx = self.raw_transaction(&spent_outpoint.txid)?;
+        let script = prev_tx
+            .output
+            .get(spent_outpoint.vout as usize)
+            .ok_or_else(|| api_break(format!("no output at '{}'", spent_outpoint)))?
+            .script_pubkey
+            .clone();
+
+        for (txid, _) in self.history(&script)? {
+            if txid == spent_outpoint.txid {
+                continue;
+            }
+            let tx = self.raw_transaction(&txid)?;
+            if tx
+                .input
+                .iter()
+                .any(|txin| &txin.previous_output == spent_outpoint)
+            {
+                return Ok(Some(txid));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn broadcast_transaction(&self, tx: &Transaction) -> Result<(), BitcoindError> {
+        let tx_hex = encode::serialize_hex(tx);
+        log::debug!("Broadcasting '{}'", tx_hex);
+        self.call("blockchain.transaction.broadcast", json!([tx_hex]))
+            .map(|_| ())
+    }
+
+    fn rebroadcast_wallet_tx(&self, txid: &Txid) -> Result<(), BitcoindError> {
+        let tx = self.raw_transaction(txid)?;
+        self.broadcast_transaction(&tx)
+    }
+
+    fn estimate_feerate(
+        &self,
+        conf_target: u16,
+        _estimate_mode: EstimateMode,
+    ) -> Result<Option<u64>, BitcoindError> {
+        let res = self.call("blockchain.estimatefee", json!([conf_target]))?;
+
+        // It's in BTC/kvbyte, and -1 if the server's node can't estimate it
+        let btc_kvb = res
+            .as_f64()
+            .ok_or_else(|| api_break("'estimatefee' didn't return a number"))?;
+        if btc_kvb < 0.0 {
+            log::debug!(
+                "No feerate estimate for a target of '{}' blocks",
+                conf_target
+            );
+            return Ok(None);
+        }
+        let sat_kvb = (btc_kvb * 100_000_000.0).round() as u64;
+        Ok(Some((sat_kvb + 999) / 1_000))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{script_hash, ElectrumClient};
+    use crate::bitcoind::{
+        backend::ChainBackend,
+        interface::{EstimateMode, UtxoInfo},
+    };
+    use common::config::ElectrumConfig;
+    use revault_tx::bitcoin::{
+        consensus::encode, hashes::hex::FromHex, Address, BlockHash, BlockHeader, Network,
+        OutPoint, Script, Transaction, TxIn, TxMerkleNode, TxOut, Txid,
+    };
+
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        str::FromStr,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use serde_json::{json, Value as Json};
+
+    // The chain served by our mock Electrum server
+    #[derive(Default)]
+    struct MockChain {
+        headers: Vec<BlockHeader>,
+        // Along with their height, 0 if unconfirmed
+        txs: Vec<(Transaction, i64)>,
+        // Close the connection at the next request instead of answering it
+        drop_connection: bool,
+    }
+
+    impl MockChain {
+        // Mine a block confirming all the unconfirmed transactions
+        fn mine(&mut self) {
+            let height = self.headers.len() as u32;
+            let prev_blockhash = self
+                .headers
+                .last()
+                .map(|h| h.block_hash())
+                .unwrap_or_else(BlockHash::default);
+            self.headers.push(BlockHeader {
+                version: 1,
+                prev_blockhash,
+                merkle_root: TxMerkleNode::default(),
+                time: 1_600_000_000 + height,
+                bits: 0x207fffff,
+                nonce: height,
+            });
+            for (_, tx_height) in self.txs.iter_mut().filter(|(_, h)| *h == 0) {
+                *tx_height = height as i64;
+            }
+        }
+
+        fn tx(&self, txid: &Txid) -> Option<&(Transaction, i64)> {
+            self.txs.iter().find(|(tx, _)| &tx.txid() == txid)
+        }
+
+        fn is_spent(&self, outpoint: &OutPoint) -> bool {
+            self.txs
+                .iter()
+                .any(|(tx, _)| tx.input.iter().any(|i| &i.previous_output == outpoint))
+        }
+
+        fn pays_to(&self, outpoint: &OutPoint, script_hash_str: &str) -> bool {
+            self.tx(&outpoint.txid)
+                .and_then(|(tx, _)| tx.output.get(outpoint.vout as usize))
+                .map(|txo| script_hash(&txo.script_pubkey) == script_hash_str)
+                .unwrap_or(false)
+        }
+
+        fn respond(&mut self, method: &str, params: &Json) -> Result<Json, Json> {
+            let unknown_tx = json!({"code": 2, "message": "missing transaction"});
+            Ok(match method {
+                "server.version" => json!(["MockElectrum 1.0", "1.4"]),
+                "blockchain.headers.subscribe" => {
+                    let height = self.headers.len() - 1;
+                    json!({
+                        "height": height,
+                        "hex": encode::serialize_hex(&self.headers[height]),
+                    })
+                }
+                "blockchain.block.header" => {
+                    let height = params[0].as_u64().unwrap() as usize;
+                    json!(encode::serialize_hex(&self.headers[height]))
+                }
+                "blockchain.transaction.get" => {
+                    let txid = Txid::from_str(params[0].as_str().unwrap()).unwrap();
+                    let (tx, _) = self.tx(&txid).ok_or(unknown_tx)?;
+                    json!(encode::serialize_hex(tx))
+                }
+                "blockchain.transaction.broadcast" => {
+                    let bytes = Vec::from_hex(params[0].as_str().unwrap()).unwrap();
+                    let tx: Transaction = encode::deserialize(&bytes).unwrap();
+                    let txid = tx.txid();
+                    self.txs.push((tx, 0));
+                    json!(txid.to_string())
+                }
+                "blockchain.scripthash.get_history" => {
+                    let hash = params[0].as_str().unwrap();
+                    let history: Vec<Json> = self
+                        .txs
+                        .iter()
+                        .filter(|(tx, _)| {
+                            tx.output
+                                .iter()
+                                .any(|txo| script_hash(&txo.script_pubkey) == hash)
+                                || tx
+                                    .input
+                                    .iter()
+                                    .any(|txin| self.pays_to(&txin.previous_output, hash))
+                        })
+                        .map(|(tx, height)| json!({"tx_hash": tx.txid().to_string(), "height": height}))
+                        .collect();
+                    json!(history)
+                }
+                "blockchain.scripthash.listunspent" => {
+                    let hash = params[0].as_str().unwrap();
+                    let mut utxos = Vec::new();
+                    for (tx, height) in self.txs.iter() {
+                        for (vout, txo) in tx.output.iter().enumerate() {
+                            let outpoint = OutPoint::new(tx.txid(), vout as u32);
+                            if script_hash(&txo.script_pubkey) == hash && !self.is_spent(&outpoint)
+                            {
+                                utxos.push(json!({
+                                    "tx_hash": tx.txid().to_string(),
+                                    "tx_pos": vout,
+                                    "height": height,
+                                    "value": txo.value,
+                                }));
+                            }
+                        }
+                    }
+                    json!(utxos)
+                }
+                "blockchain.estimatefee" => json!(0.00012),
+                _ => return Err(json!({"code": -32601, "message": "unknown method"})),
+            })
+        }
+    }
+
+    // Serve this chain to any client. We send a notification before each response to
+    // `headers.subscribe`, as a real server would on new blocks.
+    fn mock_electrum_server(chain: Arc<Mutex<MockChain>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let chain = chain.clone();
+                thread::spawn(move || {
+                    let mut writer = stream.try_clone().unwrap();
+                    for line in BufReader::new(stream).lines() {
+                        let req: Json = serde_json::from_str(&line.unwrap()).unwrap();
+                        let method = req["method"].as_str().unwrap();
+                        let mut chain = chain.lock().unwrap();
+                        if chain.drop_connection {
+                            chain.drop_connection = false;
+                            return;
+                        }
+                        let mut resp = match chain.respond(method, &req["params"]) {
+                            Ok(result) => {
+                                json!({"jsonrpc": "2.0", "id": req["id"], "result": result})
+                            }
+                            Err(error) => {
+                                json!({"jsonrpc": "2.0", "id": req["id"], "error": error})
+                            }
+                        }
+                        .to_string();
+                        if method == "blockchain.headers.subscribe" {
+                            resp = format!(
+                                "{}\n{}",
+                                json!({"jsonrpc": "2.0", "method": method, "params": [resp]}),
+                                resp
+                            );
+                        }
+                        writer.write_all(format!("{}\n", resp).as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    fn tx(inputs: Vec<OutPoint>, output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    script_sig: Script::new(),
+                    sequence: 0xff_ff_ff_ff,
+                    witness: vec![],
+                })
+                .collect(),
+            output,
+        }
+    }
+
+    #[test]
+    fn electrum_backend() {
+        let address =
+            |witscript: u8| Address::p2wsh(&Script::from(vec![witscript]), Network::Regtest);
+        let (deposit_addr, unvault_addr) = (address(0x51), address(0x52));
+        let chain = Arc::new(Mutex::new(MockChain::default()));
+        for _ in 0..101 {
+            chain.lock().unwrap().mine();
+        }
+        let config = ElectrumConfig {
+            addr: mock_electrum_server(chain.clone()),
+        };
+        let electrum = ElectrumClient::new(
+            &config,
+            vec![deposit_addr.to_string()],
+            vec![unvault_addr.to_string()],
+        )
+        .unwrap();
+
+        let tip = electrum.get_tip().unwrap();
+        assert_eq!(tip.height, 100);
+        assert_eq!(tip.hash, chain.lock().unwrap().headers[100].block_hash());
+        assert_eq!(
+            electrum.getblockhash(42).unwrap(),
+            chain.lock().unwrap().headers[42].block_hash()
+        );
+        assert_eq!(electrum.synchronization_info().unwrap().progress, 1.0);
+        assert_eq!(
+            electrum
+                .estimate_feerate(6, EstimateMode::Conservative)
+                .unwrap(),
+            Some(12)
+        );
+
+        // A deposit appears in the mempool, along with a dust one we don't care about
+        let deposit_tx = tx(
+            vec![OutPoint::null()],
+            vec![
+                TxOut {
+                    value: 100_000_000,
+                    script_pubkey: deposit_addr.script_pubkey(),
+                },
+                TxOut {
+                    value: 1_000,
+                    script_pubkey: deposit_addr.script_pubkey(),
+                },
+            ],
+        );
+        let deposit_outpoint = OutPoint::new(deposit_tx.txid(), 0);
+        chain.lock().unwrap().txs.push((deposit_tx.clone(), 0));
+        let state = electrum.sync_deposits(&HashMap::new(), 1).unwrap();
+        assert_eq!(state.new_unconf.len(), 1);
+        assert_eq!(state.new_unconf[&deposit_outpoint].txo.value, 100_000_000);
+        assert!(state.new_conf.is_empty() && state.new_spent.is_empty());
+        assert!(electrum.is_in_mempool(&deposit_outpoint.txid).unwrap());
+        let mut deposits: HashMap<OutPoint, UtxoInfo> = state.new_unconf;
+
+        // It gets confirmed
+        chain.lock().unwrap().mine();
+        let state = electrum.sync_deposits(&deposits, 1).unwrap();
+        assert!(state.new_unconf.is_empty() && state.new_spent.is_empty());
+        assert!(state.new_conf.contains_key(&deposit_outpoint));
+        deposits.get_mut(&deposit_outpoint).unwrap().is_confirmed = true;
+        assert!(!electrum.is_in_mempool(&deposit_outpoint.txid).unwrap());
+        let (hex, blockheight, received) = electrum
+            .get_wallet_transaction(&deposit_outpoint.txid)
+            .unwrap();
+        assert_eq!(hex, encode::serialize_hex(&deposit_tx));
+        assert_eq!(blockheight, Some(101));
+        assert_eq!(received, chain.lock().unwrap().headers[101].time);
+        let confirmed = electrum.confirmed_deposits(1).unwrap();
+        assert_eq!(confirmed.len(), 1);
+        assert!(confirmed[&deposit_outpoint].is_confirmed);
+        assert!(electrum.confirmed_deposits(3).unwrap().is_empty());
+
+        // We broadcast the Unvault
+        let unvault_tx = tx(
+            vec![deposit_outpoint],
+            vec![TxOut {
+                value: 99_000_000,
+                script_pubkey: unvault_addr.script_pubkey(),
+            }],
+        );
+        electrum.broadcast_transaction(&unvault_tx).unwrap();
+        assert!(electrum.is_in_mempool(&unvault_tx.txid()).unwrap());
+        let state = electrum.sync_deposits(&deposits, 1).unwrap();
+        assert!(state.new_spent.contains_key(&deposit_outpoint));
+        assert_eq!(
+            electrum
+                .get_spender_txid(&deposit_outpoint, &tip.hash)
+                .unwrap(),
+            Some(unvault_tx.txid())
+        );
+        let state = electrum.sync_unvaults(&HashMap::new()).unwrap();
+        assert!(state
+            .new_unconf
+            .contains_key(&OutPoint::new(unvault_tx.txid(), 0)));
+
+        // We only see deposits to a new address once told to watch it
+        let new_deposit_addr = address(0x53);
+        let new_deposit_tx = tx(
+            vec![OutPoint::new(deposit_tx.txid(), 1)],
+            vec![TxOut {
+                value: 50_000_000,
+                script_pubkey: new_deposit_addr.script_pubkey(),
+            }],
+        );
+        chain.lock().unwrap().txs.push((new_deposit_tx.clone(), 0));
+        assert!(electrum
+            .sync_deposits(&deposits, 1)
+            .unwrap()
+            .new_unconf
+            .is_empty());
+        electrum
+            .watch_fresh_addresses(&new_deposit_addr, &address(0x54), &address(0x55))
+            .unwrap();
+        assert!(electrum
+            .sync_deposits(&deposits, 1)
+            .unwrap()
+            .new_unconf
+            .contains_key(&OutPoint::new(new_deposit_tx.txid(), 0)));
+
+        // The server drops the connection, we reconnect and send the request again
+        chain.lock().unwrap().drop_connection = true;
+        assert_eq!(electrum.get_tip().unwrap().height, 101);
+        assert!(!chain.lock().unwrap().drop_connection);
+
+        // An error from the server for a transaction it doesn't know
+        assert!(!electrum.is_in_mempool(&Txid::default()).unwrap());
+        assert!(electrum.get_wallet_transaction(&Txid::default()).is_err());
+    }
+}
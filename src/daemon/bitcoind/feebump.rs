@@ -0,0 +1,103 @@
+//! Bump the feerate of the revocation transactions using the coins of the fee-bumping wallet.
+//!
+//! The revocation transactions (Cancel, Emergency and UnvaultEmergency) are signed with
+//! `ALL|ANYONECANPAY`: we may add inputs to them, but not outputs as the signatures commit to
+//! all of them. Therefore we attach a single coin of the fee-bumping wallet without any change,
+//! the whole of its value going to fees. To waste as little as possible, we use the smallest
+//! coin that is enough to reach the target feerate.
+
+use crate::bitcoind::{interface::BitcoinD, BitcoindError};
+use revault_tx::bitcoin::{Address, Script, Transaction, TxIn};
+
+// 4 * (32 (txid) + 4 (vout) + 1 (scriptSig length) + 4 (nSequence)) + 1 (witness items count) +
+// 1 + 72 (signature) + 1 + 33 (compressed public key)
+const P2WPKH_INPUT_WEIGHT: u64 = 41 * 4 + 108;
+
+/// The state of the fee-bumping wallet
+#[derive(Debug)]
+pub struct FeebumpReserve {
+    /// A fresh address to refill the reserve
+    pub address: Address,
+    /// The value of all its confirmed coins, in satoshis
+    pub balance: u64,
+    /// The number of confirmed coins
+    pub coins: usize,
+    /// The number of revocation transactions we can bump at once at the given feerate
+    pub bumps: usize,
+}
+
+// The total fees needed for a transaction of this weight to reach this feerate once a
+// fee-bumping input is attached to it.
+fn bumped_fees(tx_weight: u64, feerate_vb: u64) -> u64 {
+    (tx_weight + P2WPKH_INPUT_WEIGHT + 3) / 4 * feerate_vb
+}
+
+/// Attach a coin of the fee-bumping wallet to this finalized revocation transaction, for it to
+/// reach `feerate_vb`. The transaction is returned untouched if it already has this feerate.
+pub fn feebump_transaction(
+    bitcoind: &BitcoinD,
+    mut tx: Transaction,
+    presigned_fees: u64,
+    feerate_vb: u64,
+) -> Result<Transaction, BitcoindError> {
+    let needed_fees =
+        bumped_fees(tx.get_weight() as u64, feerate_vb).saturating_sub(presigned_fees);
+    if needed_fees == 0 {
+        log::debug!(
+            "Transaction '{}' already has a feerate of at least '{}'",
+            tx.txid(),
+            feerate_vb
+        );
+        return Ok(tx);
+    }
+
+    let (outpoint, txo) = bitcoind
+        .feebump_coins()?
+        .into_iter()
+        .filter(|(_, txo)| txo.value >= needed_fees)
+        .min_by_key(|(_, txo)| txo.value)
+        .ok_or_else(|| {
+            BitcoindError::Custom(format!(
+                "No coin of the fee-bumping wallet is worth at least '{}' sats",
+                needed_fees
+            ))
+        })?;
+    log::debug!(
+        "Bumping transaction '{}' to '{}' sat/vbyte with coin '{}' ('{}' sats)",
+        tx.txid(),
+        feerate_vb,
+        outpoint,
+        txo.value
+    );
+
+    tx.input.push(TxIn {
+        previous_output: outpoint,
+        script_sig: Script::new(),
+        // Signal for RBF, as we may want to bump again
+        sequence: 0xFF_FF_FF_FD,
+        witness: vec![],
+    });
+
+    bitcoind.sign_feebump_inputs(&tx)
+}
+
+/// How many revocation transactions of (at most) this weight can we bump at once to this
+/// feerate?
+pub fn feebump_reserve(
+    bitcoind: &BitcoinD,
+    revocation_weight: u64,
+    feerate_vb: u64,
+) -> Result<FeebumpReserve, BitcoindError> {
+    let coins = bitcoind.feebump_coins()?;
+    let bump_fees = bumped_fees(revocation_weight, feerate_vb);
+
+    Ok(FeebumpReserve {
+        address: bitcoind.feebump_address()?,
+        balance: coins.iter().map(|(_, txo)| txo.value).sum(),
+        coins: coins.len(),
+        bumps: coins
+            .iter()
+            .filter(|(_, txo)| txo.value >= bump_fees)
+            .count(),
+    })
+}
@@ -1,22 +1,38 @@
-use crate::{bitcoind::BitcoindError, revaultd::BlockchainTip};
+use crate::{
+    bitcoind::{backend::ChainBackend, BitcoindError},
+    revaultd::BlockchainTip,
+};
 use common::config::BitcoindConfig;
 use revault_tx::{
-    bitcoin::{consensus::encode, Address, Amount, BlockHash, OutPoint, Transaction, TxOut, Txid},
+    bitcoin::{
+        consensus::encode, hashes::hex::FromHex, Address, Amount, Block, BlockHash, OutPoint,
+        Script, Transaction, TxOut, Txid,
+    },
     transactions::{DUST_LIMIT, UNVAULT_CPFP_VALUE},
 };
 
-use std::{collections::HashMap, fs, str::FromStr, time::Duration};
+use std::{
+    collections::HashMap,
+    fs,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use jsonrpc::{arg, client::Client, simple_http::SimpleHttpTransport};
-use serde_json::Value as Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as Json};
 
 // The minimum deposit value according to revault_tx depends also on the unvault's
 // transaction fee. To have a one-value-fits-all, just take a 5% leeway.
-const MIN_DEPOSIT_VALUE: u64 = (DUST_LIMIT + UNVAULT_CPFP_VALUE) * 105 / 100;
+pub const MIN_DEPOSIT_VALUE: u64 = (DUST_LIMIT + UNVAULT_CPFP_VALUE) * 105 / 100;
 
 pub struct BitcoinD {
     node_client: Client,
     watchonly_client: Client,
+    feebump_client: Client,
+    // Whether bitcoind knows about 'gettxspendingprevout' (introduced in 24.0)
+    has_txspendingprevout: AtomicBool,
 }
 
 macro_rules! params {
@@ -33,6 +49,7 @@ impl BitcoinD {
     pub fn new(
         config: &BitcoindConfig,
         watchonly_wallet_path: String,
+        feebump_wallet_path: String,
     ) -> Result<BitcoinD, BitcoindError> {
         let cookie_string = fs::read_to_string(&config.cookie_path).map_err(|e| {
             BitcoindError::Custom(format!("Reading cookie file: {}", e.to_string()))
@@ -49,6 +66,16 @@ impl BitcoinD {
 
         let url = format!("http://{}/wallet/{}", config.addr, watchonly_wallet_path);
         let watchonly_client = Client::with_transport(
+            SimpleHttpTransport::builder()
+                .url(&url)
+                .map_err(BitcoindError::from)?
+                .timeout(Duration::from_secs(30))
+                .cookie_auth(cookie_string.clone())
+                .build(),
+        );
+
+        let url = format!("http://{}/wallet/{}", config.addr, feebump_wallet_path);
+        let feebump_client = Client::with_transport(
             SimpleHttpTransport::builder()
                 .url(&url)
                 .map_err(BitcoindError::from)?
@@ -60,6 +87,8 @@ impl BitcoinD {
         Ok(BitcoinD {
             node_client,
             watchonly_client,
+            feebump_client,
+            has_txspendingprevout: AtomicBool::new(true),
         })
     }
 
@@ -71,6 +100,10 @@ impl BitcoinD {
         "revault-unvault".to_string()
     }
 
+    fn cpfp_utxos_label(&self) -> String {
+        "revault-cpfp".to_string()
+    }
+
     fn make_request<'a, 'b>(
         &self,
         client: &Client,
@@ -102,79 +135,28 @@ impl BitcoinD {
         self.make_request(&self.watchonly_client, method, params)
     }
 
-    pub fn getblockchaininfo(&self) -> Result<Json, BitcoindError> {
-        self.make_node_request("getblockchaininfo", &[])
-    }
-
-    pub fn getblockhash(&self, height: u32) -> Result<BlockHash, BitcoindError> {
-        BlockHash::from_str(
-            self.make_node_request("getblockhash", &params!(height))?
-                .as_str()
-                .ok_or_else(|| {
-                    BitcoindError::Custom(
-                        "API break, 'getblockhash' didn't return a string.".to_string(),
-                    )
-                })?,
-        )
-        .map_err(|e| {
-            BitcoindError::Custom(format!("Invalid blockhash given by 'getblockhash': {}", e))
-        })
-    }
-
-    pub fn get_tip(&self) -> Result<BlockchainTip, BitcoindError> {
-        let json_height = self.make_node_request("getblockcount", &[])?;
-        let height = json_height.as_u64().ok_or_else(|| {
-            BitcoindError::Custom("API break, 'getblockcount' didn't return an u64.".to_string())
-        })? as u32;
-        let hash = self.getblockhash(height)?;
-
-        Ok(BlockchainTip { height, hash })
+    fn make_feebump_request<'a, 'b>(
+        &self,
+        method: &'a str,
+        params: &'b [Box<serde_json::value::RawValue>],
+    ) -> Result<Json, BitcoindError> {
+        self.make_request(&self.feebump_client, method, params)
     }
 
-    pub fn synchronization_info(&self) -> Result<SyncInfo, BitcoindError> {
-        let chaininfo = self.make_node_request("getblockchaininfo", &[])?;
-        Ok(SyncInfo {
-            headers: chaininfo
-                .get("headers")
-                .and_then(|h| h.as_u64())
-                .ok_or_else(|| {
-                    BitcoindError::Custom(
-                        "No valid 'headers' in getblockchaininfo response?".to_owned(),
-                    )
-                })?,
-            blocks: chaininfo
-                .get("blocks")
-                .and_then(|b| b.as_u64())
-                .ok_or_else(|| {
-                    BitcoindError::Custom(
-                        "No valid 'blocks' in getblockchaininfo response?".to_owned(),
-                    )
-                })?,
-            ibd: chaininfo
-                .get("initialblockdownload")
-                .and_then(|i| i.as_bool())
-                .ok_or_else(|| {
-                    BitcoindError::Custom(
-                        "No valid 'initialblockdownload' in getblockchaininfo response?".to_owned(),
-                    )
-                })?,
-            progress: chaininfo
-                .get("verificationprogress")
-                .and_then(|i| i.as_f64())
-                .ok_or_else(|| {
-                    BitcoindError::Custom(
-                        "No valid 'initialblockdownload' in getblockchaininfo response?".to_owned(),
-                    )
-                })?,
-        })
+    pub fn getblockchaininfo(&self) -> Result<Json, BitcoindError> {
+        self.make_node_request("getblockchaininfo", &[])
     }
 
-    pub fn createwallet_startup(&self, wallet_path: String) -> Result<(), BitcoindError> {
+    pub fn createwallet_startup(
+        &self,
+        wallet_path: String,
+        watchonly: bool,
+    ) -> Result<(), BitcoindError> {
         let res = self.make_node_request(
             "createwallet",
             &params!(
                 Json::String(wallet_path),
-                Json::Bool(true),             // watchonly
+                Json::Bool(watchonly),        // disable_private_keys
                 Json::Bool(false),            // blank
                 Json::String("".to_string()), // passphrase,
                 Json::Bool(false),            // avoid_reuse
@@ -345,6 +327,20 @@ impl BitcoinD {
         )
     }
 
+    pub fn startup_import_cpfp_descriptors(
+        &self,
+        descriptors: Vec<String>,
+        timestamp: u32,
+        fresh_wallet: bool,
+    ) -> Result<(), BitcoindError> {
+        self.bulk_import_descriptors(
+            descriptors,
+            timestamp,
+            self.cpfp_utxos_label(),
+            fresh_wallet,
+        )
+    }
+
     fn import_fresh_descriptor(
         &self,
         descriptor: String,
@@ -369,6 +365,19 @@ impl BitcoinD {
         )))
     }
 
+    /// Whether the CPFP descriptors were imported into our watchonly wallet. Wallets created
+    /// before we tracked the CPFP outputs don't have them.
+    pub fn has_cpfp_descriptors(&self) -> Result<bool, BitcoindError> {
+        let labels = self.make_watchonly_request("listlabels", &[])?;
+        let labels = labels.as_array().ok_or_else(|| {
+            BitcoindError::Custom("API break, 'listlabels' didn't return an array.".to_string())
+        })?;
+
+        Ok(labels
+            .iter()
+            .any(|label| label.as_str() == Some(&self.cpfp_utxos_label())))
+    }
+
     pub fn import_fresh_deposit_descriptor(&self, descriptor: String) -> Result<(), BitcoindError> {
         self.import_fresh_descriptor(descriptor, self.deposit_utxos_label())
     }
@@ -377,6 +386,10 @@ impl BitcoinD {
         self.import_fresh_descriptor(descriptor, self.unvault_utxos_label())
     }
 
+    pub fn import_fresh_cpfp_descriptor(&self, descriptor: String) -> Result<(), BitcoindError> {
+        self.import_fresh_descriptor(descriptor, self.cpfp_utxos_label())
+    }
+
     // A routine to get the txid,vout pair out of a listunspent entry
     fn outpoint_from_utxo(&self, utxo: &Json) -> Result<OutPoint, BitcoindError> {
         let txid = utxo
@@ -559,90 +572,177 @@ impl BitcoinD {
         })
     }
 
-    pub fn sync_deposits(
-        &self,
-        deposits_utxos: &HashMap<OutPoint, UtxoInfo>,
-        min_conf: u32,
-    ) -> Result<OnchainDescriptorState, BitcoindError> {
-        self.sync_chainstate(
-            deposits_utxos,
-            self.deposit_utxos_label(),
-            min_conf,
-            Some(Amount::from_sat(MIN_DEPOSIT_VALUE).as_btc()),
-        )
+    /// Get the confirmed coins of the fee-bumping wallet. Only P2WPKH ones are returned, as we
+    /// need to know the size of their witness in advance.
+    pub fn feebump_coins(&self) -> Result<Vec<(OutPoint, TxOut)>, BitcoindError> {
+        let res = self.make_feebump_request(
+            "listunspent",
+            &params!(
+                Json::Number(1.into()), // minconf
+            ),
+        )?;
+
+        let mut coins = Vec::new();
+        for utxo in res.as_array().ok_or_else(|| {
+            BitcoindError::Custom("API break, 'listunspent' didn't return an array.".to_string())
+        })? {
+            let outpoint = self.outpoint_from_utxo(&utxo)?;
+            let script_pubkey = utxo
+                .get("scriptPubKey")
+                .map(|s| s.as_str())
+                .flatten()
+                .map(|s| Vec::from_hex(s).ok())
+                .flatten()
+                .map(Script::from)
+                .ok_or_else(|| {
+                    BitcoindError::Custom(
+                        "API break, 'listunspent' entry didn't contain a valid 'scriptPubKey'."
+                            .to_string(),
+                    )
+                })?;
+            if !script_pubkey.is_v0_p2wpkh() {
+                continue;
+            }
+            let value = utxo
+                .get("amount")
+                .map(|a| a.as_f64())
+                .flatten()
+                .map(|a| Amount::from_btc(a).ok())
+                .flatten()
+                .ok_or_else(|| {
+                    BitcoindError::Custom(
+                        "API break, 'listunspent' entry didn't contain a valid 'amount'."
+                            .to_string(),
+                    )
+                })?
+                .as_sat();
+
+            coins.push((
+                outpoint,
+                TxOut {
+                    value,
+                    script_pubkey,
+                },
+            ));
+        }
+
+        Ok(coins)
     }
 
-    pub fn sync_unvaults(
-        &self,
-        unvault_utxos: &HashMap<OutPoint, UtxoInfo>,
-    ) -> Result<OnchainDescriptorState, BitcoindError> {
-        self.sync_chainstate(unvault_utxos, self.unvault_utxos_label(), 1, None)
+    /// Get a new address to receive coins on the fee-bumping wallet
+    pub fn feebump_address(&self) -> Result<Address, BitcoindError> {
+        let res = self.make_feebump_request(
+            "getnewaddress",
+            &params!(
+                Json::String("revault-feebump".to_string()), // label
+                Json::String("bech32".to_string()),          // address_type
+            ),
+        )?;
+        res.as_str()
+            .map(|a| Address::from_str(a).ok())
+            .flatten()
+            .ok_or_else(|| {
+                BitcoindError::Custom(
+                    "API break, 'getnewaddress' didn't return a valid address.".to_string(),
+                )
+            })
     }
 
-    /// Get the raw transaction as hex, the blockheight it was included in if
-    /// it's confirmed, as well as the reception time.
-    pub fn get_wallet_transaction(
-        &self,
-        txid: &Txid,
-    ) -> Result<(String, Option<u32>, u32), BitcoindError> {
-        let res = self
-            .make_watchonly_request("gettransaction", &params!(Json::String(txid.to_string())))?;
+    /// Sign the inputs of this transaction that spend coins of the fee-bumping wallet. The
+    /// other inputs are left untouched.
+    pub fn sign_feebump_inputs(&self, tx: &Transaction) -> Result<Transaction, BitcoindError> {
+        let res = self.make_feebump_request(
+            "signrawtransactionwithwallet",
+            &params!(Json::String(encode::serialize_hex(tx))),
+        )?;
+
+        // bitcoind will complain about the inputs that are not its own, that's expected.
+        let our_outpoints: Vec<String> = self
+            .feebump_coins()?
+            .into_iter()
+            .map(|(outpoint, _)| outpoint.to_string())
+            .collect();
+        if let Some(errors) = res.get("errors").map(|e| e.as_array()).flatten() {
+            for error in errors {
+                let txid = error.get("txid").map(|t| t.as_str()).flatten();
+                let vout = error.get("vout").map(|v| v.as_u64()).flatten();
+                if let (Some(txid), Some(vout)) = (txid, vout) {
+                    if our_outpoints.contains(&format!("{}:{}", txid, vout)) {
+                        return Err(BitcoindError::Custom(format!(
+                            "Error signing fee-bumping input: {:?}",
+                            error.get("error")
+                        )));
+                    }
+                }
+            }
+        }
+
         let tx_hex = res
             .get("hex")
+            .map(|h| h.as_str())
+            .flatten()
             .ok_or_else(|| {
+                BitcoindError::Custom(
+                    "API break, 'signrawtransactionwithwallet' didn't return an 'hex'.".to_string(),
+                )
+            })?;
+        Vec::from_hex(tx_hex)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| encode::deserialize(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| {
                 BitcoindError::Custom(format!(
-                    "API break: no 'hex' in 'gettransaction' result (txid: {})",
-                    txid
-                ))
-            })?
-            .as_str()
-            .ok_or_else(|| {
-                BitcoindError::Custom("API break: 'hex' is not a string ????".to_string())
-            })?
-            .to_string();
-        let blockheight = res.get("blockheight").map(|bh| bh.as_u64().unwrap() as u32);
-        let received = res
-            .get("timereceived")
-            .ok_or_else(|| {
-                BitcoindError::Custom(format!(
-                    "API break: no 'time_received' in 'gettransaction' result (txid: {})",
-                    txid
-                ))
-            })?
-            .as_u64()
-            .ok_or_else(|| {
-                BitcoindError::Custom(format!(
-                    "API break: invalid 'time_received' in 'gettransaction' result (txid: {})",
-                    txid
+                    "Invalid transaction returned by 'signrawtransactionwithwallet': {}",
+                    e
                 ))
-            })? as u32;
-
-        Ok((tx_hex, blockheight, received))
+            })
     }
 
-    /// Broadcast a transaction with 'sendrawtransaction', discarding the returned txid
-    pub fn broadcast_transaction(&self, tx: &Transaction) -> Result<(), BitcoindError> {
-        let tx_hex = encode::serialize_hex(tx);
-        log::debug!("Broadcasting '{}'", tx_hex);
-        self.make_watchonly_request("sendrawtransaction", &params!(Json::String(tx_hex)))
-            .map(|_| ())
-    }
+    /// Get the txid of the unconfirmed transaction spending this outpoint, using bitcoind's
+    /// mempool index.
+    fn mempool_spender_txid(
+        &self,
+        spent_outpoint: &OutPoint,
+    ) -> Result<Option<Txid>, BitcoindError> {
+        let res = self.make_node_request(
+            "gettxspendingprevout",
+            &params!(json!([{
+                "txid": spent_outpoint.txid.to_string(),
+                "vout": spent_outpoint.vout,
+            }])),
+        )?;
+        let entry = res
+            .as_array()
+            .map(|entries| entries.first())
+            .flatten()
+            .ok_or_else(|| {
+                BitcoindError::Custom(
+                    "API break, 'gettxspendingprevout' didn't return an array.".to_string(),
+                )
+            })?;
 
-    /// Broadcast a transaction that is already part of the wallet
-    pub fn rebroadcast_wallet_tx(&self, txid: &Txid) -> Result<(), BitcoindError> {
-        let (hex, _, _) = self.get_wallet_transaction(txid)?;
-        log::debug!("Re-broadcasting '{}'", hex);
-        self.make_watchonly_request("sendrawtransaction", &params!(Json::String(hex)))
-            .map(|_| ())
+        entry
+            .get("spendingtxid")
+            .map(|txid| {
+                txid.as_str()
+                    .map(|txid| Txid::from_str(txid).ok())
+                    .flatten()
+                    .ok_or_else(|| {
+                        BitcoindError::Custom(format!(
+                            "API break, invalid 'spendingtxid' in 'gettxspendingprevout' entry: {}",
+                            entry
+                        ))
+                    })
+            })
+            .transpose()
     }
 
-    /// So, bitcoind has no API for getting the transaction spending a wallet UTXO. Instead we are
-    /// therefore using a rather convoluted way to get it the other way around, since the spending
-    /// transaction is actually *part of the wallet transactions*.
+    /// So, bitcoind's wallet has no API for getting the transaction spending a wallet UTXO.
+    /// Instead we are therefore using a rather convoluted way to get it the other way around,
+    /// since the spending transaction is actually *part of the wallet transactions*.
     /// So, what we do there is listing all outgoing transactions of the wallet since the last poll
     /// and iterating through each of those to check if it spends the transaction we are interested
     /// in (requiring an other RPC call for each!!).
-    pub fn get_spender_txid(
+    fn wallet_spender_txid(
         &self,
         spent_outpoint: &OutPoint,
         block_hash: &BlockHash,
@@ -735,8 +835,318 @@ impl BitcoinD {
 
         Ok(None)
     }
+}
+
+impl ChainBackend for BitcoinD {
+    fn getblockhash(&self, height: u32) -> Result<BlockHash, BitcoindError> {
+        BlockHash::from_str(
+            self.make_node_request("getblockhash", &params!(height))?
+                .as_str()
+                .ok_or_else(|| {
+                    BitcoindError::Custom(
+                        "API break, 'getblockhash' didn't return a string.".to_string(),
+                    )
+                })?,
+        )
+        .map_err(|e| {
+            BitcoindError::Custom(format!("Invalid blockhash given by 'getblockhash': {}", e))
+        })
+    }
+
+    fn get_tip(&self) -> Result<BlockchainTip, BitcoindError> {
+        let json_height = self.make_node_request("getblockcount", &[])?;
+        let height = json_height.as_u64().ok_or_else(|| {
+            BitcoindError::Custom("API break, 'getblockcount' didn't return an u64.".to_string())
+        })? as u32;
+        let hash = self.getblockhash(height)?;
+
+        Ok(BlockchainTip { height, hash })
+    }
 
-    pub fn is_in_mempool(&self, txid: &Txid) -> Result<bool, BitcoindError> {
+    fn synchronization_info(&self) -> Result<SyncInfo, BitcoindError> {
+        let chaininfo = self.make_node_request("getblockchaininfo", &[])?;
+        Ok(SyncInfo {
+            headers: chaininfo
+                .get("headers")
+                .and_then(|h| h.as_u64())
+                .ok_or_else(|| {
+                    BitcoindError::Custom(
+                        "No valid 'headers' in getblockchaininfo response?".to_owned(),
+                    )
+                })?,
+            blocks: chaininfo
+                .get("blocks")
+                .and_then(|b| b.as_u64())
+                .ok_or_else(|| {
+                    BitcoindError::Custom(
+                        "No valid 'blocks' in getblockchaininfo response?".to_owned(),
+                    )
+                })?,
+            ibd: chaininfo
+                .get("initialblockdownload")
+                .and_then(|i| i.as_bool())
+                .ok_or_else(|| {
+                    BitcoindError::Custom(
+                        "No valid 'initialblockdownload' in getblockchaininfo response?".to_owned(),
+                    )
+                })?,
+            progress: chaininfo
+                .get("verificationprogress")
+                .and_then(|i| i.as_f64())
+                .ok_or_else(|| {
+                    BitcoindError::Custom(
+                        "No valid 'initialblockdownload' in getblockchaininfo response?".to_owned(),
+                    )
+                })?,
+        })
+    }
+
+    fn watch_fresh_addresses(
+        &self,
+        deposit_address: &Address,
+        unvault_address: &Address,
+        cpfp_address: &Address,
+    ) -> Result<(), BitcoindError> {
+        self.import_fresh_deposit_descriptor(self.addr_descriptor(&deposit_address.to_string())?)?;
+        self.import_fresh_unvault_descriptor(self.addr_descriptor(&unvault_address.to_string())?)?;
+        self.import_fresh_cpfp_descriptor(self.addr_descriptor(&cpfp_address.to_string())?)
+    }
+
+    fn watch_past_addresses(
+        &self,
+        deposit_address: &Address,
+        unvault_address: &Address,
+        cpfp_address: &Address,
+        since: u32,
+    ) -> Result<(), BitcoindError> {
+        // Not a fresh wallet: bitcoind rescans the block chain from this timestamp for them.
+        self.startup_import_deposit_descriptors(
+            vec![self.addr_descriptor(&deposit_address.to_string())?],
+            since,
+            false,
+        )?;
+        self.startup_import_unvault_descriptors(
+            vec![self.addr_descriptor(&unvault_address.to_string())?],
+            since,
+            false,
+        )?;
+        self.startup_import_cpfp_descriptors(
+            vec![self.addr_descriptor(&cpfp_address.to_string())?],
+            since,
+            false,
+        )
+    }
+
+    fn sync_deposits(
+        &self,
+        deposits_utxos: &HashMap<OutPoint, UtxoInfo>,
+        min_conf: u32,
+    ) -> Result<OnchainDescriptorState, BitcoindError> {
+        self.sync_chainstate(
+            deposits_utxos,
+            self.deposit_utxos_label(),
+            min_conf,
+            Some(Amount::from_sat(MIN_DEPOSIT_VALUE).as_btc()),
+        )
+    }
+
+    fn sync_unvaults(
+        &self,
+        unvault_utxos: &HashMap<OutPoint, UtxoInfo>,
+    ) -> Result<OnchainDescriptorState, BitcoindError> {
+        self.sync_chainstate(unvault_utxos, self.unvault_utxos_label(), 1, None)
+    }
+
+    fn confirmed_deposits(
+        &self,
+        min_conf: u32,
+    ) -> Result<HashMap<OutPoint, UtxoInfo>, BitcoindError> {
+        const PAGE_SIZE: u64 = 1000;
+        let mut deposits = HashMap::new();
+        let api_break = |field: &str| {
+            BitcoindError::Custom(format!(
+                "API break, 'listtransactions' entry didn't contain a valid '{}'.",
+                field
+            ))
+        };
+
+        for page in 0.. {
+            let entries = self.make_watchonly_request(
+                "listtransactions",
+                &params!(
+                    Json::String(self.deposit_utxos_label()),
+                    Json::Number(PAGE_SIZE.into()),
+                    Json::Number((page * PAGE_SIZE).into()),
+                    Json::Bool(true), // include_watchonly
+                ),
+            )?;
+            let entries = entries.as_array().ok_or_else(|| {
+                BitcoindError::Custom(
+                    "API break, 'listtransactions' didn't return an array.".to_string(),
+                )
+            })?;
+
+            for entry in entries {
+                if entry.get("category").map(|c| c.as_str()).flatten() != Some("receive") {
+                    continue;
+                }
+                let confirmations = entry
+                    .get("confirmations")
+                    .map(|c| c.as_i64())
+                    .flatten()
+                    .ok_or_else(|| api_break("confirmations"))?;
+                if confirmations < min_conf as i64 {
+                    continue;
+                }
+
+                let outpoint = self.outpoint_from_utxo(&entry)?;
+                let script_pubkey = entry
+                    .get("address")
+                    .map(|a| a.as_str().map(|a| Address::from_str(a).ok()))
+                    .flatten()
+                    .flatten()
+                    .ok_or_else(|| api_break("address"))?
+                    .script_pubkey();
+                let value = entry
+                    .get("amount")
+                    .map(|a| a.as_f64().map(|a| Amount::from_btc(a).ok()))
+                    .flatten()
+                    .flatten()
+                    .ok_or_else(|| api_break("amount"))?
+                    .as_sat();
+                if value < MIN_DEPOSIT_VALUE {
+                    continue;
+                }
+
+                deposits.insert(
+                    outpoint,
+                    UtxoInfo {
+                        txo: TxOut {
+                            value,
+                            script_pubkey,
+                        },
+                        is_confirmed: true,
+                    },
+                );
+            }
+
+            if (entries.len() as u64) < PAGE_SIZE {
+                break;
+            }
+        }
+
+        Ok(deposits)
+    }
+
+    fn get_wallet_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<(String, Option<u32>, u32), BitcoindError> {
+        let res = self
+            .make_watchonly_request("gettransaction", &params!(Json::String(txid.to_string())))?;
+        let tx_hex = res
+            .get("hex")
+            .ok_or_else(|| {
+                BitcoindError::Custom(format!(
+                    "API break: no 'hex' in 'gettransaction' result (txid: {})",
+                    txid
+                ))
+            })?
+            .as_str()
+            .ok_or_else(|| {
+                BitcoindError::Custom("API break: 'hex' is not a string ????".to_string())
+            })?
+            .to_string();
+        let blockheight = res.get("blockheight").map(|bh| bh.as_u64().unwrap() as u32);
+        let received = res
+            .get("timereceived")
+            .ok_or_else(|| {
+                BitcoindError::Custom(format!(
+                    "API break: no 'time_received' in 'gettransaction' result (txid: {})",
+                    txid
+                ))
+            })?
+            .as_u64()
+            .ok_or_else(|| {
+                BitcoindError::Custom(format!(
+                    "API break: invalid 'time_received' in 'gettransaction' result (txid: {})",
+                    txid
+                ))
+            })? as u32;
+
+        Ok((tx_hex, blockheight, received))
+    }
+
+    /// Broadcast a transaction with 'sendrawtransaction', discarding the returned txid
+    fn broadcast_transaction(&self, tx: &Transaction) -> Result<(), BitcoindError> {
+        let tx_hex = encode::serialize_hex(tx);
+        log::debug!("Broadcasting '{}'", tx_hex);
+        self.make_watchonly_request("sendrawtransaction", &params!(Json::String(tx_hex)))
+            .map(|_| ())
+    }
+
+    /// Broadcast a transaction that is already part of the wallet
+    fn rebroadcast_wallet_tx(&self, txid: &Txid) -> Result<(), BitcoindError> {
+        let (hex, _, _) = self.get_wallet_transaction(txid)?;
+        log::debug!("Re-broadcasting '{}'", hex);
+        self.make_watchonly_request("sendrawtransaction", &params!(Json::String(hex)))
+            .map(|_| ())
+    }
+
+    /// We first ask bitcoind for the transaction spending it in the mempool, as the confirmed
+    /// ones are already indexed as blocks get connected (see `get_block_transactions`). If it's
+    /// too old to tell us, or we somehow missed it, we look for it in the wallet transactions.
+    fn get_spender_txid(
+        &self,
+        spent_outpoint: &OutPoint,
+        block_hash: &BlockHash,
+    ) -> Result<Option<Txid>, BitcoindError> {
+        if self.has_txspendingprevout.load(Ordering::Relaxed) {
+            match self.mempool_spender_txid(spent_outpoint) {
+                Ok(Some(txid)) => return Ok(Some(txid)),
+                Ok(None) => {}
+                // https://github.com/bitcoin/bitcoin/blob/dca80ffb45fcc8e6eedb6dc481d500dedab4248b/src/rpc/protocol.h#L34
+                Err(BitcoindError::Server(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+                    code: -32601,
+                    ..
+                }))) => {
+                    log::info!(
+                        "bitcoind doesn't support 'gettxspendingprevout', we'll look for \
+                         unconfirmed spenders in the wallet transactions"
+                    );
+                    self.has_txspendingprevout.store(false, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.wallet_spender_txid(spent_outpoint, block_hash)
+    }
+
+    fn get_block_transactions(
+        &self,
+        block_hash: &BlockHash,
+    ) -> Result<Option<Vec<Transaction>>, BitcoindError> {
+        let block_hex = self.make_node_request(
+            "getblock",
+            &params!(Json::String(block_hash.to_string()), 0),
+        )?;
+        let block: Block = block_hex
+            .as_str()
+            .ok_or_else(|| "API break, 'getblock' didn't return a string.".to_string())
+            .and_then(|hex| Vec::from_hex(hex).map_err(|e| e.to_string()))
+            .and_then(|bytes| encode::deserialize(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                BitcoindError::Custom(format!(
+                    "Invalid block '{}' returned by 'getblock': {}",
+                    block_hash, e
+                ))
+            })?;
+
+        Ok(Some(block.txdata))
+    }
+
+    fn is_in_mempool(&self, txid: &Txid) -> Result<bool, BitcoindError> {
         match self.make_node_request("getmempoolentry", &params!(Json::String(txid.to_string()))) {
             Ok(_) => Ok(true),
             Err(BitcoindError::Server(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
@@ -746,6 +1156,34 @@ impl BitcoinD {
             Err(e) => Err(e),
         }
     }
+
+    /// Get the feerate, in sat/vbyte, bitcoind estimates for a transaction to be confirmed
+    /// within `conf_target` blocks. None if it hasn't got enough data to estimate it.
+    fn estimate_feerate(
+        &self,
+        conf_target: u16,
+        estimate_mode: EstimateMode,
+    ) -> Result<Option<u64>, BitcoindError> {
+        let res =
+            self.make_node_request("estimatesmartfee", &params!(conf_target, estimate_mode))?;
+        if let Some(errors) = res.get("errors") {
+            log::debug!(
+                "No feerate estimate for a target of '{}' blocks: '{}'",
+                conf_target,
+                errors
+            );
+        }
+
+        // It's in BTC/kvbyte
+        Ok(res.get("feerate").and_then(|f| f.as_f64()).map(|btc_kvb| {
+            let sat_kvb = (btc_kvb * 100_000_000.0).round() as u64;
+            (sat_kvb + 999) / 1_000
+        }))
+    }
+
+    fn as_bitcoind(&self) -> Option<&BitcoinD> {
+        Some(self)
+    }
 }
 
 /// Information about an utxo one of our descriptors points to.
@@ -772,3 +1210,19 @@ pub struct SyncInfo {
     pub ibd: bool,
     pub progress: f64,
 }
+
+/// The estimation mode of bitcoind's `estimatesmartfee`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EstimateMode {
+    /// Potentially lower estimates, more responsive to short term drops
+    Economical,
+    /// Potentially higher estimates, accounting for a longer history
+    Conservative,
+}
+
+impl Default for EstimateMode {
+    fn default() -> Self {
+        Self::Conservative
+    }
+}
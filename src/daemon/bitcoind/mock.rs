@@ -0,0 +1,601 @@
+//! A mock bitcoind, serving a scripted block chain over JSONRPC to drive the poller under
+//! `cargo test`.
+//!
+//! Tests mine blocks, reorg them out and broadcast or evict transactions, then poll it through
+//! the very `BitcoinD` client the daemon uses. It only implements the calls we make, and only
+//! fills the fields of their results we read. Transactions are not validated.
+
+use crate::{bitcoind::interface::BitcoinD, revaultd::BlockchainTip};
+use common::config::BitcoindConfig;
+use revault_tx::bitcoin::{
+    consensus::encode,
+    hashes::{hex::FromHex, Hash},
+    Address, Amount, Block, BlockHash, BlockHeader, Network, OutPoint, Script, Transaction, TxIn,
+    TxMerkleNode, TxOut, Txid,
+};
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Value as Json};
+
+fn rpc_error(code: i64, message: &str) -> Json {
+    json!({"code": code, "message": message})
+}
+
+fn invalid_params() -> Json {
+    rpc_error(-8, "Invalid parameters")
+}
+
+fn txid_param(param: &Json) -> Result<Txid, Json> {
+    param
+        .as_str()
+        .and_then(|txid| Txid::from_str(txid).ok())
+        .ok_or_else(invalid_params)
+}
+
+fn spends(tx: &Transaction, outpoint: &OutPoint) -> bool {
+    tx.input
+        .iter()
+        .any(|txin| &txin.previous_output == outpoint)
+}
+
+// The block chain, mempool and watchonly wallet of our mock bitcoind
+struct MockChain {
+    network: Network,
+    // The best chain, starting at a genesis block
+    blocks: Vec<Block>,
+    mempool: Vec<Transaction>,
+    // The scripts our watchonly wallet tracks, along with their label
+    watched: HashMap<Script, String>,
+    // Like bitcoind's, our wallet never forgets about a transaction even once it got evicted or
+    // reorged out. Along with the time it first heard about it.
+    wallet_txs: HashMap<Txid, (Transaction, u32)>,
+    // So that blocks and coins are never created twice
+    nonce: u32,
+}
+
+impl MockChain {
+    fn new(network: Network) -> Self {
+        let mut chain = MockChain {
+            network,
+            blocks: Vec::new(),
+            mempool: Vec::new(),
+            watched: HashMap::new(),
+            wallet_txs: HashMap::new(),
+            nonce: 0,
+        };
+        chain.mine_block(Vec::new());
+        chain
+    }
+
+    fn tip_height(&self) -> u32 {
+        self.blocks.len() as u32 - 1
+    }
+
+    fn time(&self) -> u32 {
+        1_600_000_000 + self.tip_height() * 600
+    }
+
+    fn mine_block(&mut self, txdata: Vec<Transaction>) {
+        let prev_blockhash = self
+            .blocks
+            .last()
+            .map(|block| block.block_hash())
+            .unwrap_or_else(BlockHash::default);
+        self.nonce += 1;
+        let header = BlockHeader {
+            version: 1,
+            prev_blockhash,
+            merkle_root: TxMerkleNode::default(),
+            time: 1_600_000_000 + self.blocks.len() as u32 * 600,
+            bits: 0x207fffff,
+            nonce: self.nonce,
+        };
+        self.blocks.push(Block { header, txdata });
+    }
+
+    // All the transactions in the best chain then in the mempool, along with their height if
+    // they are confirmed
+    fn transactions(&self) -> impl Iterator<Item = (&Transaction, Option<u32>)> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(height, block)| {
+                block.txdata.iter().map(move |tx| (tx, Some(height as u32)))
+            })
+            .chain(self.mempool.iter().map(|tx| (tx, None)))
+    }
+
+    fn transaction(&self, txid: &Txid) -> Option<(&Transaction, Option<u32>)> {
+        self.transactions().find(|(tx, _)| &tx.txid() == txid)
+    }
+
+    fn confirmations(&self, height: Option<u32>) -> u32 {
+        height.map(|h| self.tip_height() + 1 - h).unwrap_or(0)
+    }
+
+    fn spender(&self, outpoint: &OutPoint) -> Option<&Transaction> {
+        self.transactions()
+            .map(|(tx, _)| tx)
+            .find(|tx| spends(tx, outpoint))
+    }
+
+    fn address(&self, script_pubkey: &Script) -> String {
+        Address::from_script(script_pubkey, self.network)
+            .map(|addr| addr.to_string())
+            .unwrap_or_default()
+    }
+
+    // The output at this outpoint, if it pays to our watchonly wallet
+    fn wallet_txo(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.wallet_txs
+            .get(&outpoint.txid)
+            .and_then(|(tx, _)| tx.output.get(outpoint.vout as usize))
+            .filter(|txo| self.watched.contains_key(&txo.script_pubkey))
+    }
+
+    fn is_wallet_spend(&self, tx: &Transaction) -> bool {
+        tx.input
+            .iter()
+            .any(|txin| self.wallet_txo(&txin.previous_output).is_some())
+    }
+
+    fn is_wallet_tx(&self, tx: &Transaction) -> bool {
+        tx.output
+            .iter()
+            .any(|txo| self.watched.contains_key(&txo.script_pubkey))
+            || self.is_wallet_spend(tx)
+    }
+
+    // Add the transactions touching our watched scripts to the wallet. A transaction may only
+    // spend a wallet coin once its parent is in, hence the loop.
+    fn update_wallet(&mut self) {
+        loop {
+            let new_txs: Vec<Transaction> = self
+                .transactions()
+                .map(|(tx, _)| tx)
+                .filter(|tx| !self.wallet_txs.contains_key(&tx.txid()) && self.is_wallet_tx(tx))
+                .cloned()
+                .collect();
+            if new_txs.is_empty() {
+                return;
+            }
+
+            let time = self.time();
+            for tx in new_txs {
+                self.wallet_txs.insert(tx.txid(), (tx, time));
+            }
+        }
+    }
+
+    fn broadcast(&mut self, tx: Transaction) -> Result<Txid, Json> {
+        let txid = tx.txid();
+        match self.transaction(&txid) {
+            Some((_, Some(_))) => {
+                return Err(rpc_error(-27, "Transaction already in block chain"));
+            }
+            Some((_, None)) => return Ok(txid),
+            None => {}
+        }
+        if tx
+            .input
+            .iter()
+            .any(|txin| self.spender(&txin.previous_output).is_some())
+        {
+            return Err(rpc_error(-26, "txn-mempool-conflict"));
+        }
+
+        self.mempool.push(tx);
+        self.update_wallet();
+        Ok(txid)
+    }
+
+    fn respond(&mut self, method: &str, params: &Json) -> Result<Json, Json> {
+        Ok(match method {
+            "getblockcount" => json!(self.tip_height()),
+            "getblockhash" => {
+                let height = params[0].as_u64().ok_or_else(invalid_params)?;
+                let block = self
+                    .blocks
+                    .get(height as usize)
+                    .ok_or_else(|| rpc_error(-8, "Block height out of range"))?;
+                json!(block.block_hash().to_string())
+            }
+            "getblockchaininfo" => json!({
+                "blocks": self.tip_height(),
+                "headers": self.tip_height(),
+                "initialblockdownload": false,
+                "verificationprogress": 1.0,
+            }),
+            "getblock" => {
+                let block_hash = params[0]
+                    .as_str()
+                    .and_then(|hash| BlockHash::from_str(hash).ok())
+                    .ok_or_else(invalid_params)?;
+                let block = self
+                    .blocks
+                    .iter()
+                    .find(|block| block.block_hash() == block_hash)
+                    .ok_or_else(|| rpc_error(-5, "Block not found"))?;
+                json!(encode::serialize_hex(block))
+            }
+            "getmempoolentry" => {
+                let txid = txid_param(&params[0])?;
+                if !self.mempool.iter().any(|tx| tx.txid() == txid) {
+                    return Err(rpc_error(-5, "Transaction not in mempool"));
+                }
+                json!({})
+            }
+            "gettxspendingprevout" => {
+                let mut entries = Vec::new();
+                for prevout in params[0].as_array().ok_or_else(invalid_params)? {
+                    let outpoint = OutPoint {
+                        txid: txid_param(&prevout["txid"])?,
+                        vout: prevout["vout"].as_u64().ok_or_else(invalid_params)? as u32,
+                    };
+                    let mut entry = json!({
+                        "txid": outpoint.txid.to_string(),
+                        "vout": outpoint.vout,
+                    });
+                    if let Some(spender) = self.mempool.iter().find(|tx| spends(tx, &outpoint)) {
+                        entry["spendingtxid"] = json!(spender.txid().to_string());
+                    }
+                    entries.push(entry);
+                }
+                json!(entries)
+            }
+            "sendrawtransaction" => {
+                let tx = params[0]
+                    .as_str()
+                    .and_then(|hex| Vec::from_hex(hex).ok())
+                    .and_then(|bytes| encode::deserialize(&bytes).ok())
+                    .ok_or_else(|| rpc_error(-22, "TX decode failed"))?;
+                json!(self.broadcast(tx)?.to_string())
+            }
+            "estimatesmartfee" => json!({"feerate": 0.0001, "blocks": 2}),
+            "getdescriptorinfo" => {
+                let desc = params[0].as_str().ok_or_else(invalid_params)?;
+                json!({
+                    "descriptor": format!("{}#mockmock", desc),
+                    "checksum": "mockmock",
+                })
+            }
+            "importdescriptors" => {
+                let mut results = Vec::new();
+                for request in params[0].as_array().ok_or_else(invalid_params)? {
+                    let address = request["desc"]
+                        .as_str()
+                        .and_then(|desc| desc.split('#').next())
+                        .and_then(|desc| desc.strip_prefix("addr("))
+                        .and_then(|desc| desc.strip_suffix(')'))
+                        .and_then(|addr| Address::from_str(addr).ok());
+                    if let Some(address) = address {
+                        let label = request["label"].as_str().unwrap_or_default().to_string();
+                        self.watched.insert(address.script_pubkey(), label);
+                        results.push(json!({"success": true}));
+                    } else {
+                        results.push(json!({
+                            "success": false,
+                            "error": rpc_error(-5, "Only 'addr()' descriptors are supported"),
+                        }));
+                    }
+                }
+                self.update_wallet();
+                json!(results)
+            }
+            "listunspent" => {
+                let min_amount = params[4]["minimumAmount"].as_f64().unwrap_or(0.0);
+                let mut utxos = Vec::new();
+                for (tx, height) in self.transactions() {
+                    for (vout, txo) in tx.output.iter().enumerate() {
+                        let outpoint = OutPoint::new(tx.txid(), vout as u32);
+                        let amount = Amount::from_sat(txo.value).as_btc();
+                        let label = match self.watched.get(&txo.script_pubkey) {
+                            Some(label) => label,
+                            None => continue,
+                        };
+                        if amount < min_amount || self.spender(&outpoint).is_some() {
+                            continue;
+                        }
+                        utxos.push(json!({
+                            "txid": outpoint.txid.to_string(),
+                            "vout": outpoint.vout,
+                            "address": self.address(&txo.script_pubkey),
+                            "label": label,
+                            "amount": amount,
+                            "confirmations": self.confirmations(height),
+                        }));
+                    }
+                }
+                json!(utxos)
+            }
+            "listtransactions" => {
+                let label = params[0].as_str().ok_or_else(invalid_params)?;
+                let count = params[1].as_u64().unwrap_or(10) as usize;
+                let skip = params[2].as_u64().unwrap_or(0) as usize;
+                let mut entries = Vec::new();
+                for (tx, height) in self.transactions() {
+                    for (vout, txo) in tx.output.iter().enumerate() {
+                        if self.watched.get(&txo.script_pubkey).map(|l| l.as_str()) != Some(label) {
+                            continue;
+                        }
+                        entries.push(json!({
+                            "category": "receive",
+                            "txid": tx.txid().to_string(),
+                            "vout": vout,
+                            "address": self.address(&txo.script_pubkey),
+                            "label": label,
+                            "amount": Amount::from_sat(txo.value).as_btc(),
+                            "confirmations": self.confirmations(height),
+                        }));
+                    }
+                }
+                json!(entries
+                    .into_iter()
+                    .skip(skip)
+                    .take(count)
+                    .collect::<Vec<Json>>())
+            }
+            "listsinceblock" => {
+                // Since the genesis if it's not (anymore) in the best chain
+                let since_height = params[0]
+                    .as_str()
+                    .and_then(|hash| BlockHash::from_str(hash).ok())
+                    .and_then(|hash| {
+                        self.blocks
+                            .iter()
+                            .position(|block| block.block_hash() == hash)
+                    })
+                    .unwrap_or(0) as u32;
+                let transactions: Vec<Json> = self
+                    .transactions()
+                    .filter(|(tx, height)| {
+                        height.map(|h| h > since_height).unwrap_or(true) && self.is_wallet_spend(tx)
+                    })
+                    .map(|(tx, height)| {
+                        json!({
+                            "category": "send",
+                            "txid": tx.txid().to_string(),
+                            "confirmations": self.confirmations(height),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "transactions": transactions,
+                    "lastblock": self.blocks[self.tip_height() as usize].block_hash().to_string(),
+                })
+            }
+            "gettransaction" => {
+                let txid = txid_param(&params[0])?;
+                let (tx, time_received) = self
+                    .wallet_txs
+                    .get(&txid)
+                    .ok_or_else(|| rpc_error(-5, "Invalid or non-wallet transaction id"))?;
+                let height = self.transaction(&txid).and_then(|(_, height)| height);
+                let vin: Vec<Json> = tx
+                    .input
+                    .iter()
+                    .map(|txin| {
+                        json!({
+                            "txid": txin.previous_output.txid.to_string(),
+                            "vout": txin.previous_output.vout,
+                        })
+                    })
+                    .collect();
+                let mut res = json!({
+                    "txid": txid.to_string(),
+                    "hex": encode::serialize_hex(tx),
+                    "confirmations": self.confirmations(height),
+                    "timereceived": time_received,
+                    "decoded": {"vin": vin},
+                });
+                if let Some(height) = height {
+                    res["blockheight"] = json!(height);
+                    res["blockhash"] = json!(self.blocks[height as usize].block_hash().to_string());
+                }
+                res
+            }
+            _ => return Err(rpc_error(-32601, "Method not found")),
+        })
+    }
+}
+
+// Answer the requests sent over this connection until the client closes it
+fn serve_connection(stream: TcpStream, chain: Arc<Mutex<MockChain>>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        // The request line, then the headers up to an empty line
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let mut content_length = 0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line == "\r\n" {
+                break;
+            }
+            let mut header = line.splitn(2, ':');
+            if let (Some(name), Some(value)) = (header.next(), header.next()) {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length")
+                    })?;
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        let request: Json = serde_json::from_slice(&body)?;
+        let method = request["method"].as_str().unwrap_or_default();
+        let (result, error) = match chain.lock().unwrap().respond(method, &request["params"]) {
+            Ok(result) => (result, Json::Null),
+            Err(error) => (Json::Null, error),
+        };
+
+        // The client reads a single line of body
+        let response = format!(
+            "{}\n",
+            json!({"result": result, "error": error, "id": request["id"]})
+        );
+        write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        )?;
+        writer.flush()?;
+    }
+}
+
+/// A bitcoind serving a block chain scripted by the test
+pub struct MockBitcoind {
+    chain: Arc<Mutex<MockChain>>,
+    addr: SocketAddr,
+    cookie_path: PathBuf,
+    network: Network,
+}
+
+impl MockBitcoind {
+    /// Start serving a chain with only a genesis block. The cookie file is written in `datadir`.
+    pub fn new(network: Network, datadir: &PathBuf) -> Self {
+        let chain = Arc::new(Mutex::new(MockChain::new(network)));
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Binding the mock bitcoind");
+        let addr = listener.local_addr().expect("Bound socket");
+        thread::spawn({
+            let chain = chain.clone();
+            move || {
+                for stream in listener.incoming().filter_map(Result::ok) {
+                    let chain = chain.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve_connection(stream, chain) {
+                            log::debug!("Mock bitcoind connection error: '{}'", e);
+                        }
+                    });
+                }
+            }
+        });
+
+        fs::create_dir_all(datadir).expect("Creating the datadir");
+        let cookie_path = datadir.join(".cookie");
+        fs::write(&cookie_path, "__cookie__:mock").expect("Writing the cookie file");
+
+        MockBitcoind {
+            chain,
+            addr,
+            cookie_path,
+            network,
+        }
+    }
+
+    /// A client to our mock, as the daemon would use
+    pub fn client(&self) -> BitcoinD {
+        let config = BitcoindConfig {
+            network: self.network,
+            cookie_path: self.cookie_path.clone(),
+            addr: self.addr,
+            poll_interval_secs: Duration::from_secs(1),
+            zmqpubhashblock: None,
+            zmqpubrawtx: None,
+        };
+        BitcoinD::new(&config, "watchonly".to_string(), "feebump".to_string())
+            .expect("Connecting to the mock bitcoind")
+    }
+
+    pub fn tip(&self) -> BlockchainTip {
+        let chain = self.chain.lock().unwrap();
+        let height = chain.tip_height();
+        BlockchainTip {
+            height,
+            hash: chain.blocks[height as usize].block_hash(),
+        }
+    }
+
+    /// Track this address in the watchonly wallet, as after an 'importdescriptors'
+    pub fn watch(&self, address: &Address, label: &str) {
+        let mut chain = self.chain.lock().unwrap();
+        chain
+            .watched
+            .insert(address.script_pubkey(), label.to_string());
+        chain.update_wallet();
+    }
+
+    /// Broadcast this transaction. Panics if it conflicts with another one.
+    pub fn broadcast(&self, tx: Transaction) -> Txid {
+        self.chain
+            .lock()
+            .unwrap()
+            .broadcast(tx)
+            .unwrap_or_else(|e| panic!("Broadcasting transaction: '{}'", e))
+    }
+
+    /// Broadcast a transaction paying `amount` to this address out of a fresh coin
+    pub fn pay_to(&self, address: &Address, amount: Amount) -> Transaction {
+        let nonce = {
+            let mut chain = self.chain.lock().unwrap();
+            chain.nonce += 1;
+            chain.nonce
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&nonce.to_le_bytes()), 0),
+                script_sig: Script::new(),
+                sequence: 0xff_ff_ff_ff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: amount.as_sat(),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        self.broadcast(tx.clone());
+        tx
+    }
+
+    /// Mine `n_blocks`, the first one confirming all the transactions in the mempool
+    pub fn mine(&self, n_blocks: u32) {
+        let mut chain = self.chain.lock().unwrap();
+        for _ in 0..n_blocks {
+            let txdata = std::mem::take(&mut chain.mempool);
+            chain.mine_block(txdata);
+        }
+        chain.update_wallet();
+    }
+
+    /// Disconnect the `depth` last blocks, their transactions going back to the mempool
+    pub fn reorg(&self, depth: u32) {
+        let mut chain = self.chain.lock().unwrap();
+        assert!(depth <= chain.tip_height(), "Can't reorg the genesis block");
+        let mut disconnected = Vec::new();
+        for _ in 0..depth {
+            let block = chain.blocks.pop().expect("Checked above");
+            disconnected.splice(0..0, block.txdata);
+        }
+        disconnected.append(&mut chain.mempool);
+        chain.mempool = disconnected;
+    }
+
+    /// Drop this transaction from the mempool, as if it was replaced or expired
+    pub fn evict(&self, txid: &Txid) {
+        self.chain
+            .lock()
+            .unwrap()
+            .mempool
+            .retain(|tx| &tx.txid() != txid);
+    }
+}
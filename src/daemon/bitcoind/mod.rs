@@ -1,4 +1,4 @@
-use crate::database::DatabaseError;
+use crate::{bitcoind::electrum::ElectrumError, database::DatabaseError};
 
 use jsonrpc::{
     error::{Error, RpcError},
@@ -6,7 +6,14 @@ use jsonrpc::{
 };
 
 pub mod actions;
+pub mod backend;
+pub mod electrum;
+pub mod feebump;
 pub mod interface;
+#[cfg(test)]
+pub mod mock;
+pub mod notifications;
+pub mod spenders;
 
 /// An error happened in the bitcoind-manager thread
 #[derive(Debug)]
@@ -15,6 +22,8 @@ pub enum BitcoindError {
     Custom(String),
     /// Or directly to bitcoind's RPC server
     Server(Error),
+    /// Or to the Electrum server we use in its place
+    Electrum(ElectrumError),
     RevaultTx(revault_tx::Error),
 }
 
@@ -34,6 +43,7 @@ impl std::fmt::Display for BitcoindError {
         match self {
             BitcoindError::Custom(ref s) => write!(f, "Bitcoind manager error: {}", s),
             BitcoindError::Server(ref e) => write!(f, "Bitcoind server error: {}", e),
+            BitcoindError::Electrum(ref e) => write!(f, "Electrum {}", e),
             BitcoindError::RevaultTx(ref s) => write!(f, "Bitcoind manager error: {}", s),
         }
     }
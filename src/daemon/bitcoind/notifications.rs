@@ -0,0 +1,177 @@
+//! bitcoind's ZMQ notifications, to sync as soon as something happens on chain instead of
+//! waiting for the next poll.
+//!
+//! We subscribe to the 'hashblock' and 'rawtx' topics. A new block always warrants a sync, a
+//! new transaction only if it touches one of our coins (see `is_relevant`). Polling is kept as
+//! a fallback, as ZMQ may silently drop messages.
+//!
+//! This needs the system libzmq, hence is only available with the "zmq" feature. Without it, we
+//! always poll.
+
+use crate::{
+    bitcoind::{interface::UtxoInfo, BitcoindError},
+    revaultd::RevaultD,
+};
+use common::config::BitcoindConfig;
+#[cfg(feature = "zmq")]
+use revault_tx::bitcoin::consensus::encode;
+use revault_tx::bitcoin::{OutPoint, Transaction};
+
+#[cfg(feature = "zmq")]
+use std::sync::atomic::Ordering;
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicBool, mpsc::Sender, Arc},
+};
+
+/// How long we block on the socket before checking whether we need to shut down, in ms
+#[cfg(feature = "zmq")]
+const RECV_TIMEOUT_MS: i32 = 500;
+
+/// Something happened on chain
+#[derive(Debug)]
+pub enum ChainNotification {
+    NewBlock,
+    NewTx(Transaction),
+}
+
+#[cfg(feature = "zmq")]
+fn zmq_error(e: zmq::Error) -> BitcoindError {
+    BitcoindError::Custom(format!("ZMQ error: '{}'", e))
+}
+
+/// A subscription to bitcoind's ZMQ notifications
+#[cfg(feature = "zmq")]
+pub struct ZmqSubscriber {
+    // Kept around as dropping it would close the socket
+    _context: zmq::Context,
+    socket: zmq::Socket,
+}
+
+#[cfg(feature = "zmq")]
+impl ZmqSubscriber {
+    /// Subscribe to the endpoints in our config. None if there is none configured.
+    pub fn new(config: &BitcoindConfig) -> Result<Option<Self>, BitcoindError> {
+        let mut endpoints = Vec::with_capacity(2);
+        let mut topics: Vec<&[u8]> = Vec::with_capacity(2);
+        if let Some(ref endpoint) = config.zmqpubhashblock {
+            endpoints.push(endpoint.as_str());
+            topics.push(b"hashblock");
+        }
+        if let Some(ref endpoint) = config.zmqpubrawtx {
+            endpoints.push(endpoint.as_str());
+            topics.push(b"rawtx");
+        }
+        if endpoints.is_empty() {
+            return Ok(None);
+        }
+        // bitcoind may publish both on the same endpoint
+        endpoints.dedup();
+
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::SUB).map_err(zmq_error)?;
+        socket.set_rcvtimeo(RECV_TIMEOUT_MS).map_err(zmq_error)?;
+        for endpoint in endpoints {
+            log::debug!(
+                "Subscribing to bitcoind's ZMQ notifications at '{}'",
+                endpoint
+            );
+            socket.connect(endpoint).map_err(zmq_error)?;
+        }
+        for topic in topics {
+            socket.set_subscribe(topic).map_err(zmq_error)?;
+        }
+
+        Ok(Some(ZmqSubscriber {
+            _context: context,
+            socket,
+        }))
+    }
+
+    /// Forward the notifications to the poller until we are told to shut down.
+    pub fn listen(
+        self,
+        notif_tx: Sender<ChainNotification>,
+        shutdown: Arc<AtomicBool>,
+    ) -> Result<(), BitcoindError> {
+        while !shutdown.load(Ordering::Relaxed) {
+            // Each message is made of the topic, the body and a sequence number
+            let msg = match self.socket.recv_multipart(0) {
+                Ok(msg) => msg,
+                Err(zmq::Error::EAGAIN) | Err(zmq::Error::EINTR) => continue,
+                Err(e) => return Err(zmq_error(e)),
+            };
+            if msg.len() < 2 {
+                log::error!("Got an invalid ZMQ message from bitcoind: '{:?}'", msg);
+                continue;
+            }
+
+            let notif = match msg[0].as_slice() {
+                b"hashblock" => ChainNotification::NewBlock,
+                b"rawtx" => match encode::deserialize(&msg[1]) {
+                    Ok(tx) => ChainNotification::NewTx(tx),
+                    Err(e) => {
+                        log::error!("Got an invalid transaction from bitcoind's ZMQ: '{}'", e);
+                        continue;
+                    }
+                },
+                topic => {
+                    log::debug!("Ignoring ZMQ message with topic '{:?}'", topic);
+                    continue;
+                }
+            };
+            log::trace!("Got ZMQ notification: '{:?}'", notif);
+
+            // The poller is gone, so are we
+            if notif_tx.send(notif).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// We can't subscribe to anything without the "zmq" feature
+#[cfg(not(feature = "zmq"))]
+pub enum ZmqSubscriber {}
+
+#[cfg(not(feature = "zmq"))]
+impl ZmqSubscriber {
+    /// Always None, we warn if endpoints are configured nonetheless.
+    pub fn new(config: &BitcoindConfig) -> Result<Option<Self>, BitcoindError> {
+        if config.zmqpubhashblock.is_some() || config.zmqpubrawtx.is_some() {
+            log::warn!(
+                "ZMQ endpoints are configured but revaultd was built without the 'zmq' feature, \
+                 polling instead"
+            );
+        }
+
+        Ok(None)
+    }
+
+    pub fn listen(
+        self,
+        _notif_tx: Sender<ChainNotification>,
+        _shutdown: Arc<AtomicBool>,
+    ) -> Result<(), BitcoindError> {
+        match self {}
+    }
+}
+
+/// Does this transaction spend one of our deposit or Unvault coins, or create a deposit?
+pub fn is_relevant(
+    revaultd: &RevaultD,
+    tx: &Transaction,
+    deposits_cache: &HashMap<OutPoint, UtxoInfo>,
+    unvaults_cache: &HashMap<OutPoint, UtxoInfo>,
+) -> bool {
+    tx.input.iter().any(|txin| {
+        deposits_cache.contains_key(&txin.previous_output)
+            || unvaults_cache.contains_key(&txin.previous_output)
+    }) || tx.output.iter().any(|txo| {
+        revaultd
+            .derivation_index_map
+            .contains_key(&txo.script_pubkey)
+    })
+}
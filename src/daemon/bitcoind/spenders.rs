@@ -0,0 +1,264 @@
+//! An index of the transactions spending our coins.
+//!
+//! bitcoind has no API to get the transaction spending a confirmed outpoint, and looking for it
+//! among all the wallet transactions gets very slow with many vaults. Instead, once we notice
+//! some of our coins got spent, we go through the blocks connected since our last poll and store
+//! the transactions spending them in the database.
+
+use crate::{
+    bitcoind::{backend::ChainBackend, BitcoindError},
+    database::{actions::db_insert_spenders, interface::db_spender},
+    revaultd::BlockchainTip,
+};
+use revault_tx::bitcoin::{OutPoint, Transaction, Txid};
+
+use std::{collections::HashSet, path::PathBuf};
+
+/// Get the transactions of this block spending the `watched` outpoints. The coins they create
+/// are watched in turn, as they may be spent in the same batch of blocks (think of an Unvault
+/// and its Cancel).
+pub fn block_spenders(
+    transactions: &[Transaction],
+    watched: &mut HashSet<OutPoint>,
+) -> Vec<(OutPoint, Txid)> {
+    let mut spenders = Vec::new();
+
+    for tx in transactions {
+        let mut txid = None;
+        for txin in tx.input.iter() {
+            if watched.remove(&txin.previous_output) {
+                let spender_txid = *txid.get_or_insert_with(|| tx.txid());
+                spenders.push((txin.previous_output, spender_txid));
+            }
+        }
+
+        if let Some(txid) = txid {
+            watched.extend((0..tx.output.len() as u32).map(|vout| OutPoint { txid, vout }));
+        }
+    }
+
+    spenders
+}
+
+/// Index the spenders of the `watched` outpoints in the blocks after `from_height` up to the
+/// current tip. Returns the height we indexed up to.
+pub fn index_spenders(
+    db_path: &PathBuf,
+    bitcoind: &dyn ChainBackend,
+    from_height: u32,
+    mut watched: HashSet<OutPoint>,
+) -> Result<u32, BitcoindError> {
+    let tip = bitcoind.get_tip()?;
+
+    for height in from_height + 1..=tip.height {
+        let block_hash = bitcoind.getblockhash(height)?;
+        let transactions = match bitcoind.get_block_transactions(&block_hash)? {
+            Some(txs) => txs,
+            // It finds them on its own
+            None => return Ok(tip.height),
+        };
+
+        let spenders: Vec<(OutPoint, Txid, u32)> = block_spenders(&transactions, &mut watched)
+            .into_iter()
+            .map(|(outpoint, txid)| (outpoint, txid, height))
+            .collect();
+        if !spenders.is_empty() {
+            log::debug!(
+                "Found '{}' spenders of our coins in block '{}'",
+                spenders.len(),
+                block_hash
+            );
+            db_insert_spenders(db_path, &spenders)?;
+        }
+    }
+
+    Ok(tip.height)
+}
+
+/// Get the txid of the transaction spending this outpoint, from our index if it's confirmed or
+/// from the backend otherwise.
+pub fn spender_txid(
+    db_path: &PathBuf,
+    bitcoind: &dyn ChainBackend,
+    spent_outpoint: &OutPoint,
+    previous_tip: &BlockchainTip,
+) -> Result<Option<Txid>, BitcoindError> {
+    if let Some(txid) = db_spender(db_path, spent_outpoint)? {
+        return Ok(Some(txid));
+    }
+
+    bitcoind.get_spender_txid(spent_outpoint, &previous_tip.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::block_spenders;
+    use crate::database::{actions::db_insert_spenders, interface::db_spender, schema::SCHEMA};
+    use revault_tx::bitcoin::{hashes::Hash, OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+
+    use std::{collections::HashSet, fs, path::PathBuf, time::Instant};
+
+    use rusqlite::Connection;
+
+    fn dummy_outpoint(seed: u32) -> OutPoint {
+        OutPoint {
+            txid: Txid::hash(&seed.to_le_bytes()),
+            vout: seed % 3,
+        }
+    }
+
+    fn spending_tx(spent: &[OutPoint], n_outputs: usize) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: spent
+                .iter()
+                .map(|outpoint| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: Script::new(),
+                    sequence: 0xff_ff_ff_ff,
+                    witness: vec![],
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: 100_000,
+                    script_pubkey: Script::new(),
+                };
+                n_outputs
+            ],
+        }
+    }
+
+    #[test]
+    fn spenders_in_block() {
+        let deposits: Vec<OutPoint> = (0..3).map(dummy_outpoint).collect();
+        let mut watched: HashSet<OutPoint> = deposits.iter().copied().collect();
+
+        // An Unvault spending the first deposit, a Cancel spending its first output, and
+        // unrelated transactions.
+        let unvault_tx = spending_tx(&deposits[..1], 2);
+        let unvault_outpoint = OutPoint {
+            txid: unvault_tx.txid(),
+            vout: 0,
+        };
+        let cancel_tx = spending_tx(&[unvault_outpoint], 1);
+        let unrelated_tx = spending_tx(&[dummy_outpoint(42)], 1);
+        let spenders = block_spenders(
+            &[unrelated_tx.clone(), unvault_tx.clone(), cancel_tx.clone()],
+            &mut watched,
+        );
+        assert_eq!(
+            spenders,
+            vec![
+                (deposits[0], unvault_tx.txid()),
+                (unvault_outpoint, cancel_tx.txid())
+            ]
+        );
+        // What's spent isn't watched anymore, what's created is
+        assert!(!watched.contains(&deposits[0]));
+        assert!(watched.contains(&deposits[1]));
+        assert!(watched.contains(&OutPoint {
+            txid: cancel_tx.txid(),
+            vout: 0
+        }));
+
+        // A transaction spending two of them at once
+        let spend_tx = spending_tx(&deposits[1..], 1);
+        assert_eq!(
+            block_spenders(&[spend_tx.clone()], &mut watched),
+            vec![
+                (deposits[1], spend_tx.txid()),
+                (deposits[2], spend_tx.txid())
+            ]
+        );
+        assert!(block_spenders(&[unrelated_tx], &mut watched).is_empty());
+    }
+
+    // Run it with `cargo test --release -- --ignored --nocapture spender_index_benchmark`
+    #[test]
+    #[ignore]
+    fn spender_index_benchmark() {
+        const N_VAULTS: u32 = 5_000;
+        const N_UNRELATED_TXS: u32 = 20_000;
+
+        let datadir: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "test_data",
+            "scratch_datadir_spenders",
+        ]
+        .iter()
+        .collect();
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+        fs::create_dir_all(&datadir).unwrap();
+        let db_path = datadir.join("revaultd.sqlite3");
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(SCHEMA)
+            .unwrap();
+
+        // All the vaults get unvaulted, and half of the Unvaults get canceled, in a (huge) batch
+        // of blocks also containing many unrelated transactions.
+        let deposits: Vec<OutPoint> = (0..N_VAULTS).map(dummy_outpoint).collect();
+        let mut watched: HashSet<OutPoint> = deposits.iter().copied().collect();
+        let unvault_txs: Vec<Transaction> = deposits
+            .iter()
+            .map(|deposit| spending_tx(&[*deposit], 2))
+            .collect();
+        let cancel_txs: Vec<Transaction> = unvault_txs
+            .iter()
+            .step_by(2)
+            .map(|unvault_tx| {
+                spending_tx(
+                    &[OutPoint {
+                        txid: unvault_tx.txid(),
+                        vout: 0,
+                    }],
+                    1,
+                )
+            })
+            .collect();
+        let transactions: Vec<Transaction> = (0..N_UNRELATED_TXS)
+            .map(|i| spending_tx(&[dummy_outpoint(N_VAULTS + i)], 2))
+            .chain(unvault_txs.iter().cloned())
+            .chain(cancel_txs.iter().cloned())
+            .collect();
+
+        let start = Instant::now();
+        let spenders = block_spenders(&transactions, &mut watched);
+        let indexing_time = start.elapsed();
+        assert_eq!(spenders.len(), unvault_txs.len() + cancel_txs.len());
+
+        let start = Instant::now();
+        let spenders: Vec<(OutPoint, Txid, u32)> = spenders
+            .into_iter()
+            .map(|(outpoint, txid)| (outpoint, txid, 101))
+            .collect();
+        db_insert_spenders(&db_path, &spenders).unwrap();
+        let storing_time = start.elapsed();
+
+        let start = Instant::now();
+        for (deposit, unvault_tx) in deposits.iter().zip(unvault_txs.iter()) {
+            assert_eq!(
+                db_spender(&db_path, deposit).unwrap(),
+                Some(unvault_tx.txid())
+            );
+        }
+        assert_eq!(
+            db_spender(&db_path, &dummy_outpoint(N_VAULTS)).unwrap(),
+            None
+        );
+        let lookup_time = start.elapsed();
+
+        log::info!(
+            "{} vaults among {} transactions: indexed in {:?}, stored in {:?}, looked up in {:?}",
+            N_VAULTS,
+            transactions.len(),
+            indexing_time,
+            storing_time,
+            lookup_time
+        );
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
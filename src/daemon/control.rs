@@ -3,8 +3,9 @@
 //! This module contains useful functions for handling RPC requests.
 
 use crate::{
-    bitcoind::BitcoindError,
+    bitcoind::{feebump::FeebumpReserve, interface::EstimateMode, BitcoindError},
     database::{
+        actions::{db_insert_broadcast_event, db_insert_feebumped_tx},
         interface::{
             db_cancel_transaction, db_emer_transaction, db_unvault_emer_transaction,
             db_unvault_transaction, db_vault_by_deposit, db_vaults, db_vaults_min_status,
@@ -29,15 +30,19 @@ use revault_tx::{
     bitcoin::{
         consensus::encode,
         secp256k1::{self, Signature},
-        util::bip32::ChildNumber,
-        Address, Amount, OutPoint, PublicKey as BitcoinPubKey, SigHashType,
-        Transaction as BitcoinTransaction, Txid,
+        util::{bip32::ChildNumber, psbt::PartiallySignedTransaction as Psbt},
+        Address, Amount, OutPoint, PublicKey as BitcoinPubKey, Script, SigHashType,
+        Transaction as BitcoinTransaction, TxIn, TxOut, Txid,
+    },
+    miniscript::{
+        self,
+        descriptor::{DescriptorPublicKey, DescriptorTrait},
     },
-    miniscript::descriptor::DescriptorPublicKey,
     transactions::{
-        CancelTransaction, EmergencyTransaction, RevaultTransaction, SpendTransaction,
-        UnvaultEmergencyTransaction, UnvaultTransaction,
+        spend_tx_from_deposits, CancelTransaction, EmergencyTransaction, RevaultTransaction,
+        SpendTransaction, UnvaultEmergencyTransaction, UnvaultTransaction,
     },
+    txouts::{DepositTxOut, ExternalTxOut, SpendTxOut},
 };
 
 use std::{
@@ -52,7 +57,7 @@ use std::{
     thread::JoinHandle,
 };
 
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 
 /// A presigned transaction
 #[derive(Debug, Serialize)]
@@ -206,8 +211,8 @@ impl fmt::Display for RpcControlError {
     }
 }
 
-// Ask bitcoind for a wallet transaction
-fn bitcoind_wallet_tx(
+/// Ask bitcoind for a wallet transaction
+pub fn bitcoind_wallet_tx(
     bitcoind_tx: &Sender<BitcoindMessageOut>,
     txid: Txid,
 ) -> Result<Option<WalletTransaction>, ControlError> {
@@ -237,29 +242,78 @@ pub fn bitcoind_broadcast_unvaults(
         let (_, mut unvault_tx) = db_unvault_transaction(db_path, db_vault.id)?;
         unvault_tx.finalize(secp)?;
         let transaction = unvault_tx.into_psbt().extract_tx();
+        let txid = transaction.txid();
 
         bitcoind_tx.send(BitcoindMessageOut::BroadcastTransaction(
             transaction,
             bitrep_tx.clone(),
         ))?;
         bitrep_rx.recv()??;
+        db_insert_broadcast_event(db_path, db_vault.id, &txid)?;
     }
 
     Ok(())
 }
 
-/// Tell bitcoind to broadcast the Cancel transactions of this vault.
+// Tell bitcoind to broadcast this finalized revocation transaction. If a feerate is given, a coin
+// of the fee-bumping wallet is first attached to it for it to reach this feerate.
+// Returns the txid of the broadcasted transaction.
+fn bitcoind_broadcast_revocation(
+    bitcoind_tx: &Sender<BitcoindMessageOut>,
+    db_path: &PathBuf,
+    vault_id: u32,
+    transaction: BitcoinTransaction,
+    presigned_fees: u64,
+    feerate_vb: Option<u64>,
+) -> Result<Txid, ControlError> {
+    let transaction = if let Some(feerate_vb) = feerate_vb {
+        let (bumprep_tx, bumprep_rx) = mpsc::sync_channel(0);
+        let presigned_txid = transaction.txid();
+        bitcoind_tx.send(BitcoindMessageOut::FeebumpTransaction(
+            transaction,
+            presigned_fees,
+            feerate_vb,
+            bumprep_tx,
+        ))?;
+        let bumped_tx = bumprep_rx.recv()??;
+
+        // Remember it before broadcasting it, so that we recognize it once it's onchain.
+        let bumped_txid = bumped_tx.txid();
+        if bumped_txid != presigned_txid {
+            db_insert_feebumped_tx(db_path, &presigned_txid, &bumped_txid)?;
+        }
+        bumped_tx
+    } else {
+        transaction
+    };
+
+    let (bitrep_tx, bitrep_rx) = mpsc::sync_channel(0);
+    let txid = transaction.txid();
+    bitcoind_tx.send(BitcoindMessageOut::BroadcastTransaction(
+        transaction,
+        bitrep_tx,
+    ))?;
+    bitrep_rx.recv()??;
+    db_insert_broadcast_event(db_path, vault_id, &txid)?;
+
+    Ok(txid)
+}
+
+/// Tell bitcoind to broadcast the Cancel transactions of this vault, bumped to `feerate_vb` if
+/// it is set.
+/// Returns the txid of the broadcasted transaction.
 pub fn bitcoind_broadcast_cancel(
     bitcoind_tx: &Sender<BitcoindMessageOut>,
     db_path: &PathBuf,
     secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     vault: DbVault,
-) -> Result<(), ControlError> {
-    let (bitrep_tx, bitrep_rx) = mpsc::sync_channel(0);
+    feerate_vb: Option<u64>,
+) -> Result<Txid, ControlError> {
     // FIXME: this may not hold true in all cases, see https://github.com/revault/revaultd/issues/145
     let (_, mut cancel_tx) =
         db_cancel_transaction(&db_path, vault.id)?.expect("Must be in DB post 'Secured' status");
 
+    let presigned_fees = cancel_tx.fees();
     cancel_tx.finalize(secp)?;
     let transaction = cancel_tx.into_psbt().extract_tx();
     log::debug!(
@@ -267,14 +321,654 @@ pub fn bitcoind_broadcast_cancel(
         transaction.txid()
     );
 
-    bitcoind_tx.send(BitcoindMessageOut::BroadcastTransaction(
+    bitcoind_broadcast_revocation(
+        bitcoind_tx,
+        db_path,
+        vault.id,
         transaction,
+        presigned_fees,
+        feerate_vb,
+    )
+}
+
+/// Tell bitcoind to broadcast the Emergency transaction of this vault, bumped to `feerate_vb`
+/// if it is set. The vault must be at least 'Secured' for the Emergency to be finalizable.
+/// Returns the txid of the broadcasted transaction.
+pub fn bitcoind_broadcast_emergency(
+    bitcoind_tx: &Sender<BitcoindMessageOut>,
+    db_path: &PathBuf,
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    vault: DbVault,
+    feerate_vb: Option<u64>,
+) -> Result<Txid, ControlError> {
+    let (_, mut emer_tx) = db_emer_transaction(&db_path, vault.id)?;
+
+    let presigned_fees = emer_tx.fees();
+    emer_tx.finalize(secp)?;
+    let transaction = emer_tx.into_psbt().extract_tx();
+    log::debug!(
+        "Broadcasting Emergency transaction with id '{}'",
+        transaction.txid()
+    );
+
+    bitcoind_broadcast_revocation(
+        bitcoind_tx,
+        db_path,
+        vault.id,
+        transaction,
+        presigned_fees,
+        feerate_vb,
+    )
+}
+
+/// Tell bitcoind to broadcast the Unvault Emergency transaction of this vault, bumped to
+/// `feerate_vb` if it is set. The vault's Unvault must have been broadcast for it to be valid.
+/// Returns the txid of the broadcasted transaction.
+pub fn bitcoind_broadcast_unvault_emergency(
+    bitcoind_tx: &Sender<BitcoindMessageOut>,
+    db_path: &PathBuf,
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    vault: DbVault,
+    feerate_vb: Option<u64>,
+) -> Result<Txid, ControlError> {
+    let (_, mut unemer_tx) = db_unvault_emer_transaction(&db_path, vault.id)?;
+
+    let presigned_fees = unemer_tx.fees();
+    unemer_tx.finalize(secp)?;
+    let transaction = unemer_tx.into_psbt().extract_tx();
+    log::debug!(
+        "Broadcasting Unvault Emergency transaction with id '{}'",
+        transaction.txid()
+    );
+
+    bitcoind_broadcast_revocation(
+        bitcoind_tx,
+        db_path,
+        vault.id,
+        transaction,
+        presigned_fees,
+        feerate_vb,
+    )
+}
+
+// The size of our transactions without their witness. They all have a single input.
+//
+// All our revocation transactions spend either a deposit or an Unvault output to a single P2WSH
+// output.
+// 4 (version) + 1 (#inputs) + 41 (txin) + 1 (#outputs) + 43 (P2WSH txo) + 4 (locktime)
+const REVOCATION_TX_BASE_SIZE: u64 = 94;
+// An Unvault transaction spends a deposit to two P2WSH outputs.
+// 4 (version) + 1 (#inputs) + 41 (txin) + 1 (#outputs) + 2 * 43 (P2WSH txo) + 4 (locktime)
+const UNVAULT_TX_BASE_SIZE: u64 = 137;
+// A Spend transaction spending a single Unvault to a single P2WPKH destination.
+// 4 (version) + 1 (#inputs) + 41 (txin) + 1 (#outputs) + 31 (P2WPKH txo) + 43 (CPFP txo)
+// + 4 (locktime)
+const SPEND_TX_BASE_SIZE: u64 = 125;
+// A CPFP transaction spends a CPFP output back to a single P2WSH output.
+// 4 (version) + 1 (#inputs) + 41 (txin) + 1 (#outputs) + 43 (P2WSH txo) + 4 (locktime)
+const CPFP_TX_BASE_SIZE: u64 = 94;
+
+// The weight of one of our transactions, given its size without the witness and the weight of
+// the witness of its single input. The segwit marker and flag account for 2 WU.
+fn tx_weight(base_size: u64, satisfaction_weight: u64) -> u64 {
+    base_size * 4 + 2 + satisfaction_weight
+}
+
+// The virtual size corresponding to this weight, rounded up
+fn weight_to_vsize(weight: u64) -> u64 {
+    (weight + 3) / 4
+}
+
+// The maximum weight of the witness of a deposit and of an Unvault input.
+fn satisfaction_weights(revaultd: &RevaultD) -> Result<(u64, u64), ControlError> {
+    let index = ChildNumber::from(0);
+    let deposit_satisfaction_weight = revaultd
+        .derived_deposit_descriptor(index)
+        .inner()
+        .max_satisfaction_weight()
+        .map_err(|e| ControlError::TransactionManagement(e.to_string()))?;
+    let unvault_satisfaction_weight = revaultd
+        .derived_unvault_descriptor(index)
+        .inner()
+        .max_satisfaction_weight()
+        .map_err(|e| ControlError::TransactionManagement(e.to_string()))?;
+
+    Ok((
+        deposit_satisfaction_weight as u64,
+        unvault_satisfaction_weight as u64,
+    ))
+}
+
+/// Get the state of our fee-bumping wallet, including how many revocation transactions it can
+/// bump at once to `feerate_vb`.
+pub fn bitcoind_feebump_reserve(
+    revaultd: &RevaultD,
+    bitcoind_tx: &Sender<BitcoindMessageOut>,
+    feerate_vb: u64,
+) -> Result<FeebumpReserve, ControlError> {
+    let (deposit_satisfaction_weight, unvault_satisfaction_weight) =
+        satisfaction_weights(revaultd)?;
+    let revocation_weight = tx_weight(
+        REVOCATION_TX_BASE_SIZE,
+        std::cmp::max(deposit_satisfaction_weight, unvault_satisfaction_weight),
+    );
+
+    let (bitrep_tx, bitrep_rx) = mpsc::sync_channel(0);
+    bitcoind_tx.send(BitcoindMessageOut::FeebumpReserve(
+        revocation_weight,
+        feerate_vb,
+        bitrep_tx,
+    ))?;
+
+    Ok(bitrep_rx.recv()??)
+}
+
+// We never use an estimate below the minimum relay feerate, nor one so high we'd be burning
+// the funds.
+const MIN_ESTIMATED_FEERATE_VB: u64 = 1;
+const MAX_ESTIMATED_FEERATE_VB: u64 = 1_000;
+
+// The default confirmation target, in blocks
+const DEFAULT_CONF_TARGET: u16 = 6;
+
+/// The feerate to create a transaction at
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum FeerateTarget {
+    /// An absolute feerate, in sat/vbyte
+    Absolute(u64),
+    /// The feerate estimated by bitcoind for the default confirmation target
+    EstimateMode(EstimateMode),
+    /// The feerate estimated by bitcoind for this confirmation target, in blocks
+    ConfTarget {
+        conf_target: u16,
+        #[serde(default)]
+        estimate_mode: EstimateMode,
+    },
+}
+
+impl Default for FeerateTarget {
+    fn default() -> Self {
+        Self::EstimateMode(EstimateMode::default())
+    }
+}
+
+/// Get the feerate in sat/vbyte corresponding to this target, estimating it through bitcoind
+/// if needed.
+pub fn bitcoind_feerate(
+    bitcoind_tx: &Sender<BitcoindMessageOut>,
+    target: FeerateTarget,
+) -> Result<u64, ControlError> {
+    let (conf_target, estimate_mode) = match target {
+        FeerateTarget::Absolute(feerate_vb) => return Ok(feerate_vb),
+        FeerateTarget::EstimateMode(estimate_mode) => (DEFAULT_CONF_TARGET, estimate_mode),
+        FeerateTarget::ConfTarget {
+            conf_target,
+            estimate_mode,
+        } => (conf_target, estimate_mode),
+    };
+
+    let (bitrep_tx, bitrep_rx) = mpsc::sync_channel(0);
+    bitcoind_tx.send(BitcoindMessageOut::EstimateFeerate(
+        conf_target,
+        estimate_mode,
         bitrep_tx,
     ))?;
+    let feerate_vb = bitrep_rx.recv()??.ok_or_else(|| {
+        ControlError::Bitcoind(format!(
+            "No feerate estimate for a confirmation target of '{}' blocks",
+            conf_target
+        ))
+    })?;
+    log::debug!(
+        "Estimated feerate for a confirmation within '{}' blocks ({:?}): '{}' sat/vbyte",
+        conf_target,
+        estimate_mode,
+        feerate_vb
+    );
+
+    Ok(feerate_vb
+        .max(MIN_ESTIMATED_FEERATE_VB)
+        .min(MAX_ESTIMATED_FEERATE_VB))
+}
 
+/// The fees (in sats) to pay for each of our transactions to reach a feerate
+#[derive(Debug, Serialize)]
+pub struct EstimatedFees {
+    /// In sat/vbyte
+    pub feerate: u64,
+    pub unvault: u64,
+    /// Spending a single vault to a single destination
+    pub spend: u64,
+    pub cancel: u64,
+    pub emergency: u64,
+    pub unvault_emergency: u64,
+}
+
+/// Compute the fees our transactions would need to pay at this feerate, assuming the largest
+/// witnesses.
+pub fn estimated_fees(revaultd: &RevaultD, feerate_vb: u64) -> Result<EstimatedFees, ControlError> {
+    let (deposit_satisfaction_weight, unvault_satisfaction_weight) =
+        satisfaction_weights(revaultd)?;
+    let fees = |base_size: u64, satisfaction_weight: u64| {
+        weight_to_vsize(tx_weight(base_size, satisfaction_weight)) * feerate_vb
+    };
+
+    Ok(EstimatedFees {
+        feerate: feerate_vb,
+        unvault: fees(UNVAULT_TX_BASE_SIZE, deposit_satisfaction_weight),
+        spend: fees(SPEND_TX_BASE_SIZE, unvault_satisfaction_weight),
+        cancel: fees(REVOCATION_TX_BASE_SIZE, unvault_satisfaction_weight),
+        emergency: fees(REVOCATION_TX_BASE_SIZE, deposit_satisfaction_weight),
+        unvault_emergency: fees(REVOCATION_TX_BASE_SIZE, unvault_satisfaction_weight),
+    })
+}
+
+// We send the CPFP output back to the CPFP descriptor, it must not be dust for a P2WSH.
+const CPFP_MIN_CHANGE: u64 = 330;
+
+/// Create a child transaction spending the CPFP output of this `parent` (Unvault or Spend)
+/// transaction back to the CPFP descriptor, for the package feerate to reach `feerate_vb`.
+/// The CPFP output must have been derived at one of the `cpfp_indexes`.
+pub fn create_cpfp_psbt(
+    revaultd: &RevaultD,
+    parent: &BitcoinTransaction,
+    parent_fees: u64,
+    cpfp_indexes: &[ChildNumber],
+    feerate_vb: u64,
+) -> Result<Psbt, ControlError> {
+    let (vout, cpfp_txo, cpfp_descriptor) = parent
+        .output
+        .iter()
+        .enumerate()
+        .find_map(|(vout, txo)| {
+            cpfp_indexes
+                .iter()
+                .map(|index| revaultd.derived_cpfp_descriptor(*index))
+                .find(|desc| desc.inner().script_pubkey() == txo.script_pubkey)
+                .map(|desc| (vout, txo.clone(), desc))
+        })
+        .ok_or_else(|| {
+            ControlError::TransactionManagement(format!(
+                "No CPFP output in transaction '{}'",
+                parent.txid()
+            ))
+        })?;
+
+    let satisfaction_weight = cpfp_descriptor
+        .inner()
+        .max_satisfaction_weight()
+        .map_err(|e| ControlError::TransactionManagement(e.to_string()))?;
+    let child_weight = tx_weight(CPFP_TX_BASE_SIZE, satisfaction_weight as u64);
+    let package_vsize = weight_to_vsize(parent.get_weight() as u64 + child_weight);
+    let child_fees = package_vsize
+        .checked_mul(feerate_vb)
+        .and_then(|package_fees| package_fees.checked_sub(parent_fees))
+        .filter(|fees| *fees > 0)
+        .ok_or_else(|| {
+            ControlError::TransactionManagement(format!(
+                "Transaction '{}' already has a feerate of at least '{}'",
+                parent.txid(),
+                feerate_vb
+            ))
+        })?;
+    let change_value = cpfp_txo
+        .value
+        .checked_sub(child_fees)
+        .filter(|value| *value >= CPFP_MIN_CHANGE)
+        .ok_or_else(|| {
+            ControlError::TransactionManagement(format!(
+                "CPFP output value '{}' is not enough to pay '{}' fees",
+                cpfp_txo.value, child_fees
+            ))
+        })?;
+    log::debug!(
+        "CPFP of '{}': package vsize '{}', child fees '{}', change value '{}'",
+        parent.txid(),
+        package_vsize,
+        child_fees,
+        change_value
+    );
+
+    let child = BitcoinTransaction {
+        version: 2,
+        lock_time: revaultd.lock_time,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: parent.txid(),
+                vout: vout as u32,
+            },
+            script_sig: Script::new(),
+            // Signal for RBF, as we may want to bump again
+            sequence: 0xFF_FF_FF_FD,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: change_value,
+            script_pubkey: cpfp_txo.script_pubkey.clone(),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(child).expect("No scriptSig nor witness");
+    psbt.inputs[0].witness_utxo = Some(cpfp_txo);
+    psbt.inputs[0].witness_script = Some(cpfp_descriptor.inner().explicit_script());
+    psbt.inputs[0].sighash_type = Some(SigHashType::All);
+
+    Ok(psbt)
+}
+
+/// Finalize this signed CPFP transaction and tell bitcoind to broadcast it.
+/// Returns the txid of the broadcasted transaction.
+pub fn bitcoind_broadcast_cpfp(
+    bitcoind_tx: &Sender<BitcoindMessageOut>,
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    mut cpfp_psbt: Psbt,
+) -> Result<Txid, ControlError> {
+    let (bitrep_tx, bitrep_rx) = mpsc::sync_channel(0);
+
+    miniscript::psbt::finalize(&mut cpfp_psbt, secp)
+        .map_err(|e| ControlError::TransactionManagement(format!("Finalizing CPFP: {}", e)))?;
+    let transaction = cpfp_psbt.extract_tx();
+    let txid = transaction.txid();
+    log::debug!("Broadcasting CPFP transaction with id '{}'", txid);
+
+    bitcoind_tx.send(BitcoindMessageOut::BroadcastTransaction(
+        transaction,
+        bitrep_tx,
+    ))?;
     bitrep_rx.recv()??;
 
-    Ok(())
+    Ok(txid)
+}
+
+/// How to choose the vaults to spend when the manager doesn't specify them
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSelection {
+    /// The smallest vault that is enough alone, or as few of the largest ones as needed
+    SmallestSufficient,
+    /// The vaults that were deposited first
+    OldestFirst,
+    /// The vaults leaving the smallest change
+    MinimizeChange,
+}
+
+impl Default for CoinSelection {
+    fn default() -> Self {
+        Self::SmallestSufficient
+    }
+}
+
+// We don't try more than this number of selections when minimizing the change, as each try
+// creates a whole transaction.
+const MAX_SELECTION_TRIES: usize = 1_000;
+
+/// Create a Spend transaction from the deposits of these (Active) vaults to these
+/// destinations, adding a change output if not dust.
+/// Returns it along with the value that didn't go to the destinations, nor to the fees at
+/// `feerate_vb` (it is either in the change output or in additional fees).
+pub fn create_spend_tx(
+    revaultd: &RevaultD,
+    vaults: &[DbVault],
+    destinations: &BTreeMap<Address, u64>,
+    feerate_vb: u64,
+) -> Result<(SpendTransaction, u64), ControlError> {
+    // Reconstruct the DepositTxin s from the vaults informations
+    let txins: Vec<_> = vaults
+        .iter()
+        .map(|vault| (vault.deposit_outpoint, vault.amount, vault.derivation_index))
+        .collect();
+    // If we need a change output, use the highest derivation index of the vaults
+    // spent. This avoids leaking a new address needlessly while not introducing
+    // disrepancy between our indexes.
+    let change_index = vaults
+        .iter()
+        .map(|vault| vault.derivation_index)
+        .max()
+        .unwrap_or_else(|| ChildNumber::from(0));
+
+    // Mutable as we *may* add a change output
+    let mut txos: Vec<SpendTxOut> = destinations
+        .iter()
+        .map(|(addr, value)| {
+            let script_pubkey = addr.script_pubkey();
+            SpendTxOut::Destination(ExternalTxOut::new(TxOut {
+                value: *value,
+                script_pubkey,
+            }))
+        })
+        .collect();
+
+    log::debug!(
+        "Creating a Spend transaction with deposit txins: '{:?}' and txos: '{:?}'",
+        &txins,
+        &txos
+    );
+
+    // This adds the CPFP output so create a dummy one to accurately compute the
+    // feerate.
+    let nochange_tx = spend_tx_from_deposits(
+        txins.clone(),
+        txos.clone(),
+        &revaultd.deposit_descriptor,
+        &revaultd.unvault_descriptor,
+        &revaultd.cpfp_descriptor,
+        revaultd.lock_time,
+        /* Deactivate insane feerate check */
+        false,
+        &revaultd.secp_ctx,
+    )
+    .map_err(|e| {
+        ControlError::TransactionManagement(format!(
+            "Error while building spend transaction: {}",
+            e
+        ))
+    })?;
+
+    log::debug!(
+        "Spend tx without change: '{}'",
+        nochange_tx.as_psbt_string()
+    );
+
+    // If the feerate of the transaction would be much lower (< 90/100) than what they
+    // requested for, tell them.
+    let nochange_feerate_vb = nochange_tx
+        .max_feerate()
+        .checked_mul(4)
+        .expect("bug in feerate computation");
+    if nochange_feerate_vb * 10 < feerate_vb * 9 {
+        return Err(ControlError::TransactionManagement(format!(
+            "Required feerate ('{}') is significantly higher than actual feerate ('{}')",
+            feerate_vb, nochange_feerate_vb
+        )));
+    }
+
+    // Add a change output if it would not be dust according to our standard (200k sats
+    // atm, see DUST_LIMIT).
+    // 8 (amount) + 1 (len) + 1 (v0) + 1 (push) + 32 (witscript hash)
+    const P2WSH_TXO_WEIGHT: u64 = 43 * 4;
+    let with_change_weight = nochange_tx
+        .max_weight()
+        .checked_add(P2WSH_TXO_WEIGHT)
+        .expect("weight computation bug");
+    let cur_fees = nochange_tx.fees();
+    let want_fees = with_change_weight
+        // Mental gymnastic: sat/vbyte to sat/wu rounded up
+        .checked_mul(feerate_vb + 3)
+        .map(|vbyte| vbyte.checked_div(4).unwrap());
+    let change_value = want_fees.map(|f| cur_fees.checked_sub(f));
+    log::debug!(
+        "Weight with change: '{}'  --  Fees without change: '{}'  --  Wanted feerate: '{}'  \
+                --  Wanted fees: '{:?}'  --  Change value: '{:?}'",
+        with_change_weight,
+        cur_fees,
+        feerate_vb,
+        want_fees,
+        change_value
+    );
+
+    if let Some(Some(change_value)) = change_value {
+        // The overhead incurred to the value of the CPFP output by the change output
+        // See https://github.com/revault/practical-revault/blob/master/transactions.md#spend_tx
+        let cpfp_overhead = 16 * P2WSH_TXO_WEIGHT;
+        if change_value > revault_tx::transactions::DUST_LIMIT + cpfp_overhead {
+            let change_txo = DepositTxOut::new(
+                // arithmetic checked above
+                change_value - cpfp_overhead,
+                &revaultd
+                    .deposit_descriptor
+                    .derive(change_index, &revaultd.secp_ctx),
+            );
+            log::debug!("Adding a change txo: '{:?}'", change_txo);
+            txos.push(SpendTxOut::Change(change_txo));
+        }
+    }
+
+    // Now we can hand them the resulting transaction (sanity checked for insane fees).
+    let spend_tx = spend_tx_from_deposits(
+        txins,
+        txos,
+        &revaultd.deposit_descriptor,
+        &revaultd.unvault_descriptor,
+        &revaultd.cpfp_descriptor,
+        revaultd.lock_time,
+        true,
+        &revaultd.secp_ctx,
+    )
+    .map_err(|e| {
+        ControlError::TransactionManagement(format!(
+            "Error while building spend transaction: {}",
+            e
+        ))
+    })?;
+
+    Ok((spend_tx, change_value.flatten().unwrap_or(0)))
+}
+
+// Try to create a Spend transaction with each of the selections, in order. Returns the first
+// one that works.
+fn first_sufficient_selection<'a>(
+    revaultd: &RevaultD,
+    selections: impl Iterator<Item = &'a [DbVault]>,
+    destinations: &BTreeMap<Address, u64>,
+    feerate_vb: u64,
+) -> Option<(Vec<DbVault>, SpendTransaction)> {
+    selections.find_map(|selection| {
+        create_spend_tx(revaultd, selection, destinations, feerate_vb)
+            .map_err(|e| log::trace!("Selection '{:?}' is not sufficient: '{}'", selection, e))
+            .ok()
+            .map(|(spend_tx, _)| (selection.to_vec(), spend_tx))
+    })
+}
+
+// Search for the selection of vaults leaving the least change. The search is depth-first,
+// largest vaults first, and does not extend a sufficient selection as adding a vault would
+// only increase the change.
+fn min_change_selection(
+    revaultd: &RevaultD,
+    vaults: &[DbVault],
+    destinations: &BTreeMap<Address, u64>,
+    feerate_vb: u64,
+) -> Option<(Vec<DbVault>, SpendTransaction)> {
+    let mut vaults = vaults.to_vec();
+    vaults.sort_unstable_by(|a, b| b.amount.cmp(&a.amount));
+    // The value of all the vaults after this index, to prune the selections that can't be
+    // sufficient.
+    let mut remaining_values = vec![0; vaults.len() + 1];
+    for i in (0..vaults.len()).rev() {
+        remaining_values[i] = remaining_values[i + 1] + vaults[i].amount.as_sat();
+    }
+    let sent_value: u64 = destinations.values().sum();
+
+    let mut best: Option<(Vec<DbVault>, SpendTransaction, u64)> = None;
+    let mut tries = 0;
+    // The indexes of the vaults in the selection being tried, and its value
+    let mut selection: Vec<usize> = Vec::with_capacity(vaults.len());
+    let mut selection_value = 0;
+    let mut next = 0;
+    loop {
+        let can_extend = next < vaults.len()
+            && selection_value + remaining_values[next] >= sent_value
+            && tries < MAX_SELECTION_TRIES;
+        if can_extend {
+            selection.push(next);
+            selection_value += vaults[next].amount.as_sat();
+            next += 1;
+
+            tries += 1;
+            let selected: Vec<DbVault> = selection.iter().map(|i| vaults[*i]).collect();
+            if let Ok((spend_tx, change)) =
+                create_spend_tx(revaultd, &selected, destinations, feerate_vb)
+            {
+                if best.as_ref().map(|(_, _, c)| change < *c).unwrap_or(true) {
+                    best = Some((selected, spend_tx, change));
+                }
+                if change == 0 {
+                    break;
+                }
+            } else {
+                continue;
+            }
+        }
+
+        // Either we can't extend this selection or it is sufficient already: replace its last
+        // vault with the next one.
+        match selection.pop() {
+            Some(last) => {
+                selection_value -= vaults[last].amount.as_sat();
+                next = last + 1;
+            }
+            None => break,
+        }
+    }
+
+    best.map(|(selected, spend_tx, _)| (selected, spend_tx))
+}
+
+/// Choose among these Active vaults the ones to spend to pay these destinations at this feerate,
+/// and create the Spend transaction.
+pub fn select_vaults_and_create_spend_tx(
+    revaultd: &RevaultD,
+    mut vaults: Vec<DbVault>,
+    destinations: &BTreeMap<Address, u64>,
+    feerate_vb: u64,
+    strategy: CoinSelection,
+) -> Result<(Vec<DbVault>, SpendTransaction), ControlError> {
+    let selection = match strategy {
+        CoinSelection::SmallestSufficient => {
+            vaults.sort_unstable_by_key(|vault| vault.amount);
+            let single =
+                first_sufficient_selection(revaultd, vaults.chunks(1), destinations, feerate_vb);
+            single.or_else(|| {
+                vaults.reverse();
+                first_sufficient_selection(
+                    revaultd,
+                    (2..=vaults.len()).map(|n| &vaults[..n]),
+                    destinations,
+                    feerate_vb,
+                )
+            })
+        }
+        CoinSelection::OldestFirst => {
+            vaults.sort_unstable_by_key(|vault| (vault.blockheight, vault.received_at));
+            first_sufficient_selection(
+                revaultd,
+                (1..=vaults.len()).map(|n| &vaults[..n]),
+                destinations,
+                feerate_vb,
+            )
+        }
+        CoinSelection::MinimizeChange => {
+            min_change_selection(revaultd, &vaults, destinations, feerate_vb)
+        }
+    };
+
+    selection.ok_or_else(|| {
+        ControlError::TransactionManagement(format!(
+            "Not enough Active vaults to pay '{}' sats at a feerate of '{}' sat/vbyte",
+            destinations.values().sum::<u64>(),
+            feerate_vb
+        ))
+    })
 }
 
 /// List the vaults from DB, and filter out the info the RPC wants
@@ -785,4 +1479,75 @@ pub struct RpcUtils {
     pub bitcoind_thread: Arc<RwLock<JoinHandle<()>>>,
     pub sigfetcher_tx: Sender<SigFetcherMessageOut>,
     pub sigfetcher_thread: Arc<RwLock<JoinHandle<()>>>,
+    pub watchtowers_tx: Sender<WatchtowersMessageOut>,
+    pub watchtowers_thread: Arc<RwLock<JoinHandle<()>>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_servers::{MockCoordinator, MockCosigner},
+        test_utils::dummy_revaultd,
+    };
+
+    use std::{fs, str::FromStr, thread, time};
+
+    // The Spend transaction stored by the database tests
+    const SPEND_PSBT: &str = "cHNidP8BAGcCAAAAAciTbKS43sH49TJWX6xJ+MxqWfNQhRl+vkttRZ9sLUkHAAAAAAClAQAAAoAyAAAAAAAAIgAggxumgjPgMj5oHWn8QkvKqPIN0N5nuAbyQ+FEgOJZpjygjAIAAAAAAAAAAAAAAAEBK0ANAwAAAAAAIgAgKb0SdnuqeHAJpRuZTbk3r81qbXpuHrMEmxT9Kph47HQBAwQBAAAAAQWqIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRh2R2qRTtiGxBD5KrMQQU6UGx2zsKMMf6nIisa3apFCDKte9IuDeF0D4GA/JRUNX4xgt+iKxsk1KHZ1IhAzTPPnjrvzPFmi+raNR6sY8WTt1KNusVwp82uWebzWDwIQKl21mZX7WAQhRvdhhwqUAuQfIemg9zkTCCyMQ+Q8CVFVKvAqUBsmgAAQElIQMfu47eLiYeHN6Y3C1Vk0ckgmWifMy5IUhaPHbNELV93axRhwAA";
+
+    #[test]
+    fn spend_cosigning_and_announcement() {
+        let mut revaultd = dummy_revaultd("scratch_datadir_control");
+        let coordinator = MockCoordinator::start(&[revaultd.noise_pubkey()]);
+        let cosigner = MockCosigner::start(
+            &[revaultd.noise_pubkey()],
+            secp256k1::SecretKey::from_slice(&[42; 32]).unwrap(),
+        );
+        revaultd.coordinator_host = coordinator.addr;
+        revaultd.coordinator_noisekey = coordinator.noise_key;
+        revaultd.cosigs = Some(vec![(cosigner.addr, cosigner.noise_key)]);
+
+        // The Cosigning Server signs our Spend, and would sign it again
+        let mut spend_tx = SpendTransaction::from_psbt_str(SPEND_PSBT).unwrap();
+        fetch_cosigner_signatures(&revaultd, &mut spend_tx).unwrap();
+        assert!(spend_tx.inner_tx().inputs[0]
+            .partial_sigs
+            .contains_key(&cosigner.pubkey));
+        let mut same_spend_tx = SpendTransaction::from_psbt_str(SPEND_PSBT).unwrap();
+        fetch_cosigner_signatures(&revaultd, &mut same_spend_tx).unwrap();
+        assert_eq!(spend_tx, same_spend_tx);
+
+        // But not another one spending the same Unvault
+        let mut conflicting_spend_tx = SpendTransaction::from_psbt_str(SPEND_PSBT).unwrap();
+        conflicting_spend_tx
+            .inner_tx_mut()
+            .global
+            .unsigned_tx
+            .output[0]
+            .value -= 1_000;
+        fetch_cosigner_signatures(&revaultd, &mut conflicting_spend_tx).unwrap_err();
+        assert!(conflicting_spend_tx.inner_tx().inputs[0]
+            .partial_sigs
+            .is_empty());
+
+        // We announce it to the Coordinator for the watchtowers to know about it
+        let deposit_outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let spend_txid = spend_tx.txid();
+        announce_spend_transaction(&revaultd, spend_tx, vec![deposit_outpoint]).unwrap();
+        // There is no response to a 'set_spend_tx' message, wait for it to be processed
+        let start = time::Instant::now();
+        while coordinator.spend_tx(&deposit_outpoint).map(|tx| tx.txid()) != Some(spend_txid) {
+            assert!(
+                start.elapsed() < time::Duration::from_secs(10),
+                "The Coordinator never got our Spend transaction"
+            );
+            thread::sleep(time::Duration::from_millis(50));
+        }
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
 }
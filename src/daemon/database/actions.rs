@@ -1,11 +1,14 @@
 use crate::{
     database::{
         interface::*,
-        schema::{DbTransaction, RevaultTx, TransactionType, SCHEMA},
+        migrations::migrate_db,
+        schema::{DbTransaction, EventKind, RevaultTx, TransactionType, UnvaultDecision, SCHEMA},
         DatabaseError, DB_VERSION,
     },
     revaultd::{BlockchainTip, RevaultD, VaultStatus},
+    vault_state::{Transition, VaultState},
 };
+use revault_net::noise::PublicKey as NoisePubKey;
 use revault_tx::{
     bitcoin::{
         secp256k1, util::bip32::ChildNumber, Amount, OutPoint, PublicKey as BitcoinPubKey, Txid,
@@ -24,10 +27,10 @@ use std::{
     fs,
     path::PathBuf,
     str::FromStr,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use rusqlite::params;
+use rusqlite::{backup::Backup, params, Connection};
 
 // Sqlite supports up to i64, thus rusqlite prevents us from inserting u64's.
 // We use this to panic rather than inserting a truncated integer into the database (as we'd have
@@ -72,10 +75,14 @@ fn create_db_file(db_path: &PathBuf) -> Result<(), std::io::Error> {
 // information
 fn create_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
     let db_path = revaultd.db_file();
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|dur| timestamp_to_u32(dur.as_secs()))
-        .map_err(|e| DatabaseError(format!("Computing time since epoch: {}", e.to_string())))?;
+    // When recovering, bitcoind will rescan the block chain from the wallet creation time
+    let timestamp = match revaultd.recovery_timestamp {
+        Some(timestamp) => timestamp,
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|dur| timestamp_to_u32(dur.as_secs()))
+            .map_err(|e| DatabaseError(format!("Computing time since epoch: {}", e.to_string())))?,
+    };
     let deposit_descriptor = revaultd.deposit_descriptor.to_string();
     let unvault_descriptor = revaultd.unvault_descriptor.to_string();
     let our_man_xpub_str = revaultd.our_man_xpub.as_ref().map(|xpub| xpub.to_string());
@@ -126,15 +133,22 @@ fn create_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
 fn check_db(revaultd: &RevaultD) -> Result<(), DatabaseError> {
     let db_path = revaultd.db_file();
 
-    // Check if their database is not from the future.
-    // We'll eventually do migration here if version < VERSION, but be strict until then.
+    // Check if their database is not from the future, and upgrade it if it's from the past.
     let version = db_version(&db_path)?;
-    if version != DB_VERSION {
+    if version > DB_VERSION {
         return Err(DatabaseError(format!(
-            "Unexpected database version: got '{}', expected '{}'",
+            "Unexpected database version: got '{}', expected at most '{}'",
             version, DB_VERSION
         )));
     }
+    if version < DB_VERSION {
+        log::info!(
+            "Upgrading database from version '{}' to version '{}'",
+            version,
+            DB_VERSION
+        );
+        migrate_db(&db_path, version)?;
+    }
 
     let db_net = db_network(&db_path)?;
     if db_net != revaultd.bitcoind_config.network {
@@ -215,6 +229,187 @@ pub fn setup_db(revaultd: &mut RevaultD) -> Result<(), DatabaseError> {
     Ok(())
 }
 
+// Copy the database at `src_path` to a new file at `dst_path` using SQLite's online backup API,
+// in a single step so the copy is consistent even if the database is being written to.
+fn copy_db(src_path: &PathBuf, dst_path: &PathBuf) -> Result<(), DatabaseError> {
+    create_db_file(dst_path).map_err(|e| {
+        DatabaseError(format!(
+            "Creating backup file at '{:?}': {}",
+            dst_path,
+            e.to_string()
+        ))
+    })?;
+
+    let copy = || -> Result<(), rusqlite::Error> {
+        let src = Connection::open(src_path)?;
+        let mut dst = Connection::open(dst_path)?;
+        Backup::new(&src, &mut dst)?.run_to_completion(-1, Duration::from_millis(250), None)
+    };
+    copy().map_err(|e| {
+        // Don't leave a half-written database behind
+        fs::remove_file(dst_path).unwrap_or_else(|_| ());
+        DatabaseError(format!("Copying database: {}", e.to_string()))
+    })
+}
+
+/// Write a snapshot of our database to a new file at `backup_path`, while we keep running.
+pub fn backup_db(revaultd: &RevaultD, backup_path: &PathBuf) -> Result<(), DatabaseError> {
+    copy_db(&revaultd.db_file(), backup_path)
+}
+
+/// Install the database backup at `backup_path` in place of our missing database, after
+/// checking it was made by this very wallet. It is upgraded at startup if it's from a previous
+/// version.
+pub fn restore_db(revaultd: &RevaultD, backup_path: &PathBuf) -> Result<(), DatabaseError> {
+    let db_path = revaultd.db_file();
+    if db_path.exists() {
+        return Err(DatabaseError(format!(
+            "There is already a database at '{:?}'",
+            db_path
+        )));
+    }
+    // Don't let SQLite create it for us
+    if !backup_path.is_file() {
+        return Err(DatabaseError(format!(
+            "No database backup at '{:?}'",
+            backup_path
+        )));
+    }
+
+    let version = db_version(backup_path)?;
+    if version > DB_VERSION {
+        return Err(DatabaseError(format!(
+            "Unexpected backup version: got '{}', expected at most '{}'",
+            version, DB_VERSION
+        )));
+    }
+
+    let network = db_network(backup_path)?;
+    if network != revaultd.bitcoind_config.network {
+        return Err(DatabaseError(format!(
+            "Invalid network. Backup is on '{}' but config says '{}'.",
+            network, revaultd.bitcoind_config.network
+        )));
+    }
+
+    let wallet = db_wallet(backup_path)?;
+    if wallet.deposit_descriptor != revaultd.deposit_descriptor.to_string()
+        || wallet.unvault_descriptor != revaultd.unvault_descriptor.to_string()
+    {
+        return Err(DatabaseError(
+            "The backup is for a wallet with different descriptors".to_string(),
+        ));
+    }
+    if wallet.our_man_xpub != revaultd.our_man_xpub || wallet.our_stk_xpub != revaultd.our_stk_xpub
+    {
+        return Err(DatabaseError(
+            "The backup is for a participant with different keys".to_string(),
+        ));
+    }
+
+    copy_db(backup_path, &db_path)
+}
+
+// Append to the history an event about this vault, along with its current status.
+fn dbtx_insert_event(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    kind: EventKind,
+    txid: Option<&Txid>,
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT INTO events (vault_id, kind, status, txid, timestamp, blockheight) \
+             SELECT id, (?2), status, (?3), strftime('%s','now'), (SELECT blockheight FROM tip) \
+             FROM vaults WHERE id = (?1)",
+            params![vault_id, kind as u32, txid.map(|txid| txid.to_vec())],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting event: {}", e.to_string())))?;
+
+    Ok(())
+}
+
+// Append to the history the current status of this vault, unless it's the same as the last
+// one recorded.
+fn dbtx_insert_transition(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    kind: EventKind,
+) -> Result<(), DatabaseError> {
+    db_tx
+        .execute(
+            "INSERT INTO events (vault_id, kind, status, txid, timestamp, blockheight) \
+             SELECT id, (?2), status, NULL, strftime('%s','now'), (SELECT blockheight FROM tip) \
+             FROM vaults WHERE id = (?1) AND status IS NOT ( \
+                SELECT status FROM events WHERE vault_id = (?1) AND kind IN ((?3), (?4)) \
+                ORDER BY id DESC LIMIT 1 \
+             )",
+            params![
+                vault_id,
+                kind as u32,
+                EventKind::StatusChange as u32,
+                EventKind::Rewind as u32
+            ],
+        )
+        .map_err(|e| DatabaseError(format!("Inserting event: {}", e.to_string())))?;
+
+    Ok(())
+}
+
+// The current status of this vault
+fn dbtx_vault_status(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+) -> Result<VaultStatus, DatabaseError> {
+    let status: u32 = db_tx
+        .prepare("SELECT status FROM vaults WHERE id = (?1)")?
+        .query(params![vault_id])?
+        .next()?
+        .ok_or_else(|| DatabaseError(format!("Vault with id '{}' not found in db", vault_id)))?
+        .get(0)?;
+
+    status
+        .try_into()
+        .map_err(|_| DatabaseError(format!("Invalid status '{}' in db", status)))
+}
+
+// Change the status of this vault and record it in the history, unless the transition is illegal.
+fn dbtx_transition(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+    status: VaultStatus,
+    transition: Transition,
+) -> Result<(), DatabaseError> {
+    VaultState::new(dbtx_vault_status(db_tx, vault_id)?)
+        .transition(status, transition)
+        .map_err(|e| DatabaseError(format!("Updating vault '{}': {}", vault_id, e)))?;
+
+    db_tx
+        .execute(
+            "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') WHERE id = (?2)",
+            params![status as u32, vault_id],
+        )
+        .map_err(|e| DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string())))?;
+    let kind = match transition {
+        Transition::Progress => EventKind::StatusChange,
+        Transition::Rewind => EventKind::Rewind,
+    };
+    dbtx_insert_transition(db_tx, vault_id, kind)?;
+
+    Ok(())
+}
+
+/// Record in the history that we broadcast this transaction of this vault
+pub fn db_insert_broadcast_event(
+    db_path: &PathBuf,
+    vault_id: u32,
+    txid: &Txid,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |db_tx| {
+        dbtx_insert_event(db_tx, vault_id, EventKind::Broadcast, Some(txid))
+    })
+}
+
 pub fn db_update_tip_dbtx(
     db_tx: &rusqlite::Transaction,
     tip: &BlockchainTip,
@@ -280,6 +475,7 @@ pub fn db_insert_new_unconfirmed_vault(
             ],
         )
         .map_err(|e| DatabaseError(format!("Inserting vault: {}", e.to_string())))?;
+        dbtx_insert_transition(tx, tx.last_insert_rowid() as u32, EventKind::StatusChange)?;
 
         Ok(())
     })
@@ -330,12 +526,13 @@ pub fn db_confirm_deposit(
         .id;
 
     db_exec(db_path, |db_tx| {
+        dbtx_transition(db_tx, vault_id, VaultStatus::Funded, Transition::Progress)?;
         db_tx
             .execute(
-                "UPDATE vaults SET status = (?1), blockheight = (?2), updated_at = strftime('%s','now') WHERE id = (?3)",
-                params![VaultStatus::Funded as u32, blockheight, vault_id,],
+                "UPDATE vaults SET blockheight = (?1) WHERE id = (?2)",
+                params![blockheight, vault_id],
             )
-            .map_err(|e| DatabaseError(format!("Updating vault to 'funded': {}", e.to_string())))?;
+            .map_err(|e| DatabaseError(format!("Updating vault height: {}", e.to_string())))?;
 
         match (emer_tx, unemer_tx) {
             (Some(emer_tx), Some(unemer_tx)) => {
@@ -361,6 +558,17 @@ pub fn db_unconfirm_deposit_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
 ) -> Result<(), DatabaseError> {
+    dbtx_transition(
+        db_tx,
+        vault_id,
+        VaultStatus::Unconfirmed,
+        Transition::Rewind,
+    )?;
+    db_tx.execute(
+        "UPDATE vaults SET blockheight = 0 WHERE id = (?1)",
+        params![vault_id],
+    )?;
+
     // FIXME: don't delete everything. This is unnecessary and confusing.
 
     // This is going to cascade and DELETE the spend_inputs.
@@ -372,27 +580,20 @@ pub fn db_unconfirm_deposit_dbtx(
          )",
         params![vault_id],
     )?;
+    // The revocation transactions the watchtowers acked are gone, they'll need the new ones.
     db_tx.execute(
-        "DELETE FROM presigned_transactions WHERE vault_id = (?1)",
+        "DELETE FROM watchtower_acks WHERE vault_id = (?1)",
         params![vault_id],
     )?;
     db_tx.execute(
-        "UPDATE vaults SET status = (?1), blockheight = (?2), updated_at = strftime('%s','now') \
-         WHERE id = (?3)",
-        params![VaultStatus::Unconfirmed as u32, 0, vault_id],
+        "DELETE FROM feebumped_transactions WHERE presigned_id IN ( \
+            SELECT id FROM presigned_transactions WHERE vault_id = (?1) \
+         )",
+        params![vault_id],
     )?;
-
-    Ok(())
-}
-
-fn dbtx_downgrade(
-    db_tx: &rusqlite::Transaction,
-    vault_id: u32,
-    status: VaultStatus,
-) -> Result<(), DatabaseError> {
     db_tx.execute(
-        "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') WHERE id = (?2)",
-        params![status as u32, vault_id],
+        "DELETE FROM presigned_transactions WHERE vault_id = (?1)",
+        params![vault_id],
     )?;
 
     Ok(())
@@ -403,7 +604,7 @@ pub fn db_unconfirm_unvault_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::Unvaulting)
+    dbtx_transition(db_tx, vault_id, VaultStatus::Unvaulting, Transition::Rewind)
 }
 
 /// Downgrade a vault from 'spent' to 'spending'
@@ -411,7 +612,7 @@ pub fn db_unconfirm_spend_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::Spending)
+    dbtx_transition(db_tx, vault_id, VaultStatus::Spending, Transition::Rewind)
 }
 
 /// Downgrade a vault from 'canceled' to 'canceling'
@@ -419,7 +620,46 @@ pub fn db_unconfirm_cancel_dbtx(
     db_tx: &rusqlite::Transaction,
     vault_id: u32,
 ) -> Result<(), DatabaseError> {
-    dbtx_downgrade(db_tx, vault_id, VaultStatus::Canceling)
+    dbtx_transition(db_tx, vault_id, VaultStatus::Canceling, Transition::Rewind)
+}
+
+/// Downgrade a vault from 'emergencyvaulted' to 'emergencyvaulting'
+pub fn db_unconfirm_emer_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+) -> Result<(), DatabaseError> {
+    dbtx_transition(
+        db_tx,
+        vault_id,
+        VaultStatus::EmergencyVaulting,
+        Transition::Rewind,
+    )
+}
+
+/// Downgrade a vault from 'unvaultemergencyvaulted' to 'unvaultemergencyvaulting'
+pub fn db_unconfirm_unemer_dbtx(
+    db_tx: &rusqlite::Transaction,
+    vault_id: u32,
+) -> Result<(), DatabaseError> {
+    dbtx_transition(
+        db_tx,
+        vault_id,
+        VaultStatus::UnvaultEmergencyVaulting,
+        Transition::Rewind,
+    )
+}
+
+// Get the id of the vault this presigned transaction belongs to
+fn dbtx_vault_id_by_txid(
+    db_tx: &rusqlite::Transaction,
+    txid: &Txid,
+) -> Result<Option<u32>, DatabaseError> {
+    Ok(db_tx
+        .prepare("SELECT vault_id FROM presigned_transactions WHERE txid = (?1)")?
+        .query(params![txid.to_vec()])?
+        .next()?
+        .map(|row| row.get(0))
+        .transpose()?)
 }
 
 fn db_status_from_unvault_txid(
@@ -428,12 +668,9 @@ fn db_status_from_unvault_txid(
     status: VaultStatus,
 ) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') \
-             WHERE vaults.id IN (SELECT vault_id FROM presigned_transactions WHERE txid = (?2))",
-            params![status as u32, unvault_txid.to_vec(),],
-        )
-        .map_err(|e| DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string())))?;
+        if let Some(vault_id) = dbtx_vault_id_by_txid(tx, unvault_txid)? {
+            dbtx_transition(tx, vault_id, status, Transition::Progress)?;
+        }
 
         Ok(())
     })
@@ -453,6 +690,11 @@ pub fn db_cancel_unvault(db_path: &PathBuf, unvault_txid: &Txid) -> Result<(), D
     db_status_from_unvault_txid(db_path, unvault_txid, VaultStatus::Canceling)
 }
 
+/// Mark a vault as being in the 'unvaultemergencyvaulting' state, out of the Unvault txid
+pub fn db_emer_unvault(db_path: &PathBuf, unvault_txid: &Txid) -> Result<(), DatabaseError> {
+    db_status_from_unvault_txid(db_path, unvault_txid, VaultStatus::UnvaultEmergencyVaulting)
+}
+
 /// Mark a vault as being in the 'spending' state, out of the Unvault txid
 pub fn db_spend_unvault(
     db_path: &PathBuf,
@@ -460,12 +702,14 @@ pub fn db_spend_unvault(
     spend_txid: &Txid,
 ) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now'), spend_txid = (?2) \
-             WHERE vaults.id IN (SELECT vault_id FROM presigned_transactions WHERE txid = (?3))",
-            params![VaultStatus::Spending as u32, spend_txid.to_vec(), unvault_txid.to_vec(),],
-        )
-        .map_err(|e| DatabaseError(format!("Updating vault to 'spending': {}", e.to_string())))?;
+        if let Some(vault_id) = dbtx_vault_id_by_txid(tx, unvault_txid)? {
+            dbtx_transition(tx, vault_id, VaultStatus::Spending, Transition::Progress)?;
+            tx.execute(
+                "UPDATE vaults SET spend_txid = (?1) WHERE id = (?2)",
+                params![spend_txid.to_vec(), vault_id],
+            )
+            .map_err(|e| DatabaseError(format!("Updating vault Spend: {}", e.to_string())))?;
+        }
 
         Ok(())
     })
@@ -477,16 +721,10 @@ fn db_mark_vault_as(
     status: VaultStatus,
 ) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
-        tx.execute(
-            "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') \
-             WHERE vaults.id = (?2)",
-            params![status as u32, vault_id,],
-        )
-        .map_err(|e| DatabaseError(format!("Updating vault to '{}': {}", status, e.to_string())))?;
-
-        Ok(())
+        dbtx_transition(tx, vault_id, status, Transition::Progress)
     })
 }
+
 pub fn db_mark_spent_unvault(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
     db_mark_vault_as(&db_path, vault_id, VaultStatus::Spent)
 }
@@ -495,37 +733,201 @@ pub fn db_mark_canceled_unvault(db_path: &PathBuf, vault_id: u32) -> Result<(),
     db_mark_vault_as(&db_path, vault_id, VaultStatus::Canceled)
 }
 
-/// Mark that we actually signed this vault's revocation txs, and stored the signatures for it.
-pub fn db_mark_securing_vault(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+/// Record that this watchtower acknowledged the revocation transactions of this vault
+pub fn db_mark_watchtower_ack(
+    db_path: &PathBuf,
+    vault_id: u32,
+    watchtower_key: &NoisePubKey,
+) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
         tx.execute(
-            "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') \
-             WHERE vaults.id = (?2) AND vaults.status = (?3)",
-            params![
-                VaultStatus::Securing as u32,
-                vault_id,
-                VaultStatus::Funded as u32
-            ],
+            "INSERT OR IGNORE INTO watchtower_acks (vault_id, watchtower_key, acked_at) \
+             VALUES (?1, ?2, strftime('%s','now'))",
+            params![vault_id, watchtower_key.0.to_vec()],
         )
-        .map_err(|e| DatabaseError(format!("Updating vault to 'securing': {}", e.to_string())))?;
+        .map_err(|e| DatabaseError(format!("Inserting watchtower ack: {}", e.to_string())))?;
 
         Ok(())
     })
 }
 
-/// Mark that we actually signed this vault's Unvault tx, and stored the signature for it.
-pub fn db_mark_activating_vault(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+/// Log a decision about the Unvault of this vault. The txid is the one of the announced Spend
+/// if it was allowed, or the one of the Cancel if we revaulted.
+pub fn db_insert_unvault_audit(
+    db_path: &PathBuf,
+    vault_id: u32,
+    decision: UnvaultDecision,
+    txid: Option<&Txid>,
+    reason: &str,
+) -> Result<(), DatabaseError> {
     db_exec(db_path, |tx| {
         tx.execute(
-            "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') \
-             WHERE vaults.id = (?2) AND vaults.status = (?3)",
+            "INSERT INTO unvault_audits (vault_id, decision, txid, reason, timestamp) \
+             VALUES (?1, ?2, ?3, ?4, strftime('%s','now'))",
             params![
-                VaultStatus::Activating as u32,
                 vault_id,
-                VaultStatus::Secured as u32
+                decision as u32,
+                txid.map(|txid| txid.to_vec()),
+                reason
             ],
         )
-        .map_err(|e| DatabaseError(format!("Updating vault to 'securing': {}", e.to_string())))?;
+        .map_err(|e| DatabaseError(format!("Inserting unvault audit: {}", e.to_string())))?;
+
+        Ok(())
+    })
+}
+
+/// Log a failed attempt at canceling the Unvault of this vault. We retry at each poll, so we keep
+/// a single such entry per vault and only update its reason and time on later failures.
+pub fn db_cancel_failed_unvault_audit(
+    db_path: &PathBuf,
+    vault_id: u32,
+    reason: &str,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        let updated = tx
+            .execute(
+                "UPDATE unvault_audits SET reason = (?1), timestamp = strftime('%s','now') \
+                 WHERE vault_id = (?2) AND decision = (?3)",
+                params![reason, vault_id, UnvaultDecision::CancelFailed as u32],
+            )
+            .map_err(|e| DatabaseError(format!("Updating unvault audit: {}", e.to_string())))?;
+        if updated == 0 {
+            tx.execute(
+                "INSERT INTO unvault_audits (vault_id, decision, txid, reason, timestamp) \
+                 VALUES (?1, ?2, NULL, ?3, strftime('%s','now'))",
+                params![vault_id, UnvaultDecision::CancelFailed as u32, reason],
+            )
+            .map_err(|e| DatabaseError(format!("Inserting unvault audit: {}", e.to_string())))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Store the txid of a revocation transaction we are about to broadcast with a fee-bumping
+/// input attached.
+pub fn db_insert_feebumped_tx(
+    db_path: &PathBuf,
+    presigned_txid: &Txid,
+    txid: &Txid,
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        tx.execute(
+            "INSERT OR IGNORE INTO feebumped_transactions (presigned_id, txid) \
+             SELECT id, (?2) FROM presigned_transactions WHERE txid = (?1)",
+            params![presigned_txid.to_vec(), txid.to_vec()],
+        )
+        .map_err(|e| {
+            DatabaseError(format!(
+                "Inserting fee-bumped transaction: {}",
+                e.to_string()
+            ))
+        })?;
+
+        Ok(())
+    })
+}
+
+/// Store the transactions spending these outpoints, along with the height of the block they
+/// were found in.
+pub fn db_insert_spenders(
+    db_path: &PathBuf,
+    spenders: &[(OutPoint, Txid, u32)],
+) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        for (outpoint, spender_txid, blockheight) in spenders {
+            tx.execute(
+                "INSERT OR REPLACE INTO spenders (txid, vout, spender_txid, blockheight) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    outpoint.txid.to_vec(),
+                    outpoint.vout,
+                    spender_txid.to_vec(),
+                    blockheight
+                ],
+            )
+            .map_err(|e| DatabaseError(format!("Inserting spender: {}", e.to_string())))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Forget about all the spenders we indexed, for instance because the blocks they were found in
+/// may have been reorged out.
+pub fn db_clear_spenders_dbtx(db_tx: &rusqlite::Transaction) -> Result<(), DatabaseError> {
+    db_tx
+        .execute("DELETE FROM spenders", params![])
+        .map_err(|e| DatabaseError(format!("Clearing spenders: {}", e.to_string())))
+        .map(|_| ())
+}
+
+/// Mark a vault as being in the 'emergencyvaulting' state, its deposit being spent by the
+/// Emergency transaction.
+pub fn db_emer_deposit(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+    db_mark_vault_as(&db_path, vault_id, VaultStatus::EmergencyVaulting)
+}
+
+pub fn db_mark_emergencied_vault(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+    db_mark_vault_as(&db_path, vault_id, VaultStatus::EmergencyVaulted)
+}
+
+pub fn db_mark_emergencied_unvault(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+    db_mark_vault_as(&db_path, vault_id, VaultStatus::UnvaultEmergencyVaulted)
+}
+
+/// Mark a vault whose Emergency transaction was evicted from the mempool as being back to its
+/// pre-emergency state, as recorded in its history.
+pub fn db_unemer_deposit(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        let status: Option<u32> = tx
+            .prepare(
+                "SELECT status FROM events WHERE vault_id = (?1) AND kind IN ((?2), (?3)) \
+                 AND id < ( \
+                    SELECT MAX(id) FROM events WHERE vault_id = (?1) AND kind = (?2) \
+                    AND status = (?4) \
+                 ) ORDER BY id DESC LIMIT 1",
+            )?
+            .query(params![
+                vault_id,
+                EventKind::StatusChange as u32,
+                EventKind::Rewind as u32,
+                VaultStatus::EmergencyVaulting as u32
+            ])?
+            .next()?
+            .map(|row| row.get(0))
+            .transpose()?;
+        // The history of a vault that was already emergencying when we started recording it
+        // doesn't go that far back. It must at least have been secured to be emergencied by us.
+        let status = match status {
+            Some(status) => status
+                .try_into()
+                .map_err(|_| DatabaseError(format!("Invalid status '{}' in db", status)))?,
+            None => VaultStatus::Secured,
+        };
+
+        dbtx_transition(tx, vault_id, status, Transition::Progress)
+    })
+}
+
+/// Mark that we actually signed this vault's revocation txs, and stored the signatures for it.
+pub fn db_mark_securing_vault(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        if dbtx_vault_status(tx, vault_id)? == VaultStatus::Funded {
+            dbtx_transition(tx, vault_id, VaultStatus::Securing, Transition::Progress)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Mark that we actually signed this vault's Unvault tx, and stored the signature for it.
+pub fn db_mark_activating_vault(db_path: &PathBuf, vault_id: u32) -> Result<(), DatabaseError> {
+    db_exec(db_path, |tx| {
+        if dbtx_vault_status(tx, vault_id)? == VaultStatus::Secured {
+            dbtx_transition(tx, vault_id, VaultStatus::Activating, Transition::Progress)?;
+        }
 
         Ok(())
     })
@@ -535,11 +937,11 @@ fn revault_tx_merge_sigs(
     tx: &mut impl RevaultTransaction,
     sigs: BTreeMap<BitcoinPubKey, Vec<u8>>,
     secp_ctx: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
-) -> Result<(bool, Vec<u8>), DatabaseError> {
+) -> Result<(bool, Vec<u8>, Txid), DatabaseError> {
     tx.inner_tx_mut().inputs[0].partial_sigs.extend(sigs);
     let fully_signed = tx.is_finalizable(secp_ctx);
     let raw_psbt = tx.as_psbt_serialized();
-    Ok((fully_signed, raw_psbt))
+    Ok((fully_signed, raw_psbt, tx.txid()))
 }
 
 /// Update the presigned transaction in-db. If the transaction is valid and no more revocation
@@ -568,7 +970,7 @@ pub fn db_update_presigned_tx(
             })?
             .try_into()?;
         // Now we are safe merging the signatures on what is the latest version of the PSBT
-        let (fully_signed, raw_psbt) = match presigned_tx.psbt {
+        let (fully_signed, raw_psbt, txid) = match presigned_tx.psbt {
             RevaultTx::Cancel(mut tx) => revault_tx_merge_sigs(&mut tx, sigs, secp_ctx)?,
             RevaultTx::Emergency(mut tx) => revault_tx_merge_sigs(&mut tx, sigs, secp_ctx)?,
 
@@ -583,10 +985,16 @@ pub fn db_update_presigned_tx(
             "UPDATE presigned_transactions SET psbt = (?1), fullysigned = (?2) WHERE id = (?3)",
             params![raw_psbt, fully_signed, tx_db_id],
         )?;
+        dbtx_insert_event(db_tx, vault_id, EventKind::Signature, Some(&txid))?;
 
+        // The signatures may still come in once the vault moved on (for instance if it was
+        // unvaulted by the other participants), only upgrade it during the signing stage.
         if fully_signed {
             // Are there some remaining unsigned revocation txs?
-            if db_tx
+            if matches!(
+                dbtx_vault_status(db_tx, vault_id)?,
+                VaultStatus::Funded | VaultStatus::Securing
+            ) && db_tx
                 .prepare(
                     "SELECT * FROM presigned_transactions WHERE fullysigned = 0 AND type != (?1) AND vault_id = (?2)",
                 )?
@@ -596,26 +1004,17 @@ pub fn db_update_presigned_tx(
                 .is_none()
             {
                 // Nope. Mark the vault as 'secured'
-                db_tx
-                    .execute(
-                        "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') WHERE id = (?2) ",
-                        params![VaultStatus::Secured as u32, vault_id],
-                    )
-                    .map_err(|e| {
-                        DatabaseError(format!("Updating vault to 'secured': {}", e.to_string()))
-                    })?;
+                dbtx_transition(db_tx, vault_id, VaultStatus::Secured, Transition::Progress)?;
             }
 
             // Was it the unvault that was fully signed ? If so, mark the vault as active.
-            if is_unvault {
-                db_tx
-                    .execute(
-                        "UPDATE vaults SET status = (?1), updated_at = strftime('%s','now') WHERE id = (?2) ",
-                        params![VaultStatus::Active as u32, vault_id],
-                    )
-                    .map_err(|e| {
-                        DatabaseError(format!("Updating vault to 'active': {}", e.to_string()))
-                    })?;
+            if is_unvault
+                && matches!(
+                    dbtx_vault_status(db_tx, vault_id)?,
+                    VaultStatus::Secured | VaultStatus::Activating
+                )
+            {
+                dbtx_transition(db_tx, vault_id, VaultStatus::Active, Transition::Progress)?;
             }
         }
 
@@ -706,6 +1105,20 @@ pub fn db_mark_broadcasted_spend(
             "UPDATE spend_transactions SET broadcasted = 1 WHERE txid = (?1)",
             params![spend_txid.to_vec()],
         )?;
+
+        let vault_ids = db_tx
+            .prepare(
+                "SELECT ptx.vault_id FROM spend_transactions as stx \
+                 INNER JOIN spend_inputs as sin ON stx.id = sin.spend_id \
+                 INNER JOIN presigned_transactions as ptx ON ptx.id = sin.unvault_id \
+                 WHERE stx.txid = (?1)",
+            )?
+            .query_map(params![spend_txid.to_vec()], |row| row.get::<_, u32>(0))?
+            .collect::<rusqlite::Result<Vec<u32>>>()?;
+        for vault_id in vault_ids {
+            dbtx_insert_event(db_tx, vault_id, EventKind::Broadcast, Some(spend_txid))?;
+        }
+
         Ok(())
     })
 }
@@ -731,7 +1144,7 @@ pub fn db_mark_rebroadcastable_spend(
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::revaultd::RevaultD;
+    use crate::{database::schema::DbVault, revaultd::RevaultD};
     use common::config::Config;
     use revault_tx::{
         bitcoin::{Network, OutPoint, PublicKey},
@@ -772,6 +1185,11 @@ mod test {
         RevaultD::from_config(config).expect("Creating state from config")
     }
 
+    const EMER_PSBT: &str = "cHNidP8BAF4CAAAAAVqQwvZ+XLjEW+P90WnqdbVWkC1riPNhF8j9Ca4dM0RiAAAAAAD9////AfhgAwAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK4iUAwAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQBAwSBAAAAAQVHUiED35umh5GhiToV6GS7lTokWfq/Rvy+rRI9XMQuf+foOoEhA9GtXpHhUvxcj9DJWbaRvz59CNsMwH2NEvmRa8gc2WRkUq4AAA==";
+    const CANCEL_PSBT: &str = "cHNidP8BAF4CAAAAARoHs0elD2sCfWV4+b7PH3aRA+BkRVNf3m/P+Epjx2fNAAAAAAD9////AdLKAgAAAAAAIgAgB6abzQJ4vo5CO9XW3r3JnNumTwlpQbZm9FVICsLHPYQAAAAAAAEBK0ANAwAAAAAAIgAglEs6phQpv+twnAQSdjDvAEic65OtUIijeePBzAAqr50BAwSBAAAAAQWrIQO4lrAuffeRLuEEuwp2hAMZIPmqaHMTUySM3OwdA2hIW6xRh2R2qRTflccImFIy5NdTqwPuPZFB7g1pvYisa3apFOQxXoLeQv/aDFfav/l6YnYRKt+1iKxsk1KHZ1IhA32Q1DEqQ/kUP2MvQYFW46RCexZ5aYk17Arhp01th+37IQNrXQtfIXQdrv+RyyHLilJsb4ujlUMddG9X2jYkeXiWoFKvA3nxALJoAAEBR1IhA9+bpoeRoYk6Fehku5U6JFn6v0b8vq0SPVzELn/n6DqBIQPRrV6R4VL8XI/QyVm2kb8+fQjbDMB9jRL5kWvIHNlkZFKuAA==";
+    const UNEMER_PSBT: &str = "cHNidP8BAF4CAAAAAZHNg0DZSHTBSpVaGwH2apdYBRu88ZeeB/XmrijJpvH5AAAAAAD9////AdLKAgAAAAAAIgAg8Wcu+wsgQXcO9MAiWSMtqsVSQkptpfTXJ51MFSdhJAoAAAAAAAEBK0ANAwAAAAAAIgAgtSqMFDOQ2FkdNrt/yUTzVjikth3tOm+um6yLFzLTilcBAwSBAAAAAQWrIQJF6Amv78N3ctJ3+oSlIasXN3/N8H/bu2si9Vu3QNBRuKxRh2R2qRS77fZRBsFKSf1uP2HBT3uhL1oRloisa3apFIPfFe62NUR/RApmlyj0VsJJdJ4CiKxsk1KHZ1IhA5scAvk3lvCVQmoWDTHhcd8utuA6Swf2PolVbdB7yVwnIQIXS76HRC/hWucQkpC43HriwIukm1se8QRc9nIlODCN81KvA37BALJoAAA=";
+    const UNVAULT_PSBT: &str = "cHNidP8BAIkCAAAAAcRWqIPG85zGye1nuRlbwWKkko4g91Vd/508Ff6vKklpAAAAAAD9////AkANAwAAAAAAIgAgsT7u0Lo8o2WEfxS1nXWtQzsdJTMJnnOC5fwg0nYPvpowdQAAAAAAACIAIAx0DegrXfBr4D0XdetrGgAT2Q3AZANYm0rJL8L/Epp/AAAAAAABASuIlAMAAAAAACIAIGaHQ5brMNbT+WCtfE/WPW8gkmMir5NXAKRsQZAs9cT2AQMEAQAAAAEFR1IhAwYSJ4FeXdf/XPw6lFHpeMFeGvh88f+rWN2VtnaW75TNIQOn5Sg6nytLwT5FT9z5KmV/LMN1pZRsqbworUMwRdRN0lKuAAEBqiEDdDY+WLVpanVLROFc6wsvXyFG4FUgYknnTic2GPQNIy6sUYdkdqkUNlKGE2FxZM1sR08UC7GJfzRqXlSIrGt2qRQoTG+3hS6ElXzBw+21PRDtEJ9sKoisbJNSh2dSIQNiqGzCWTbNvmnTm7l6YNTctgzoP5xaOW6hiXSWVkoClCEC/w0jRRlaB3Oa5c0OPrRAxbxE1kdfzV24OWsaSCGLgIVSrwLWNLJoAAEBJSEDdDY+WLVpanVLROFc6wsvXyFG4FUgYknnTic2GPQNIy6sUYcA";
+
     fn revault_tx_add_dummy_sig(tx: &mut impl RevaultTransaction, input_index: usize) {
         let pubkey = PublicKey::from_str(
             "022634c3c8001a9e7700905281ae601dd73a4375e0e7801c22ffcc0443f5599935",
@@ -788,6 +1206,54 @@ mod test {
             .insert(pubkey, sig);
     }
 
+    // The presigned transactions of the dummy vault, in the same order as the poller gets them
+    fn dummy_presigned_txs() -> (
+        UnvaultTransaction,
+        CancelTransaction,
+        EmergencyTransaction,
+        UnvaultEmergencyTransaction,
+    ) {
+        (
+            UnvaultTransaction::from_psbt_str(UNVAULT_PSBT).unwrap(),
+            CancelTransaction::from_psbt_str(CANCEL_PSBT).unwrap(),
+            EmergencyTransaction::from_psbt_str(EMER_PSBT).unwrap(),
+            UnvaultEmergencyTransaction::from_psbt_str(UNEMER_PSBT).unwrap(),
+        )
+    }
+
+    // Insert an unconfirmed deposit in the database and return the corresponding vault
+    fn insert_dummy_vault(db_path: &PathBuf) -> DbVault {
+        let outpoint = OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap();
+        db_insert_new_unconfirmed_vault(
+            db_path,
+            1,
+            &outpoint,
+            &Amount::from_sat(123456),
+            ChildNumber::from(33334),
+            1615297315,
+        )
+        .unwrap();
+        db_vault_by_deposit(db_path, &outpoint).unwrap().unwrap()
+    }
+
+    // Confirm the dummy deposit at this height, storing its presigned transactions
+    fn confirm_dummy_vault(db_path: &PathBuf, outpoint: &OutPoint, height: u32) {
+        let (unvault_tx, cancel_tx, emer_tx, unemer_tx) = dummy_presigned_txs();
+        db_confirm_deposit(
+            db_path,
+            outpoint,
+            height,
+            &unvault_tx,
+            &cancel_tx,
+            Some(&emer_tx),
+            Some(&unemer_tx),
+        )
+        .unwrap();
+    }
+
     fn test_db_creation() {
         let mut revaultd = dummy_revaultd();
 
@@ -811,6 +1277,19 @@ mod test {
         })
         .unwrap();
         check_db(&mut revaultd).unwrap_err();
+        // But it would upgrade one from the past
+        db_exec(&revaultd.db_file(), |tx| {
+            tx.execute_batch(
+                "DROP TABLE watchtower_acks; DROP TABLE unvault_audits; \
+                 DROP TABLE feebumped_transactions; DROP TABLE events; \
+                 DROP TABLE spenders; UPDATE version SET version = 0;",
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        check_db(&mut revaultd).unwrap();
+        assert_eq!(db_version(&revaultd.db_file()).unwrap(), DB_VERSION);
 
         fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
     }
@@ -1407,6 +1886,474 @@ mod test {
         assert!(db_spend_transaction(&db_path, &txid_b).unwrap().is_none());
     }
 
+    fn test_db_emergency_statuses() {
+        let mut revaultd = dummy_revaultd();
+        let db_path = revaultd.db_file();
+
+        setup_db(&mut revaultd).unwrap();
+
+        let db_vault = insert_dummy_vault(&db_path);
+        let outpoint = db_vault.deposit_outpoint;
+
+        confirm_dummy_vault(&db_path, &outpoint, 700000);
+        let (fresh_unvault_tx, _, fresh_emer_tx, fresh_unemer_tx) = dummy_presigned_txs();
+        let vault_status = |db_path: &PathBuf| {
+            db_vault_by_deposit(db_path, &outpoint)
+                .unwrap()
+                .unwrap()
+                .status
+        };
+
+        // The deposit gets spent by the Emergency transaction
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Secured).unwrap();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Activating).unwrap();
+        assert!(db_emering_vaults(&db_path).unwrap().is_empty());
+        db_emer_deposit(&db_path, db_vault.id).unwrap();
+        assert_eq!(vault_status(&db_path), VaultStatus::EmergencyVaulting);
+        let emering = db_emering_vaults(&db_path).unwrap();
+        assert_eq!(emering.len(), 1);
+        assert_eq!(emering[0].0.id, db_vault.id);
+        assert_eq!(emering[0].1, fresh_emer_tx);
+
+        // Which confirms, then gets reorged out
+        db_mark_emergencied_vault(&db_path, db_vault.id).unwrap();
+        assert_eq!(vault_status(&db_path), VaultStatus::EmergencyVaulted);
+        assert!(db_emering_vaults(&db_path).unwrap().is_empty());
+        db_exec(&db_path, |db_tx| {
+            assert_eq!(
+                db_emer_dbtx(&db_tx, db_vault.id).unwrap(),
+                Some(fresh_emer_tx.clone())
+            );
+            db_unconfirm_emer_dbtx(&db_tx, db_vault.id).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(vault_status(&db_path), VaultStatus::EmergencyVaulting);
+
+        // And finally gets evicted from the mempool, the vault is back to where it was
+        db_unemer_deposit(&db_path, db_vault.id).unwrap();
+        assert_eq!(vault_status(&db_path), VaultStatus::Activating);
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
+
+        // Now the same dance for the Unvault Emergency
+        let unvault_txid = fresh_unvault_tx.txid();
+        db_unvault_deposit(&db_path, &unvault_txid).unwrap();
+        assert_eq!(vault_status(&db_path), VaultStatus::Unvaulting);
+        assert!(db_unemering_vaults(&db_path).unwrap().is_empty());
+        db_emer_unvault(&db_path, &unvault_txid).unwrap();
+        assert_eq!(
+            vault_status(&db_path),
+            VaultStatus::UnvaultEmergencyVaulting
+        );
+        let unemering = db_unemering_vaults(&db_path).unwrap();
+        assert_eq!(unemering.len(), 1);
+        assert_eq!(unemering[0].0.id, db_vault.id);
+        assert_eq!(unemering[0].1, fresh_unemer_tx);
+
+        db_mark_emergencied_unvault(&db_path, db_vault.id).unwrap();
+        assert_eq!(vault_status(&db_path), VaultStatus::UnvaultEmergencyVaulted);
+        assert!(db_unemering_vaults(&db_path).unwrap().is_empty());
+        db_exec(&db_path, |db_tx| {
+            assert_eq!(
+                db_unvault_emer_dbtx(&db_tx, db_vault.id).unwrap(),
+                Some(fresh_unemer_tx.clone())
+            );
+            db_unconfirm_unemer_dbtx(&db_tx, db_vault.id).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            vault_status(&db_path),
+            VaultStatus::UnvaultEmergencyVaulting
+        );
+
+        // Illegal transitions are refused, and leave the vault untouched
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Secured).unwrap_err();
+        db_mark_spent_unvault(&db_path, db_vault.id).unwrap_err();
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id)
+        })
+        .unwrap_err();
+        assert_eq!(
+            vault_status(&db_path),
+            VaultStatus::UnvaultEmergencyVaulting
+        );
+        db_unvault_transaction(&db_path, db_vault.id).unwrap();
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+
+    fn test_db_watchtower_acks() {
+        let mut revaultd = dummy_revaultd();
+        let db_path = revaultd.db_file();
+
+        setup_db(&mut revaultd).unwrap();
+
+        let db_vault = insert_dummy_vault(&db_path);
+        let outpoint = db_vault.deposit_outpoint;
+
+        confirm_dummy_vault(&db_path, &outpoint, 700000);
+
+        let first_wt = NoisePubKey([1; 32]);
+        let second_wt = NoisePubKey([2; 32]);
+
+        // The revocation transactions aren't signed yet, nothing to share
+        assert!(db_vaults_unacked(&db_path, &first_wt).unwrap().is_empty());
+
+        // Now they are, both watchtowers need to be sent them
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Secured).unwrap();
+        assert_eq!(
+            db_vaults_unacked(&db_path, &first_wt).unwrap()[0].id,
+            db_vault.id
+        );
+        assert_eq!(
+            db_vaults_unacked(&db_path, &second_wt).unwrap()[0].id,
+            db_vault.id
+        );
+
+        // The first one acked them. Acking twice is fine.
+        db_mark_watchtower_ack(&db_path, db_vault.id, &first_wt).unwrap();
+        db_mark_watchtower_ack(&db_path, db_vault.id, &first_wt).unwrap();
+        assert!(db_vaults_unacked(&db_path, &first_wt).unwrap().is_empty());
+        assert_eq!(db_vaults_unacked(&db_path, &second_wt).unwrap().len(), 1);
+
+        // If the deposit gets reorged out, the acks are wiped along with the transactions
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id)
+        })
+        .unwrap();
+        confirm_dummy_vault(&db_path, &outpoint, 700000);
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Secured).unwrap();
+        assert_eq!(db_vaults_unacked(&db_path, &first_wt).unwrap().len(), 1);
+        assert_eq!(db_vaults_unacked(&db_path, &second_wt).unwrap().len(), 1);
+
+        // Once the vault is gone, the watchtowers don't need to care anymore
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Canceling).unwrap();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Canceled).unwrap();
+        assert!(db_vaults_unacked(&db_path, &second_wt).unwrap().is_empty());
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+
+    fn test_db_unvault_audits() {
+        let mut revaultd = dummy_revaultd();
+        let db_path = revaultd.db_file();
+
+        setup_db(&mut revaultd).unwrap();
+
+        let db_vault = insert_dummy_vault(&db_path);
+        let outpoint = db_vault.deposit_outpoint;
+
+        confirm_dummy_vault(&db_path, &outpoint, 700000);
+        let (fresh_unvault_tx, fresh_cancel_tx, _, _) = dummy_presigned_txs();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
+        assert!(db_unaudited_unvaults(&db_path).unwrap().is_empty());
+
+        // Once unvaulted, we need to take a decision about it
+        db_unvault_deposit(&db_path, &fresh_unvault_tx.txid()).unwrap();
+        let unaudited = db_unaudited_unvaults(&db_path).unwrap();
+        assert_eq!(unaudited.len(), 1);
+        assert_eq!(unaudited[0].0.id, db_vault.id);
+        assert_eq!(unaudited[0].1, fresh_unvault_tx);
+
+        // A failed attempt at canceling is logged, but we'll need to retry
+        db_cancel_failed_unvault_audit(&db_path, db_vault.id, "Broadcasting Cancel: oops").unwrap();
+        assert_eq!(db_unaudited_unvaults(&db_path).unwrap().len(), 1);
+
+        // Failing again doesn't log it twice
+        db_cancel_failed_unvault_audit(&db_path, db_vault.id, "Broadcasting Cancel: oops again")
+            .unwrap();
+        assert_eq!(db_unaudited_unvaults(&db_path).unwrap().len(), 1);
+        let conn = Connection::open(&db_path).unwrap();
+        let reasons: Vec<String> = conn
+            .prepare("SELECT reason FROM unvault_audits WHERE vault_id = (?1)")
+            .unwrap()
+            .query_map(params![db_vault.id], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(reasons, vec!["Broadcasting Cancel: oops again".to_string()]);
+
+        // Once canceled, we are done with it
+        db_insert_unvault_audit(
+            &db_path,
+            db_vault.id,
+            UnvaultDecision::Canceled,
+            Some(&fresh_cancel_tx.txid()),
+            "No Spend transaction was announced",
+        )
+        .unwrap();
+        assert!(db_unaudited_unvaults(&db_path).unwrap().is_empty());
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+
+    fn test_db_unannounced_spends() {
+        let mut revaultd = dummy_revaultd();
+        let db_path = revaultd.db_file();
+
+        setup_db(&mut revaultd).unwrap();
+
+        let db_vault = insert_dummy_vault(&db_path);
+        let outpoint = db_vault.deposit_outpoint;
+        let announced_txid =
+            Txid::from_str("c9cc1a3a4a8a6c2b43a6e1b5e6cd7d6b0a2e1ea9b7aa1b0a03e1a2c6e2e7c2a1")
+                .unwrap();
+        let other_txid =
+            Txid::from_str("0c6bb3c6f4e1a6d5fa61f9f1cc8a9da6e6bb2b9a4a3e2e5d8f2c3b1a7f6e5d4c")
+                .unwrap();
+
+        confirm_dummy_vault(&db_path, &outpoint, 700000);
+        let (fresh_unvault_tx, fresh_cancel_tx, _, _) = dummy_presigned_txs();
+        let unvault_txid = fresh_unvault_tx.txid();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Active).unwrap();
+        db_unvault_deposit(&db_path, &unvault_txid).unwrap();
+
+        // We let the Unvault through for the announced Spend
+        db_insert_unvault_audit(
+            &db_path,
+            db_vault.id,
+            UnvaultDecision::Allowed,
+            Some(&announced_txid),
+            "A Spend transaction was announced to the Coordinator",
+        )
+        .unwrap();
+        assert!(db_unannounced_spends(&db_path).unwrap().is_empty());
+
+        // It's spent by the announced Spend, all good
+        db_spend_unvault(&db_path, &unvault_txid, &announced_txid).unwrap();
+        assert!(db_unannounced_spends(&db_path).unwrap().is_empty());
+
+        // It got evicted and another transaction spends the Unvault, we need to revault
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Unvaulted).unwrap();
+        db_spend_unvault(&db_path, &unvault_txid, &other_txid).unwrap();
+        let unannounced = db_unannounced_spends(&db_path).unwrap();
+        assert_eq!(unannounced.len(), 1);
+        assert_eq!(unannounced[0].0.id, db_vault.id);
+        assert_eq!(unannounced[0].0.spend_txid, Some(other_txid));
+        assert_eq!(unannounced[0].1, announced_txid);
+
+        // Once canceled, we are done with it
+        db_insert_unvault_audit(
+            &db_path,
+            db_vault.id,
+            UnvaultDecision::Canceled,
+            Some(&fresh_cancel_tx.txid()),
+            "The Unvault is being spent by another transaction than the announced one",
+        )
+        .unwrap();
+        assert!(db_unannounced_spends(&db_path).unwrap().is_empty());
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+
+    fn test_db_feebumped_txs() {
+        let mut revaultd = dummy_revaultd();
+        let db_path = revaultd.db_file();
+
+        setup_db(&mut revaultd).unwrap();
+
+        let db_vault = insert_dummy_vault(&db_path);
+        let outpoint = db_vault.deposit_outpoint;
+
+        confirm_dummy_vault(&db_path, &outpoint, 700000);
+        let (_, fresh_cancel_tx, fresh_emer_tx, _) = dummy_presigned_txs();
+        let cancel_txid = fresh_cancel_tx.txid();
+        assert!(db_feebumped_txids(&db_path, &cancel_txid)
+            .unwrap()
+            .is_empty());
+
+        // We may bump it more than once, but only record each version once
+        let bumped_txids = [
+            Txid::from_str("c9cc1a3a4a8a6c2b43a6e1b5e6cd7d6b0a2e1ea9b7aa1b0a03e1a2c6e2e7c2a1")
+                .unwrap(),
+            Txid::from_str("0c6bb3c6f4e1a6d5fa61f9f1cc8a9da6e6bb2b9a4a3e2e5d8f2c3b1a7f6e5d4c")
+                .unwrap(),
+        ];
+        for bumped_txid in bumped_txids.iter().chain(bumped_txids.iter()) {
+            db_insert_feebumped_tx(&db_path, &cancel_txid, bumped_txid).unwrap();
+        }
+        let mut txids = db_feebumped_txids(&db_path, &cancel_txid).unwrap();
+        txids.sort();
+        let mut expected_txids = bumped_txids.to_vec();
+        expected_txids.sort();
+        assert_eq!(txids, expected_txids);
+        // They are only tied to the Cancel
+        assert!(db_feebumped_txids(&db_path, &fresh_emer_tx.txid())
+            .unwrap()
+            .is_empty());
+
+        // If the deposit gets unconfirmed, they are wiped along with the presigned transactions
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id)
+        })
+        .unwrap();
+        assert!(db_feebumped_txids(&db_path, &cancel_txid)
+            .unwrap()
+            .is_empty());
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+
+    fn test_db_events() {
+        let mut revaultd = dummy_revaultd();
+        let db_path = revaultd.db_file();
+
+        setup_db(&mut revaultd).unwrap();
+
+        let db_vault = insert_dummy_vault(&db_path);
+        let outpoint = db_vault.deposit_outpoint;
+        let statuses = |db_path: &PathBuf, kind: EventKind| {
+            let filter = EventsFilter {
+                kinds: Some(vec![kind]),
+                ..EventsFilter::default()
+            };
+            db_events(db_path, &filter, 0, 100)
+                .unwrap()
+                .into_iter()
+                .map(|ev| ev.status)
+                .collect::<Vec<VaultStatus>>()
+        };
+
+        // The vault history starts as soon as we see the deposit
+        let events = db_events(&db_path, &EventsFilter::default(), 0, 100).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].vault_id, db_vault.id);
+        assert_eq!(events[0].deposit_outpoint, outpoint);
+        assert_eq!(events[0].kind, EventKind::StatusChange);
+        assert_eq!(events[0].status, VaultStatus::Unconfirmed);
+        assert_eq!(events[0].txid, None);
+
+        confirm_dummy_vault(&db_path, &outpoint, 700000);
+        let (_, fresh_cancel_tx, _, _) = dummy_presigned_txs();
+
+        // Updating the status to the same one isn't recorded twice
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Secured).unwrap();
+        db_mark_vault_as(&db_path, db_vault.id, VaultStatus::Secured).unwrap();
+        assert_eq!(
+            statuses(&db_path, EventKind::StatusChange),
+            vec![
+                VaultStatus::Unconfirmed,
+                VaultStatus::Funded,
+                VaultStatus::Secured
+            ]
+        );
+
+        // Broadcasts are recorded along with the transaction
+        let cancel_txid = fresh_cancel_tx.txid();
+        db_insert_broadcast_event(&db_path, db_vault.id, &cancel_txid).unwrap();
+        let broadcast_filter = EventsFilter {
+            kinds: Some(vec![EventKind::Broadcast]),
+            ..EventsFilter::default()
+        };
+        let broadcasts = db_events(&db_path, &broadcast_filter, 0, 100).unwrap();
+        assert_eq!(broadcasts.len(), 1);
+        assert_eq!(broadcasts[0].txid, Some(cancel_txid));
+        assert_eq!(broadcasts[0].status, VaultStatus::Secured);
+
+        // A reorg is recorded as a rewind, and the vault may then take the same status again
+        db_exec(&db_path, |db_tx| {
+            db_unconfirm_deposit_dbtx(&db_tx, db_vault.id)
+        })
+        .unwrap();
+        assert_eq!(
+            statuses(&db_path, EventKind::Rewind),
+            vec![VaultStatus::Unconfirmed]
+        );
+        confirm_dummy_vault(&db_path, &outpoint, 700001);
+        assert_eq!(
+            statuses(&db_path, EventKind::StatusChange).last(),
+            Some(&VaultStatus::Funded)
+        );
+
+        // The history is ordered and can be paginated
+        let events = db_events(&db_path, &EventsFilter::default(), 0, 100).unwrap();
+        assert_eq!(events.len(), 6);
+        assert!(events.windows(2).all(|w| w[0].id < w[1].id));
+        let page = db_events(&db_path, &EventsFilter::default(), 2, 3).unwrap();
+        assert_eq!(page, events[2..5].to_vec());
+
+        // And filtered by vault, time and height
+        let filter = EventsFilter {
+            vault_ids: Some(vec![db_vault.id + 1]),
+            ..EventsFilter::default()
+        };
+        assert!(db_events(&db_path, &filter, 0, 100).unwrap().is_empty());
+        let filter = EventsFilter {
+            vault_ids: Some(vec![db_vault.id]),
+            start_time: Some(events[0].timestamp),
+            end_height: Some(events[0].blockheight),
+            ..EventsFilter::default()
+        };
+        assert_eq!(db_events(&db_path, &filter, 0, 100).unwrap(), events);
+        let filter = EventsFilter {
+            start_height: Some(events[0].blockheight + 1),
+            ..EventsFilter::default()
+        };
+        assert!(db_events(&db_path, &filter, 0, 100).unwrap().is_empty());
+        let filter = EventsFilter {
+            end_time: Some(events[0].timestamp - 1),
+            ..EventsFilter::default()
+        };
+        assert!(db_events(&db_path, &filter, 0, 100).unwrap().is_empty());
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+
+    fn test_db_backup_restore() {
+        let mut revaultd = dummy_revaultd();
+        let db_path = revaultd.db_file();
+        let backup_path = revaultd.data_dir.join("backup.sqlite3");
+
+        setup_db(&mut revaultd).unwrap();
+        let outpoint = OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap();
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            1,
+            &outpoint,
+            &Amount::from_sat(123456),
+            ChildNumber::from(33334),
+            1615297315,
+        )
+        .unwrap();
+
+        // We can't restore a backup that does not exist, or over an existing database
+        restore_db(&revaultd, &backup_path).unwrap_err();
+        assert!(!backup_path.exists());
+        backup_db(&revaultd, &backup_path).unwrap();
+        restore_db(&revaultd, &backup_path).unwrap_err();
+        // Nor overwrite an existing file with a backup
+        backup_db(&revaultd, &backup_path).unwrap_err();
+
+        // We can restore it in place of a lost database
+        fs::remove_file(&db_path).unwrap();
+        restore_db(&revaultd, &backup_path).unwrap();
+        setup_db(&mut revaultd).unwrap();
+        assert!(db_vault_by_deposit(&db_path, &outpoint).unwrap().is_some());
+
+        // But only if it's for the same wallet
+        fs::remove_file(&db_path).unwrap();
+        revaultd.bitcoind_config.network = Network::Testnet;
+        restore_db(&revaultd, &backup_path).unwrap_err();
+        revaultd.bitcoind_config.network = Network::Bitcoin;
+        let our_man_xpub = revaultd.our_man_xpub.take();
+        restore_db(&revaultd, &backup_path).unwrap_err();
+        revaultd.our_man_xpub = our_man_xpub;
+        db_exec(&backup_path, |tx| {
+            tx.execute("UPDATE version SET version = (?1)", params![DB_VERSION + 1])
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        restore_db(&revaultd, &backup_path).unwrap_err();
+        assert!(!db_path.exists());
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+
     // We disabled #[test] for the above, as they may erase the db concurrently.
     // Instead, run them sequentially.
     #[test]
@@ -1416,5 +2363,12 @@ mod test {
         test_db_store_presigned_txs();
         test_db_concurrent_write();
         test_db_spend_storage();
+        test_db_emergency_statuses();
+        test_db_watchtower_acks();
+        test_db_unvault_audits();
+        test_db_unannounced_spends();
+        test_db_feebumped_txs();
+        test_db_events();
+        test_db_backup_restore();
     }
 }
@@ -2,12 +2,14 @@ use crate::{
     assert_tx_type,
     database::{
         schema::{
-            DbSpendTransaction, DbTransaction, DbVault, DbWallet, RevaultTx, TransactionType,
+            DbEvent, DbSpendTransaction, DbTransaction, DbVault, DbWallet, EventKind, RevaultTx,
+            TransactionType, UnvaultDecision,
         },
         DatabaseError,
     },
     revaultd::{BlockchainTip, VaultStatus},
 };
+use revault_net::noise::PublicKey as NoisePubKey;
 use revault_tx::{
     bitcoin::{
         consensus::encode,
@@ -171,11 +173,6 @@ pub fn db_wallet(db_path: &PathBuf) -> Result<DbWallet, DatabaseError> {
             None
         };
 
-        assert!(
-            our_man_xpub.is_some() || our_stk_xpub.is_some(),
-            "The database is messed up, and we could not catch the error."
-        );
-
         Ok(DbWallet {
             id: row.get(0)?,
             timestamp: row.get(1)?,
@@ -255,6 +252,19 @@ pub fn db_vaults_min_status(
     )
 }
 
+/// Get all the vaults with this exact `status`
+pub fn db_vaults_by_status(
+    db_path: &PathBuf,
+    status: VaultStatus,
+) -> Result<Vec<DbVault>, DatabaseError> {
+    db_query::<_, _, DbVault>(
+        db_path,
+        "SELECT * FROM vaults WHERE status = (?1) ORDER BY updated_at DESC",
+        params![status as u32],
+        |row| row.try_into(),
+    )
+}
+
 /// Get all the vaults we know about from an already-created transaction
 pub fn db_vaults_dbtx(db_tx: &Transaction) -> Result<Vec<DbVault>, DatabaseError> {
     db_query_tx(db_tx, "SELECT * FROM vaults", params![], |row| {
@@ -272,6 +282,29 @@ pub fn db_deposits(db_path: &PathBuf) -> Result<Vec<DbVault>, DatabaseError> {
     )
 }
 
+/// Get the vaults whose revocation transactions are fully signed and still relevant, but that
+/// this watchtower did not acknowledge yet.
+pub fn db_vaults_unacked(
+    db_path: &PathBuf,
+    watchtower_key: &NoisePubKey,
+) -> Result<Vec<DbVault>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT * FROM vaults WHERE status IN ((?1), (?2), (?3), (?4), (?5)) \
+         AND id NOT IN (SELECT vault_id FROM watchtower_acks WHERE watchtower_key = (?6)) \
+         ORDER BY updated_at DESC",
+        params![
+            VaultStatus::Secured as u32,
+            VaultStatus::Activating as u32,
+            VaultStatus::Active as u32,
+            VaultStatus::Unvaulting as u32,
+            VaultStatus::Unvaulted as u32,
+            watchtower_key.0.to_vec(),
+        ],
+        |row| row.try_into(),
+    )
+}
+
 /// Get a vault from a deposit outpoint. Returns None if we never heard of such a vault.
 pub fn db_vault_by_deposit(
     db_path: &PathBuf,
@@ -310,6 +343,61 @@ pub fn db_unvaulted_vaults(
         },
     )
 }
+/// Get the vaults that were unvaulted but for which we did not decide yet whether to let the
+/// Unvault be or to Cancel it, along with the respective Unvault transaction.
+pub fn db_unaudited_unvaults(
+    db_path: &PathBuf,
+) -> Result<Vec<(DbVault, UnvaultTransaction)>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.*, ptx.psbt FROM vaults \
+         INNER JOIN presigned_transactions as ptx ON ptx.vault_id = vaults.id \
+         WHERE vaults.status IN ((?1), (?2)) AND ptx.type = (?3) \
+         AND vaults.id NOT IN (SELECT vault_id FROM unvault_audits WHERE decision IN ((?4), (?5)))",
+        params![
+            VaultStatus::Unvaulting as u32,
+            VaultStatus::Unvaulted as u32,
+            TransactionType::Unvault as u32,
+            UnvaultDecision::Allowed as u32,
+            UnvaultDecision::Canceled as u32,
+        ],
+        |row| {
+            let db_vault: DbVault = row.try_into()?;
+            let unvault_tx: Vec<u8> = row.get(11)?;
+            let unvault_tx = UnvaultTransaction::from_psbt_serialized(&unvault_tx)
+                .expect("We store it with to_psbt_serialized");
+
+            Ok((db_vault, unvault_tx))
+        },
+    )
+}
+
+/// Get the vaults whose Unvault we let through for an announced Spend transaction, but which are
+/// being spent by another one, along with the txid of the announced Spend. Those we already
+/// canceled are not returned.
+pub fn db_unannounced_spends(db_path: &PathBuf) -> Result<Vec<(DbVault, Txid)>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.*, audits.txid FROM vaults \
+         INNER JOIN unvault_audits as audits ON audits.vault_id = vaults.id \
+         WHERE vaults.status = (?1) AND audits.decision = (?2) \
+         AND vaults.spend_txid IS NOT audits.txid \
+         AND vaults.id NOT IN (SELECT vault_id FROM unvault_audits WHERE decision = (?3))",
+        params![
+            VaultStatus::Spending as u32,
+            UnvaultDecision::Allowed as u32,
+            UnvaultDecision::Canceled as u32,
+        ],
+        |row| {
+            let db_vault: DbVault = row.try_into()?;
+            let announced_txid: Txid =
+                encode::deserialize(&row.get::<_, Vec<u8>>(11)?).expect("We store it");
+
+            Ok((db_vault, announced_txid))
+        },
+    )
+}
+
 /// Get the vaults that are in the process of being spent, along with the respective Unvault
 /// transaction.
 pub fn db_spending_vaults(
@@ -360,6 +448,56 @@ pub fn db_canceling_vaults(
     )
 }
 
+/// Get the vaults that are in the process of being emergencied, along with the respective
+/// Emergency transaction.
+pub fn db_emering_vaults(
+    db_path: &PathBuf,
+) -> Result<Vec<(DbVault, EmergencyTransaction)>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.*, ptx.psbt FROM vaults \
+         INNER JOIN presigned_transactions as ptx ON ptx.vault_id = vaults.id \
+         WHERE vaults.status = (?1) AND ptx.type = (?2)",
+        &[
+            VaultStatus::EmergencyVaulting as u32,
+            TransactionType::Emergency as u32,
+        ],
+        |row| {
+            let db_vault: DbVault = row.try_into()?;
+            let emer_tx: Vec<u8> = row.get(11)?;
+            let emer_tx = EmergencyTransaction::from_psbt_serialized(&emer_tx)
+                .expect("We store it with to_psbt_serialized");
+
+            Ok((db_vault, emer_tx))
+        },
+    )
+}
+
+/// Get the vaults that are in the process of being emergencied after their Unvault was
+/// broadcast, along with the respective Unvault Emergency transaction.
+pub fn db_unemering_vaults(
+    db_path: &PathBuf,
+) -> Result<Vec<(DbVault, UnvaultEmergencyTransaction)>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT vaults.*, ptx.psbt FROM vaults \
+         INNER JOIN presigned_transactions as ptx ON ptx.vault_id = vaults.id \
+         WHERE vaults.status = (?1) AND ptx.type = (?2)",
+        &[
+            VaultStatus::UnvaultEmergencyVaulting as u32,
+            TransactionType::UnvaultEmergency as u32,
+        ],
+        |row| {
+            let db_vault: DbVault = row.try_into()?;
+            let unemer_tx: Vec<u8> = row.get(11)?;
+            let unemer_tx = UnvaultEmergencyTransaction::from_psbt_serialized(&unemer_tx)
+                .expect("We store it with to_psbt_serialized");
+
+            Ok((db_vault, unemer_tx))
+        },
+    )
+}
+
 impl TryFrom<&Row<'_>> for DbTransaction {
     type Error = rusqlite::Error;
 
@@ -534,6 +672,25 @@ pub fn db_emer_transaction(
     ))
 }
 
+/// Get the Emergency transaction for this vault from an existing database transaction.
+/// Will return None if called by a non-stakeholder.
+pub fn db_emer_dbtx(
+    db_tx: &Transaction,
+    vault_id: u32,
+) -> Result<Option<EmergencyTransaction>, DatabaseError> {
+    db_query_tx(
+        db_tx,
+        "SELECT * FROM presigned_transactions WHERE vault_id = (?1) AND type = (?2)",
+        params![vault_id, TransactionType::Emergency as u32],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| {
+        rows.pop().map(|db_tx: DbTransaction| {
+            assert_tx_type!(db_tx.psbt, Emergency, "We just queryed it")
+        })
+    })
+}
+
 /// Get the Unvault Emergency transaction corresponding to this vault
 /// Will error if there are none, ie if called by a non-stakeholder!
 pub fn db_unvault_emer_transaction(
@@ -559,6 +716,25 @@ pub fn db_unvault_emer_transaction(
     ))
 }
 
+/// Get the Unvault Emergency transaction for this vault from an existing database transaction.
+/// Will return None if called by a non-stakeholder.
+pub fn db_unvault_emer_dbtx(
+    db_tx: &Transaction,
+    vault_id: u32,
+) -> Result<Option<UnvaultEmergencyTransaction>, DatabaseError> {
+    db_query_tx(
+        db_tx,
+        "SELECT * FROM presigned_transactions WHERE vault_id = (?1) AND type = (?2)",
+        params![vault_id, TransactionType::UnvaultEmergency as u32],
+        |row| row.try_into(),
+    )
+    .map(|mut rows| {
+        rows.pop().map(|db_tx: DbTransaction| {
+            assert_tx_type!(db_tx.psbt, UnvaultEmergency, "We just queryed it")
+        })
+    })
+}
+
 /// Get a vault and its Unvault transaction out of an Unvault txid
 pub fn db_vault_by_unvault_txid(
     db_path: &PathBuf,
@@ -704,6 +880,38 @@ pub fn db_broadcastable_spend_transactions(
     )
 }
 
+/// Get the txids of the fee-bumped versions of this presigned revocation transaction
+pub fn db_feebumped_txids(
+    db_path: &PathBuf,
+    presigned_txid: &Txid,
+) -> Result<Vec<Txid>, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT fbtx.txid FROM feebumped_transactions as fbtx \
+         INNER JOIN presigned_transactions as ptx ON ptx.id = fbtx.presigned_id \
+         WHERE ptx.txid = (?1)",
+        params![presigned_txid.to_vec()],
+        |row| {
+            let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(0)?).expect("We store it");
+            Ok(txid)
+        },
+    )
+}
+
+/// Get the txid of the transaction we found spending this outpoint, if any
+pub fn db_spender(db_path: &PathBuf, outpoint: &OutPoint) -> Result<Option<Txid>, DatabaseError> {
+    Ok(db_query(
+        db_path,
+        "SELECT spender_txid FROM spenders WHERE txid = (?1) AND vout = (?2)",
+        params![outpoint.txid.to_vec(), outpoint.vout],
+        |row| {
+            let txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(0)?).expect("We store it");
+            Ok(txid)
+        },
+    )?
+    .pop())
+}
+
 /// Get a single Spend transaction from DB by its txid
 pub fn db_spend_transaction(
     db_path: &PathBuf,
@@ -746,3 +954,123 @@ pub fn db_vaults_from_spend(
 
     Ok(db_vaults)
 }
+
+impl TryFrom<&Row<'_>> for DbEvent {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let id = row.get(0)?;
+        let vault_id = row.get(1)?;
+        let deposit_txid: Txid = encode::deserialize(&row.get::<_, Vec<u8>>(2)?)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+        let deposit_outpoint = OutPoint {
+            txid: deposit_txid,
+            vout: row.get(3)?,
+        };
+        let kind: EventKind = row.get::<_, u32>(4)?.try_into().map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unknown kind for event id '{}'",
+                id
+            ))))
+        })?;
+        let status: VaultStatus = row.get::<_, u32>(5)?.try_into().map_err(|_| {
+            FromSqlError::Other(Box::new(DatabaseError(format!(
+                "Unknown status for event id '{}'",
+                id
+            ))))
+        })?;
+        let txid = row
+            .get::<_, Option<Vec<u8>>>(6)?
+            .map(|raw_txid| encode::deserialize(&raw_txid).expect("We only store valid txids"));
+
+        Ok(DbEvent {
+            id,
+            vault_id,
+            deposit_outpoint,
+            kind,
+            status,
+            txid,
+            timestamp: row.get(7)?,
+            blockheight: row.get(8)?,
+        })
+    }
+}
+
+/// The criteria to select events from the history. A None field does not filter.
+#[derive(Debug, Default)]
+pub struct EventsFilter {
+    pub vault_ids: Option<Vec<u32>>,
+    pub kinds: Option<Vec<EventKind>>,
+    pub start_time: Option<u32>,
+    pub end_time: Option<u32>,
+    pub start_height: Option<u32>,
+    pub end_height: Option<u32>,
+    /// Only the events recorded after the event with this id
+    pub after_id: Option<i64>,
+}
+
+/// Get at most `limit` events matching this filter from the history, oldest first, skipping
+/// the `offset` first ones.
+pub fn db_events(
+    db_path: &PathBuf,
+    filter: &EventsFilter,
+    offset: u32,
+    limit: u32,
+) -> Result<Vec<DbEvent>, DatabaseError> {
+    // These are integers, no need to pass them as parameters.
+    let in_list = |column: &str, values: Vec<u32>| {
+        format!(
+            "AND {} IN ({}) ",
+            column,
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    };
+    let vaults_clause = filter
+        .vault_ids
+        .as_ref()
+        .map(|ids| in_list("ev.vault_id", ids.clone()))
+        .unwrap_or_default();
+    let kinds_clause = filter
+        .kinds
+        .as_ref()
+        .map(|kinds| in_list("ev.kind", kinds.iter().map(|k| *k as u32).collect()))
+        .unwrap_or_default();
+
+    db_query(
+        db_path,
+        &format!(
+            "SELECT ev.id, ev.vault_id, vaults.deposit_txid, vaults.deposit_vout, ev.kind, \
+             ev.status, ev.txid, ev.timestamp, ev.blockheight \
+             FROM events as ev INNER JOIN vaults ON vaults.id = ev.vault_id \
+             WHERE ((?1) IS NULL OR ev.timestamp >= (?1)) AND ((?2) IS NULL OR ev.timestamp <= (?2)) \
+             AND ((?3) IS NULL OR ev.blockheight >= (?3)) AND ((?4) IS NULL OR ev.blockheight <= (?4)) \
+             AND ((?7) IS NULL OR ev.id > (?7)) {}{}ORDER BY ev.id ASC LIMIT (?5) OFFSET (?6)",
+            vaults_clause, kinds_clause
+        ),
+        params![
+            filter.start_time,
+            filter.end_time,
+            filter.start_height,
+            filter.end_height,
+            limit,
+            offset,
+            filter.after_id
+        ],
+        |row| row.try_into(),
+    )
+}
+
+/// Get the id of the last event recorded in the history, 0 if it's empty
+pub fn db_last_event_id(db_path: &PathBuf) -> Result<i64, DatabaseError> {
+    db_query(
+        db_path,
+        "SELECT COALESCE(MAX(id), 0) FROM events",
+        NO_PARAMS,
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|mut rows| rows.pop().unwrap_or(0))
+}
@@ -0,0 +1,409 @@
+//! Upgrade databases created by previous versions of revaultd to the current schema.
+//!
+//! `MIGRATIONS[n]` upgrades a database at version `n` to version `n + 1`. Once released, a
+//! migration must never be modified: change the `SCHEMA`, bump the `DB_VERSION` and append the
+//! statements upgrading the previous version instead.
+
+use crate::database::{interface::db_exec, DatabaseError, DB_VERSION};
+
+use std::{fs, path::PathBuf};
+
+use rusqlite::params;
+
+pub const MIGRATIONS: &[&str] = &[
+    // 0 -> 1: watchtowers acknowledgements, Unvault audits, fee-bumped transactions and the
+    // vaults history. The history of existing vaults starts with their current status.
+    "\
+CREATE TABLE watchtower_acks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    watchtower_key BLOB NOT NULL,
+    acked_at INTEGER NOT NULL,
+    UNIQUE (vault_id, watchtower_key),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE TABLE unvault_audits (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    decision INTEGER NOT NULL,
+    txid BLOB,
+    reason TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE TABLE feebumped_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    presigned_id INTEGER NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    FOREIGN KEY (presigned_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE TABLE events (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    txid BLOB,
+    timestamp INTEGER NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+CREATE INDEX vault_events ON events (vault_id);
+
+INSERT INTO events (vault_id, kind, status, txid, timestamp, blockheight)
+    SELECT id, 0, status, NULL, updated_at, (SELECT blockheight FROM tip) FROM vaults;
+",
+    // 1 -> 2: the index of the spenders of our coins. It's filled as spends are detected.
+    "\
+CREATE TABLE spenders (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    spender_txid BLOB NOT NULL,
+    blockheight INTEGER NOT NULL,
+    UNIQUE (txid, vout)
+);
+",
+];
+
+// Where we copy the database before upgrading it from this version.
+fn backup_path(db_path: &PathBuf, version: u32) -> PathBuf {
+    let mut path = db_path.clone().into_os_string();
+    path.push(format!(".v{}.bak", version));
+    PathBuf::from(path)
+}
+
+/// Upgrade the database at `db_path` from `version` to `DB_VERSION`. A copy of the database as
+/// it was is made beforehand, and all the migrations are applied in a single transaction so we
+/// never leave it at an intermediary version.
+pub fn migrate_db(db_path: &PathBuf, version: u32) -> Result<(), DatabaseError> {
+    assert_eq!(MIGRATIONS.len(), DB_VERSION as usize);
+    if version >= DB_VERSION {
+        return Err(DatabaseError(format!(
+            "Can't migrate database at version '{}' to version '{}'",
+            version, DB_VERSION
+        )));
+    }
+
+    let backup = backup_path(db_path, version);
+    fs::copy(db_path, &backup).map_err(|e| {
+        DatabaseError(format!(
+            "Backing up database to '{:?}': {}",
+            backup,
+            e.to_string()
+        ))
+    })?;
+    log::info!("Backed up database to '{:?}'", backup);
+
+    db_exec(db_path, |tx| {
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::debug!("Upgrading database from version '{}' to '{}'", i, i + 1);
+            tx.execute_batch(migration).map_err(|e| {
+                DatabaseError(format!(
+                    "Upgrading database from version '{}': {}",
+                    i,
+                    e.to_string()
+                ))
+            })?;
+        }
+        tx.execute("UPDATE version SET version = (?1)", params![DB_VERSION])?;
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backup_path, migrate_db, MIGRATIONS};
+    use crate::database::{
+        interface::{db_exec, db_version},
+        schema::SCHEMA,
+        DB_VERSION,
+    };
+
+    use std::{fs, path::PathBuf};
+
+    use rusqlite::{params, Connection, NO_PARAMS};
+
+    // The schema of the databases at version 0
+    const SCHEMA_V0: &str = "\
+CREATE TABLE version (
+    version INTEGER NOT NULL
+);
+
+CREATE TABLE tip (
+    network TEXT NOT NULL,
+    blockheight INTEGER NOT NULL,
+    blockhash BLOB NOT NULL
+);
+
+/* This stores metadata about our wallet. We only support single wallet for
+ * now (and the foreseeable future). This MUST be in sync with bitcoind's
+ * wallet.
+ */
+CREATE TABLE wallets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    timestamp INTEGER NOT NULL,
+    deposit_descriptor TEXT NOT NULL,
+    unvault_descriptor TEXT NOT NULL,
+    our_manager_xpub TEXT,
+    our_stakeholder_xpub TEXT,
+    deposit_derivation_index INTEGER NOT NULL
+);
+
+/* This stores the vaults we heard about. The deposit may be unconfirmed,
+ * in which case the blockheight will be 0 (FIXME: should be NULL instead?).
+ * For any vault entry a deposit transaction MUST be present in bitcoind's
+ * wallet.
+ * The spend_txid is stored to not harass bitcoind trying to guess the spending
+ * txid out of a deposit outpoint. It MUST be NOT NULL if status is 'spending'
+ * or 'spent'.
+ */
+CREATE TABLE vaults (
+    id INTEGER PRIMARY KEY NOT NULL,
+    wallet_id INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    blockheight INTEGER NOT NULL,
+    deposit_txid BLOB NOT NULL,
+    deposit_vout INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    received_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    spend_txid BLOB,
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* This stores transactions we presign:
+ * - Emergency (only for stakeholders)
+ * - Unvault
+ * - Cancel
+ * - Unvault Emergency (only for stakeholders)
+ */
+CREATE TABLE presigned_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    type INTEGER NOT NULL,
+    psbt BLOB UNIQUE NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    fullysigned BOOLEAN NOT NULL CHECK (fullysigned IN (0,1)),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* A bridge between the Unvault transactions a Spend transaction
+ * may refer and the possible Spend transactions an Unvault one
+ * may be associated with.
+ */
+CREATE TABLE spend_inputs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    unvault_id INTEGER NOT NULL,
+    spend_id INTEGER NOT NULL,
+    FOREIGN KEY (unvault_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT,
+    FOREIGN KEY (spend_id) REFERENCES spend_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE CASCADE
+);
+
+/* This stores Spend transactions we created. A txid column is there to
+ * ease research.
+ * The 'broadcasted' column indicates wether a Spend transaction is:
+ *  - Not elligible for broadcast (NULL)
+ *  - Waiting to be broadcasted (0)
+ *  - Already broadcasted (1)
+ */
+CREATE TABLE spend_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    psbt BLOB UNIQUE NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    broadcasted BOOLEAN CHECK (broadcasted IN (NULL, 0,1))
+);
+
+CREATE INDEX vault_status ON vaults (status);
+CREATE INDEX vault_transactions ON presigned_transactions (vault_id);
+";
+
+    // The schema of the databases created by each previous version, by version. The schema of
+    // version 1 is the one of version 0 once upgraded, as this very test checked back then.
+    const HISTORICAL_SCHEMAS: &[&[&str]] = &[&[SCHEMA_V0], &[SCHEMA_V0, MIGRATIONS[0]]];
+
+    // The name of the tables and indexes, along with the definition of their columns
+    fn schema_of(db_path: &PathBuf) -> Vec<(String, Vec<String>)> {
+        let conn = Connection::open(db_path).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT name FROM sqlite_master ORDER BY name")
+            .unwrap();
+        let names: Vec<String> = stmt
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        names
+            .into_iter()
+            .map(|name| {
+                let mut stmt = conn
+                    .prepare(&format!("PRAGMA table_info({})", name))
+                    .unwrap();
+                let columns = stmt
+                    .query_map(NO_PARAMS, |row| {
+                        Ok(format!(
+                            "{} {} {} {:?}",
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, bool>(3)?,
+                            row.get::<_, Option<String>>(4)?,
+                        ))
+                    })
+                    .unwrap()
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                (name, columns)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn migrate_historical_schemas() {
+        let datadir: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "test_data",
+            "scratch_datadir_migrations",
+        ]
+        .iter()
+        .collect();
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+        fs::create_dir_all(&datadir).unwrap();
+
+        let current_db = datadir.join("current.sqlite3");
+        Connection::open(&current_db)
+            .unwrap()
+            .execute_batch(SCHEMA)
+            .unwrap();
+        let current_schema = schema_of(&current_db);
+
+        for (version, schema) in HISTORICAL_SCHEMAS.iter().enumerate() {
+            let version = version as u32;
+            let db_path = datadir.join(format!("v{}.sqlite3", version));
+
+            // A database created by this version, with a vault in it
+            let conn = Connection::open(&db_path).unwrap();
+            for statements in schema.iter() {
+                conn.execute_batch(statements).unwrap();
+            }
+            conn.execute(
+                "INSERT INTO version (version) VALUES (?1)",
+                params![version],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO tip (network, blockheight, blockhash) VALUES (?1, ?2, ?3)",
+                params!["regtest", 105, vec![0u8; 32]],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO wallets (timestamp, deposit_descriptor, unvault_descriptor, \
+                 deposit_derivation_index) VALUES (?1, ?2, ?3, ?4)",
+                params![1615297315, "deposit", "unvault", 0],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO vaults (wallet_id, status, blockheight, deposit_txid, \
+                 deposit_vout, amount, derivation_index, received_at, updated_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    1,
+                    3,
+                    101,
+                    vec![1u8; 32],
+                    0,
+                    123456,
+                    0,
+                    1615297315,
+                    1615297400
+                ],
+            )
+            .unwrap();
+            drop(conn);
+
+            migrate_db(&db_path, version).unwrap();
+            assert_eq!(db_version(&db_path).unwrap(), DB_VERSION);
+            assert_eq!(schema_of(&db_path), current_schema);
+            // The copy is left untouched
+            assert_eq!(
+                db_version(&backup_path(&db_path, version)).unwrap(),
+                version
+            );
+
+            // The vault and its history survived
+            let conn = Connection::open(&db_path).unwrap();
+            let (status, updated_at): (u32, u32) = conn
+                .query_row("SELECT status, updated_at FROM vaults", NO_PARAMS, |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .unwrap();
+            assert_eq!((status, updated_at), (3, 1615297400));
+            if version == 0 {
+                let event: (u32, u32, u32, u32, u32) = conn
+                    .query_row(
+                        "SELECT vault_id, kind, status, timestamp, blockheight FROM events",
+                        NO_PARAMS,
+                        |row| {
+                            Ok((
+                                row.get(0)?,
+                                row.get(1)?,
+                                row.get(2)?,
+                                row.get(3)?,
+                                row.get(4)?,
+                            ))
+                        },
+                    )
+                    .unwrap();
+                assert_eq!(event, (1, 0, 3, 1615297400, 105));
+            }
+
+            // It can't be migrated twice
+            migrate_db(&db_path, DB_VERSION).unwrap_err();
+        }
+
+        // A failing migration leaves the database untouched
+        let db_path = datadir.join("failing.sqlite3");
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(SCHEMA_V0).unwrap();
+        conn.execute("INSERT INTO version (version) VALUES (0)", NO_PARAMS)
+            .unwrap();
+        conn.execute_batch("CREATE TABLE events (id INTEGER PRIMARY KEY NOT NULL);")
+            .unwrap();
+        drop(conn);
+        migrate_db(&db_path, 0).unwrap_err();
+        assert_eq!(db_version(&db_path).unwrap(), 0);
+        db_exec(&db_path, |tx| {
+            let count: u32 = tx.query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'watchtower_acks'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )?;
+            assert_eq!(count, 0);
+            Ok(())
+        })
+        .unwrap();
+
+        fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod actions;
 pub mod interface;
+pub mod migrations;
 pub mod schema;
 
 use revault_tx::bitcoin::util::psbt::Error as PsbtError;
@@ -33,4 +34,4 @@ impl From<PsbtError> for DatabaseError {
     }
 }
 
-pub const DB_VERSION: u32 = 0;
+pub const DB_VERSION: u32 = 2;
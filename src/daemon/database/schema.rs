@@ -10,7 +10,7 @@ use revault_tx::{
     },
 };
 
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt, str::FromStr};
 
 pub const SCHEMA: &str = "\
 CREATE TABLE version (
@@ -110,8 +110,87 @@ CREATE TABLE spend_transactions (
     broadcasted BOOLEAN CHECK (broadcasted IN (NULL, 0,1))
 );
 
+/* This stores the acknowledgements of our watchtowers. A row is inserted once
+ * a watchtower (identified by its Noise static public key) acknowledged it
+ * got all the fully signed revocation transactions of a vault.
+ */
+CREATE TABLE watchtower_acks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    watchtower_key BLOB NOT NULL,
+    acked_at INTEGER NOT NULL,
+    UNIQUE (vault_id, watchtower_key),
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* This logs the decisions taken about Unvault transactions when running as
+ * our own watchtower. The txid is the one of the announced Spend transaction
+ * if we let the Unvault be, or the one of the Cancel transaction if we
+ * revaulted.
+ */
+CREATE TABLE unvault_audits (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    decision INTEGER NOT NULL,
+    txid BLOB,
+    reason TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* This stores the txids of the revocation transactions we broadcast with a
+ * coin of the fee-bumping wallet attached. Adding an input changes the txid of
+ * the presigned transaction, so we need to remember them to track it.
+ */
+CREATE TABLE feebumped_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    presigned_id INTEGER NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    FOREIGN KEY (presigned_id) REFERENCES presigned_transactions (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* This is an append-only history of what happened to the vaults: their
+ * status transitions (including the ones rewound because of a reorg), the
+ * transactions we broadcast and the signatures we received. The status is the
+ * one of the vault after the event, the blockheight is the one of our tip at
+ * the time.
+ */
+CREATE TABLE events (
+    id INTEGER PRIMARY KEY NOT NULL,
+    vault_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    txid BLOB,
+    timestamp INTEGER NOT NULL,
+    blockheight INTEGER NOT NULL,
+    FOREIGN KEY (vault_id) REFERENCES vaults (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
+
+/* This indexes the transactions spending our deposit and Unvault coins (and
+ * the coins they create), as found in the blocks. It saves us from looking up
+ * the spender of a coin among all the wallet transactions. The blockheight is
+ * the one of the block the spender was found in.
+ */
+CREATE TABLE spenders (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    spender_txid BLOB NOT NULL,
+    blockheight INTEGER NOT NULL,
+    UNIQUE (txid, vout)
+);
+
 CREATE INDEX vault_status ON vaults (status);
 CREATE INDEX vault_transactions ON presigned_transactions (vault_id);
+CREATE INDEX vault_events ON events (vault_id);
 ";
 
 /// A row in the "wallets" table
@@ -178,6 +257,87 @@ tx_type_from_tx!(CancelTransaction, Cancel);
 tx_type_from_tx!(EmergencyTransaction, Emergency);
 tx_type_from_tx!(UnvaultEmergencyTransaction, UnvaultEmergency);
 
+/// A decision about an Unvault, as stored in the "unvault_audits" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnvaultDecision {
+    /// A valid Spend transaction was announced for this Unvault, let it be
+    Allowed,
+    /// No valid Spend transaction was announced for this Unvault, or it is being spent by another
+    /// one. We broadcast the Cancel
+    Canceled,
+    /// We wanted to broadcast the Cancel, but failed to
+    CancelFailed,
+}
+
+/// The kind of an event, as stored in the "events" table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    /// The vault changed status
+    StatusChange,
+    /// The vault went back to a previous status because of a reorg
+    Rewind,
+    /// We broadcast a transaction of this vault
+    Broadcast,
+    /// We received signatures for a presigned transaction of this vault
+    Signature,
+}
+
+impl TryFrom<u32> for EventKind {
+    type Error = ();
+
+    fn try_from(n: u32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Self::StatusChange),
+            1 => Ok(Self::Rewind),
+            2 => Ok(Self::Broadcast),
+            3 => Ok(Self::Signature),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "statuschange" => Ok(Self::StatusChange),
+            "rewind" => Ok(Self::Rewind),
+            "broadcast" => Ok(Self::Broadcast),
+            "signature" => Ok(Self::Signature),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::StatusChange => "statuschange",
+                Self::Rewind => "rewind",
+                Self::Broadcast => "broadcast",
+                Self::Signature => "signature",
+            }
+        )
+    }
+}
+
+/// A row in the "events" table, along with the deposit outpoint of its vault
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbEvent {
+    pub id: i64,
+    pub vault_id: u32,
+    pub deposit_outpoint: OutPoint,
+    pub kind: EventKind,
+    pub status: VaultStatus,
+    pub txid: Option<Txid>,
+    pub timestamp: u32,
+    pub blockheight: u32,
+}
+
 /// A transaction stored in the 'presigned_transactions' table
 #[derive(Debug, PartialEq, Clone)]
 pub enum RevaultTx {
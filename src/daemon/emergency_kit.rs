@@ -0,0 +1,185 @@
+//! The emergency kit is a file holding everything a stakeholder needs to carry out the
+//! emergency plan with nothing but a Bitcoin node: the finalized Emergency and UnvaultEmergency
+//! transactions of all the vaults that may still need them, along with the descriptors.
+//!
+//! We keep one up to date in our data directory, and our user may export one anywhere (for
+//! instance on a USB stick, or to print it).
+
+use crate::{
+    database::{
+        interface::{db_emer_transaction, db_unvault_emer_transaction, db_vaults},
+        DatabaseError,
+    },
+    revaultd::{RevaultD, VaultStatus},
+};
+use revault_tx::{bitcoin::consensus::encode, transactions::RevaultTransaction};
+
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+/// Bumped on any breaking change to the format of the file
+pub const EMERGENCY_KIT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum EmergencyKitError {
+    Database(DatabaseError),
+    Io(io::Error),
+}
+
+impl fmt::Display for EmergencyKitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "Emergency kit: {}", e),
+            Self::Io(e) => write!(f, "Emergency kit: I/O error: '{}'", e),
+        }
+    }
+}
+
+impl std::error::Error for EmergencyKitError {}
+
+impl From<DatabaseError> for EmergencyKitError {
+    fn from(e: DatabaseError) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl From<revault_tx::Error> for EmergencyKitError {
+    fn from(e: revault_tx::Error) -> Self {
+        Self::Database(e.into())
+    }
+}
+
+impl From<io::Error> for EmergencyKitError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+// Once all the revocation transactions are signed, and until the deposit or the Unvault
+// output is definitively spent, the emergency transactions may have to be broadcast.
+fn needs_emergency(status: VaultStatus) -> bool {
+    matches!(
+        status,
+        VaultStatus::Secured
+            | VaultStatus::Activating
+            | VaultStatus::Active
+            | VaultStatus::Unvaulting
+            | VaultStatus::Unvaulted
+            | VaultStatus::Canceling
+            | VaultStatus::EmergencyVaulting
+            | VaultStatus::UnvaultEmergencyVaulting
+            | VaultStatus::Spending
+    )
+}
+
+fn finalized_hex(
+    revaultd: &RevaultD,
+    mut tx: impl RevaultTransaction,
+) -> Result<String, EmergencyKitError> {
+    tx.finalize(&revaultd.secp_ctx)?;
+    Ok(encode::serialize_hex(&tx.into_psbt().extract_tx()))
+}
+
+/// The content of the emergency kit, along with the number of vaults in there. Only
+/// stakeholders have one.
+pub fn emergency_kit(revaultd: &RevaultD) -> Result<(serde_json::Value, usize), EmergencyKitError> {
+    let db_path = revaultd.db_file();
+    let emergency_address = revaultd
+        .emergency_address
+        .as_ref()
+        .expect("Only stakeholders have an emergency kit");
+
+    let mut vaults = Vec::new();
+    for db_vault in db_vaults(&db_path)? {
+        if !needs_emergency(db_vault.status) {
+            continue;
+        }
+        let (_, emer_tx) = db_emer_transaction(&db_path, db_vault.id)?;
+        let (_, unemer_tx) = db_unvault_emer_transaction(&db_path, db_vault.id)?;
+        let derivation_index: u32 = db_vault.derivation_index.into();
+
+        vaults.push(json!({
+            "deposit_outpoint": db_vault.deposit_outpoint.to_string(),
+            "amount": db_vault.amount.as_sat(),
+            "derivation_index": derivation_index,
+            "status": db_vault.status.to_string(),
+            "emergency_tx": finalized_hex(revaultd, emer_tx)?,
+            "unvault_emergency_tx": finalized_hex(revaultd, unemer_tx)?,
+        }));
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .unwrap_or(0);
+    let n_vaults = vaults.len();
+
+    Ok((
+        json!({
+            "version": EMERGENCY_KIT_VERSION,
+            "network": revaultd.bitcoind_config.network.to_string(),
+            "timestamp": timestamp,
+            "deposit_descriptor": revaultd.deposit_descriptor.to_string(),
+            "unvault_descriptor": revaultd.unvault_descriptor.to_string(),
+            "cpfp_descriptor": revaultd.cpfp_descriptor.to_string(),
+            "emergency_address": emergency_address.address().to_string(),
+            "vaults": vaults,
+        }),
+        n_vaults,
+    ))
+}
+
+/// Write the emergency kit to `path`, replacing any previous version of it. The file is only
+/// readable by the user. Returns the number of vaults in there.
+pub fn write_emergency_kit(
+    revaultd: &RevaultD,
+    path: &PathBuf,
+) -> Result<usize, EmergencyKitError> {
+    let (kit, n_vaults) = emergency_kit(revaultd)?;
+
+    // Write it next to it first, to never leave a truncated kit behind
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(
+        serde_json::to_string_pretty(&kit)
+            .expect("Serializing a JSON value")
+            .as_bytes(),
+    )?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(n_vaults)
+}
+
+/// Refresh the emergency kit in our data directory, if we are a stakeholder.
+pub fn refresh_emergency_kit(revaultd: &RevaultD) -> Result<(), EmergencyKitError> {
+    if !revaultd.is_stakeholder() {
+        return Ok(());
+    }
+
+    let path = revaultd.emergency_kit_file();
+    let n_vaults = write_emergency_kit(revaultd, &path)?;
+    log::debug!(
+        "Refreshed emergency kit at {:?} with '{}' vaults",
+        path,
+        n_vaults
+    );
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! Run the commands configured by the user when something happens to a vault, akin to
+//! bitcoind's `-walletnotify`.
+//!
+//! All the status changes and broadcasts are recorded in the vaults history by the database
+//! functions that perform them, we run the hooks as we go through it in the bitcoind poller.
+
+use crate::{
+    database::{
+        interface::{db_cancel_transaction, db_feebumped_txids, db_unvault_transaction},
+        schema::{DbEvent, EventKind},
+        DatabaseError,
+    },
+    revaultd::{RevaultD, VaultStatus},
+};
+use revault_tx::{bitcoin::Txid, transactions::RevaultTransaction};
+
+use std::{process::Command, thread};
+
+// The command configured for this event if any, along with the txid involved if there is one.
+fn event_hook<'a>(
+    revaultd: &'a RevaultD,
+    event: &DbEvent,
+) -> Result<Option<(&'a String, Option<Txid>)>, DatabaseError> {
+    let notify_config = &revaultd.notify_config;
+    let db_path = revaultd.db_file();
+
+    Ok(match (event.kind, event.status) {
+        // Vaults are only ever inserted as unconfirmed, afterward it's a rewind
+        (EventKind::StatusChange, VaultStatus::Unconfirmed) => notify_config
+            .deposit
+            .as_ref()
+            .map(|cmd| (cmd, Some(event.deposit_outpoint.txid))),
+        (EventKind::StatusChange, VaultStatus::Secured) => {
+            notify_config.secured.as_ref().map(|cmd| (cmd, None))
+        }
+        (EventKind::StatusChange, VaultStatus::Active) => {
+            notify_config.active.as_ref().map(|cmd| (cmd, None))
+        }
+        (EventKind::StatusChange, VaultStatus::Unvaulting) => match notify_config.unvault {
+            Some(ref cmd) => {
+                let (_, unvault_tx) = db_unvault_transaction(&db_path, event.vault_id)?;
+                Some((cmd, Some(unvault_tx.txid())))
+            }
+            None => None,
+        },
+        (EventKind::Rewind, _) => notify_config.rewind.as_ref().map(|cmd| (cmd, None)),
+        (EventKind::Broadcast, _) => match (&notify_config.cancel, event.txid) {
+            (Some(cmd), Some(txid)) => {
+                // We may have broadcast a fee-bumped version of the Cancel
+                let is_cancel = match db_cancel_transaction(&db_path, event.vault_id)? {
+                    Some((_, cancel_tx)) => {
+                        let cancel_txid = cancel_tx.txid();
+                        txid == cancel_txid
+                            || db_feebumped_txids(&db_path, &cancel_txid)?.contains(&txid)
+                    }
+                    None => false,
+                };
+                if is_cancel {
+                    Some((cmd, Some(txid)))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+// Replace the placeholders in the command configured by the user.
+fn hook_command(command: &str, event: &DbEvent, txid: Option<Txid>) -> String {
+    command
+        .replace("%o", &event.deposit_outpoint.to_string())
+        .replace("%t", &txid.map(|txid| txid.to_string()).unwrap_or_default())
+        .replace("%s", &event.status.to_string())
+}
+
+/// Run the command configured for this event of the vaults history, if any. We don't wait
+/// for it to complete.
+pub fn run_event_hook(revaultd: &RevaultD, event: &DbEvent) -> Result<(), DatabaseError> {
+    let (command, txid) = match event_hook(revaultd, event)? {
+        Some(hook) => hook,
+        None => return Ok(()),
+    };
+    let command = hook_command(command, event, txid);
+    log::debug!("Running hook '{}' for event '{}'", command, event.id);
+
+    thread::spawn(move || {
+        #[cfg(not(windows))]
+        let status = Command::new("sh").arg("-c").arg(&command).status();
+        #[cfg(windows)]
+        let status = Command::new("cmd").arg("/C").arg(&command).status();
+
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => log::warn!("Hook '{}' exited with '{}'", command, status),
+            Err(e) => log::error!("Error running hook '{}': '{}'", command, e),
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hook_command;
+    use crate::{
+        database::schema::{DbEvent, EventKind},
+        revaultd::VaultStatus,
+    };
+    use revault_tx::bitcoin::{OutPoint, Txid};
+
+    use std::str::FromStr;
+
+    #[test]
+    fn hook_placeholders() {
+        let outpoint = OutPoint::from_str(
+            "4d799e993665149109682555ba482b386aea03c5dbd62c059b48eb8f40f2f040:0",
+        )
+        .unwrap();
+        let txid =
+            Txid::from_str("c9cc1a3a4a8a6c2b43a6e1b5e6cd7d6b0a2e1ea9b7aa1b0a03e1a2c6e2e7c2a1")
+                .unwrap();
+        let event = DbEvent {
+            id: 1,
+            vault_id: 1,
+            deposit_outpoint: outpoint,
+            kind: EventKind::StatusChange,
+            status: VaultStatus::Unvaulting,
+            txid: None,
+            timestamp: 1615297315,
+            blockheight: 700000,
+        };
+
+        assert_eq!(
+            hook_command("page-ops '%t unvaults %o (%s)'", &event, Some(txid)),
+            format!("page-ops '{} unvaults {} (unvaulting)'", txid, outpoint)
+        );
+        assert_eq!(
+            hook_command("echo '%o,%t' >> rewinds.csv", &event, None),
+            format!("echo '{},' >> rewinds.csv", outpoint)
+        );
+    }
+}
@@ -4,42 +4,54 @@
 
 use crate::{
     control::{
-        announce_spend_transaction, bitcoind_broadcast_cancel, bitcoind_broadcast_unvaults,
-        check_revocation_signatures, check_spend_signatures, check_unvault_signatures,
+        announce_spend_transaction, bitcoind_broadcast_cancel, bitcoind_broadcast_cpfp,
+        bitcoind_broadcast_emergency, bitcoind_broadcast_unvault_emergency,
+        bitcoind_broadcast_unvaults, bitcoind_feebump_reserve, bitcoind_feerate,
+        bitcoind_wallet_tx, check_revocation_signatures, check_spend_signatures,
+        check_unvault_signatures, create_cpfp_psbt, create_spend_tx, estimated_fees,
         fetch_cosigner_signatures, listvaults_from_db, onchain_txs_list_from_outpoints,
-        presigned_txs_list_from_outpoints, share_rev_signatures, share_unvault_signatures,
-        ListSpendEntry, RpcUtils,
+        presigned_txs_list_from_outpoints, select_vaults_and_create_spend_tx, share_rev_signatures,
+        share_unvault_signatures, CoinSelection, FeerateTarget, ListSpendEntry, RpcUtils,
     },
     database::{
         actions::{
-            db_delete_spend, db_insert_spend, db_mark_activating_vault,
+            backup_db, db_delete_spend, db_insert_spend, db_mark_activating_vault,
             db_mark_broadcastable_spend, db_mark_securing_vault, db_update_presigned_tx,
             db_update_spend,
         },
         interface::{
-            db_cancel_transaction, db_emer_transaction, db_list_spends, db_spend_transaction,
-            db_tip, db_unvault_emer_transaction, db_unvault_transaction, db_vault_by_deposit,
-            db_vault_by_unvault_txid, db_vaults_from_spend,
+            db_cancel_transaction, db_emer_transaction, db_events, db_list_spends,
+            db_spend_transaction, db_tip, db_unvault_emer_transaction, db_unvault_transaction,
+            db_vault_by_deposit, db_vault_by_unvault_txid, db_vaults, db_vaults_by_status,
+            db_vaults_from_spend, EventsFilter,
         },
+        schema::{EventKind, RevaultTx},
     },
-    jsonrpc::UserRole,
+    emergency_kit::write_emergency_kit,
+    jsonrpc::{RpcSocket, UserRole},
     revaultd::{BlockchainTip, VaultStatus},
     threadmessages::*,
 };
 use common::VERSION;
 
 use revault_tx::{
-    bitcoin::{util::bip32, Address, OutPoint, TxOut, Txid},
+    bitcoin::{
+        consensus::encode,
+        hashes::hex::FromHex,
+        util::{bip32, psbt::PartiallySignedTransaction as Psbt},
+        Address, OutPoint, Transaction as BitcoinTransaction, Txid,
+    },
     transactions::{
-        spend_tx_from_deposits, transaction_chain, CancelTransaction, EmergencyTransaction,
-        RevaultTransaction, SpendTransaction, UnvaultEmergencyTransaction, UnvaultTransaction,
+        transaction_chain, CancelTransaction, EmergencyTransaction, RevaultTransaction,
+        SpendTransaction, UnvaultEmergencyTransaction, UnvaultTransaction,
     },
     txins::DepositTxIn,
-    txouts::{DepositTxOut, ExternalTxOut, SpendTxOut},
+    txouts::DepositTxOut,
 };
 
 use std::{
     collections::BTreeMap,
+    path::{Component, Path, PathBuf},
     str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -47,7 +59,7 @@ use std::{
     },
 };
 
-use jsonrpc_core::Error as JsonRpcError;
+use jsonrpc_core::{futures::future, Error as JsonRpcError, MetaIoHandler, Params};
 use jsonrpc_derive::rpc;
 use serde_json::json;
 
@@ -56,6 +68,8 @@ pub struct JsonRpcMetaData {
     pub shutdown: Arc<AtomicBool>,
     pub role: UserRole,
     pub rpc_utils: RpcUtils,
+    /// Whether the calls come from a client on another host, through the TCP or HTTP listener
+    pub remote: bool,
 }
 impl jsonrpc_core::Metadata for JsonRpcMetaData {}
 
@@ -65,6 +79,7 @@ impl JsonRpcMetaData {
             shutdown: Arc::from(AtomicBool::from(false)),
             role,
             rpc_utils,
+            remote: false,
         }
     }
 
@@ -78,6 +93,27 @@ impl JsonRpcMetaData {
     }
 }
 
+/// The handler for the JSONRPC commands this socket serves to this role. The others are refused
+/// before reaching `RpcImpl`.
+pub fn rpc_io_handler(role: UserRole, socket: RpcSocket) -> MetaIoHandler<JsonRpcMetaData> {
+    let mut jsonrpc_io = MetaIoHandler::<JsonRpcMetaData, _>::default();
+    let (allowed, forbidden): (Vec<_>, Vec<_>) = RpcImpl
+        .to_delegate()
+        .into_iter()
+        .partition(|(name, _)| socket.serves(role, name));
+
+    jsonrpc_io.extend_with(allowed);
+    for (name, _) in forbidden {
+        jsonrpc_io.add_method_with_meta(&name, |_: Params, _: JsonRpcMetaData| {
+            future::err::<serde_json::Value, _>(JsonRpcError::invalid_params(
+                "This is not a read-only command".to_string(),
+            ))
+        });
+    }
+
+    jsonrpc_io
+}
+
 #[rpc(server)]
 pub trait RpcApi {
     type Metadata;
@@ -99,6 +135,27 @@ pub trait RpcApi {
         outpoints: Option<Vec<OutPoint>>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
+    /// Get the history of what happened to the vaults
+    #[rpc(meta, name = "listevents")]
+    #[allow(clippy::too_many_arguments)]
+    fn listevents(
+        &self,
+        meta: Self::Metadata,
+        kinds: Option<Vec<String>>,
+        outpoints: Option<Vec<OutPoint>>,
+        start_time: Option<u32>,
+        end_time: Option<u32>,
+        start_height: Option<u32>,
+        end_height: Option<u32>,
+        offset: Option<u32>,
+        limit: Option<u32>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Keep the connection open to be notified of what happens to the vaults. The streaming
+    /// itself is done by the server.
+    #[rpc(meta, name = "subscribe")]
+    fn subscribe(&self, meta: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value>;
+
     /// Get an address to receive funds to the stakeholders' descriptor
     #[rpc(meta, name = "getdepositaddress")]
     fn getdepositaddress(
@@ -169,7 +226,8 @@ pub trait RpcApi {
         meta: Self::Metadata,
         outpoint: Vec<OutPoint>,
         outputs: BTreeMap<Address, u64>,
-        feerate: u64,
+        feerate: FeerateTarget,
+        coin_selection: Option<CoinSelection>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 
     #[rpc(meta, name = "updatespendtx")]
@@ -201,6 +259,58 @@ pub trait RpcApi {
         &self,
         meta: Self::Metadata,
         deposit_outpoint: OutPoint,
+        feerate: Option<u64>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Broadcast the Emergency (or Unvault Emergency) transaction of all our vaults
+    #[rpc(meta, name = "emergency")]
+    fn emergency(
+        &self,
+        meta: Self::Metadata,
+        feerate: Option<u64>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the state of the wallet used to bump the revocation transactions
+    #[rpc(meta, name = "getfeebumpinfo")]
+    fn getfeebumpinfo(
+        &self,
+        meta: Self::Metadata,
+        feerate: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get the fees our transactions would have to pay to be confirmed in time
+    #[rpc(meta, name = "estimatefees")]
+    fn estimatefees(
+        &self,
+        meta: Self::Metadata,
+        feerate: Option<FeerateTarget>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Get a transaction spending the CPFP output of an Unvault or Spend transaction to bump
+    /// its feerate, or broadcast it once signed.
+    #[rpc(meta, name = "bumpfee")]
+    fn bumpfee(
+        &self,
+        meta: Self::Metadata,
+        txid: Txid,
+        feerate: u64,
+        cpfp_tx: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Write a snapshot of the database to a new file
+    #[rpc(meta, name = "backup")]
+    fn backup(
+        &self,
+        meta: Self::Metadata,
+        path: PathBuf,
+    ) -> jsonrpc_core::Result<serde_json::Value>;
+
+    /// Write the signed emergency transactions of all our vaults to a file
+    #[rpc(meta, name = "exportemergencykit")]
+    fn exportemergencykit(
+        &self,
+        meta: Self::Metadata,
+        path: Option<PathBuf>,
     ) -> jsonrpc_core::Result<serde_json::Value>;
 }
 
@@ -236,6 +346,24 @@ macro_rules! manager_only {
     };
 }
 
+// Only stakeholders have a fee-bumping wallet, and bumping to a null feerate makes no sense
+macro_rules! check_feebump_feerate {
+    ($meta:ident, $feerate:expr) => {
+        if let Some(feerate) = $feerate {
+            if matches!($meta.role, UserRole::Manager) {
+                return Err(JsonRpcError::invalid_params(
+                    "Only stakeholders have a fee-bumping wallet".to_string(),
+                ));
+            }
+            if feerate < 1 {
+                return Err(JsonRpcError::invalid_params(
+                    "Feerate can't be <1".to_string(),
+                ));
+            }
+        }
+    };
+}
+
 macro_rules! parse_vault_status {
     ($status:expr) => {
         VaultStatus::from_str(&$status).map_err(|_| {
@@ -269,6 +397,41 @@ macro_rules! invalid_status {
     };
 }
 
+// The maximum number of events listevents returns if not told otherwise
+const DEFAULT_EVENTS_LIMIT: u32 = 1_000;
+
+// Check a path we were given to write a new file to. Clients on another host may only have us
+// write within our data directory.
+fn check_new_file_path(
+    meta: &JsonRpcMetaData,
+    data_dir: &Path,
+    path: &Path,
+) -> jsonrpc_core::Result<()> {
+    // We don't want it to depend on our working directory
+    if !path.is_absolute() {
+        return Err(JsonRpcError::invalid_params(format!(
+            "'{:?}' is not an absolute path",
+            path
+        )));
+    }
+    if meta.remote
+        && (!path.starts_with(data_dir) || path.components().any(|c| c == Component::ParentDir))
+    {
+        return Err(JsonRpcError::invalid_params(format!(
+            "'{:?}' is not within the data directory",
+            path
+        )));
+    }
+    if path.exists() {
+        return Err(JsonRpcError::invalid_params(format!(
+            "'{:?}' already exists",
+            path
+        )));
+    }
+
+    Ok(())
+}
+
 pub struct RpcImpl;
 impl RpcApi for RpcImpl {
     type Metadata = JsonRpcMetaData;
@@ -284,6 +447,10 @@ impl RpcApi for RpcImpl {
             .sigfetcher_tx
             .send(SigFetcherMessageOut::Shutdown)
             .map_err(|e| internal_error!(e))?;
+        meta.rpc_utils
+            .watchtowers_tx
+            .send(WatchtowersMessageOut::Shutdown)
+            .map_err(|e| internal_error!(e))?;
         meta.shutdown();
 
         Ok(())
@@ -376,6 +543,89 @@ impl RpcApi for RpcImpl {
         Ok(json!({ "vaults": vaults }))
     }
 
+    #[allow(clippy::too_many_arguments)]
+    fn listevents(
+        &self,
+        meta: Self::Metadata,
+        kinds: Option<Vec<String>>,
+        outpoints: Option<Vec<OutPoint>>,
+        start_time: Option<u32>,
+        end_time: Option<u32>,
+        start_height: Option<u32>,
+        end_height: Option<u32>,
+        offset: Option<u32>,
+        limit: Option<u32>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let db_path = meta.rpc_utils.revaultd.read().unwrap().db_file();
+
+        // As for listvaults, an empty array means no filter.
+        let kinds = kinds
+            .filter(|kinds| !kinds.is_empty())
+            .map(|kinds| {
+                kinds
+                    .into_iter()
+                    .map(|kind_str| {
+                        EventKind::from_str(&kind_str).map_err(|_| {
+                            JsonRpcError::invalid_params(format!(
+                                "'{}' is not a valid event kind",
+                                &kind_str
+                            ))
+                        })
+                    })
+                    .collect::<jsonrpc_core::Result<Vec<EventKind>>>()
+            })
+            .transpose()?;
+        let vault_ids = outpoints
+            .filter(|outpoints| !outpoints.is_empty())
+            .map(|outpoints| {
+                outpoints
+                    .into_iter()
+                    .map(|outpoint| {
+                        db_vault_by_deposit(&db_path, &outpoint)
+                            .map_err(|e| internal_error!(e))?
+                            .map(|vault| vault.id)
+                            .ok_or_else(|| unknown_outpoint!(outpoint))
+                    })
+                    .collect::<jsonrpc_core::Result<Vec<u32>>>()
+            })
+            .transpose()?;
+
+        let filter = EventsFilter {
+            vault_ids,
+            kinds,
+            start_time,
+            end_time,
+            start_height,
+            end_height,
+        };
+        let events: Vec<serde_json::Value> = db_events(
+            &db_path,
+            &filter,
+            offset.unwrap_or(0),
+            limit.unwrap_or(DEFAULT_EVENTS_LIMIT),
+        )
+        .map_err(|e| internal_error!(e))?
+        .into_iter()
+        .map(|event| {
+            json!({
+                "id": event.id,
+                "vault_outpoint": event.deposit_outpoint.to_string(),
+                "kind": event.kind.to_string(),
+                "status": event.status.to_string(),
+                "txid": event.txid.map(|txid| txid.to_string()),
+                "timestamp": event.timestamp,
+                "blockheight": event.blockheight,
+            })
+        })
+        .collect();
+
+        Ok(json!({ "events": events }))
+    }
+
+    fn subscribe(&self, _: Self::Metadata) -> jsonrpc_core::Result<serde_json::Value> {
+        Ok(json!({}))
+    }
+
     fn getdepositaddress(
         &self,
         meta: Self::Metadata,
@@ -812,152 +1062,58 @@ impl RpcApi for RpcImpl {
         meta: Self::Metadata,
         outpoints: Vec<OutPoint>,
         destinations: BTreeMap<Address, u64>,
-        feerate_vb: u64,
+        feerate: FeerateTarget,
+        coin_selection: Option<CoinSelection>,
     ) -> jsonrpc_core::Result<serde_json::Value> {
         manager_only!(meta);
 
-        if feerate_vb < 1 {
+        if feerate == FeerateTarget::Absolute(0) {
             return Err(JsonRpcError::invalid_params(
                 "Feerate can't be <1".to_string(),
             ));
         }
+        let feerate_vb = bitcoind_feerate(&meta.rpc_utils.bitcoind_tx, feerate)
+            .map_err(|e| JsonRpcError::invalid_params(e.to_string()))?;
 
         let revaultd = meta.rpc_utils.revaultd.read().unwrap();
         let db_file = &revaultd.db_file();
 
-        // Reconstruct the DepositTxin s from the outpoints and the vaults informations
-        let mut txins = Vec::with_capacity(outpoints.len());
-        // If we need a change output, use the highest derivation index of the vaults
-        // spent. This avoids leaking a new address needlessly while not introducing
-        // disrepancy between our indexes.
-        let mut change_index = bip32::ChildNumber::from(0);
-        for outpoint in outpoints.iter() {
-            let vault = db_vault_by_deposit(db_file, &outpoint)
-                .map_err(|e| internal_error!(e))?
-                .ok_or_else(|| unknown_outpoint!(outpoint))?;
-            if matches!(vault.status, VaultStatus::Active) {
-                if vault.derivation_index > change_index {
-                    change_index = vault.derivation_index;
+        let (vaults, spend_tx) = if outpoints.is_empty() {
+            // They let us choose the vaults to spend among the Active ones.
+            let active_vaults = db_vaults_by_status(db_file, VaultStatus::Active)
+                .map_err(|e| internal_error!(e))?;
+            select_vaults_and_create_spend_tx(
+                &revaultd,
+                active_vaults,
+                &destinations,
+                feerate_vb,
+                coin_selection.unwrap_or_default(),
+            )
+            .map_err(|e| JsonRpcError::invalid_params(e.to_string()))?
+        } else {
+            let mut vaults = Vec::with_capacity(outpoints.len());
+            for outpoint in outpoints.iter() {
+                let vault = db_vault_by_deposit(db_file, &outpoint)
+                    .map_err(|e| internal_error!(e))?
+                    .ok_or_else(|| unknown_outpoint!(outpoint))?;
+                if !matches!(vault.status, VaultStatus::Active) {
+                    return Err(invalid_status!(vault.status, VaultStatus::Active));
                 }
-                txins.push((*outpoint, vault.amount, vault.derivation_index));
-            } else {
-                return Err(invalid_status!(vault.status, VaultStatus::Active));
+                vaults.push(vault);
             }
-        }
-
-        // Mutable as we *may* add a change output
-        let mut txos: Vec<SpendTxOut> = destinations
-            .into_iter()
-            .map(|(addr, value)| {
-                let script_pubkey = addr.script_pubkey();
-                SpendTxOut::Destination(ExternalTxOut::new(TxOut {
-                    value,
-                    script_pubkey,
-                }))
-            })
-            .collect();
-
-        log::debug!(
-            "Creating a Spend transaction with deposit txins: '{:?}' and txos: '{:?}'",
-            &txins,
-            &txos
-        );
-
-        // This adds the CPFP output so create a dummy one to accurately compute the
-        // feerate.
-        let nochange_tx = spend_tx_from_deposits(
-            txins.clone(),
-            txos.clone(),
-            &revaultd.deposit_descriptor,
-            &revaultd.unvault_descriptor,
-            &revaultd.cpfp_descriptor,
-            revaultd.lock_time,
-            /* Deactivate insane feerate check */
-            false,
-            &revaultd.secp_ctx,
-        )
-        .map_err(|e| {
-            JsonRpcError::invalid_params(format!("Error while building spend transaction: {}", e))
-        })?;
-
-        log::debug!(
-            "Spend tx without change: '{}'",
-            nochange_tx.as_psbt_string()
-        );
-
-        // If the feerate of the transaction would be much lower (< 90/100) than what they
-        // requested for, tell them.
-        let nochange_feerate_vb = nochange_tx
-            .max_feerate()
-            .checked_mul(4)
-            .expect("bug in feerate computation");
-        if nochange_feerate_vb * 10 < feerate_vb * 9 {
-            return Err(JsonRpcError::invalid_params(format!(
-                "Required feerate ('{}') is significantly higher than actual feerate ('{}')",
-                feerate_vb, nochange_feerate_vb
-            )));
-        }
-
-        // Add a change output if it would not be dust according to our standard (200k sats
-        // atm, see DUST_LIMIT).
-        // 8 (amount) + 1 (len) + 1 (v0) + 1 (push) + 32 (witscript hash)
-        const P2WSH_TXO_WEIGHT: u64 = 43 * 4;
-        let with_change_weight = nochange_tx
-            .max_weight()
-            .checked_add(P2WSH_TXO_WEIGHT)
-            .expect("weight computation bug");
-        let cur_fees = nochange_tx.fees();
-        let want_fees = with_change_weight
-            // Mental gymnastic: sat/vbyte to sat/wu rounded up
-            .checked_mul(feerate_vb + 3)
-            .map(|vbyte| vbyte.checked_div(4).unwrap());
-        let change_value = want_fees.map(|f| cur_fees.checked_sub(f));
-        log::debug!(
-            "Weight with change: '{}'  --  Fees without change: '{}'  --  Wanted feerate: '{}'  \
-                    --  Wanted fees: '{:?}'  --  Change value: '{:?}'",
-            with_change_weight,
-            cur_fees,
-            feerate_vb,
-            want_fees,
-            change_value
-        );
-
-        if let Some(Some(change_value)) = change_value {
-            // The overhead incurred to the value of the CPFP output by the change output
-            // See https://github.com/revault/practical-revault/blob/master/transactions.md#spend_tx
-            let cpfp_overhead = 16 * P2WSH_TXO_WEIGHT;
-            if change_value > revault_tx::transactions::DUST_LIMIT + cpfp_overhead {
-                let change_txo = DepositTxOut::new(
-                    // arithmetic checked above
-                    change_value - cpfp_overhead,
-                    &revaultd
-                        .deposit_descriptor
-                        .derive(change_index, &revaultd.secp_ctx),
-                );
-                log::debug!("Adding a change txo: '{:?}'", change_txo);
-                txos.push(SpendTxOut::Change(change_txo));
-            }
-        }
-
-        // Now we can hand them the resulting transaction (sanity checked for insane fees).
-        let tx_res = spend_tx_from_deposits(
-            txins,
-            txos,
-            &revaultd.deposit_descriptor,
-            &revaultd.unvault_descriptor,
-            &revaultd.cpfp_descriptor,
-            revaultd.lock_time,
-            true,
-            &revaultd.secp_ctx,
-        )
-        .map_err(|e| {
-            JsonRpcError::invalid_params(format!("Error while building spend transaction: {}", e))
-        })?
-        .as_psbt_string();
+            let (spend_tx, _) = create_spend_tx(&revaultd, &vaults, &destinations, feerate_vb)
+                .map_err(|e| JsonRpcError::invalid_params(e.to_string()))?;
+            (vaults, spend_tx)
+        };
+        let tx_res = spend_tx.as_psbt_string();
         log::debug!("Final Spend transaction: '{:?}'", tx_res);
 
         Ok(json!({
             "spend_tx": tx_res,
+            "outpoints": vaults
+                .iter()
+                .map(|vault| vault.deposit_outpoint.to_string())
+                .collect::<Vec<String>>(),
         }))
     }
 
@@ -1133,7 +1289,9 @@ impl RpcApi for RpcImpl {
         &self,
         meta: Self::Metadata,
         deposit_outpoint: OutPoint,
+        feerate_vb: Option<u64>,
     ) -> jsonrpc_core::Result<serde_json::Value> {
+        check_feebump_feerate!(meta, feerate_vb);
         let revaultd = meta.rpc_utils.revaultd.read().unwrap();
         let db_path = revaultd.db_file();
 
@@ -1155,6 +1313,7 @@ impl RpcApi for RpcImpl {
             &db_path,
             &revaultd.secp_ctx,
             vault,
+            feerate_vb,
         )
         .map_err(|e| {
             JsonRpcError::invalid_params(format!("Broadcasting Cancel transaction: '{}'", e))
@@ -1162,4 +1321,244 @@ impl RpcApi for RpcImpl {
 
         Ok(json!({}))
     }
+
+    fn emergency(
+        &self,
+        meta: Self::Metadata,
+        feerate_vb: Option<u64>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        stakeholder_only!(meta);
+        check_feebump_feerate!(meta, feerate_vb);
+        let revaultd = meta.rpc_utils.revaultd.read().unwrap();
+        let db_path = revaultd.db_file();
+
+        // Don't stop at the first failure: we'd rather get as much funds as possible to the
+        // Emergency Deep Vault and tell them about what went wrong.
+        let mut broadcasted = Vec::new();
+        let mut failed = Vec::new();
+        for vault in db_vaults(&db_path).map_err(|e| internal_error!(e))? {
+            // Until the Unvault is broadcast the funds are still in the deposit, and we
+            // need all the revocation signatures for the Emergency to be finalizable.
+            let res = match vault.status {
+                VaultStatus::Secured | VaultStatus::Activating | VaultStatus::Active => {
+                    bitcoind_broadcast_emergency(
+                        &meta.rpc_utils.bitcoind_tx,
+                        &db_path,
+                        &revaultd.secp_ctx,
+                        vault,
+                        feerate_vb,
+                    )
+                }
+                // The Unvault output is still there as long as the Cancel or Spend isn't mined
+                VaultStatus::Unvaulting
+                | VaultStatus::Unvaulted
+                | VaultStatus::Spending
+                | VaultStatus::Canceling => bitcoind_broadcast_unvault_emergency(
+                    &meta.rpc_utils.bitcoind_tx,
+                    &db_path,
+                    &revaultd.secp_ctx,
+                    vault,
+                    feerate_vb,
+                ),
+                _ => continue,
+            };
+
+            match res {
+                Ok(txid) => broadcasted.push(json!({
+                    "vault_outpoint": vault.deposit_outpoint,
+                    "txid": txid,
+                })),
+                Err(e) => {
+                    log::error!(
+                        "Error broadcasting emergency transaction for vault at '{}': '{}'",
+                        vault.deposit_outpoint,
+                        e
+                    );
+                    failed.push(json!({
+                        "vault_outpoint": vault.deposit_outpoint,
+                        "error": e.to_string(),
+                    }));
+                }
+            }
+        }
+
+        Ok(json!({
+            "broadcasted": broadcasted,
+            "failed": failed,
+        }))
+    }
+
+    fn getfeebumpinfo(
+        &self,
+        meta: Self::Metadata,
+        feerate_vb: u64,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        stakeholder_only!(meta);
+        check_feebump_feerate!(meta, Some(feerate_vb));
+        let revaultd = meta.rpc_utils.revaultd.read().unwrap();
+
+        let reserve = bitcoind_feebump_reserve(&revaultd, &meta.rpc_utils.bitcoind_tx, feerate_vb)
+            .map_err(|e| internal_error!(e))?;
+
+        Ok(json!({
+            "address": reserve.address.to_string(),
+            "balance": reserve.balance,
+            "coins": reserve.coins,
+            "bumps": reserve.bumps,
+        }))
+    }
+
+    fn estimatefees(
+        &self,
+        meta: Self::Metadata,
+        feerate: Option<FeerateTarget>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let feerate = feerate.unwrap_or_default();
+        if feerate == FeerateTarget::Absolute(0) {
+            return Err(JsonRpcError::invalid_params(
+                "Feerate can't be <1".to_string(),
+            ));
+        }
+        let feerate_vb = bitcoind_feerate(&meta.rpc_utils.bitcoind_tx, feerate)
+            .map_err(|e| JsonRpcError::invalid_params(e.to_string()))?;
+
+        let revaultd = meta.rpc_utils.revaultd.read().unwrap();
+        let fees = estimated_fees(&revaultd, feerate_vb).map_err(|e| internal_error!(e))?;
+
+        Ok(json!(fees))
+    }
+
+    fn bumpfee(
+        &self,
+        meta: Self::Metadata,
+        txid: Txid,
+        feerate_vb: u64,
+        cpfp_tx: Option<String>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        manager_only!(meta);
+
+        if feerate_vb < 1 {
+            return Err(JsonRpcError::invalid_params(
+                "Feerate can't be <1".to_string(),
+            ));
+        }
+
+        let revaultd = meta.rpc_utils.revaultd.read().unwrap();
+        let db_path = revaultd.db_file();
+
+        // The CPFP output of an Unvault is derived at the index of its vault, the one of a Spend
+        // at the index of one of the vaults it spends.
+        let (parent_fees, cpfp_indexes) = if let Some((db_vault, db_tx)) =
+            db_vault_by_unvault_txid(&db_path, &txid).map_err(|e| internal_error!(e))?
+        {
+            let unvault_tx = match db_tx.psbt {
+                RevaultTx::Unvault(tx) => tx,
+                _ => unreachable!("We queried an Unvault"),
+            };
+            (unvault_tx.fees(), vec![db_vault.derivation_index])
+        } else if let Some(db_spend) =
+            db_spend_transaction(&db_path, &txid).map_err(|e| internal_error!(e))?
+        {
+            let spent_vaults =
+                db_vaults_from_spend(&db_path, &txid).map_err(|e| internal_error!(e))?;
+            (
+                db_spend.psbt.fees(),
+                spent_vaults
+                    .values()
+                    .map(|db_vault| db_vault.derivation_index)
+                    .collect(),
+            )
+        } else {
+            return Err(JsonRpcError::invalid_params(format!(
+                "'{}' is not a known Unvault or Spend transaction",
+                txid
+            )));
+        };
+
+        // We can only bump it if it's still unconfirmed.
+        let wallet_tx = bitcoind_wallet_tx(&meta.rpc_utils.bitcoind_tx, txid)
+            .map_err(|e| internal_error!(e))?
+            .ok_or_else(|| {
+                JsonRpcError::invalid_params(format!("Transaction '{}' was not broadcast", txid))
+            })?;
+        if wallet_tx.blockheight.is_some() {
+            return Err(JsonRpcError::invalid_params(format!(
+                "Transaction '{}' is already confirmed",
+                txid
+            )));
+        }
+        let parent: BitcoinTransaction = Vec::from_hex(&wallet_tx.hex)
+            .map_err(|e| internal_error!(e))
+            .and_then(|bytes| encode::deserialize(&bytes).map_err(|e| internal_error!(e)))?;
+
+        let cpfp_psbt =
+            create_cpfp_psbt(&revaultd, &parent, parent_fees, &cpfp_indexes, feerate_vb)
+                .map_err(|e| JsonRpcError::invalid_params(e.to_string()))?;
+
+        // If they didn't give us the signed CPFP transaction yet, give it to them to sign.
+        let cpfp_tx = match cpfp_tx {
+            Some(cpfp_tx) => cpfp_tx,
+            None => {
+                return Ok(json!({
+                    "cpfp_tx": base64::encode(encode::serialize(&cpfp_psbt)),
+                }))
+            }
+        };
+        let signed_psbt: Psbt = base64::decode(&cpfp_tx)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| encode::deserialize(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                JsonRpcError::invalid_params(format!("Invalid CPFP transaction: '{}'", e))
+            })?;
+        if signed_psbt.global.unsigned_tx != cpfp_psbt.global.unsigned_tx {
+            return Err(JsonRpcError::invalid_params(
+                "The CPFP transaction does not bump this transaction to this feerate".to_string(),
+            ));
+        }
+
+        let cpfp_txid =
+            bitcoind_broadcast_cpfp(&meta.rpc_utils.bitcoind_tx, &revaultd.secp_ctx, signed_psbt)
+                .map_err(|e| {
+                JsonRpcError::invalid_params(format!("Broadcasting CPFP transaction: '{}'", e))
+            })?;
+
+        Ok(json!({
+            "txid": cpfp_txid,
+        }))
+    }
+
+    fn backup(
+        &self,
+        meta: Self::Metadata,
+        path: PathBuf,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        let revaultd = meta.rpc_utils.revaultd.read().unwrap();
+        check_new_file_path(&meta, &revaultd.data_dir, &path)?;
+        backup_db(&revaultd, &path).map_err(|e| internal_error!(e))?;
+
+        Ok(json!({}))
+    }
+
+    fn exportemergencykit(
+        &self,
+        meta: Self::Metadata,
+        path: Option<PathBuf>,
+    ) -> jsonrpc_core::Result<serde_json::Value> {
+        stakeholder_only!(meta);
+
+        let revaultd = meta.rpc_utils.revaultd.read().unwrap();
+        let path = match path {
+            Some(path) => {
+                check_new_file_path(&meta, &revaultd.data_dir, &path)?;
+                path
+            }
+            None => revaultd.emergency_kit_file(),
+        };
+        let n_vaults = write_emergency_kit(&revaultd, &path).map_err(|e| internal_error!(e))?;
+
+        Ok(json!({
+            "path": path,
+            "vaults": n_vaults,
+        }))
+    }
 }
@@ -0,0 +1,571 @@
+//! An optional HTTP listener mapping REST routes onto the JSONRPC commands, for HTTP-native
+//! clients. Requests are translated to calls to the same `RpcImpl` handlers (hence the same role
+//! checks) and answered with the same JSON as the `result` of the JSONRPC responses. Clients
+//! authenticate with the token of our cookie file as a bearer token.
+//! The status changes of the vaults are streamed on a WebSocket at `/ws`.
+
+use crate::{
+    jsonrpc::{
+        api::{rpc_io_handler, JsonRpcMetaData},
+        server::{notification_bytes, NotificationSenders},
+        RpcSocket,
+    },
+    threadmessages::Notification,
+};
+
+use revault_net::sodiumoxide;
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use jsonrpc_core::{
+    futures::Future, types::error::ErrorCode, Call, Id, MetaIoHandler, MethodCall, Output, Params,
+    Version,
+};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+// Don't let a client make us buffer an endless body. PSBTs can be big, though.
+const MAX_BODY_LEN: u64 = 4 * 1024 * 1024;
+
+// How long a WebSocket client may go without hearing from us before we check it's still there
+const WEBSOCKET_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+// To unregister the channel of a WebSocket client once it's gone
+static NEXT_WEBSOCKET_ID: AtomicU64 = AtomicU64::new(0);
+
+// Where to get a parameter of the JSONRPC command from
+enum Param {
+    // The variable segment of the path
+    Path,
+    // The variable segment of the path, as a single-element list
+    PathList,
+    // A query string parameter
+    Query(&'static str),
+    // A comma-separated query string parameter, as a list
+    QueryList(&'static str),
+    // A field of the JSON object in the request body
+    Body(&'static str),
+}
+
+// A REST route for a JSONRPC command. The path may contain one variable segment, starting with
+// ':'. Missing parameters are passed as null.
+struct Route {
+    method: Method,
+    path: &'static str,
+    command: &'static str,
+    params: &'static [Param],
+}
+
+macro_rules! route {
+    ($method:ident $path:literal => $command:literal $(, $param:expr)* $(,)?) => {
+        Route {
+            method: Method::$method,
+            path: $path,
+            command: $command,
+            params: &[$($param),*],
+        }
+    };
+}
+
+fn routes() -> Vec<Route> {
+    use Param::*;
+
+    vec![
+        route!(Get "/info" => "getinfo"),
+        route!(Post "/stop" => "stop"),
+        route!(Get "/vaults" => "listvaults", QueryList("status"), QueryList("outpoint")),
+        route!(Get "/vaults/:outpoint/presigned" => "listpresignedtransactions", PathList),
+        route!(Get "/vaults/:outpoint/onchain" => "listonchaintransactions", PathList),
+        route!(Get "/vaults/:outpoint/revocationtxs" => "getrevocationtxs", Path),
+        route!(
+            Post "/vaults/:outpoint/revocationtxs" => "revocationtxs",
+            Path,
+            Body("cancel_tx"),
+            Body("emergency_tx"),
+            Body("emergency_unvault_tx"),
+        ),
+        route!(Get "/vaults/:outpoint/unvaulttx" => "getunvaulttx", Path),
+        route!(Post "/vaults/:outpoint/unvaulttx" => "unvaulttx", Path, Body("unvault_tx")),
+        route!(Post "/vaults/:outpoint/revault" => "revault", Path, Body("feerate")),
+        route!(Get "/presigned" => "listpresignedtransactions", QueryList("outpoint")),
+        route!(Get "/onchain" => "listonchaintransactions", QueryList("outpoint")),
+        route!(
+            Get "/events" => "listevents",
+            QueryList("kind"),
+            QueryList("outpoint"),
+            Query("start_time"),
+            Query("end_time"),
+            Query("start_height"),
+            Query("end_height"),
+            Query("offset"),
+            Query("limit"),
+        ),
+        route!(Get "/depositaddress" => "getdepositaddress", Query("index")),
+        route!(Get "/spend" => "listspendtxs"),
+        route!(
+            Post "/spend" => "getspendtx",
+            Body("outpoints"),
+            Body("outputs"),
+            Body("feerate"),
+            Body("coin_selection"),
+        ),
+        route!(Put "/spend" => "updatespendtx", Body("spend_tx")),
+        route!(Delete "/spend/:txid" => "delspendtx", Path),
+        route!(Post "/spend/:txid/broadcast" => "setspendtx", Path),
+        route!(Post "/emergency" => "emergency", Body("feerate")),
+        route!(Get "/feebump" => "getfeebumpinfo", Query("feerate")),
+        route!(Get "/fees" => "estimatefees", Query("feerate")),
+        route!(Post "/bumpfee" => "bumpfee", Body("txid"), Body("feerate"), Body("cpfp_tx")),
+        route!(Post "/backup" => "backup", Body("path")),
+        route!(Post "/emergencykit" => "exportemergencykit", Body("path")),
+    ]
+}
+
+// Decode the %-escaped bytes of an URL component
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let key = percent_decode(kv.next().expect("splitn always yields once"));
+            let value = percent_decode(&kv.next().unwrap_or("").replace('+', " "));
+            (key, value)
+        })
+        .collect()
+}
+
+// Find the route for this request, along with the value of its variable segment if any
+fn find_route<'r, 'p>(
+    routes: &'r [Route],
+    method: &Method,
+    path: &'p str,
+) -> Option<(&'r Route, Option<&'p str>)> {
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+
+    routes.iter().find_map(|route| {
+        if &route.method != method {
+            return None;
+        }
+
+        let route_segments: Vec<&str> = route.path.split('/').collect();
+        if route_segments.len() != segments.len() {
+            return None;
+        }
+
+        let mut variable = None;
+        for (route_segment, segment) in route_segments.iter().zip(segments.iter()) {
+            if route_segment.starts_with(':') {
+                variable = Some(*segment);
+            } else if route_segment != segment {
+                return None;
+            }
+        }
+
+        Some((route, variable))
+    })
+}
+
+// The parameters of the JSONRPC command, in order. A query string value is passed as JSON if it
+// is valid JSON (think of numbers), as a string otherwise.
+fn command_params(
+    route: &Route,
+    variable: Option<&str>,
+    query: &HashMap<String, String>,
+    mut body: serde_json::Map<String, Value>,
+) -> Vec<Value> {
+    let variable = variable.map(|v| Value::String(percent_decode(v)));
+
+    route
+        .params
+        .iter()
+        .map(|param| match param {
+            Param::Path => variable.clone().unwrap_or(Value::Null),
+            Param::PathList => variable
+                .clone()
+                .map(|v| Value::Array(vec![v]))
+                .unwrap_or(Value::Null),
+            Param::Query(name) => query
+                .get(*name)
+                .map(|v| serde_json::from_str(v).unwrap_or_else(|_| Value::String(v.clone())))
+                .unwrap_or(Value::Null),
+            Param::QueryList(name) => query
+                .get(*name)
+                .map(|v| {
+                    Value::Array(
+                        v.split(',')
+                            .filter(|item| !item.is_empty())
+                            .map(|item| Value::String(item.to_string()))
+                            .collect(),
+                    )
+                })
+                .unwrap_or(Value::Null),
+            Param::Body(name) => body.remove(*name).unwrap_or(Value::Null),
+        })
+        .collect()
+}
+
+fn json_response(status: u16, body: &Value) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_data(serde_json::to_vec(body).expect("Serializing a JSON value"))
+        .with_status_code(status)
+        .with_header(
+            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                .expect("Valid header"),
+        )
+}
+
+fn error_response(status: u16, message: &str) -> Response<io::Cursor<Vec<u8>>> {
+    json_response(status, &json!({ "error": { "message": message } }))
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+// Check the bearer token of this request
+fn is_authorized(request: &Request, token: &str) -> bool {
+    header(request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|given| sodiumoxide::utils::memcmp(given.trim().as_bytes(), token.as_bytes()))
+        .unwrap_or(false)
+}
+
+// Call the JSONRPC command for this route, and translate its response
+fn handle_route(
+    jsonrpc_io: &MetaIoHandler<JsonRpcMetaData>,
+    metadata: &JsonRpcMetaData,
+    request: &mut Request,
+    route: &Route,
+    variable: Option<&str>,
+    query: &HashMap<String, String>,
+) -> Response<io::Cursor<Vec<u8>>> {
+    let mut body = String::new();
+    if let Err(e) = request
+        .as_reader()
+        .take(MAX_BODY_LEN)
+        .read_to_string(&mut body)
+    {
+        return error_response(400, &format!("Reading request body: {}", e));
+    }
+    let body = if body.trim().is_empty() {
+        serde_json::Map::new()
+    } else {
+        match serde_json::from_str(&body) {
+            Ok(Value::Object(map)) => map,
+            _ => return error_response(400, "The request body must be a JSON object"),
+        }
+    };
+
+    let call = MethodCall {
+        jsonrpc: Some(Version::V2),
+        method: route.command.to_string(),
+        params: Params::Array(command_params(route, variable, query, body)),
+        id: Id::Num(0),
+    };
+    let output = jsonrpc_io
+        .handle_call(Call::MethodCall(call), metadata.clone())
+        .wait()
+        .expect("jsonrpc_core says: Handler calls can never fail.");
+
+    match output {
+        Some(Output::Success(success)) => json_response(200, &success.result),
+        Some(Output::Failure(failure)) => {
+            let status = match failure.error.code {
+                ErrorCode::ParseError | ErrorCode::InvalidRequest | ErrorCode::InvalidParams => 400,
+                ErrorCode::MethodNotFound => 404,
+                _ => 500,
+            };
+            json_response(status, &json!({ "error": failure.error }))
+        }
+        None => error_response(500, "No response to a method call"),
+    }
+}
+
+// Upgrade the connection to a WebSocket and stream the status changes of the vaults on it
+fn serve_websocket(request: Request, notifications: NotificationSenders) {
+    let is_upgrade = header(&request, "Upgrade")
+        .map(|protocol| protocol.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+    let accept_key = match header(&request, "Sec-WebSocket-Key") {
+        Some(key) if is_upgrade => derive_accept_key(key.as_bytes()),
+        _ => {
+            if let Err(e) = request.respond(error_response(400, "Expected a WebSocket upgrade")) {
+                log::error!("HTTP JSONRPC server: error writing response: '{}'", e);
+            }
+            return;
+        }
+    };
+    let response = Response::empty(101)
+        .with_header(Header::from_bytes(&b"Upgrade"[..], &b"websocket"[..]).expect("Valid header"))
+        .with_header(Header::from_bytes(&b"Connection"[..], &b"Upgrade"[..]).expect("Valid header"))
+        .with_header(
+            Header::from_bytes(&b"Sec-WebSocket-Accept"[..], accept_key.as_bytes())
+                .expect("Valid header"),
+        );
+    // Register before the handshake completes, so they don't miss anything once it did
+    let id = NEXT_WEBSOCKET_ID.fetch_add(1, Ordering::Relaxed);
+    let (notif_tx, notif_rx) = mpsc::channel();
+    notifications.write().unwrap().insert(id, notif_tx);
+    let stream = request.upgrade("websocket", response);
+    let mut websocket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    if let Err(e) = stream_notifications(&mut websocket, notif_rx) {
+        log::debug!("HTTP JSONRPC server: closing WebSocket: '{}'", e);
+    }
+    notifications.write().unwrap().remove(&id);
+}
+
+// Write the status changes on this WebSocket as we receive them, until the client goes away or
+// the other threads are done. We can't wait for both a notification and a message from the client,
+// so once there was nothing to send for a while we ping the client and read until it answers. This
+// is where we notice it closed the connection.
+fn stream_notifications<S: Read + Write>(
+    websocket: &mut WebSocket<S>,
+    notif_rx: mpsc::Receiver<Notification>,
+) -> Result<(), tungstenite::Error> {
+    loop {
+        match notif_rx.recv_timeout(WEBSOCKET_PING_INTERVAL) {
+            Ok(notif @ Notification::StatusChange { .. }) => {
+                let msg = String::from_utf8(notification_bytes(notif)).expect("JSON is utf-8");
+                websocket.write_message(Message::Text(msg))?;
+            }
+            Ok(_) => {}
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                websocket.write_message(Message::Ping(Vec::new()))?;
+                loop {
+                    match websocket.read_message()? {
+                        Message::Pong(_) => break,
+                        // Our reply is queued, it's sent once we get 'ConnectionClosed'
+                        Message::Close(_) => {
+                            return match websocket.write_pending() {
+                                Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Ok(()),
+                                Err(e) => Err(e),
+                            }
+                        }
+                        // Pings are answered for us, and clients have nothing to tell us
+                        _ => {}
+                    }
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// The HTTP listener, along with the token clients must present
+pub struct HttpRpcListener {
+    server: Server,
+    token: String,
+}
+
+impl HttpRpcListener {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr()
+    }
+}
+
+/// Bind to `addr`, for clients authenticating with this bearer `token`
+pub fn http_rpcserver_setup(addr: SocketAddr, token: String) -> Result<HttpRpcListener, io::Error> {
+    let server = Server::http(addr).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(HttpRpcListener { server, token })
+}
+
+/// Handle each HTTP request in its own thread. WebSocket clients are sent the status changes
+/// received through a channel registered in `notifications`. `on_shutdown` is called once a client
+/// told us to stop, for the main loop to notice it.
+pub fn http_rpcserver_loop<F>(
+    listener: HttpRpcListener,
+    metadata: JsonRpcMetaData,
+    notifications: NotificationSenders,
+    on_shutdown: F,
+) where
+    F: Fn() + Send + Sync + 'static,
+{
+    let jsonrpc_io = Arc::new(rpc_io_handler(metadata.role, RpcSocket::Main));
+    let routes = Arc::new(routes());
+    let on_shutdown = Arc::new(on_shutdown);
+    let HttpRpcListener { server, token } = listener;
+
+    log::info!(
+        "HTTP JSONRPC server listening on '{}'",
+        server.server_addr()
+    );
+    for mut request in server.incoming_requests() {
+        if metadata.is_shutdown() {
+            break;
+        }
+
+        if !is_authorized(&request, &token) {
+            log::warn!(
+                "Refusing HTTP JSONRPC request from '{:?}': invalid token",
+                request.remote_addr()
+            );
+            if let Err(e) = request.respond(error_response(401, "Invalid token")) {
+                log::error!("HTTP JSONRPC server: error writing response: '{}'", e);
+            }
+            continue;
+        }
+
+        let (jsonrpc_io, metadata, routes, notifications, on_shutdown) = (
+            jsonrpc_io.clone(),
+            metadata.clone(),
+            routes.clone(),
+            notifications.clone(),
+            on_shutdown.clone(),
+        );
+        thread::spawn(move || {
+            let url = request.url().to_string();
+            let mut url = url.splitn(2, '?');
+            let path = url.next().expect("splitn always yields once");
+            let query = parse_query(url.next().unwrap_or(""));
+            log::trace!("HTTP JSONRPC request: {} {}", request.method(), path);
+
+            if request.method() == &Method::Get && path == "/ws" {
+                return serve_websocket(request, notifications);
+            }
+
+            let response = match find_route(&routes, request.method(), path) {
+                Some((route, variable)) => handle_route(
+                    &jsonrpc_io,
+                    &metadata,
+                    &mut request,
+                    route,
+                    variable,
+                    &query,
+                ),
+                None => error_response(404, "Unknown route"),
+            };
+            if let Err(e) = request.respond(response) {
+                log::error!("HTTP JSONRPC server: error writing response: '{}'", e);
+            }
+
+            if metadata.is_shutdown() {
+                on_shutdown();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_params, find_route, parse_query, percent_decode, routes};
+
+    use serde_json::{json, Map};
+    use tiny_http::Method;
+
+    #[test]
+    fn rest_routes() {
+        let routes = routes();
+        let outpoint = "cafebabe00000000000000000000000000000000000000000000000000000000:1";
+
+        // A listing with filters in the query string
+        let query = parse_query("status=active,unvaulted&outpoint=");
+        let (route, variable) = find_route(&routes, &Method::Get, "/vaults").unwrap();
+        assert_eq!(route.command, "listvaults");
+        assert_eq!(
+            command_params(route, variable, &query, Map::new()),
+            vec![json!(["active", "unvaulted"]), json!([])]
+        );
+        let (route, variable) = find_route(&routes, &Method::Get, "/vaults/").unwrap();
+        assert_eq!(
+            command_params(route, variable, &parse_query(""), Map::new()),
+            vec![json!(null), json!(null)]
+        );
+
+        // The vault in the path, possibly escaped
+        let path = format!("/vaults/{}/presigned", outpoint.replace(':', "%3A"));
+        let (route, variable) = find_route(&routes, &Method::Get, &path).unwrap();
+        assert_eq!(route.command, "listpresignedtransactions");
+        assert_eq!(
+            command_params(route, variable, &parse_query(""), Map::new()),
+            vec![json!([outpoint])]
+        );
+
+        // The parameters in the body, whatever their order, and the numbers of the query string
+        let body = json!({"feerate": 12, "outputs": {"bc1qaddr": 42}, "outpoints": [outpoint]});
+        let (route, variable) = find_route(&routes, &Method::Post, "/spend").unwrap();
+        assert_eq!(route.command, "getspendtx");
+        assert_eq!(
+            command_params(
+                route,
+                variable,
+                &parse_query(""),
+                body.as_object().unwrap().clone()
+            ),
+            vec![
+                json!([outpoint]),
+                json!({"bc1qaddr": 42}),
+                json!(12),
+                json!(null)
+            ]
+        );
+        let (route, variable) = find_route(&routes, &Method::Get, "/events").unwrap();
+        assert_eq!(
+            command_params(
+                route,
+                variable,
+                &parse_query("limit=10&kind=broadcast"),
+                Map::new()
+            ),
+            vec![
+                json!(["broadcast"]),
+                json!(null),
+                json!(null),
+                json!(null),
+                json!(null),
+                json!(null),
+                json!(null),
+                json!(10)
+            ]
+        );
+
+        // The method matters
+        let (route, _) = find_route(&routes, &Method::Get, "/spend").unwrap();
+        assert_eq!(route.command, "listspendtxs");
+        assert!(find_route(&routes, &Method::Delete, "/vaults").is_none());
+        assert!(find_route(&routes, &Method::Get, "/vaults/a/b/c").is_none());
+
+        assert_eq!(percent_decode("a%3Ab%2c%zz%"), "a:b,%zz%");
+    }
+}
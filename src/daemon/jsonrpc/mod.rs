@@ -1,11 +1,66 @@
 mod api;
+pub mod http;
 pub mod server;
+pub mod tcp;
 
 /// Some calls are Stakeholder-only or Manager-only. This makes the API code
 /// aware of whether we were started as such to immediately forbid some of them.
+/// An Observer may only call the read-only commands of `OBSERVER_COMMANDS`.
 #[derive(Debug, Clone, Copy)]
 pub enum UserRole {
     Manager,
     Stakeholder,
     ManagerStakeholder,
+    Observer,
+}
+
+/// The commands an Observer may call. They never sign, share or broadcast anything, nor touch
+/// the database.
+pub const OBSERVER_COMMANDS: &[&str] = &[
+    "getinfo",
+    "listvaults",
+    "listevents",
+    "listpresignedtransactions",
+    "listonchaintransactions",
+    "listspendtxs",
+    "subscribe",
+];
+
+impl UserRole {
+    /// Whether this role may call this command at all
+    pub fn allows(&self, command: &str) -> bool {
+        match self {
+            UserRole::Observer => OBSERVER_COMMANDS.contains(&command),
+            UserRole::Manager | UserRole::Stakeholder | UserRole::ManagerStakeholder => true,
+        }
+    }
+}
+
+/// The commands served on a socket
+#[derive(Debug, Clone, Copy)]
+pub enum RpcSocket {
+    /// The main socket, and the remote listeners. It serves what our role may call, and `stop`.
+    Main,
+    /// The 'revaultd_observer_rpc' socket, which only serves the `OBSERVER_COMMANDS`
+    Observer,
+}
+
+impl RpcSocket {
+    /// Whether this socket serves this command to a daemon with this role
+    pub fn serves(&self, role: UserRole, command: &str) -> bool {
+        match self {
+            RpcSocket::Main => command == "stop" || role.allows(command),
+            RpcSocket::Observer => OBSERVER_COMMANDS.contains(&command),
+        }
+    }
+}
+
+/// Whether this is the name of one of our commands
+pub fn is_command(name: &str) -> bool {
+    use api::RpcApi;
+
+    api::RpcImpl
+        .to_delegate()
+        .into_iter()
+        .any(|(command, _)| command == name)
 }
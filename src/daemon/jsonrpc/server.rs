@@ -1,33 +1,45 @@
 //! Here we handle incoming connections and communication on the RPC socket.
 //! Actual JSONRPC2 commands are handled in the `api` mod.
+//! Clients that called `subscribe` keep their connection open, and we stream them JSONRPC2
+//! notifications for what the bitcoind and signature fetcher threads notify us of.
+//! Clients on another host may connect to the optional TCP and HTTP listeners of the `tcp` and
+//! `http` mods instead.
+//! On Linux, the calls may further be restricted to some users of the local clients by an ACL
+//! checked against the credentials of the connecting process.
 
 use crate::{
     control::RpcUtils,
     jsonrpc::{
-        api::{JsonRpcMetaData, RpcApi, RpcImpl},
-        UserRole,
+        api::{rpc_io_handler, JsonRpcMetaData},
+        http::{http_rpcserver_loop, HttpRpcListener},
+        tcp::{tcp_rpcserver_loop, TcpRpcListener},
+        RpcSocket, UserRole,
     },
+    threadmessages::Notification,
 };
-use common::assume_some;
+use common::{assume_some, config::RpcAclEntry};
 
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     io::{self, Write},
     path::PathBuf,
     process,
-    sync::{Arc, RwLock},
+    sync::{mpsc, Arc, RwLock},
     thread,
 };
 
 #[cfg(not(windows))]
 use mio::{
     net::{UnixListener, UnixStream},
-    Events, Interest, Poll, Token,
+    Events, Interest, Poll, Token, Waker,
 };
 #[cfg(windows)]
 use uds_windows::{UnixListener, UnixStream};
 
-use jsonrpc_core::{futures::Future, Call, MethodCall, Response};
+use jsonrpc_core::{
+    futures::Future, Call, Error as JsonRpcError, Failure, MethodCall, Output, Response,
+};
+use serde_json::json;
 
 // Maximum number of concurrent handlers for incoming RPC commands
 const MAX_HANDLER_THREADS: usize = 4;
@@ -135,17 +147,225 @@ fn write_byte_stream(stream: &mut UnixStream, resp: Vec<u8>) -> Result<Option<Ve
     }
 }
 
+// Write as much as we can from this queue of responses to the stream.
+#[cfg(not(windows))]
+fn write_resp_queue(stream: &mut UnixStream, resp_queue: &RwLock<VecDeque<Vec<u8>>>, token: Token) {
+    // FIFO
+    loop {
+        // We can't use while let Some(resp) because deadlock
+        let resp = match resp_queue.write().unwrap().pop_front() {
+            Some(resp) => resp,
+            None => break,
+        };
+
+        log::trace!("Writing response for {:?} ({} bytes)", token, resp.len());
+        // If we could not write the data, don't lose track of it! This would only
+        // reasonably happen on `WouldBlock`.
+        match write_byte_stream(stream, resp) {
+            Ok(Some(resp)) => resp_queue.write().unwrap().push_front(resp),
+            Ok(None) => {}
+            Err(e) => log::error!("Error writing resp for {:?}: '{}'", token, e),
+        }
+    }
+}
+
+// Serialize a notification from the other threads as a JSONRPC2 notification.
+pub(super) fn notification_bytes(notif: Notification) -> Vec<u8> {
+    let (method, params) = match notif {
+        Notification::Deposit { outpoint, amount } => (
+            "deposit",
+            json!({"vault_outpoint": outpoint.to_string(), "amount": amount.as_sat()}),
+        ),
+        Notification::StatusChange { outpoint, status } => (
+            "statuschange",
+            json!({"vault_outpoint": outpoint.to_string(), "status": status.to_string()}),
+        ),
+        Notification::Signature { outpoint, txid } => (
+            "signature",
+            json!({"vault_outpoint": outpoint.to_string(), "txid": txid.to_string()}),
+        ),
+        Notification::Broadcast { outpoint, txid } => (
+            "broadcast",
+            json!({"vault_outpoint": outpoint.to_string(), "txid": txid.to_string()}),
+        ),
+    };
+
+    serde_json::to_vec(&json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+    }))
+    .expect("Serializing a JSON value")
+}
+
+// The channels to forward the notifications to, for the WebSocket clients of the HTTP listener.
+// They are indexed by an id for each client to remove its own once it's gone.
+pub(super) type NotificationSenders = Arc<RwLock<HashMap<u64, mpsc::Sender<Notification>>>>;
+
+/// The listeners for the clients on other hosts, if configured. They are served from their own
+/// threads.
+#[derive(Default)]
+pub struct RemoteListeners {
+    pub tcp: Option<TcpRpcListener>,
+    pub http: Option<HttpRpcListener>,
+}
+
+impl RemoteListeners {
+    // Start serving the remote clients. `on_shutdown` is called once one of them told us to stop.
+    fn serve<F>(
+        self,
+        metadata: &JsonRpcMetaData,
+        notifications: NotificationSenders,
+        on_shutdown: F,
+    ) where
+        F: Fn() + Clone + Send + Sync + 'static,
+    {
+        let metadata = JsonRpcMetaData {
+            remote: true,
+            ..metadata.clone()
+        };
+        if let Some(tcp_listener) = self.tcp {
+            let (metadata, on_shutdown) = (metadata.clone(), on_shutdown.clone());
+            thread::spawn(move || tcp_rpcserver_loop(tcp_listener, metadata, on_shutdown));
+        }
+        if let Some(http_listener) = self.http {
+            let metadata = metadata.clone();
+            thread::spawn(move || {
+                http_rpcserver_loop(http_listener, metadata, notifications, on_shutdown)
+            });
+        }
+    }
+}
+
+/// The user of the process on the other end of a connection
+#[derive(Debug, Clone, Copy)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+// Get the credentials of the process on the other end of this connection, as it was when it
+// connected.
+#[cfg(target_os = "linux")]
+fn peer_credentials(stream: &UnixStream) -> Option<PeerCredentials> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        log::error!(
+            "Error getting the credentials of a JSONRPC client: '{}'",
+            io::Error::last_os_error()
+        );
+        return None;
+    }
+
+    Some(PeerCredentials {
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+// FIXME: use getpeereid() on the BSDs
+#[cfg(all(not(windows), not(target_os = "linux")))]
+fn peer_credentials(_: &UnixStream) -> Option<PeerCredentials> {
+    None
+}
+
+/// Which commands each user may call, as configured. We may call anything, and once an ACL is
+/// set the other users may only call what it lists for them.
+#[derive(Debug, Clone, Default)]
+pub struct RpcAcl {
+    own_uid: u32,
+    // None if no ACL is set
+    allowed: Option<HashMap<u32, HashSet<String>>>,
+}
+
+impl RpcAcl {
+    pub fn new(own_uid: u32, entries: &[RpcAclEntry]) -> Self {
+        if entries.is_empty() {
+            return RpcAcl {
+                own_uid,
+                allowed: None,
+            };
+        }
+
+        let mut allowed = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            allowed
+                .entry(entry.uid)
+                .or_insert_with(HashSet::new)
+                .extend(entry.methods.iter().cloned());
+        }
+
+        RpcAcl {
+            own_uid,
+            allowed: Some(allowed),
+        }
+    }
+
+    /// Whether this peer may call this command. If we could not get its credentials, it may not
+    /// call anything once an ACL is set.
+    pub fn allows(&self, peer: Option<PeerCredentials>, method: &str) -> bool {
+        let allowed = match self.allowed {
+            Some(ref allowed) => allowed,
+            None => return true,
+        };
+
+        match peer {
+            Some(peer) if peer.uid == self.own_uid => true,
+            Some(peer) => allowed
+                .get(&peer.uid)
+                .map(|methods| methods.contains(method))
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+}
+
+// The error response to a call the ACL doesn't allow
+fn denied_response(message: &MethodCall) -> Vec<u8> {
+    let resp = Response::Single(Output::Failure(Failure {
+        jsonrpc: message.jsonrpc,
+        // TODO: we should declare some custom error codes instead of abusing -32602
+        error: JsonRpcError::invalid_params(
+            "This command is not allowed for this user".to_string(),
+        ),
+        id: message.id.clone(),
+    }));
+
+    serde_json::to_vec(&resp).expect("jsonrpc_core says: This should never fail.")
+}
+
 // Used to check if, when receiving an event for a token, we have an ongoing connection and stream
 // for it.
 #[cfg(not(windows))]
 type ConnectionMap = HashMap<Token, (UnixStream, Arc<RwLock<VecDeque<Vec<u8>>>>)>;
 
+// The response queues of the connections that subscribed to the notifications.
+#[cfg(not(windows))]
+type SubscribersMap = Arc<RwLock<HashMap<Token, Arc<RwLock<VecDeque<Vec<u8>>>>>>>;
+
+// Returns true if the request was handled successfully.
 fn handle_single_request(
     jsonrpc_io: Arc<RwLock<jsonrpc_core::MetaIoHandler<JsonRpcMetaData>>>,
     metadata: JsonRpcMetaData,
     resp_queue: Arc<RwLock<VecDeque<Vec<u8>>>>,
     message: MethodCall,
-) {
+) -> bool {
     let res = assume_some!(
         jsonrpc_io
             .read()
@@ -155,10 +375,13 @@ fn handle_single_request(
             .expect("jsonrpc_core says: Handler calls can never fail."),
         "This is a method call, there is always a response."
     );
+    let success = matches!(res, Output::Success(_));
     let resp = Response::Single(res);
     let resp_bytes = serde_json::to_vec(&resp).expect("jsonrpc_core says: This should never fail.");
 
     resp_queue.write().unwrap().push_back(resp_bytes);
+
+    success
 }
 
 // Read request from the stream, parse it as JSON and handle the JSONRPC command.
@@ -166,23 +389,28 @@ fn handle_single_request(
 // Extend the cache with data read from the stream, and parse it as a set of JSONRPC requests (no
 // notification). If there are remaining bytes not interpretable as a valid JSONRPC request, leave
 // it in the cache.
-// Will return true if we read at least one valid JSONRPC request.
+// The calls the ACL doesn't allow this peer to make are refused.
+// Will return true if they successfully subscribed to the notifications.
+#[allow(clippy::too_many_arguments)]
 fn read_handle_request(
     cache: &mut Vec<u8>,
     stream: &mut UnixStream,
     resp_queue: &mut Arc<RwLock<VecDeque<Vec<u8>>>>,
     jsonrpc_io: &Arc<RwLock<jsonrpc_core::MetaIoHandler<JsonRpcMetaData>>>,
     metadata: &JsonRpcMetaData,
+    peer: Option<PeerCredentials>,
+    acl: &RpcAcl,
     handler_threads: &mut VecDeque<thread::JoinHandle<()>>,
-) -> Result<(), io::Error> {
+) -> Result<bool, io::Error> {
     // We use an optional index if there is some left unparsed bytes, because borrow checker :)
     let mut leftover = None;
+    let mut subscribed = false;
 
     if let Some(new) = read_bytes_from_stream(stream)? {
         cache.extend(new);
     } else {
         // Nothing new? We can short-circuit.
-        return Ok(());
+        return Ok(false);
     }
 
     let mut de = serde_json::Deserializer::from_slice(cache).into_iter::<MethodCall>();
@@ -191,6 +419,14 @@ fn read_handle_request(
         log::trace!("Got JSONRPC request '{:#?}", method_call);
 
         match method_call {
+            Ok(m) if !acl.allows(peer, m.method.as_str()) => {
+                log::warn!(
+                    "Denied JSONRPC call to '{}' for client with credentials {:?}",
+                    m.method,
+                    peer
+                );
+                resp_queue.write().unwrap().push_back(denied_response(&m));
+            }
             // Get a response and append it to the response queue
             Ok(m) => {
                 let t_io_handler = jsonrpc_io.clone();
@@ -203,6 +439,10 @@ fn read_handle_request(
                 // hand.
                 if m.method.as_str() == "stop" {
                     handle_single_request(t_io_handler, t_meta, t_queue, m);
+                } else if m.method.as_str() == "subscribe" {
+                    // Same for 'subscribe', as its response must be written before any
+                    // notification.
+                    subscribed |= handle_single_request(t_io_handler, t_meta, t_queue, m);
                 } else {
                     // If there are too many threads spawned, wait for the oldest one to complete.
                     // FIXME: we can be smarter than that..
@@ -215,7 +455,7 @@ fn read_handle_request(
                     }
 
                     handler_threads.push_back(thread::spawn(move || {
-                        handle_single_request(t_io_handler, t_meta, t_queue, m)
+                        handle_single_request(t_io_handler, t_meta, t_queue, m);
                     }));
                 }
             }
@@ -240,26 +480,67 @@ fn read_handle_request(
         cache.clear();
     }
 
-    Ok(())
+    Ok(subscribed)
 }
 
 // For all but Windows, we use Mio.
 #[cfg(not(windows))]
 fn mio_loop(
     mut listener: UnixListener,
+    remote_listeners: RemoteListeners,
     jsonrpc_io: jsonrpc_core::MetaIoHandler<JsonRpcMetaData>,
     metadata: JsonRpcMetaData,
+    acl: RpcAcl,
+    notifications: mpsc::Receiver<Notification>,
 ) -> Result<(), io::Error> {
     const JSONRPC_SERVER: Token = Token(0);
+    const NOTIFIER: Token = Token(1);
     let mut poller = Poll::new()?;
     let mut events = Events::with_capacity(16);
 
     // UID per connection
-    let mut unique_token = Token(JSONRPC_SERVER.0 + 1);
+    let mut unique_token = Token(NOTIFIER.0 + 1);
     let mut connections_map: ConnectionMap = HashMap::with_capacity(8);
 
+    // Queue the notifications for the subscribed connections as we receive them, and wake us up
+    // to write them. Forward them to the WebSocket clients too. This stops once the other threads
+    // are done.
+    let subscribers: SubscribersMap = Arc::new(RwLock::new(HashMap::with_capacity(8)));
+    let remote_subscribers: NotificationSenders = Arc::new(RwLock::new(HashMap::new()));
+    let waker = Arc::new(Waker::new(poller.registry(), NOTIFIER)?);
+    thread::spawn({
+        let subscribers = subscribers.clone();
+        let remote_subscribers = remote_subscribers.clone();
+        let waker = waker.clone();
+        move || {
+            for notif in notifications {
+                log::trace!("Got notification '{:?}'", notif);
+                remote_subscribers
+                    .write()
+                    .unwrap()
+                    .retain(|_, notif_tx| notif_tx.send(notif.clone()).is_ok());
+                let notif = notification_bytes(notif);
+                for resp_queue in subscribers.read().unwrap().values() {
+                    resp_queue.write().unwrap().push_back(notif.clone());
+                }
+                if let Err(e) = waker.wake() {
+                    log::error!("Error waking up the JSONRPC server: '{}'", e);
+                }
+            }
+        }
+    });
+
+    // The remote clients may tell us to stop too, wake us up if they do.
+    remote_listeners.serve(&metadata, remote_subscribers, move || {
+        if let Err(e) = waker.wake() {
+            log::error!("Error waking up the JSONRPC server: '{}'", e);
+        }
+    });
+
     // Cache what we read from the socket, in case we read only half a message.
     let mut read_cache_map: HashMap<Token, Vec<u8>> = HashMap::with_capacity(8);
+    // Who is on the other end of each connection, to check their calls against the ACL
+    let mut peers_map: HashMap<Token, Option<PeerCredentials>> = HashMap::with_capacity(8);
     let jsonrpc_io = Arc::from(RwLock::from(jsonrpc_io));
     // Handle to thread currently handling commands we were sent.
     let mut handler_threads: VecDeque<std::thread::JoinHandle<_>> =
@@ -280,6 +561,8 @@ fn mio_loop(
                         Ok((mut stream, _)) => {
                             let curr_token = Token(unique_token.0);
                             unique_token.0 += 1;
+                            let peer = peer_credentials(&stream);
+                            log::trace!("New connection {:?} from {:?}", curr_token, peer);
 
                             // So we actually know they want to discuss :)
                             poller.registry().register(
@@ -298,6 +581,7 @@ fn mio_loop(
                             );
 
                             read_cache_map.insert(curr_token, Vec::with_capacity(1024));
+                            peers_map.insert(curr_token, peer);
                         }
                         Err(e) => {
                             // Ok; next time then!
@@ -310,6 +594,28 @@ fn mio_loop(
                         }
                     }
                 }
+            } else if event.token() == NOTIFIER {
+                for token in subscribers.read().unwrap().keys() {
+                    if let Some((stream, resp_queue)) = connections_map.get_mut(token) {
+                        write_resp_queue(stream, resp_queue, *token);
+                    }
+                }
+
+                // We may have been told to stop by a remote client, in which case we won't get any
+                // event for the subscribed connections. Don't wait for the others to be closed if
+                // there are none.
+                if metadata.is_shutdown() {
+                    for (token, _) in subscribers.write().unwrap().drain() {
+                        log::trace!("Dropping subscribed connection for {:?}", token);
+                        connections_map.remove(&token);
+                    }
+                    if connections_map.is_empty() {
+                        while let Some(t) = handler_threads.pop_front() {
+                            t.join().unwrap();
+                        }
+                        return Ok(());
+                    }
+                }
             } else if connections_map.contains_key(&event.token()) {
                 // Under normal circumstances we are always interested in both
                 // Writable (do we got something for them from the resp_queue?)
@@ -330,14 +636,23 @@ fn mio_loop(
                         "Entry is always set when connection_map's entry is"
                     );
 
-                    read_handle_request(
+                    let subscribed = read_handle_request(
                         read_cache,
                         stream,
                         resp_queue,
                         &jsonrpc_io,
                         &metadata,
+                        peers_map.get(&event.token()).copied().flatten(),
+                        &acl,
                         &mut handler_threads,
                     )?;
+                    if subscribed {
+                        log::debug!("{:?} subscribed to notifications", event.token());
+                        subscribers
+                            .write()
+                            .unwrap()
+                            .insert(event.token(), resp_queue.clone());
+                    }
                 }
 
                 if event.is_writable() {
@@ -347,34 +662,23 @@ fn mio_loop(
                         resp_queue.read().unwrap().len()
                     );
 
-                    // FIFO
-                    loop {
-                        // We can't use while let Some(resp) because deadlock
-                        let resp = match resp_queue.write().unwrap().pop_front() {
-                            Some(resp) => resp,
-                            None => break,
-                        };
-
-                        log::trace!(
-                            "Writing response for {:?} ({} bytes)",
-                            event.token(),
-                            resp.len()
-                        );
-                        // If we could not write the data, don't lose track of it! This would only
-                        // reasonably happen on `WouldBlock`.
-                        match write_byte_stream(stream, resp) {
-                            Ok(Some(resp)) => resp_queue.write().unwrap().push_front(resp),
-                            Ok(None) => {}
-                            Err(e) => {
-                                log::error!("Error writing resp for {:?}: '{}'", event.token(), e)
-                            }
-                        }
+                    write_resp_queue(stream, resp_queue, event.token());
+                }
+
+                // Subscribed connections are never closed by the client if we are shutting
+                // down, do it ourselves.
+                if metadata.is_shutdown() {
+                    for (token, _) in subscribers.write().unwrap().drain() {
+                        log::trace!("Dropping subscribed connection for {:?}", token);
+                        connections_map.remove(&token);
                     }
                 }
 
                 if event.is_read_closed() || event.is_error() {
                     log::trace!("Dropping connection for {:?}", event.token());
                     connections_map.remove(&event.token());
+                    peers_map.remove(&event.token());
+                    subscribers.write().unwrap().remove(&event.token());
 
                     // If this was the last connection alive and we are shutting down,
                     // actually shut down.
@@ -391,12 +695,18 @@ fn mio_loop(
 }
 
 // For windows, we don't: Mio UDS support for Windows is not yet implemented.
+// Notifications are not supported, as we never keep a connection open.
 #[cfg(windows)]
 fn windows_loop(
     listener: UnixListener,
+    remote_listeners: RemoteListeners,
     jsonrpc_io: jsonrpc_core::MetaIoHandler<JsonRpcMetaData>,
     metadata: JsonRpcMetaData,
 ) -> Result<(), io::Error> {
+    // FIXME: we can't wake up this loop, so it only stops after a 'stop' from a remote client once
+    // it gets a new connection.
+    remote_listeners.serve(&metadata, Arc::new(RwLock::new(HashMap::new())), || {});
+
     for mut stream in listener.incoming() {
         let mut stream = stream?;
 
@@ -460,12 +770,18 @@ fn bind(socket_path: PathBuf) -> Result<UnixListener, io::Error> {
     }
 }
 
-/// Bind to the UDS at `socket_path`
-pub fn rpcserver_setup(socket_path: PathBuf) -> Result<UnixListener, io::Error> {
-    // Create the socket with RW permissions only for the user
+/// Bind to the UDS at `socket_path`. With `group_access`, users of our group may connect too
+/// (their calls are then checked against the ACL).
+pub fn rpcserver_setup(
+    socket_path: PathBuf,
+    group_access: bool,
+) -> Result<UnixListener, io::Error> {
+    // Create the socket with RW permissions only for the user (and the group)
     // FIXME: find a workaround for Windows...
     #[cfg(unix)]
-    let old_umask = unsafe { libc::umask(0o177) };
+    let old_umask = unsafe { libc::umask(if group_access { 0o117 } else { 0o177 }) };
+    #[cfg(not(unix))]
+    let _ = group_access;
     let listener = bind(socket_path);
     #[cfg(unix)]
     unsafe {
@@ -475,37 +791,68 @@ pub fn rpcserver_setup(socket_path: PathBuf) -> Result<UnixListener, io::Error>
     listener
 }
 
-/// The main event loop for the JSONRPC interface, polling the UDS listener
+/// The main event loop for the JSONRPC interface, polling the UDS listener and streaming the
+/// `notifications` to the subscribed clients. The remote clients, if any, are served from other
+/// threads. The calls on the UDS are checked against the configured ACL.
 pub fn rpcserver_loop(
     listener: UnixListener,
+    remote_listeners: RemoteListeners,
     user_role: UserRole,
+    rpc_socket: RpcSocket,
     rpc_utils: RpcUtils,
+    notifications: mpsc::Receiver<Notification>,
 ) -> Result<(), io::Error> {
-    let mut jsonrpc_io = jsonrpc_core::MetaIoHandler::<JsonRpcMetaData, _>::default();
-    jsonrpc_io.extend_with(RpcImpl.to_delegate());
+    let jsonrpc_io = rpc_io_handler(user_role, rpc_socket);
+    #[cfg(not(windows))]
+    let acl = RpcAcl::new(
+        unsafe { libc::getuid() },
+        &rpc_utils.revaultd.read().unwrap().rpc_acl,
+    );
     let metadata = JsonRpcMetaData::new(user_role, rpc_utils);
 
     log::info!("JSONRPC server started.");
     #[cfg(not(windows))]
-    return mio_loop(listener, jsonrpc_io, metadata);
+    return mio_loop(
+        listener,
+        remote_listeners,
+        jsonrpc_io,
+        metadata,
+        acl,
+        notifications,
+    );
     #[cfg(windows)]
-    return windows_loop(listener, jsonrpc_io, metadata);
+    {
+        // The config refuses an ACL but on Linux
+        drop(notifications);
+        return windows_loop(listener, remote_listeners, jsonrpc_io, metadata);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        read_bytes_from_stream, rpcserver_loop, rpcserver_setup, trimmed, RpcUtils, UserRole,
+        read_bytes_from_stream, rpc_io_handler, rpcserver_loop, rpcserver_setup, trimmed,
+        JsonRpcMetaData, PeerCredentials, RemoteListeners, RpcAcl, RpcSocket, RpcUtils, UserRole,
     };
     use crate::{
-        revaultd::RevaultD,
-        threadmessages::{BitcoindMessageOut, SigFetcherMessageOut},
+        jsonrpc::{
+            api::{RpcApi, RpcImpl},
+            http::http_rpcserver_setup,
+            tcp::{tcp_rpcserver_setup, write_cookie, TcpAuth},
+            OBSERVER_COMMANDS,
+        },
+        revaultd::{RevaultD, VaultStatus},
+        threadmessages::{
+            BitcoindMessageOut, Notification, SigFetcherMessageOut, WatchtowersMessageOut,
+        },
     };
-    use common::config::Config;
+    use common::config::{Config, RpcAclEntry};
+    use revault_net::{sodiumoxide, transport::KKTransport};
 
     use std::{
         fs,
         io::{Cursor, Read, Write},
+        net::TcpStream,
         path::PathBuf,
         sync::{mpsc, Arc, RwLock},
         thread,
@@ -520,7 +867,7 @@ mod tests {
     // Get a dummy handle for the RPC calls. We don't actually test RPC calls requiring it here but
     // we need to because types.
     // FIXME: we could do something cleaner at some point
-    fn dummy_rpcutil() -> RpcUtils {
+    fn dummy_rpcutil(datadir_name: &str) -> RpcUtils {
         let repo_root = PathBuf::from(file!())
             .parent()
             .unwrap()
@@ -531,13 +878,9 @@ mod tests {
             .parent()
             .unwrap()
             .to_path_buf();
-        let datadir_path: PathBuf = [
-            repo_root.to_str().unwrap(),
-            "test_data",
-            "scratch_datadir_jsonrpc",
-        ]
-        .iter()
-        .collect();
+        let datadir_path: PathBuf = [repo_root.to_str().unwrap(), "test_data", datadir_name]
+            .iter()
+            .collect();
 
         fs::remove_dir_all(&datadir_path).unwrap_or_else(|_| ());
 
@@ -590,6 +933,7 @@ mod tests {
 
         let (bitcoind_tx, bitcoind_rx) = mpsc::channel();
         let (sigfetcher_tx, sigfetcher_rx) = mpsc::channel();
+        let (watchtowers_tx, watchtowers_rx) = mpsc::channel();
 
         let bitcoind_thread = Arc::from(RwLock::from(thread::spawn(move || {
             for msg in bitcoind_rx {
@@ -606,6 +950,13 @@ mod tests {
                 }
             }
         })));
+        let watchtowers_thread = Arc::from(RwLock::from(thread::spawn(move || {
+            for msg in watchtowers_rx {
+                match msg {
+                    WatchtowersMessageOut::Shutdown => return,
+                }
+            }
+        })));
 
         RpcUtils {
             revaultd,
@@ -613,6 +964,8 @@ mod tests {
             bitcoind_thread,
             sigfetcher_tx,
             sigfetcher_thread,
+            watchtowers_tx,
+            watchtowers_thread,
         }
     }
 
@@ -620,14 +973,23 @@ mod tests {
     // until the functional tests suite can run on it.
     #[test]
     fn simple_write_recv() {
-        let rpcutils = dummy_rpcutil();
+        let rpcutils = dummy_rpcutil("scratch_datadir_jsonrpc");
         let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
         let mut rpc_socket_path = revaultd_datadir.clone();
         rpc_socket_path.push("revaultd_rpc");
 
-        let socket = rpcserver_setup(rpc_socket_path.clone()).unwrap();
+        let socket = rpcserver_setup(rpc_socket_path.clone(), false).unwrap();
+        let (_, notif_rx) = mpsc::channel();
         let server_loop_thread = thread::spawn(move || {
-            rpcserver_loop(socket, UserRole::Stakeholder, rpcutils).unwrap_or_else(|e| {
+            rpcserver_loop(
+                socket,
+                RemoteListeners::default(),
+                UserRole::Stakeholder,
+                RpcSocket::Main,
+                rpcutils,
+                notif_rx,
+            )
+            .unwrap_or_else(|e| {
                 panic!("Error in JSONRPC server event loop: {}", e.to_string());
             })
         });
@@ -703,6 +1065,415 @@ mod tests {
         fs::remove_dir_all(&revaultd_datadir).unwrap();
     }
 
+    #[test]
+    fn observer_role() {
+        let rpcutils = dummy_rpcutil("scratch_datadir_jsonrpc_observer");
+        let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        let jsonrpc_io = rpc_io_handler(UserRole::Observer, RpcSocket::Observer);
+        let metadata = JsonRpcMetaData::new(UserRole::Observer, rpcutils);
+
+        // Anything but the read-only commands is refused before even looking at the parameters
+        for (method, _) in RpcImpl.to_delegate() {
+            if OBSERVER_COMMANDS.contains(&method.as_str()) {
+                continue;
+            }
+            let msg = format!(
+                r#"{{"jsonrpc": "2.0", "id": 0, "method": "{}", "params": []}}"#,
+                method
+            );
+            let resp = jsonrpc_io
+                .handle_request_sync(&msg, metadata.clone())
+                .unwrap();
+            assert!(
+                resp.contains("This is not a read-only command"),
+                "'{}' was not refused: {}",
+                method,
+                resp
+            );
+        }
+        // In particular, they can't stop us
+        assert!(!metadata.is_shutdown());
+        // Though a daemon that is only an observer may be stopped on its main socket
+        assert!(RpcSocket::Main.serves(UserRole::Observer, "stop"));
+        assert!(!RpcSocket::Observer.serves(UserRole::Observer, "stop"));
+        assert!(!RpcSocket::Main.serves(UserRole::Observer, "revault"));
+
+        // The read-only ones are handled as usual
+        let msg = r#"{"jsonrpc": "2.0", "id": 1, "method": "getinfo", "params": {"a": "b"}}"#;
+        let resp = jsonrpc_io
+            .handle_request_sync(msg, metadata.clone())
+            .unwrap();
+        assert!(resp.contains("No parameters were expected"), "{}", resp);
+
+        fs::remove_dir_all(&revaultd_datadir).unwrap();
+    }
+
+    #[test]
+    fn remote_file_paths() {
+        let rpcutils = dummy_rpcutil("scratch_datadir_jsonrpc_remote_paths");
+        let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        let jsonrpc_io = rpc_io_handler(UserRole::Stakeholder, RpcSocket::Main);
+        let metadata = JsonRpcMetaData {
+            remote: true,
+            ..JsonRpcMetaData::new(UserRole::Stakeholder, rpcutils)
+        };
+
+        // Remote clients may not have us write files outside of our data directory
+        let escaping = revaultd_datadir.join("..").join("backup.sqlite3");
+        for path in &[PathBuf::from("/tmp/revaultd_backup.sqlite3"), escaping] {
+            for method in &["backup", "exportemergencykit"] {
+                let msg = format!(
+                    r#"{{"jsonrpc": "2.0", "id": 0, "method": "{}", "params": [{:?}]}}"#,
+                    method, path
+                );
+                let resp = jsonrpc_io
+                    .handle_request_sync(&msg, metadata.clone())
+                    .unwrap();
+                assert!(
+                    resp.contains("is not within the data directory"),
+                    "{}",
+                    resp
+                );
+            }
+        }
+
+        fs::remove_dir_all(&revaultd_datadir).unwrap();
+    }
+
+    // Start the server loop with these remote listeners. Returns the sender of the notifications
+    // to the server.
+    #[cfg(not(windows))]
+    fn start_remote_server(
+        rpcutils: RpcUtils,
+        remote_listeners: RemoteListeners,
+    ) -> (thread::JoinHandle<()>, mpsc::Sender<Notification>) {
+        let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        let socket = rpcserver_setup(revaultd_datadir.join("revaultd_rpc"), false).unwrap();
+
+        let (notif_tx, notif_rx) = mpsc::channel();
+        let server_loop_thread = thread::spawn(move || {
+            rpcserver_loop(
+                socket,
+                remote_listeners,
+                UserRole::Stakeholder,
+                RpcSocket::Main,
+                rpcutils,
+                notif_rx,
+            )
+            .unwrap_or_else(|e| {
+                panic!("Error in JSONRPC server event loop: {}", e.to_string());
+            })
+        });
+
+        (server_loop_thread, notif_tx)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unix_socket_acl() {
+        let rpcutils = dummy_rpcutil("scratch_datadir_jsonrpc_acl");
+        let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        let uid = unsafe { libc::getuid() };
+        rpcutils.revaultd.write().unwrap().rpc_acl = vec![RpcAclEntry {
+            uid: uid + 1,
+            methods: vec!["listvaults".to_string()],
+        }];
+        let (server_loop_thread, _) = start_remote_server(rpcutils, RemoteListeners::default());
+        let mut sock = UnixStream::connect(revaultd_datadir.join("revaultd_rpc")).unwrap();
+
+        // The ACL doesn't list us, but we may call anything
+        let msg = r#"{"jsonrpc": "2.0", "id": 0, "method": "stop", "params": []}"#;
+        sock.write_all(msg.as_bytes()).unwrap();
+        sock.flush().unwrap();
+        drop(sock);
+        server_loop_thread.join().unwrap();
+
+        fs::remove_dir_all(&revaultd_datadir).unwrap();
+    }
+
+    #[test]
+    fn rpc_acl() {
+        let peer = |uid| Some(PeerCredentials { uid, gid: 1000 });
+
+        // Without an ACL, anyone who can connect may call anything
+        let acl = RpcAcl::new(1000, &[]);
+        assert!(acl.allows(peer(1001), "revault"));
+        assert!(acl.allows(None, "revault"));
+
+        let acl = RpcAcl::new(
+            1000,
+            &[
+                RpcAclEntry {
+                    uid: 1001,
+                    methods: vec!["revault".to_string(), "setspendtx".to_string()],
+                },
+                RpcAclEntry {
+                    uid: 1002,
+                    methods: vec!["listvaults".to_string()],
+                },
+                RpcAclEntry {
+                    uid: 1002,
+                    methods: vec!["getinfo".to_string()],
+                },
+            ],
+        );
+        // We may still call anything
+        assert!(acl.allows(peer(1000), "emergency"));
+        // The others only what is listed for them
+        assert!(acl.allows(peer(1001), "revault"));
+        assert!(acl.allows(peer(1001), "setspendtx"));
+        assert!(!acl.allows(peer(1001), "emergency"));
+        assert!(!acl.allows(peer(1001), "delspendtx"));
+        assert!(acl.allows(peer(1002), "listvaults"));
+        assert!(acl.allows(peer(1002), "getinfo"));
+        assert!(!acl.allows(peer(1002), "revault"));
+        // Nothing for the ones it doesn't list, or that we can't identify
+        assert!(!acl.allows(peer(0), "getinfo"));
+        assert!(!acl.allows(None, "getinfo"));
+    }
+
+    // The Windows loop only notices a 'stop' from a TCP client on its next connection
+    #[cfg(not(windows))]
+    #[test]
+    fn tcp_cookie_auth() {
+        let rpcutils = dummy_rpcutil("scratch_datadir_jsonrpc_cookie");
+        let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        let cookie_path = rpcutils.revaultd.read().unwrap().rpc_cookie_file();
+        let token = write_cookie(&cookie_path).unwrap();
+        assert_eq!(fs::read_to_string(&cookie_path).unwrap(), token);
+        assert_eq!(token.len(), 64);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&cookie_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let tcp_listener = tcp_rpcserver_setup(
+            "127.0.0.1:0".parse().unwrap(),
+            TcpAuth::Cookie(token.clone()),
+        )
+        .unwrap();
+        let addr = tcp_listener.local_addr().unwrap();
+        let remote_listeners = RemoteListeners {
+            tcp: Some(tcp_listener),
+            http: None,
+        };
+        let (server_loop_thread, _) = start_remote_server(rpcutils, remote_listeners);
+
+        // With an invalid token, they just hang up
+        let mut sock = TcpStream::connect(addr).unwrap();
+        sock.write_all(b"aabbccdd\n").unwrap();
+        let mut response = Vec::new();
+        sock.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty());
+
+        // With the right one, the requests are handled as on the Unix socket
+        let mut sock = TcpStream::connect(addr).unwrap();
+        sock.write_all(format!("{}\n", token).as_bytes()).unwrap();
+        let invalid_msg = r#"{"jsonrpc": "2.0", "id": 0, "method": "stop", "params": {"a": "b"}}"#;
+        sock.write_all(invalid_msg.as_bytes()).unwrap();
+        let mut response = vec![0; 256];
+        let read = sock.read(&mut response).unwrap();
+        assert_eq!(
+            String::from_utf8(trimmed(response, read)).unwrap(),
+            String::from(
+                r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Invalid parameters: No parameters were expected","data":"Map({\"a\": String(\"b\")})"},"id":0}"#
+            )
+        );
+
+        // But we can't subscribe to notifications
+        let msg = r#"{"jsonrpc": "2.0", "id": 1, "method": "subscribe", "params": []}"#;
+        sock.write_all(msg.as_bytes()).unwrap();
+        let mut response = vec![0; 256];
+        let read = sock.read(&mut response).unwrap();
+        assert_eq!(
+            String::from_utf8(trimmed(response, read)).unwrap(),
+            String::from(
+                r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"Notifications are only streamed on the Unix socket"},"id":1}"#
+            )
+        );
+
+        // Telling it to stop over TCP stops the main loop
+        let msg = r#"{"jsonrpc": "2.0", "id": 2, "method": "stop", "params": []}"#;
+        sock.write_all(msg.as_bytes()).unwrap();
+        server_loop_thread.join().unwrap();
+
+        fs::remove_dir_all(&revaultd_datadir).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn tcp_noise_auth() {
+        let rpcutils = dummy_rpcutil("scratch_datadir_jsonrpc_noise");
+        let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        let (noise_secret, our_noise_key) = {
+            let revaultd = rpcutils.revaultd.read().unwrap();
+            (revaultd.noise_secret.clone(), revaultd.noise_pubkey())
+        };
+        sodiumoxide::init().unwrap();
+        let (client_noise_key, client_noise_secret) = sodiumoxide::crypto::box_::gen_keypair();
+        let (_, stranger_noise_secret) = sodiumoxide::crypto::box_::gen_keypair();
+        let auth = TcpAuth::Noise {
+            noise_secret,
+            clients: vec![client_noise_key],
+        };
+        let tcp_listener = tcp_rpcserver_setup("127.0.0.1:0".parse().unwrap(), auth).unwrap();
+        let addr = tcp_listener.local_addr().unwrap();
+        let remote_listeners = RemoteListeners {
+            tcp: Some(tcp_listener),
+            http: None,
+        };
+        let (server_loop_thread, _) = start_remote_server(rpcutils, remote_listeners);
+
+        // A client that doesn't even start the handshake doesn't prevent others from connecting
+        let _silent_client = TcpStream::connect(addr).unwrap();
+
+        // An unknown key can't even complete the handshake
+        KKTransport::connect(addr, &stranger_noise_secret, &our_noise_key)
+            .expect_err("Unknown client key");
+
+        // A known one can send requests
+        let mut transport = KKTransport::connect(addr, &client_noise_secret, &our_noise_key)
+            .expect("Known client key");
+        transport
+            .write(br#"{"jsonrpc": "2.0", "id": 0, "method": "aaa", "params": []}"#)
+            .unwrap();
+        assert_eq!(
+            transport.read().unwrap(),
+            br#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"Method not found"},"id":0}"#
+                .to_vec()
+        );
+
+        transport
+            .write(br#"{"jsonrpc": "2.0", "id": 1, "method": "stop", "params": []}"#)
+            .unwrap();
+        assert_eq!(
+            transport.read().unwrap(),
+            br#"{"jsonrpc":"2.0","result":null,"id":1}"#.to_vec()
+        );
+        server_loop_thread.join().unwrap();
+
+        fs::remove_dir_all(&revaultd_datadir).unwrap();
+    }
+
+    // Send an HTTP request, get the status code and the JSON body of the response
+    #[cfg(not(windows))]
+    fn http_request(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        token: &str,
+        body: &str,
+    ) -> (u16, serde_json::Value) {
+        let mut sock = TcpStream::connect(addr).unwrap();
+        write!(
+            sock,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            token,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        sock.read_to_string(&mut response).unwrap();
+
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .expect("Status line");
+        let body = &response[response.find("\r\n\r\n").expect("End of headers") + 4..];
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn http_rest_gateway() {
+        use tungstenite::{client::IntoClientRequest, Message};
+
+        let rpcutils = dummy_rpcutil("scratch_datadir_jsonrpc_http");
+        let revaultd_datadir = rpcutils.revaultd.read().unwrap().data_dir.clone();
+        let deposit_address = rpcutils.revaultd.read().unwrap().deposit_address();
+        let token = write_cookie(&rpcutils.revaultd.read().unwrap().rpc_cookie_file()).unwrap();
+        let http_listener =
+            http_rpcserver_setup("127.0.0.1:0".parse().unwrap(), token.clone()).unwrap();
+        let addr = http_listener.local_addr();
+        let remote_listeners = RemoteListeners {
+            tcp: None,
+            http: Some(http_listener),
+        };
+        let (server_loop_thread, notif_tx) = start_remote_server(rpcutils, remote_listeners);
+
+        // We need the token
+        let (status, _) = http_request(addr, "GET", "/depositaddress", "aabbccdd", "");
+        assert_eq!(status, 401);
+
+        // The result is the same as over JSONRPC
+        let (status, body) = http_request(addr, "GET", "/depositaddress", &token, "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            serde_json::json!({ "address": deposit_address.to_string() })
+        );
+
+        // As are the errors
+        let (status, body) = http_request(addr, "GET", "/vaults/aa/revocationtxs", &token, "");
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["code"], -32602);
+        let (status, _) = http_request(addr, "GET", "/nothere", &token, "");
+        assert_eq!(status, 404);
+        let (status, _) = http_request(addr, "POST", "/spend", &token, "[]");
+        assert_eq!(status, 400);
+
+        // The status changes are streamed on the WebSocket, and only them
+        let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+        request.headers_mut().insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let (mut websocket, _) =
+            tungstenite::client(request, TcpStream::connect(addr).unwrap()).unwrap();
+        let outpoint = "cafebabe00000000000000000000000000000000000000000000000000000000:1"
+            .parse()
+            .unwrap();
+        notif_tx
+            .send(Notification::Broadcast {
+                outpoint,
+                txid: outpoint.txid,
+            })
+            .unwrap();
+        notif_tx
+            .send(Notification::StatusChange {
+                outpoint,
+                status: VaultStatus::Unvaulting,
+            })
+            .unwrap();
+        match websocket.read_message().unwrap() {
+            Message::Text(msg) => assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&msg).unwrap(),
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "method": "statuschange",
+                    "params": {
+                        "vault_outpoint": outpoint.to_string(),
+                        "status": "unvaulting"
+                    }
+                })
+            ),
+            msg => panic!("Unexpected message '{:?}'", msg),
+        }
+
+        // Telling it to stop over HTTP stops the main loop
+        let (status, body) = http_request(addr, "POST", "/stop", &token, "");
+        assert_eq!((status, body), (200, serde_json::Value::Null));
+        server_loop_thread.join().unwrap();
+
+        fs::remove_dir_all(&revaultd_datadir).unwrap();
+    }
+
     #[test]
     fn test_bytes_reader() {
         let samples = [vec![22; 22], vec![1; 522], vec![189; 28903]];
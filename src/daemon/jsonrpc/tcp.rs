@@ -0,0 +1,304 @@
+//! An optional TCP listener for the JSONRPC interface, for clients on another host (think of a
+//! web GUI). Unlike with the Unix socket, anyone reaching it could talk to us, so clients must
+//! authenticate either with a Noise KK handshake or with the token of our cookie file.
+//! Requests are handled by the same `RpcImpl` handlers, with the same role checks.
+
+use crate::jsonrpc::{
+    api::{rpc_io_handler, JsonRpcMetaData},
+    RpcSocket,
+};
+
+use revault_net::{
+    noise::{
+        KKChannel, KKHandshakeActTwo, KKMessageActOne, NoiseEncryptedHeader, NoiseEncryptedMessage,
+        PublicKey as NoisePubKey, SecretKey as NoisePrivKey, KK_MSG_1_SIZE,
+        NOISE_MESSAGE_HEADER_SIZE,
+    },
+    sodiumoxide,
+};
+use revault_tx::bitcoin::hashes::hex::ToHex;
+
+use std::{
+    fs,
+    io::{self, BufRead, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use jsonrpc_core::{
+    futures::Future, types::error::ErrorCode, Call, Error as JsonRpcError, Failure, MetaIoHandler,
+    Output, Request, Response,
+};
+
+// Don't let a client make us buffer an endless first line
+const MAX_TOKEN_LINE_LEN: u64 = 128;
+
+// How long a client has to authenticate, with the Noise handshake or its token
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often we check whether we were told to stop, while waiting for a client to connect
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How the TCP clients authenticate
+pub enum TcpAuth {
+    /// A Noise KK handshake with one of these clients, using our revault_net static key
+    Noise {
+        noise_secret: NoisePrivKey,
+        clients: Vec<NoisePubKey>,
+    },
+    /// The client sends this token, from our cookie file (see `write_cookie`), on its first line
+    Cookie(String),
+}
+
+/// Write a new random token to the cookie file at this path, only readable by us. The TCP and
+/// HTTP clients authenticate with it.
+pub fn write_cookie(cookie_path: &Path) -> Result<String, io::Error> {
+    sodiumoxide::init()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Could not initialize libsodium"))?;
+    let token = sodiumoxide::randombytes::randombytes(32).to_hex();
+
+    // Don't inherit the permissions of a leftover one
+    if let Err(e) = fs::remove_file(cookie_path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // FIXME: handle Windows ACLs
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(cookie_path)?.write_all(token.as_bytes())?;
+
+    Ok(token)
+}
+
+/// The TCP listener, along with how clients must authenticate to it
+pub struct TcpRpcListener {
+    listener: TcpListener,
+    auth: TcpAuth,
+}
+
+impl TcpRpcListener {
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+}
+
+/// Bind to `addr`, for clients authenticating with `auth`
+pub fn tcp_rpcserver_setup(addr: SocketAddr, auth: TcpAuth) -> Result<TcpRpcListener, io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    // So we don't wait for a client to notice we were told to stop
+    listener.set_nonblocking(true)?;
+
+    Ok(TcpRpcListener { listener, auth })
+}
+
+// A Noise KK channel with an authenticated client. The same as revault_net's `KKTransport`, which
+// can only do the handshake when accepting the connection: we do it from the client's thread.
+struct NoiseClient {
+    stream: TcpStream,
+    channel: KKChannel,
+}
+
+impl NoiseClient {
+    // Do the handshake as the responder, for one of these clients
+    fn handshake(
+        mut stream: TcpStream,
+        noise_secret: &NoisePrivKey,
+        clients: &[NoisePubKey],
+    ) -> Result<NoiseClient, revault_net::Error> {
+        stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+        let mut msg_1 = [0u8; KK_MSG_1_SIZE];
+        stream.read_exact(&mut msg_1)?;
+        let (handshake, msg_2) =
+            KKHandshakeActTwo::responder(noise_secret, clients, &KKMessageActOne(msg_1))?;
+        stream.write_all(&msg_2.0)?;
+        let channel = KKChannel::from_handshake(handshake)?;
+        // Once authenticated, they may take their time between requests
+        stream.set_read_timeout(None)?;
+
+        Ok(NoiseClient { stream, channel })
+    }
+
+    fn read(&mut self) -> Result<Vec<u8>, revault_net::Error> {
+        let mut header = [0u8; NOISE_MESSAGE_HEADER_SIZE];
+        self.stream.read_exact(&mut header)?;
+        let msg_len = self.channel.decrypt_header(&NoiseEncryptedHeader(header))?;
+        let mut body = vec![0u8; msg_len as usize];
+        self.stream.read_exact(&mut body)?;
+
+        Ok(self.channel.decrypt_message(&NoiseEncryptedMessage(body))?)
+    }
+
+    fn write(&mut self, msg: &[u8]) -> Result<(), revault_net::Error> {
+        let msg = self.channel.encrypt_message(msg)?;
+        Ok(self.stream.write_all(&msg.0)?)
+    }
+}
+
+// Handle a request from a TCP client. We don't stream them notifications, so refuse their
+// subscription.
+fn handle_request(
+    jsonrpc_io: &MetaIoHandler<JsonRpcMetaData>,
+    metadata: &JsonRpcMetaData,
+    request: Request,
+) -> Option<Response> {
+    if let Request::Single(Call::MethodCall(ref m)) = request {
+        if m.method.as_str() == "subscribe" {
+            return Some(Response::Single(Output::Failure(Failure {
+                jsonrpc: m.jsonrpc,
+                error: JsonRpcError {
+                    code: ErrorCode::MethodNotFound,
+                    message: "Notifications are only streamed on the Unix socket".to_string(),
+                    data: None,
+                },
+                id: m.id.clone(),
+            })));
+        }
+    }
+
+    jsonrpc_io
+        .handle_rpc_request(request, metadata.clone())
+        .wait()
+        .expect("jsonrpc_core says: Handler calls can never fail.")
+}
+
+// Check the token on the first line, then handle the requests following it on the stream
+fn serve_cookie_client(
+    stream: TcpStream,
+    token: &str,
+    jsonrpc_io: &MetaIoHandler<JsonRpcMetaData>,
+    metadata: &JsonRpcMetaData,
+    on_shutdown: &dyn Fn(),
+) -> Result<(), io::Error> {
+    let mut writer = stream.try_clone()?;
+    stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let mut reader = io::BufReader::new(stream);
+
+    let mut line = String::new();
+    reader
+        .by_ref()
+        .take(MAX_TOKEN_LINE_LEN)
+        .read_line(&mut line)?;
+    if !sodiumoxide::utils::memcmp(line.trim_end().as_bytes(), token.as_bytes()) {
+        log::warn!(
+            "Refusing TCP JSONRPC client '{}': invalid token",
+            writer.peer_addr()?
+        );
+        return Ok(());
+    }
+    // Once authenticated, they may take their time between requests
+    reader.get_ref().set_read_timeout(None)?;
+
+    for request in serde_json::Deserializer::from_reader(reader).into_iter::<Request>() {
+        if let Some(resp) = handle_request(jsonrpc_io, metadata, request?) {
+            writer.write_all(&serde_json::to_vec(&resp)?)?;
+        }
+
+        if metadata.is_shutdown() {
+            on_shutdown();
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Handle each message of the Noise channel as a request, until they close it
+fn serve_noise_client(
+    mut transport: NoiseClient,
+    jsonrpc_io: &MetaIoHandler<JsonRpcMetaData>,
+    metadata: &JsonRpcMetaData,
+    on_shutdown: &dyn Fn(),
+) {
+    while let Ok(msg) = transport.read() {
+        let request = match serde_json::from_slice(&msg) {
+            Ok(request) => request,
+            Err(e) => {
+                log::error!("TCP JSONRPC server: error interpreting request: '{}'", e);
+                continue;
+            }
+        };
+
+        if let Some(resp) = handle_request(jsonrpc_io, metadata, request) {
+            let resp =
+                serde_json::to_vec(&resp).expect("jsonrpc_core says: This should never fail.");
+            if let Err(e) = transport.write(&resp) {
+                log::error!("TCP JSONRPC server: error writing response: '{}'", e);
+                return;
+            }
+        }
+
+        if metadata.is_shutdown() {
+            on_shutdown();
+            return;
+        }
+    }
+}
+
+/// Accept the TCP clients and handle each of them in its own thread, where they authenticate.
+/// `on_shutdown` is called once one of them told us to stop, for the main loop to notice it.
+pub fn tcp_rpcserver_loop<F>(listener: TcpRpcListener, metadata: JsonRpcMetaData, on_shutdown: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let jsonrpc_io = Arc::new(rpc_io_handler(metadata.role, RpcSocket::Main));
+    let on_shutdown = Arc::new(on_shutdown);
+    let TcpRpcListener { listener, auth } = listener;
+    let auth = Arc::new(auth);
+
+    log::info!(
+        "TCP JSONRPC server listening on '{:?}'",
+        listener.local_addr()
+    );
+    while !metadata.is_shutdown() {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Error accepting TCP JSONRPC connection: '{}'", e);
+                continue;
+            }
+        };
+        // It may inherit the non-blocking mode of the listener
+        if let Err(e) = stream.set_nonblocking(false) {
+            log::error!("TCP JSONRPC server: error setting up connection: '{}'", e);
+            continue;
+        }
+
+        let (jsonrpc_io, metadata, on_shutdown, auth) = (
+            jsonrpc_io.clone(),
+            metadata.clone(),
+            on_shutdown.clone(),
+            auth.clone(),
+        );
+        thread::spawn(move || match *auth {
+            TcpAuth::Noise {
+                ref noise_secret,
+                ref clients,
+            } => match NoiseClient::handshake(stream, noise_secret, clients) {
+                Ok(transport) => {
+                    serve_noise_client(transport, &jsonrpc_io, &metadata, &*on_shutdown)
+                }
+                Err(e) => log::warn!("Error accepting TCP JSONRPC connection: '{}'", e),
+            },
+            TcpAuth::Cookie(ref token) => {
+                if let Err(e) =
+                    serve_cookie_client(stream, token, &jsonrpc_io, &metadata, &*on_shutdown)
+                {
+                    log::error!("TCP JSONRPC server: error with client: '{}'", e);
+                }
+            }
+        });
+    }
+}
@@ -1,21 +1,36 @@
 mod bitcoind;
 mod control;
 mod database;
+mod emergency_kit;
+mod hooks;
 mod jsonrpc;
+#[cfg(test)]
+mod mock_servers;
 mod revaultd;
 mod sigfetcher;
+#[cfg(test)]
+mod test_utils;
 mod threadmessages;
+mod vault_state;
+mod watchtowers;
 
 use crate::{
-    bitcoind::actions::{bitcoind_main_loop, start_bitcoind},
+    bitcoind::{
+        actions::{bitcoind_main_loop, start_bitcoind, start_electrum},
+        backend::ChainBackend,
+    },
     control::RpcUtils,
-    database::actions::setup_db,
+    database::actions::{restore_db, setup_db},
     jsonrpc::{
-        server::{rpcserver_loop, rpcserver_setup},
-        UserRole,
+        http::http_rpcserver_setup,
+        server::{rpcserver_loop, rpcserver_setup, RemoteListeners},
+        tcp::{tcp_rpcserver_setup, write_cookie, TcpAuth},
+        RpcSocket, UserRole,
     },
     revaultd::RevaultD,
     sigfetcher::signature_fetcher_loop,
+    threadmessages::Notification,
+    watchtowers::watchtowers_loop,
 };
 use common::{assume_ok, config::Config};
 use revault_net::sodiumoxide;
@@ -33,18 +48,108 @@ use std::{
 
 use daemonize_simple::Daemonize;
 
-fn parse_args(args: Vec<String>) -> Option<PathBuf> {
-    if args.len() == 1 {
-        return None;
-    }
+// What we were started with
+#[derive(Default)]
+struct Args {
+    conf_file: Option<PathBuf>,
+    backup_file: Option<PathBuf>,
+    recovery_timestamp: Option<u32>,
+}
 
-    if args.len() != 3 {
-        eprintln!("Unknown arguments '{:?}'.", args);
-        eprintln!("Only '--conf <configuration file path>' is supported.");
+fn parse_args(args: Vec<String>) -> Args {
+    let mut parsed = Args::default();
+
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        match (arg.as_str(), args_iter.next()) {
+            ("--conf", Some(path)) => parsed.conf_file = Some(PathBuf::from(path)),
+            ("--restore", Some(path)) => parsed.backup_file = Some(PathBuf::from(path)),
+            ("--recover", Some(timestamp)) => match timestamp.parse() {
+                Ok(timestamp) => parsed.recovery_timestamp = Some(timestamp),
+                Err(e) => {
+                    eprintln!("Invalid rescan start timestamp '{}': {}", timestamp, e);
+                    process::exit(1);
+                }
+            },
+            _ => {
+                eprintln!("Unknown arguments '{:?}'.", args);
+                eprintln!(
+                    "Only '--conf <configuration file path>', \
+                     '--restore <database backup path>' and \
+                     '--recover <rescan start timestamp>' are supported."
+                );
+                process::exit(1);
+            }
+        }
+    }
+    if parsed.backup_file.is_some() && parsed.recovery_timestamp.is_some() {
+        eprintln!("Can't both restore a database backup and recover from the block chain.");
         process::exit(1);
     }
 
-    Some(PathBuf::from(args[2].to_owned()))
+    parsed
+}
+
+// Bind the TCP and HTTP listeners for the JSONRPC clients on other hosts, if configured
+fn setup_remote_listeners(revaultd: &RevaultD) -> RemoteListeners {
+    let needs_cookie = revaultd.rpc_http_config.is_some()
+        || revaultd
+            .rpc_tcp_config
+            .as_ref()
+            .map(|tcp_config| tcp_config.noise_keys.is_empty())
+            .unwrap_or(false);
+    let cookie_token = if needs_cookie {
+        Some(assume_ok!(
+            write_cookie(&revaultd.rpc_cookie_file()),
+            "Writing the JSONRPC cookie file"
+        ))
+    } else {
+        None
+    };
+
+    let tcp = revaultd.rpc_tcp_config.clone().map(|tcp_config| {
+        let auth = if tcp_config.noise_keys.is_empty() {
+            TcpAuth::Cookie(cookie_token.clone().expect("Written above"))
+        } else {
+            TcpAuth::Noise {
+                noise_secret: revaultd.noise_secret.clone(),
+                clients: tcp_config.noise_keys,
+            }
+        };
+        assume_ok!(
+            tcp_rpcserver_setup(tcp_config.addr, auth),
+            "Setting up TCP JSONRPC server"
+        )
+    });
+    let http = revaultd.rpc_http_config.as_ref().map(|http_config| {
+        assume_ok!(
+            http_rpcserver_setup(
+                http_config.addr,
+                cookie_token.clone().expect("Written above")
+            ),
+            "Setting up HTTP JSONRPC server"
+        )
+    });
+
+    RemoteListeners { tcp, http }
+}
+
+// Forward the notifications to two receivers, for the main and the observer RPC servers
+fn tee_notifications(
+    notifications: mpsc::Receiver<Notification>,
+) -> (mpsc::Receiver<Notification>, mpsc::Receiver<Notification>) {
+    let (first_tx, first_rx) = mpsc::channel();
+    let (second_tx, second_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for notif in notifications {
+            // Don't stop forwarding to one if the other is gone
+            let _ = first_tx.send(notif.clone());
+            let _ = second_tx.send(notif);
+        }
+    });
+
+    (first_rx, second_rx)
 }
 
 fn daemon_main(mut revaultd: RevaultD) {
@@ -52,25 +157,52 @@ fn daemon_main(mut revaultd: RevaultD) {
         (true, false) => UserRole::Stakeholder,
         (false, true) => UserRole::Manager,
         (true, true) => UserRole::ManagerStakeholder,
-        _ => unreachable!(),
+        (false, false) => UserRole::Observer,
     };
 
     // First and foremost
     log::info!("Setting up database");
     assume_ok!(setup_db(&mut revaultd), "Error setting up database");
 
-    log::info!("Setting up bitcoind connection");
-    let bitcoind = assume_ok!(start_bitcoind(&mut revaultd), "Error setting up bitcoind");
+    let chain_backend: Arc<RwLock<dyn ChainBackend>> = match revaultd.electrum_config.clone() {
+        Some(electrum_config) => {
+            log::info!("Setting up Electrum server connection");
+            Arc::new(RwLock::new(assume_ok!(
+                start_electrum(&mut revaultd, &electrum_config),
+                "Error setting up Electrum server"
+            )))
+        }
+        None => {
+            log::info!("Setting up bitcoind connection");
+            Arc::new(RwLock::new(assume_ok!(
+                start_bitcoind(&mut revaultd),
+                "Error setting up bitcoind"
+            )))
+        }
+    };
 
     log::info!("Starting JSONRPC server");
+    // The ACL tells what the users of our group may call, if they may connect at all
+    let group_access = revaultd.rpc_group_access;
     let socket = assume_ok!(
-        rpcserver_setup(revaultd.rpc_socket_file()),
+        rpcserver_setup(revaultd.rpc_socket_file(), group_access),
         "Setting up JSONRPC server"
     );
+    // Only the read-only commands are served on this one
+    let observer_socket = if revaultd.observer_socket {
+        Some(assume_ok!(
+            rpcserver_setup(revaultd.rpc_observer_socket_file(), group_access),
+            "Setting up observer JSONRPC server"
+        ))
+    } else {
+        None
+    };
+    let remote_listeners = setup_remote_listeners(&revaultd);
 
-    // We start two threads, the bitcoind one to poll bitcoind for chain updates,
-    // and the sigfetcher one to poll the coordinator for missing signatures
-    // for pre-signed transactions.
+    // We start three threads, the bitcoind one to poll bitcoind for chain updates,
+    // the sigfetcher one to poll the coordinator for missing signatures
+    // for pre-signed transactions, and the watchtowers one to share the fully
+    // signed revocation transactions with our watchtowers.
     // The RPC requests are handled in the main thread, which may send requests
     // to the others.
 
@@ -80,11 +212,18 @@ fn daemon_main(mut revaultd: RevaultD) {
     // The communication from us to the signature poller
     let (sigfetcher_tx, sigfetcher_rx) = mpsc::channel();
 
+    // The communication from us to the watchtowers thread
+    let (watchtowers_tx, watchtowers_rx) = mpsc::channel();
+
+    // The notifications from the bitcoind and signature poller threads to the RPC server
+    let (notif_tx, notif_rx) = mpsc::channel();
+
     let revaultd = Arc::new(RwLock::new(revaultd));
     let bit_revaultd = revaultd.clone();
+    let bit_notif_tx = notif_tx.clone();
     let bitcoind_thread = thread::spawn(move || {
         assume_ok!(
-            bitcoind_main_loop(bitcoind_rx, bit_revaultd, Arc::new(RwLock::new(bitcoind))),
+            bitcoind_main_loop(bitcoind_rx, bit_revaultd, chain_backend, bit_notif_tx),
             "Error in bitcoind main loop"
         );
     });
@@ -92,11 +231,24 @@ fn daemon_main(mut revaultd: RevaultD) {
     let sigfetcher_revaultd = revaultd.clone();
     let sigfetcher_thread = thread::spawn(move || {
         assume_ok!(
-            signature_fetcher_loop(sigfetcher_rx, sigfetcher_revaultd),
+            signature_fetcher_loop(sigfetcher_rx, sigfetcher_revaultd, notif_tx),
             "Error in signature fetcher thread"
         )
     });
 
+    let watchtowers_revaultd = revaultd.clone();
+    let watchtowers_bitcoind_tx = bitcoind_tx.clone();
+    let watchtowers_thread = thread::spawn(move || {
+        assume_ok!(
+            watchtowers_loop(
+                watchtowers_rx,
+                watchtowers_revaultd,
+                watchtowers_bitcoind_tx
+            ),
+            "Error in watchtowers thread"
+        )
+    });
+
     log::info!(
         "revaultd started on network {}",
         revaultd.read().unwrap().bitcoind_config.network
@@ -105,21 +257,55 @@ fn daemon_main(mut revaultd: RevaultD) {
     // Handle RPC commands until we die.
     let bitcoind_thread = Arc::new(RwLock::new(bitcoind_thread));
     let sigfetcher_thread = Arc::new(RwLock::new(sigfetcher_thread));
+    let watchtowers_thread = Arc::new(RwLock::new(watchtowers_thread));
     let rpc_utils = RpcUtils {
         revaultd,
         bitcoind_tx,
         bitcoind_thread: bitcoind_thread.clone(),
         sigfetcher_tx,
         sigfetcher_thread: sigfetcher_thread.clone(),
+        watchtowers_tx,
+        watchtowers_thread: watchtowers_thread.clone(),
+    };
+    // The observer socket has its own loop, and is told about the same notifications. It can't
+    // stop us.
+    let notif_rx = match observer_socket {
+        Some(observer_socket) => {
+            let (notif_rx, observer_notif_rx) = tee_notifications(notif_rx);
+            let observer_rpc_utils = rpc_utils.clone();
+            thread::spawn(move || {
+                assume_ok!(
+                    rpcserver_loop(
+                        observer_socket,
+                        RemoteListeners::default(),
+                        UserRole::Observer,
+                        RpcSocket::Observer,
+                        observer_rpc_utils,
+                        observer_notif_rx
+                    ),
+                    "Error in the observer JSONRPC server loop"
+                )
+            });
+            notif_rx
+        }
+        None => notif_rx,
     };
     assume_ok!(
-        rpcserver_loop(socket, user_role, rpc_utils),
+        rpcserver_loop(
+            socket,
+            remote_listeners,
+            user_role,
+            RpcSocket::Main,
+            rpc_utils,
+            notif_rx
+        ),
         "Error in the main loop"
     );
 
     // If the RPC server loop stops, we've been told to shutdown!
     let bitcoind_thread = unsafe { Arc::into_raw(bitcoind_thread).read().into_inner() };
     let sigfetcher_thread = unsafe { Arc::into_raw(sigfetcher_thread).read().into_inner() };
+    let watchtowers_thread = unsafe { Arc::into_raw(watchtowers_thread).read().into_inner() };
     bitcoind_thread
         .unwrap()
         .join()
@@ -128,6 +314,10 @@ fn daemon_main(mut revaultd: RevaultD) {
         .unwrap()
         .join()
         .expect("Joining sigfetcher thread");
+    watchtowers_thread
+        .unwrap()
+        .join()
+        .expect("Joining watchtowers thread");
 
     // We are always logging to stdout, should it be then piped to the log file (if daemon) or
     // not. So just make sure that all messages were actually written.
@@ -184,7 +374,7 @@ fn setup_panic_hook() {
 
 fn main() {
     let args = env::args().collect();
-    let conf_file = parse_args(args);
+    let args = parse_args(args);
 
     // We use libsodium for Noise keys and Noise channels (through revault_net)
     sodiumoxide::init().unwrap_or_else(|_| {
@@ -192,7 +382,7 @@ fn main() {
         process::exit(1);
     });
 
-    let config = Config::from_file(conf_file).unwrap_or_else(|e| {
+    let config = Config::from_file(args.conf_file).unwrap_or_else(|e| {
         eprintln!("Error parsing config: {}", e);
         process::exit(1);
     });
@@ -201,7 +391,7 @@ fn main() {
         process::exit(1);
     });
     // FIXME: should probably be from_db(), would allow us to not use Option members
-    let revaultd = RevaultD::from_config(config).unwrap_or_else(|e| {
+    let mut revaultd = RevaultD::from_config(config).unwrap_or_else(|e| {
         log::error!("Error creating global state: {}", e);
         process::exit(1);
     });
@@ -217,6 +407,30 @@ fn main() {
 
     setup_panic_hook();
 
+    if let Some(backup_file) = args.backup_file {
+        log::info!("Restoring database from {:?}", backup_file);
+        assume_ok!(
+            restore_db(&revaultd, &backup_file),
+            "Error restoring database"
+        );
+    }
+
+    if let Some(timestamp) = args.recovery_timestamp {
+        // We only ever rebuild the database from scratch
+        if revaultd.db_file().exists() {
+            log::error!(
+                "Can't recover vaults: there is already a database at {:?}",
+                revaultd.db_file()
+            );
+            process::exit(1);
+        }
+        log::info!(
+            "Recovering vaults from the block chain since '{}'",
+            timestamp
+        );
+        revaultd.recovery_timestamp = Some(timestamp);
+    }
+
     if revaultd.daemon {
         let log_file = revaultd.log_file();
         let daemon = Daemonize {
@@ -0,0 +1,276 @@
+//! A mock Coordinator and Cosigning Server, speaking revault_net on localhost to test the
+//! signatures exchange under `cargo test`.
+//!
+//! Like the real ones, they only accept Noise KK connections from the keys they are given. Unlike
+//! them, they don't check the signatures and transactions they are sent.
+
+use revault_net::{
+    message::{
+        cosigner::{SignRequest, SignResponse},
+        server::{GetSigs, GetSpendTx, SetSpendTx, Sig, Sigs, SpendTx},
+    },
+    noise::{PublicKey as NoisePubKey, SecretKey as NoisePrivKey},
+    sodiumoxide,
+    transport::KKTransport,
+};
+use revault_tx::{
+    bitcoin::{
+        secp256k1, OutPoint, PublicKey as BitcoinPubKey, SigHashType,
+        Transaction as BitcoinTransaction, Txid,
+    },
+    transactions::{RevaultTransaction, SpendTransaction},
+};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde::Deserialize;
+
+// Listen on a random port, with a fresh Noise key
+fn bind() -> (TcpListener, NoisePubKey, NoisePrivKey) {
+    sodiumoxide::init().expect("Initializing libsodium");
+    let (noise_pubkey, noise_secret) = sodiumoxide::crypto::box_::gen_keypair();
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Binding mock server");
+
+    (listener, noise_pubkey, noise_secret)
+}
+
+// Accept the connections of these clients, and answer each of their messages with `respond` until
+// they close it.
+fn serve<F>(
+    listener: TcpListener,
+    noise_secret: NoisePrivKey,
+    clients: Vec<NoisePubKey>,
+    respond: F,
+) where
+    F: Fn(&[u8]) -> Result<Option<Vec<u8>>, serde_json::Error> + Send + Sync + 'static,
+{
+    let respond = Arc::new(respond);
+
+    thread::spawn(move || loop {
+        let mut transport = match KKTransport::accept(&listener, &noise_secret, &clients) {
+            Ok(transport) => transport,
+            Err(e) => {
+                log::debug!("Mock server: error accepting connection: '{}'", e);
+                continue;
+            }
+        };
+
+        let respond = respond.clone();
+        thread::spawn(move || {
+            while let Ok(msg) = transport.read() {
+                match respond(&msg) {
+                    Ok(Some(resp)) => {
+                        if let Err(e) = transport.write(&resp) {
+                            log::debug!("Mock server: error writing response: '{}'", e);
+                            return;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::debug!(
+                        "Mock server: invalid message '{}': '{}'",
+                        String::from_utf8_lossy(&msg),
+                        e
+                    ),
+                }
+            }
+        });
+    });
+}
+
+// The messages aren't tagged, we tell them apart by their fields. Order matters as a 'sig' would
+// be a valid 'get_sigs'.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CoordinatorMessage {
+    Sig(Sig),
+    GetSigs(GetSigs),
+    SetSpendTx(SetSpendTx),
+    GetSpendTx(GetSpendTx),
+}
+
+#[derive(Default)]
+struct CoordinatorState {
+    // The signatures of each presigned transaction
+    sigs: HashMap<Txid, BTreeMap<secp256k1::PublicKey, secp256k1::Signature>>,
+    // The Spend transaction announced for each vault
+    spend_txs: HashMap<OutPoint, BitcoinTransaction>,
+}
+
+/// A Coordinator storing the signatures and Spend transactions it is sent
+pub struct MockCoordinator {
+    pub addr: SocketAddr,
+    pub noise_key: NoisePubKey,
+    state: Arc<Mutex<CoordinatorState>>,
+}
+
+impl MockCoordinator {
+    /// Start serving these clients (stakeholders, managers and watchtowers)
+    pub fn start(clients: &[NoisePubKey]) -> Self {
+        let (listener, noise_key, noise_secret) = bind();
+        let addr = listener.local_addr().expect("Bound socket");
+        let state = Arc::new(Mutex::new(CoordinatorState::default()));
+
+        let server_state = state.clone();
+        serve(listener, noise_secret, clients.to_vec(), move |msg| {
+            let mut state = server_state.lock().unwrap();
+            let resp = match serde_json::from_slice(msg)? {
+                CoordinatorMessage::Sig(Sig {
+                    pubkey,
+                    signature,
+                    id,
+                }) => {
+                    state.sigs.entry(id).or_default().insert(pubkey, signature);
+                    None
+                }
+                CoordinatorMessage::GetSigs(GetSigs { id }) => {
+                    let signatures = state.sigs.get(&id).cloned().unwrap_or_default();
+                    Some(serde_json::to_vec(&Sigs { signatures })?)
+                }
+                CoordinatorMessage::SetSpendTx(SetSpendTx {
+                    deposit_outpoints,
+                    transaction,
+                }) => {
+                    for outpoint in deposit_outpoints {
+                        state.spend_txs.insert(outpoint, transaction.clone());
+                    }
+                    None
+                }
+                CoordinatorMessage::GetSpendTx(GetSpendTx { deposit_outpoint }) => {
+                    let transaction = state.spend_txs.get(&deposit_outpoint).cloned();
+                    Some(serde_json::to_vec(&SpendTx { transaction })?)
+                }
+            };
+
+            Ok(resp)
+        });
+
+        MockCoordinator {
+            addr,
+            noise_key,
+            state,
+        }
+    }
+
+    /// The signatures we were sent for this transaction
+    pub fn sigs(&self, txid: &Txid) -> BTreeMap<secp256k1::PublicKey, secp256k1::Signature> {
+        self.state
+            .lock()
+            .unwrap()
+            .sigs
+            .get(txid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Store a signature, as if another participant had shared it
+    pub fn add_sig(
+        &self,
+        txid: Txid,
+        pubkey: secp256k1::PublicKey,
+        signature: secp256k1::Signature,
+    ) {
+        self.state
+            .lock()
+            .unwrap()
+            .sigs
+            .entry(txid)
+            .or_default()
+            .insert(pubkey, signature);
+    }
+
+    /// The Spend transaction that was announced for this vault, if any
+    pub fn spend_tx(&self, deposit_outpoint: &OutPoint) -> Option<BitcoinTransaction> {
+        self.state
+            .lock()
+            .unwrap()
+            .spend_txs
+            .get(deposit_outpoint)
+            .cloned()
+    }
+}
+
+/// A Cosigning Server, signing any Spend transaction as long as it didn't sign another one
+/// spending the same outpoints.
+pub struct MockCosigner {
+    pub addr: SocketAddr,
+    pub noise_key: NoisePubKey,
+    /// The key it signs with
+    pub pubkey: BitcoinPubKey,
+}
+
+impl MockCosigner {
+    /// Start serving these managers
+    pub fn start(managers: &[NoisePubKey], signing_key: secp256k1::SecretKey) -> Self {
+        let (listener, noise_key, noise_secret) = bind();
+        let addr = listener.local_addr().expect("Bound socket");
+        let secp = secp256k1::Secp256k1::new();
+        let pubkey = BitcoinPubKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&secp, &signing_key),
+        };
+        // The Spend transaction we signed for each outpoint
+        let signed: Mutex<HashMap<OutPoint, Txid>> = Mutex::new(HashMap::new());
+
+        serve(listener, noise_secret, managers.to_vec(), move |msg| {
+            let SignRequest { tx } = serde_json::from_slice(msg)?;
+            let resp = SignResponse {
+                tx: cosign(&secp, &signing_key, &mut signed.lock().unwrap(), tx),
+            };
+
+            Ok(Some(serde_json::to_vec(&resp)?))
+        });
+
+        MockCosigner {
+            addr,
+            noise_key,
+            pubkey,
+        }
+    }
+}
+
+// Sign all the inputs of this Spend transaction, unless we already signed another one spending
+// one of them.
+fn cosign(
+    secp: &secp256k1::Secp256k1<secp256k1::All>,
+    signing_key: &secp256k1::SecretKey,
+    signed: &mut HashMap<OutPoint, Txid>,
+    mut tx: SpendTransaction,
+) -> Option<SpendTransaction> {
+    let txid = tx.txid();
+    let outpoints: Vec<OutPoint> = tx
+        .inner_tx()
+        .global
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect();
+    if outpoints
+        .iter()
+        .any(|outpoint| signed.get(outpoint).map(|t| t != &txid).unwrap_or(false))
+    {
+        return None;
+    }
+
+    let pubkey = BitcoinPubKey {
+        compressed: true,
+        key: secp256k1::PublicKey::from_secret_key(secp, signing_key),
+    };
+    for i in 0..outpoints.len() {
+        let sighash = tx
+            .signature_hash_internal_input(i, SigHashType::All)
+            .expect("Input exists");
+        let sighash = secp256k1::Message::from_slice(&sighash).expect("sighash is 32 bytes");
+        let mut sig = secp.sign(&sighash, signing_key).serialize_der().to_vec();
+        sig.push(SigHashType::All as u8);
+        tx.inner_tx_mut().inputs[i].partial_sigs.insert(pubkey, sig);
+    }
+    signed.extend(outpoints.into_iter().map(|outpoint| (outpoint, txid)));
+
+    Some(tx)
+}
@@ -1,4 +1,8 @@
-use common::config::{config_folder_path, BitcoindConfig, Config, ConfigError};
+use crate::jsonrpc::is_command;
+use common::config::{
+    config_folder_path, BitcoindConfig, Config, ConfigError, ElectrumConfig, NotifyConfig,
+    RpcAclEntry, RpcHttpConfig, RpcTcpConfig,
+};
 
 use std::{
     collections::HashMap,
@@ -35,7 +39,7 @@ use revault_tx::{
 
 /// The status of a [Vault], depends both on the block chain and the set of pre-signed
 /// transactions
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum VaultStatus {
     /// The deposit transaction has less than 6 confirmations
     Unconfirmed,
@@ -235,6 +239,8 @@ pub struct RevaultD {
     // Bitcoind stuff
     /// Everything we need to know to talk to bitcoind
     pub bitcoind_config: BitcoindConfig,
+    /// Some() if we use an Electrum server instead of bitcoind
+    pub electrum_config: Option<ElectrumConfig>,
     /// Last block we heard about
     pub tip: Option<BlockchainTip>,
     /// Minimum confirmations before considering a deposit as mature
@@ -248,6 +254,8 @@ pub struct RevaultD {
     pub deposit_descriptor: DepositDescriptor,
     /// The miniscript descriptor of unvault's outputs scripts
     pub unvault_descriptor: UnvaultDescriptor,
+    /// The relative timelock of the unvault's outputs scripts
+    pub unvault_csv: u32,
     /// The miniscript descriptor of CPFP output scripts (in unvault and spend transaction)
     pub cpfp_descriptor: CpfpDescriptor,
     /// The Emergency address, only available if we are a stakeholder
@@ -277,6 +285,24 @@ pub struct RevaultD {
     /// The ip:port (TODO: Tor) and Noise public key of each cosigning server, only set if we are
     /// a manager.
     pub cosigs: Option<Vec<(SocketAddr, NoisePubKey)>>,
+    /// The ip:port (TODO: Tor) and Noise public key of each watchtower, only set if we are a
+    /// stakeholder.
+    pub watchtowers: Option<Vec<(SocketAddr, NoisePubKey)>>,
+    /// Should we cancel Unvaults that weren't announced to the Coordinator by ourselves? Only
+    /// ever true if we are a stakeholder.
+    pub local_watchtower: bool,
+    /// The commands to run when something happens to a vault
+    pub notify_config: NotifyConfig,
+    /// Where to serve the JSONRPC API over TCP, if we do
+    pub rpc_tcp_config: Option<RpcTcpConfig>,
+    /// Where to serve the JSONRPC API over HTTP, if we do
+    pub rpc_http_config: Option<RpcHttpConfig>,
+    /// Whether to serve the read-only commands on a second socket
+    pub observer_socket: bool,
+    /// Who may call what on the Unix sockets
+    pub rpc_acl: Vec<RpcAclEntry>,
+    /// Whether the users of our group may connect to the Unix sockets
+    pub rpc_group_access: bool,
 
     // 'Wallet' stuff
     /// A map from a scriptPubKey to a derivation index. Used to retrieve the actual public
@@ -291,6 +317,9 @@ pub struct RevaultD {
     pub data_dir: PathBuf,
     /// Should we run as a daemon? (Default: yes)
     pub daemon: bool,
+    /// Only set when started with '--recover': the time from which to look for our vaults in
+    /// the block chain, to rebuild a lost database.
+    pub recovery_timestamp: Option<u32>,
     // TODO: servers connection stuff
 }
 
@@ -314,10 +343,18 @@ fn create_datadir(datadir_path: &PathBuf) -> Result<(), std::io::Error> {
 impl RevaultD {
     /// Creates our global state by consuming the static configuration
     pub fn from_config(config: Config) -> Result<RevaultD, Box<dyn std::error::Error>> {
+        // A typo would silently deny the command
+        for method in config.rpc_acl.iter().flat_map(|entry| entry.methods.iter()) {
+            if !is_command(method) {
+                return Err(Box::from(ConfigError(format!(
+                    r#"Unknown command '{}' in "rpc_acl""#,
+                    method
+                ))));
+            }
+        }
+
         let our_man_xpub = config.manager_config.as_ref().map(|x| x.xpub);
         let our_stk_xpub = config.stakeholder_config.as_ref().map(|x| x.xpub);
-        // Config should have checked that!
-        assert!(our_man_xpub.is_some() || our_stk_xpub.is_some());
 
         let managers_pubkeys = config.managers_xpubs;
         let stakeholders_pubkeys = config.stakeholders_xpubs;
@@ -332,7 +369,22 @@ impl RevaultD {
             config.unvault_csv,
         )?;
         let cpfp_descriptor = CpfpDescriptor::new(managers_pubkeys.clone())?;
-        let emergency_address = config.stakeholder_config.map(|x| x.emergency_address);
+        let local_watchtower = config
+            .stakeholder_config
+            .as_ref()
+            .map(|x| x.local_watchtower)
+            .unwrap_or(false);
+        let emergency_address = config
+            .stakeholder_config
+            .as_ref()
+            .map(|x| x.emergency_address.clone());
+        let watchtowers = config.stakeholder_config.map(|config| {
+            config
+                .watchtowers
+                .into_iter()
+                .map(|config| (config.host, config.noise_key))
+                .collect()
+        });
 
         let mut data_dir = config.data_dir.unwrap_or(config_folder_path()?);
         data_dir.push(config.bitcoind_config.network.to_string());
@@ -346,6 +398,12 @@ impl RevaultD {
             }
         }
         data_dir = fs::canonicalize(data_dir)?;
+        // The users of our group need to go through it to reach the sockets, but may not list it
+        #[cfg(unix)]
+        if config.rpc_group_access {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&data_dir, fs::Permissions::from_mode(0o710))?;
+        }
 
         let data_dir_str = data_dir
             .to_str()
@@ -375,6 +433,7 @@ impl RevaultD {
             our_man_xpub,
             deposit_descriptor,
             unvault_descriptor,
+            unvault_csv: config.unvault_csv,
             managers_pubkeys,
             cpfp_descriptor,
             secp_ctx,
@@ -386,9 +445,18 @@ impl RevaultD {
             coordinator_noisekey,
             coordinator_poll_interval,
             cosigs,
+            watchtowers,
+            local_watchtower,
+            notify_config: config.notify_config.unwrap_or_default(),
+            rpc_tcp_config: config.rpc_tcp_config,
+            rpc_http_config: config.rpc_http_config,
+            observer_socket: config.observer_socket,
+            rpc_acl: config.rpc_acl,
+            rpc_group_access: config.rpc_group_access,
             lock_time: 0,
             min_conf: config.min_conf,
             bitcoind_config: config.bitcoind_config,
+            electrum_config: config.electrum_config,
             tip: None,
             // Will be updated by the database
             current_unused_index: ChildNumber::from(0),
@@ -396,6 +464,7 @@ impl RevaultD {
             derivation_index_map: HashMap::new(),
             // Will be updated soon (:tm:)
             wallet_id: None,
+            recovery_timestamp: None,
         })
     }
 
@@ -430,6 +499,14 @@ impl RevaultD {
             .expect("unvault_descriptor is a wsh")
     }
 
+    pub fn cpfp_address(&self, child_number: ChildNumber) -> Address {
+        self.cpfp_descriptor
+            .derive(child_number, &self.secp_ctx)
+            .inner()
+            .address(self.bitcoind_config.network)
+            .expect("cpfp_descriptor is a wsh")
+    }
+
     pub fn gap_limit(&self) -> u32 {
         100
     }
@@ -439,6 +516,11 @@ impl RevaultD {
             .map(|ref id| format!("revaultd-watchonly-wallet-{}", id))
     }
 
+    pub fn feebump_wallet_name(&self) -> Option<String> {
+        self.wallet_id
+            .map(|ref id| format!("revaultd-feebump-wallet-{}", id))
+    }
+
     pub fn log_file(&self) -> PathBuf {
         self.file_from_datadir("log")
     }
@@ -460,10 +542,31 @@ impl RevaultD {
         })
     }
 
+    pub fn feebump_wallet_file(&self) -> Option<String> {
+        self.feebump_wallet_name().map(|ref name| {
+            self.file_from_datadir(name)
+                .to_str()
+                .expect("Valid utf-8")
+                .to_string()
+        })
+    }
+
+    pub fn emergency_kit_file(&self) -> PathBuf {
+        self.file_from_datadir("emergency_kit.json")
+    }
+
     pub fn rpc_socket_file(&self) -> PathBuf {
         self.file_from_datadir("revaultd_rpc")
     }
 
+    pub fn rpc_cookie_file(&self) -> PathBuf {
+        self.file_from_datadir("rpc_cookie")
+    }
+
+    pub fn rpc_observer_socket_file(&self) -> PathBuf {
+        self.file_from_datadir("revaultd_observer_rpc")
+    }
+
     pub fn is_stakeholder(&self) -> bool {
         self.our_stk_xpub.is_some()
     }
@@ -472,6 +575,11 @@ impl RevaultD {
         self.our_man_xpub.is_some()
     }
 
+    /// Neither a stakeholder nor a manager, we only watch the vaults
+    pub fn is_observer(&self) -> bool {
+        !self.is_stakeholder() && !self.is_manager()
+    }
+
     pub fn deposit_address(&self) -> Address {
         self.vault_address(self.current_unused_index)
     }
@@ -488,6 +596,12 @@ impl RevaultD {
         self.unvault_address(ChildNumber::from(raw_index + self.gap_limit()))
     }
 
+    pub fn last_cpfp_address(&self) -> Address {
+        let raw_index: u32 = self.current_unused_index.into();
+        // FIXME: this should fail instead of creating a hardened index
+        self.cpfp_address(ChildNumber::from(raw_index + self.gap_limit()))
+    }
+
     /// All deposit addresses as strings up to the gap limit (100)
     pub fn all_deposit_addresses(&mut self) -> Vec<String> {
         self.derivation_index_map
@@ -512,6 +626,17 @@ impl RevaultD {
             .collect()
     }
 
+    /// All CPFP addresses as strings up to the gap limit (100)
+    pub fn all_cpfp_addresses(&mut self) -> Vec<String> {
+        let raw_index: u32 = self.current_unused_index.into();
+        (0..raw_index + self.gap_limit())
+            .map(|raw_index| {
+                // FIXME: this should fail instead of creating a hardened index
+                self.cpfp_address(ChildNumber::from(raw_index)).to_string()
+            })
+            .collect()
+    }
+
     pub fn derived_deposit_descriptor(&self, index: ChildNumber) -> DerivedDepositDescriptor {
         self.deposit_descriptor.derive(index, &self.secp_ctx)
     }
@@ -528,7 +653,7 @@ impl RevaultD {
 #[cfg(test)]
 mod tests {
     use super::RevaultD;
-    use common::config::Config;
+    use common::config::{Config, RpcAclEntry};
 
     use std::path::PathBuf;
 
@@ -541,8 +666,23 @@ mod tests {
 
         path.pop();
         path.push("valid_config.toml");
-        let config = Config::from_file(Some(path)).expect("Parsing valid config file");
+        let config = Config::from_file(Some(path.clone())).expect("Parsing valid config file");
         RevaultD::from_config(config).expect("Creating state from config");
+
+        // The ACL may only name existing commands
+        let mut config = Config::from_file(Some(path.clone())).expect("Parsing valid config file");
+        config.rpc_acl = vec![RpcAclEntry {
+            uid: 1001,
+            methods: vec!["listvaults".to_string(), "stopdaemon".to_string()],
+        }];
+        RevaultD::from_config(config).expect_err("Unknown command in the ACL");
+
+        path.pop();
+        path.push("observer_config.toml");
+        let config = Config::from_file(Some(path)).expect("Parsing observer config file");
+        let revaultd = RevaultD::from_config(config).expect("Creating observer state from config");
+        assert!(revaultd.is_observer());
+        assert!(revaultd.observer_socket);
         // TODO: test actual fields..
     }
 }
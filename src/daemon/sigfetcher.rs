@@ -3,12 +3,15 @@ use crate::{
     control::send_sig_msg,
     database::{
         actions::db_update_presigned_tx,
-        interface::{db_transactions_current_vaults, db_transactions_sig_missing},
-        schema::{DbTransaction, RevaultTx, TransactionType},
+        interface::{
+            db_events, db_last_event_id, db_transactions_current_vaults,
+            db_transactions_sig_missing, EventsFilter,
+        },
+        schema::{DbTransaction, EventKind, RevaultTx, TransactionType},
         DatabaseError,
     },
     revaultd::RevaultD,
-    threadmessages::SigFetcherMessageOut,
+    threadmessages::{Notification, SigFetcherMessageOut},
 };
 use revault_net::{
     message::server::{GetSigs, Sigs},
@@ -230,10 +233,10 @@ fn get_sigs(
     Ok(())
 }
 
-// Sequentially poll the coordinator for all the `txs` signatures.
+/// Sequentially poll the coordinator for all the `txs` signatures.
 // TODO: poll in parallel, it's worthwile as we are going to proxy the communications
 // through Tor.
-fn fetch_all_signatures(
+pub fn fetch_all_signatures(
     revaultd: &RevaultD,
     mut txs: Vec<DbTransaction>,
 ) -> Result<(), SignatureFetcherError> {
@@ -263,21 +266,57 @@ fn fetch_all_signatures(
     Ok(())
 }
 
+// Stream to the JSONRPC server the signatures recorded in the vaults history since the last
+// one we notified, be they fetched from the Coordinator or given by our user.
+fn notify_new_signatures(
+    revaultd: &RevaultD,
+    last_event_id: &mut i64,
+    notif_tx: &mpsc::Sender<Notification>,
+) -> Result<(), SignatureFetcherError> {
+    let filter = EventsFilter {
+        kinds: Some(vec![EventKind::Signature]),
+        after_id: Some(*last_event_id),
+        ..EventsFilter::default()
+    };
+
+    for event in db_events(&revaultd.db_file(), &filter, 0, u32::MAX)? {
+        *last_event_id = event.id;
+        if let Some(txid) = event.txid {
+            let notif = Notification::Signature {
+                outpoint: event.deposit_outpoint,
+                txid,
+            };
+            // The JSONRPC server may be gone if we are shutting down
+            if let Err(e) = notif_tx.send(notif) {
+                log::debug!("Sending notification to the JSONRPC server: '{}'", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
 // Poll the Coordinator for revocation transactions signatures indefinitely.
 pub fn signature_fetcher_loop(
     rx: mpsc::Receiver<SigFetcherMessageOut>,
     revaultd: Arc<RwLock<RevaultD>>,
+    notif_tx: mpsc::Sender<Notification>,
 ) -> Result<(), SignatureFetcherError> {
     let mut last_poll = time::Instant::now();
     let poll_interval = revaultd.read().unwrap().coordinator_poll_interval;
+    // Only notify what happened from now on
+    let mut last_event_id = db_last_event_id(&revaultd.read().unwrap().db_file())?;
 
     log::info!("Signature fetcher thread started.");
 
     // Make sure the coordinator has got all our signatures for current vaults.
     // FIXME: this is bulk, be smarter (may just be checking it has got it after sharing it in the
     // first place, or mark it as shared in DB).
-    if let Err(e) = share_all_signatures(&revaultd.read().unwrap()) {
-        log::error!("Error sharing all our signatures: '{}'", e);
+    // An observer never shares anything, it only fetches the signatures of the participants.
+    if !revaultd.read().unwrap().is_observer() {
+        if let Err(e) = share_all_signatures(&revaultd.read().unwrap()) {
+            log::error!("Error sharing all our signatures: '{}'", e);
+        }
     }
 
     loop {
@@ -302,6 +341,12 @@ pub fn signature_fetcher_loop(
             fetch_all_signatures(&revaultd.read().unwrap(), txs).unwrap_or_else(|e| {
                 log::warn!("Error while fetching signatures: '{}'", e);
             });
+            // We'll retry the ones we could not fetch on the next round
+            if let Err(e) =
+                notify_new_signatures(&revaultd.read().unwrap(), &mut last_event_id, &notif_tx)
+            {
+                log::error!("Error notifying new signatures: '{}'", e);
+            }
 
             last_poll = time::Instant::now();
         }
@@ -310,3 +355,139 @@ pub fn signature_fetcher_loop(
         thread::sleep(time::Duration::from_millis(500));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        control::share_unvault_signatures,
+        database::{
+            actions::{db_confirm_deposit, db_insert_new_unconfirmed_vault},
+            interface::{db_cancel_transaction, db_unvault_transaction, db_vault_by_deposit},
+        },
+        mock_servers::MockCoordinator,
+        test_utils::dummy_revaultd,
+    };
+    use revault_tx::{
+        bitcoin::{util::bip32::ChildNumber, Amount, OutPoint},
+        transactions::transaction_chain_manager,
+    };
+
+    use std::{fs, str::FromStr};
+
+    // A signature of this presigned transaction by a dummy key
+    fn dummy_sig(
+        tx: &impl RevaultTransaction,
+        hashtype: SigHashType,
+        seed: u8,
+    ) -> (secp256k1::PublicKey, secp256k1::Signature) {
+        let secp = secp256k1::Secp256k1::new();
+        let key = secp256k1::SecretKey::from_slice(&[seed; 32]).unwrap();
+
+        (
+            secp256k1::PublicKey::from_secret_key(&secp, &key),
+            secp.sign(&presigned_tx_sighash(tx, hashtype), &key),
+        )
+    }
+
+    #[test]
+    fn signatures_exchange() {
+        let mut revaultd = dummy_revaultd("scratch_datadir_sigfetcher");
+        let coordinator = MockCoordinator::start(&[revaultd.noise_pubkey()]);
+        revaultd.coordinator_host = coordinator.addr;
+        revaultd.coordinator_noisekey = coordinator.noise_key;
+        let db_path = revaultd.db_file();
+
+        // A confirmed vault, whose presigned transactions are yet to be signed
+        let deposit_outpoint = OutPoint::from_str(
+            "c9cf38058b720050bcba47490ee27f4a29d57a5aa2ee0f3c97731e140dbeced7:1",
+        )
+        .unwrap();
+        let amount = Amount::from_sat(50_000_000);
+        let derivation_index = ChildNumber::from(3);
+        db_insert_new_unconfirmed_vault(
+            &db_path,
+            1,
+            &deposit_outpoint,
+            &amount,
+            derivation_index,
+            1615297315,
+        )
+        .unwrap();
+        let (unvault_tx, cancel_tx) = transaction_chain_manager(
+            deposit_outpoint,
+            amount,
+            &revaultd.deposit_descriptor,
+            &revaultd.unvault_descriptor,
+            &revaultd.cpfp_descriptor,
+            derivation_index,
+            revaultd.lock_time,
+            &revaultd.secp_ctx,
+        )
+        .unwrap();
+        db_confirm_deposit(
+            &db_path,
+            &deposit_outpoint,
+            101,
+            &unvault_tx,
+            &cancel_tx,
+            None,
+            None,
+        )
+        .unwrap();
+        let vault = db_vault_by_deposit(&db_path, &deposit_outpoint)
+            .unwrap()
+            .unwrap();
+
+        // We share our Unvault signature, the Coordinator stores it
+        let (our_pubkey, our_sig) = dummy_sig(&unvault_tx, SigHashType::All, 1);
+        let mut signed_unvault_tx = unvault_tx.clone();
+        signed_unvault_tx
+            .add_signature(
+                0,
+                BitcoinPubKey {
+                    compressed: true,
+                    key: our_pubkey,
+                },
+                (our_sig, SigHashType::All),
+            )
+            .unwrap();
+        share_unvault_signatures(&revaultd, &signed_unvault_tx).unwrap();
+        // There is no response to a 'sig' message, wait for the Coordinator to process it
+        let start = time::Instant::now();
+        while !coordinator
+            .sigs(&unvault_tx.txid())
+            .contains_key(&our_pubkey)
+        {
+            assert!(
+                start.elapsed() < time::Duration::from_secs(10),
+                "The Coordinator never got our signature"
+            );
+            thread::sleep(time::Duration::from_millis(50));
+        }
+
+        // Another stakeholder shared their Cancel signature, and someone sent garbage
+        let (their_pubkey, their_sig) = dummy_sig(&cancel_tx, SigHashType::AllPlusAnyoneCanPay, 2);
+        coordinator.add_sig(cancel_tx.txid(), their_pubkey, their_sig);
+        let (bogus_pubkey, bogus_sig) = dummy_sig(&unvault_tx, SigHashType::All, 3);
+        coordinator.add_sig(cancel_tx.txid(), bogus_pubkey, bogus_sig);
+
+        // We fetch all of them, but only store the valid ones
+        fetch_all_signatures(&revaultd, db_transactions_sig_missing(&db_path).unwrap()).unwrap();
+        let (_, db_cancel_tx) = db_cancel_transaction(&db_path, vault.id).unwrap().unwrap();
+        let cancel_sigs = &db_cancel_tx.inner_tx().inputs[0].partial_sigs;
+        assert_eq!(cancel_sigs.len(), 1);
+        assert!(cancel_sigs.contains_key(&BitcoinPubKey {
+            compressed: true,
+            key: their_pubkey
+        }));
+        let (_, db_unvault_tx) = db_unvault_transaction(&db_path, vault.id).unwrap();
+        let unvault_sigs = &db_unvault_tx.inner_tx().inputs[0].partial_sigs;
+        assert!(unvault_sigs.contains_key(&BitcoinPubKey {
+            compressed: true,
+            key: our_pubkey
+        }));
+
+        fs::remove_dir_all(&revaultd.data_dir).unwrap_or_else(|_| ());
+    }
+}
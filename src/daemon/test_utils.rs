@@ -0,0 +1,23 @@
+//! What the unit tests of the different modules share.
+
+use crate::{database::actions::setup_db, revaultd::RevaultD};
+use common::config::Config;
+
+use std::{fs, path::PathBuf};
+
+/// A manager's state, as configured by `test_data/db_test_config.toml`, on a fresh scratch data
+/// directory under `test_data/` and with its database set up.
+pub fn dummy_revaultd(datadir_name: &str) -> RevaultD {
+    let test_data: PathBuf = [env!("CARGO_MANIFEST_DIR"), "test_data"].iter().collect();
+    let datadir = test_data.join(datadir_name);
+    // Just in case there is a leftover from a previous run
+    fs::remove_dir_all(&datadir).unwrap_or_else(|_| ());
+
+    let mut config = Config::from_file(Some(test_data.join("db_test_config.toml")))
+        .expect("Parsing valid config file");
+    config.data_dir = Some(datadir);
+    let mut revaultd = RevaultD::from_config(config).expect("Creating state from config");
+    setup_db(&mut revaultd).expect("Setting up the database");
+
+    revaultd
+}
@@ -1,5 +1,8 @@
-use crate::bitcoind::BitcoindError;
-use revault_tx::bitcoin::{Transaction as BitcoinTransaction, Txid};
+use crate::{
+    bitcoind::{feebump::FeebumpReserve, interface::EstimateMode, BitcoindError},
+    revaultd::VaultStatus,
+};
+use revault_tx::bitcoin::{Amount, OutPoint, Transaction as BitcoinTransaction, Txid};
 
 use std::sync::mpsc::SyncSender;
 
@@ -10,6 +13,23 @@ pub enum BitcoindMessageOut {
     SyncProgress(SyncSender<f64>),
     WalletTransaction(Txid, SyncSender<Option<WalletTransaction>>),
     BroadcastTransaction(BitcoinTransaction, SyncSender<Result<(), BitcoindError>>),
+    /// Attach a coin of the fee-bumping wallet to this finalized revocation transaction, given
+    /// its presigned fees and the target feerate
+    FeebumpTransaction(
+        BitcoinTransaction,
+        u64,
+        u64,
+        SyncSender<Result<BitcoinTransaction, BitcoindError>>,
+    ),
+    /// Get the state of the fee-bumping wallet, given the weight of the revocation transactions
+    /// and the target feerate
+    FeebumpReserve(u64, u64, SyncSender<Result<FeebumpReserve, BitcoindError>>),
+    /// Get the feerate (in sat/vbyte) estimated for a confirmation within this number of blocks
+    EstimateFeerate(
+        u16,
+        EstimateMode,
+        SyncSender<Result<Option<u64>, BitcoindError>>,
+    ),
 }
 
 /// Outgoing to the signature fetcher thread
@@ -18,6 +38,29 @@ pub enum SigFetcherMessageOut {
     Shutdown,
 }
 
+/// Outgoing to the watchtowers thread
+#[derive(Debug)]
+pub enum WatchtowersMessageOut {
+    Shutdown,
+}
+
+/// Incoming from the bitcoind poller and signature fetcher threads, streamed by the JSONRPC
+/// server to the clients that subscribed to it
+#[derive(Debug, Clone)]
+pub enum Notification {
+    /// We detected a new deposit
+    Deposit { outpoint: OutPoint, amount: Amount },
+    /// This vault's status changed, possibly backward because of a reorg
+    StatusChange {
+        outpoint: OutPoint,
+        status: VaultStatus,
+    },
+    /// We got new signatures for this presigned transaction of this vault
+    Signature { outpoint: OutPoint, txid: Txid },
+    /// We broadcast this transaction of this vault
+    Broadcast { outpoint: OutPoint, txid: Txid },
+}
+
 #[derive(Debug)]
 pub struct WalletTransaction {
     pub hex: String,
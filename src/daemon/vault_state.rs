@@ -0,0 +1,463 @@
+//! The lifecycle of a vault.
+//!
+//! A vault moves forward as its presigned transactions get signed and as the transactions of its
+//! chain get broadcast and mined, and backward when a reorg unconfirms some of them. Any change
+//! of a vault status in database goes through [VaultState], which refuses the ones that can't
+//! happen.
+
+use crate::revaultd::VaultStatus;
+
+use std::fmt;
+
+/// How a vault changes status
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    /// The vault moves on in its lifecycle. This includes going back to the previous status when
+    /// a transaction gets evicted from the mempool.
+    Progress,
+    /// The vault goes back to a previous status because of a block chain reorganisation
+    Rewind,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Progress => write!(f, "transition"),
+            Self::Rewind => write!(f, "rewind"),
+        }
+    }
+}
+
+/// A change of status that can't happen to a vault
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidTransition {
+    pub from: VaultStatus,
+    pub to: VaultStatus,
+    pub transition: Transition,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid {} from '{}' to '{}'",
+            self.transition, self.from, self.to
+        )
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+// What we may find spent a confirmed deposit. The Unvault may be spent (or confirmed) by the time
+// we notice it, and our signatures may lag behind the ones of the other participants.
+const DEPOSIT_SPENT: &[VaultStatus] = &[
+    VaultStatus::Unvaulting,
+    VaultStatus::Unvaulted,
+    VaultStatus::Canceling,
+    VaultStatus::Spending,
+    VaultStatus::UnvaultEmergencyVaulting,
+    VaultStatus::EmergencyVaulting,
+];
+
+/// The status of a vault, which only changes along the legal transitions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VaultState(VaultStatus);
+
+impl VaultState {
+    pub fn new(status: VaultStatus) -> Self {
+        VaultState(status)
+    }
+
+    pub fn status(&self) -> VaultStatus {
+        self.0
+    }
+
+    /// The statuses a vault in this state may move on to
+    pub fn next(&self) -> &'static [VaultStatus] {
+        match self.0 {
+            VaultStatus::Unconfirmed => &[VaultStatus::Funded],
+            VaultStatus::Funded => &[
+                VaultStatus::Securing,
+                VaultStatus::Secured,
+                VaultStatus::Unvaulting,
+                VaultStatus::Unvaulted,
+                VaultStatus::Canceling,
+                VaultStatus::Spending,
+                VaultStatus::UnvaultEmergencyVaulting,
+                VaultStatus::EmergencyVaulting,
+            ],
+            VaultStatus::Securing => &[
+                VaultStatus::Secured,
+                VaultStatus::Unvaulting,
+                VaultStatus::Unvaulted,
+                VaultStatus::Canceling,
+                VaultStatus::Spending,
+                VaultStatus::UnvaultEmergencyVaulting,
+                VaultStatus::EmergencyVaulting,
+            ],
+            VaultStatus::Secured => &[
+                VaultStatus::Activating,
+                VaultStatus::Active,
+                VaultStatus::Unvaulting,
+                VaultStatus::Unvaulted,
+                VaultStatus::Canceling,
+                VaultStatus::Spending,
+                VaultStatus::UnvaultEmergencyVaulting,
+                VaultStatus::EmergencyVaulting,
+            ],
+            VaultStatus::Activating => &[
+                VaultStatus::Active,
+                VaultStatus::Unvaulting,
+                VaultStatus::Unvaulted,
+                VaultStatus::Canceling,
+                VaultStatus::Spending,
+                VaultStatus::UnvaultEmergencyVaulting,
+                VaultStatus::EmergencyVaulting,
+            ],
+            VaultStatus::Active => DEPOSIT_SPENT,
+            VaultStatus::Unvaulting => &[
+                VaultStatus::Unvaulted,
+                VaultStatus::Canceling,
+                VaultStatus::Spending,
+                VaultStatus::UnvaultEmergencyVaulting,
+            ],
+            VaultStatus::Unvaulted => &[
+                VaultStatus::Canceling,
+                VaultStatus::Spending,
+                VaultStatus::UnvaultEmergencyVaulting,
+            ],
+            // Back to 'unvaulted' if evicted from the mempool
+            VaultStatus::Canceling => &[VaultStatus::Canceled, VaultStatus::Unvaulted],
+            VaultStatus::Spending => &[VaultStatus::Spent, VaultStatus::Unvaulted],
+            // We may broadcast it before the Unvault is mined, in which case it's back to
+            // 'unvaulting' if evicted.
+            VaultStatus::UnvaultEmergencyVaulting => &[
+                VaultStatus::UnvaultEmergencyVaulted,
+                VaultStatus::Unvaulted,
+                VaultStatus::Unvaulting,
+            ],
+            // Back to where it was when the deposit got spent if evicted
+            VaultStatus::EmergencyVaulting => &[
+                VaultStatus::EmergencyVaulted,
+                VaultStatus::Funded,
+                VaultStatus::Securing,
+                VaultStatus::Secured,
+                VaultStatus::Activating,
+                VaultStatus::Active,
+            ],
+            VaultStatus::Canceled
+            | VaultStatus::Spent
+            | VaultStatus::EmergencyVaulted
+            | VaultStatus::UnvaultEmergencyVaulted => &[],
+        }
+    }
+
+    /// The statuses a reorg may take a vault in this state back to
+    pub fn previous(&self) -> &'static [VaultStatus] {
+        match self.0 {
+            // The deposit isn't confirmed anymore
+            VaultStatus::Funded
+            | VaultStatus::Securing
+            | VaultStatus::Secured
+            | VaultStatus::Activating
+            | VaultStatus::Active => &[VaultStatus::Unconfirmed],
+            // The Unvault isn't confirmed anymore, or only what spent it isn't
+            VaultStatus::Unvaulted => &[VaultStatus::Unvaulting],
+            VaultStatus::Canceling
+            | VaultStatus::Spending
+            | VaultStatus::UnvaultEmergencyVaulting => &[VaultStatus::Unvaulting],
+            VaultStatus::Canceled => &[VaultStatus::Canceling, VaultStatus::Unvaulting],
+            VaultStatus::Spent => &[VaultStatus::Spending, VaultStatus::Unvaulting],
+            VaultStatus::UnvaultEmergencyVaulted => &[
+                VaultStatus::UnvaultEmergencyVaulting,
+                VaultStatus::Unvaulting,
+            ],
+            // The deposit is spent by the Emergency, which will be mined again
+            VaultStatus::EmergencyVaulted => &[VaultStatus::EmergencyVaulting],
+            VaultStatus::Unconfirmed | VaultStatus::Unvaulting | VaultStatus::EmergencyVaulting => {
+                &[]
+            }
+        }
+    }
+
+    /// Whether a vault in this state may change to this status. Staying in the same status always
+    /// is legal.
+    pub fn allows(&self, to: VaultStatus, transition: Transition) -> bool {
+        to == self.0
+            || match transition {
+                Transition::Progress => self.next().contains(&to),
+                Transition::Rewind => self.previous().contains(&to),
+            }
+    }
+
+    /// Change the status, if the transition is legal
+    pub fn transition(
+        &mut self,
+        to: VaultStatus,
+        transition: Transition,
+    ) -> Result<(), InvalidTransition> {
+        if !self.allows(to, transition) {
+            return Err(InvalidTransition {
+                from: self.0,
+                to,
+                transition,
+            });
+        }
+
+        self.0 = to;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        collections::{HashSet, VecDeque},
+        convert::TryFrom,
+    };
+
+    use proptest::prelude::*;
+
+    // The changes of status a vault may go through, written down independently from the tables
+    // of [VaultState] so that they can be checked against them. Staying in the same status is
+    // always legal on top of these.
+    const PROGRESSIONS: &[(VaultStatus, VaultStatus)] = &[
+        (VaultStatus::Unconfirmed, VaultStatus::Funded),
+        (VaultStatus::Funded, VaultStatus::Securing),
+        (VaultStatus::Funded, VaultStatus::Secured),
+        (VaultStatus::Funded, VaultStatus::Unvaulting),
+        (VaultStatus::Funded, VaultStatus::Unvaulted),
+        (VaultStatus::Funded, VaultStatus::Canceling),
+        (VaultStatus::Funded, VaultStatus::Spending),
+        (VaultStatus::Funded, VaultStatus::UnvaultEmergencyVaulting),
+        (VaultStatus::Funded, VaultStatus::EmergencyVaulting),
+        (VaultStatus::Securing, VaultStatus::Secured),
+        (VaultStatus::Securing, VaultStatus::Unvaulting),
+        (VaultStatus::Securing, VaultStatus::Unvaulted),
+        (VaultStatus::Securing, VaultStatus::Canceling),
+        (VaultStatus::Securing, VaultStatus::Spending),
+        (VaultStatus::Securing, VaultStatus::UnvaultEmergencyVaulting),
+        (VaultStatus::Securing, VaultStatus::EmergencyVaulting),
+        (VaultStatus::Secured, VaultStatus::Activating),
+        (VaultStatus::Secured, VaultStatus::Active),
+        (VaultStatus::Secured, VaultStatus::Unvaulting),
+        (VaultStatus::Secured, VaultStatus::Unvaulted),
+        (VaultStatus::Secured, VaultStatus::Canceling),
+        (VaultStatus::Secured, VaultStatus::Spending),
+        (VaultStatus::Secured, VaultStatus::UnvaultEmergencyVaulting),
+        (VaultStatus::Secured, VaultStatus::EmergencyVaulting),
+        (VaultStatus::Activating, VaultStatus::Active),
+        (VaultStatus::Activating, VaultStatus::Unvaulting),
+        (VaultStatus::Activating, VaultStatus::Unvaulted),
+        (VaultStatus::Activating, VaultStatus::Canceling),
+        (VaultStatus::Activating, VaultStatus::Spending),
+        (
+            VaultStatus::Activating,
+            VaultStatus::UnvaultEmergencyVaulting,
+        ),
+        (VaultStatus::Activating, VaultStatus::EmergencyVaulting),
+        (VaultStatus::Active, VaultStatus::Unvaulting),
+        (VaultStatus::Active, VaultStatus::Unvaulted),
+        (VaultStatus::Active, VaultStatus::Canceling),
+        (VaultStatus::Active, VaultStatus::Spending),
+        (VaultStatus::Active, VaultStatus::UnvaultEmergencyVaulting),
+        (VaultStatus::Active, VaultStatus::EmergencyVaulting),
+        (VaultStatus::Unvaulting, VaultStatus::Unvaulted),
+        (VaultStatus::Unvaulting, VaultStatus::Canceling),
+        (VaultStatus::Unvaulting, VaultStatus::Spending),
+        (
+            VaultStatus::Unvaulting,
+            VaultStatus::UnvaultEmergencyVaulting,
+        ),
+        (VaultStatus::Unvaulted, VaultStatus::Canceling),
+        (VaultStatus::Unvaulted, VaultStatus::Spending),
+        (
+            VaultStatus::Unvaulted,
+            VaultStatus::UnvaultEmergencyVaulting,
+        ),
+        (VaultStatus::Canceling, VaultStatus::Canceled),
+        (VaultStatus::Canceling, VaultStatus::Unvaulted),
+        (VaultStatus::Spending, VaultStatus::Spent),
+        (VaultStatus::Spending, VaultStatus::Unvaulted),
+        (
+            VaultStatus::UnvaultEmergencyVaulting,
+            VaultStatus::UnvaultEmergencyVaulted,
+        ),
+        (
+            VaultStatus::UnvaultEmergencyVaulting,
+            VaultStatus::Unvaulted,
+        ),
+        (
+            VaultStatus::UnvaultEmergencyVaulting,
+            VaultStatus::Unvaulting,
+        ),
+        (
+            VaultStatus::EmergencyVaulting,
+            VaultStatus::EmergencyVaulted,
+        ),
+        (VaultStatus::EmergencyVaulting, VaultStatus::Funded),
+        (VaultStatus::EmergencyVaulting, VaultStatus::Securing),
+        (VaultStatus::EmergencyVaulting, VaultStatus::Secured),
+        (VaultStatus::EmergencyVaulting, VaultStatus::Activating),
+        (VaultStatus::EmergencyVaulting, VaultStatus::Active),
+    ];
+    const REWINDS: &[(VaultStatus, VaultStatus)] = &[
+        (VaultStatus::Funded, VaultStatus::Unconfirmed),
+        (VaultStatus::Securing, VaultStatus::Unconfirmed),
+        (VaultStatus::Secured, VaultStatus::Unconfirmed),
+        (VaultStatus::Activating, VaultStatus::Unconfirmed),
+        (VaultStatus::Active, VaultStatus::Unconfirmed),
+        (VaultStatus::Unvaulted, VaultStatus::Unvaulting),
+        (VaultStatus::Canceling, VaultStatus::Unvaulting),
+        (VaultStatus::Spending, VaultStatus::Unvaulting),
+        (
+            VaultStatus::UnvaultEmergencyVaulting,
+            VaultStatus::Unvaulting,
+        ),
+        (VaultStatus::Canceled, VaultStatus::Canceling),
+        (VaultStatus::Canceled, VaultStatus::Unvaulting),
+        (VaultStatus::Spent, VaultStatus::Spending),
+        (VaultStatus::Spent, VaultStatus::Unvaulting),
+        (
+            VaultStatus::UnvaultEmergencyVaulted,
+            VaultStatus::UnvaultEmergencyVaulting,
+        ),
+        (
+            VaultStatus::UnvaultEmergencyVaulted,
+            VaultStatus::Unvaulting,
+        ),
+        (
+            VaultStatus::EmergencyVaulted,
+            VaultStatus::EmergencyVaulting,
+        ),
+    ];
+
+    fn is_legal(from: VaultStatus, to: VaultStatus, transition: Transition) -> bool {
+        let pairs = match transition {
+            Transition::Progress => PROGRESSIONS,
+            Transition::Rewind => REWINDS,
+        };
+        from == to || pairs.contains(&(from, to))
+    }
+
+    fn all_statuses() -> Vec<VaultStatus> {
+        (0..16u32)
+            .map(|n| VaultStatus::try_from(n).expect("There are 16 statuses"))
+            .collect()
+    }
+
+    // The statuses a vault in this status may reach by moving on in its lifecycle
+    fn reachable(from: VaultStatus) -> HashSet<VaultStatus> {
+        let mut reached = HashSet::new();
+        let mut queue: VecDeque<VaultStatus> = vec![from].into();
+
+        while let Some(status) = queue.pop_front() {
+            for next in VaultState::new(status).next() {
+                if reached.insert(*next) {
+                    queue.push_back(*next);
+                }
+            }
+        }
+
+        reached
+    }
+
+    fn status_strategy() -> impl Strategy<Value = VaultStatus> {
+        (0..16u32).prop_map(|n| VaultStatus::try_from(n).unwrap())
+    }
+
+    fn transition_strategy() -> impl Strategy<Value = Transition> {
+        prop_oneof![Just(Transition::Progress), Just(Transition::Rewind)]
+    }
+
+    #[test]
+    fn transitions() {
+        for from in all_statuses() {
+            for to in all_statuses() {
+                for transition in &[Transition::Progress, Transition::Rewind] {
+                    assert_eq!(
+                        VaultState::new(from).allows(to, *transition),
+                        is_legal(from, to, *transition),
+                        "{} from '{}' to '{}'",
+                        transition,
+                        from,
+                        to
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lifecycle() {
+        // Any status can be reached from a new deposit
+        let from_deposit = reachable(VaultStatus::Unconfirmed);
+        for status in all_statuses() {
+            assert!(
+                status == VaultStatus::Unconfirmed || from_deposit.contains(&status),
+                "'{}' can't be reached",
+                status
+            );
+        }
+
+        // Once the deposit is confirmed, we never get back to 'unconfirmed' but by a reorg
+        assert!(!reachable(VaultStatus::Funded).contains(&VaultStatus::Unconfirmed));
+
+        // The vault is gone once the last transaction of its chain is mined
+        for status in &[
+            VaultStatus::Canceled,
+            VaultStatus::Spent,
+            VaultStatus::EmergencyVaulted,
+            VaultStatus::UnvaultEmergencyVaulted,
+        ] {
+            assert!(VaultState::new(*status).next().is_empty());
+        }
+    }
+
+    proptest! {
+        // A reorg only ever takes the vault back to a status it went through, and from which it
+        // can get to where it was once the transactions are mined again.
+        #[test]
+        fn rewinds_can_be_replayed(from in status_strategy()) {
+            for previous in VaultState::new(from).previous() {
+                prop_assert!(reachable(*previous).contains(&from));
+            }
+        }
+
+        // Wherever it starts from, the status only changes along the legal transitions and an
+        // illegal one leaves it untouched.
+        #[test]
+        fn random_walk(
+            start in status_strategy(),
+            steps in prop::collection::vec((status_strategy(), transition_strategy()), 0..64)
+        ) {
+            let mut state = VaultState::new(start);
+
+            for (to, transition) in steps {
+                let before = state;
+                let legal = is_legal(before.status(), to, transition);
+
+                match state.transition(to, transition) {
+                    Ok(()) => {
+                        prop_assert!(legal);
+                        prop_assert_eq!(state.status(), to);
+                    }
+                    Err(e) => {
+                        prop_assert!(!legal);
+                        prop_assert_eq!(state, before);
+                        prop_assert_eq!(
+                            e,
+                            InvalidTransition {
+                                from: before.status(),
+                                to,
+                                transition
+                            }
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
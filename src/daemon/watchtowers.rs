@@ -0,0 +1,561 @@
+///! Background thread that will share the fully signed revocation transactions with our
+///! watchtowers, and optionally act as a watchtower itself.
+use crate::{
+    control::{bitcoind_broadcast_cancel, bitcoind_wallet_tx, ControlError},
+    database::{
+        actions::{
+            db_cancel_failed_unvault_audit, db_insert_unvault_audit, db_mark_watchtower_ack,
+        },
+        interface::{
+            db_cancel_transaction, db_emer_transaction, db_unannounced_spends,
+            db_unaudited_unvaults, db_unvault_emer_transaction, db_vaults_unacked,
+        },
+        schema::{DbVault, UnvaultDecision},
+        DatabaseError,
+    },
+    revaultd::RevaultD,
+    threadmessages::{BitcoindMessageOut, WatchtowersMessageOut},
+};
+use common::assume_ok;
+use revault_net::{
+    message::server::{GetSpendTx, SpendTx},
+    noise::PublicKey as NoisePubKey,
+    transport::KKTransport,
+};
+use revault_tx::{
+    bitcoin::{
+        secp256k1::{self, Signature},
+        util::bip143::SigHashCache,
+        OutPoint, SigHashType, Transaction as BitcoinTransaction,
+    },
+    miniscript::descriptor::DescriptorTrait,
+    transactions::{
+        CancelTransaction, EmergencyTransaction, RevaultTransaction, UnvaultEmergencyTransaction,
+        UnvaultTransaction,
+    },
+};
+
+use std::{
+    net::SocketAddr,
+    sync::mpsc,
+    sync::{Arc, RwLock},
+    thread, time,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum WatchtowerError {
+    DbError(DatabaseError),
+    NetError(revault_net::Error),
+    SerializationError(serde_json::Error),
+    TransactionError(revault_tx::Error),
+    ControlError(ControlError),
+    ChannelDisconnected,
+}
+
+impl std::fmt::Display for WatchtowerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::DbError(ref s) => write!(f, "Database error in watchtowers thread: '{}'", s),
+            Self::NetError(ref s) => {
+                write!(f, "Communication error in watchtowers thread: '{}'", s)
+            }
+            Self::SerializationError(ref s) => {
+                write!(f, "Encoding error in watchtowers thread: '{}'", s)
+            }
+            Self::TransactionError(ref s) => {
+                write!(f, "Transaction error in watchtowers thread: '{}'", s)
+            }
+            Self::ControlError(ref s) => write!(f, "Error in watchtowers thread: '{}'", s),
+            Self::ChannelDisconnected => {
+                write!(f, "Channel disconnected error in watchtowers thread")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WatchtowerError {}
+
+impl From<DatabaseError> for WatchtowerError {
+    fn from(e: DatabaseError) -> Self {
+        Self::DbError(e)
+    }
+}
+
+impl From<revault_net::Error> for WatchtowerError {
+    fn from(e: revault_net::Error) -> Self {
+        Self::NetError(e)
+    }
+}
+
+impl From<serde_json::Error> for WatchtowerError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::SerializationError(e)
+    }
+}
+
+impl From<revault_tx::Error> for WatchtowerError {
+    fn from(e: revault_tx::Error) -> Self {
+        Self::TransactionError(e)
+    }
+}
+
+impl From<ControlError> for WatchtowerError {
+    fn from(e: ControlError) -> Self {
+        Self::ControlError(e)
+    }
+}
+
+// If we can't reach the Coordinator, how many blocks before the expiration of the Unvault
+// timelock do we give up and revault.
+const CSV_SAFETY_MARGIN: u32 = 6;
+
+// FIXME: we should upstream these to revault_net along with the other messages.
+/// Sent to a watchtower for it to start watching a vault, along with all the fully signed
+/// revocation transactions it may have to broadcast.
+#[derive(Debug, Serialize)]
+struct WatchVault {
+    deposit_outpoint: OutPoint,
+    cancel_tx: CancelTransaction,
+    emergency_tx: EmergencyTransaction,
+    unvault_emergency_tx: UnvaultEmergencyTransaction,
+}
+
+/// The answer of a watchtower to a `WatchVault` message.
+#[derive(Debug, Deserialize)]
+struct WatchVaultAck {
+    ack: bool,
+}
+
+// Get the finalized revocation transactions of this vault out of the database
+fn watch_vault_msg(revaultd: &RevaultD, vault: &DbVault) -> Result<WatchVault, WatchtowerError> {
+    let db_path = revaultd.db_file();
+    let secp = &revaultd.secp_ctx;
+
+    let mut cancel_tx = db_cancel_transaction(&db_path, vault.id)?
+        .map(|(_, tx)| tx)
+        .ok_or_else(|| DatabaseError(format!("No Cancel transaction for vault '{}'", vault.id)))?;
+    let (_, mut emergency_tx) = db_emer_transaction(&db_path, vault.id)?;
+    let (_, mut unvault_emergency_tx) = db_unvault_emer_transaction(&db_path, vault.id)?;
+    cancel_tx.finalize(secp)?;
+    emergency_tx.finalize(secp)?;
+    unvault_emergency_tx.finalize(secp)?;
+
+    Ok(WatchVault {
+        deposit_outpoint: vault.deposit_outpoint,
+        cancel_tx,
+        emergency_tx,
+        unvault_emergency_tx,
+    })
+}
+
+// Send the revocation transactions of all the vaults this watchtower did not acknowledge yet,
+// and record its acknowledgements in database.
+fn share_revocation_txs(
+    revaultd: &RevaultD,
+    host: SocketAddr,
+    noise_key: &NoisePubKey,
+) -> Result<(), WatchtowerError> {
+    let db_path = revaultd.db_file();
+    let vaults = db_vaults_unacked(&db_path, noise_key)?;
+    if vaults.is_empty() {
+        return Ok(());
+    }
+
+    let mut transport = KKTransport::connect(host, &revaultd.noise_secret, noise_key)?;
+    for vault in vaults {
+        // An error with one vault must not prevent us from sharing the others. We'll retry
+        // at the next poll.
+        if let Err(e) =
+            share_vault_revocation_txs(revaultd, &mut transport, host, noise_key, &vault)
+        {
+            log::error!(
+                "Error sharing revocation transactions for vault '{}' with watchtower '{}': '{}'",
+                vault.deposit_outpoint,
+                host,
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Send the revocation transactions of this vault to this watchtower, and record its
+// acknowledgement in database.
+fn share_vault_revocation_txs(
+    revaultd: &RevaultD,
+    transport: &mut KKTransport,
+    host: SocketAddr,
+    noise_key: &NoisePubKey,
+    vault: &DbVault,
+) -> Result<(), WatchtowerError> {
+    let msg = watch_vault_msg(revaultd, vault)?;
+    log::debug!(
+        "Sending to watchtower '{}': '{}'",
+        host,
+        serde_json::to_string(&msg)?,
+    );
+    transport.write(&serde_json::to_vec(&msg)?)?;
+    let recvd_raw = transport.read()?;
+    log::debug!(
+        "Received from watchtower '{}': '{}'",
+        host,
+        &String::from_utf8_lossy(&recvd_raw)
+    );
+    let WatchVaultAck { ack } = serde_json::from_slice(&recvd_raw)?;
+
+    if ack {
+        db_mark_watchtower_ack(&revaultd.db_file(), vault.id, noise_key)?;
+    } else {
+        // We'll retry at the next poll
+        log::warn!(
+            "Watchtower '{}' did not acknowledge revocation transactions for vault '{}'",
+            host,
+            vault.deposit_outpoint
+        );
+    }
+
+    Ok(())
+}
+
+// Ask the Coordinator for the Spend transaction that was announced for this vault, if any.
+fn announced_spend(
+    revaultd: &RevaultD,
+    deposit_outpoint: OutPoint,
+) -> Result<Option<BitcoinTransaction>, WatchtowerError> {
+    let getspendtx_msg = GetSpendTx { deposit_outpoint };
+    let mut transport = KKTransport::connect(
+        revaultd.coordinator_host,
+        &revaultd.noise_secret,
+        &revaultd.coordinator_noisekey,
+    )?;
+
+    log::debug!(
+        "Sending to sync server: '{}'",
+        serde_json::to_string(&getspendtx_msg)?,
+    );
+    transport.write(&serde_json::to_vec(&getspendtx_msg)?)?;
+    let recvd_raw = transport.read()?;
+    log::debug!(
+        "Received from sync server: '{}'",
+        &String::from_utf8_lossy(&recvd_raw)
+    );
+    let SpendTx { transaction } = serde_json::from_slice(&recvd_raw)?;
+
+    Ok(transaction)
+}
+
+// Check that the Spend transaction announced for this vault spends its Unvault output, and is
+// finalized with a valid signature from all the managers for it.
+fn check_announced_spend(
+    revaultd: &RevaultD,
+    vault: &DbVault,
+    unvault_tx: &UnvaultTransaction,
+    spend_tx: &BitcoinTransaction,
+) -> Result<(), String> {
+    let spend_txid = spend_tx.txid();
+    let unvault_descriptor = revaultd.derived_unvault_descriptor(vault.derivation_index);
+    let unvault_txin = unvault_tx.revault_unvault_txin(&unvault_descriptor);
+    let input_index = spend_tx
+        .input
+        .iter()
+        .position(|txin| txin.previous_output == unvault_txin.outpoint())
+        .ok_or_else(|| {
+            format!(
+                "The announced Spend transaction '{}' does not spend the Unvault",
+                spend_txid
+            )
+        })?;
+
+    // The witness script must be the last element of the witness stack
+    let witness_script = unvault_descriptor.inner().explicit_script();
+    let witness = &spend_tx.input[input_index].witness;
+    if witness.last() != Some(&witness_script.to_bytes()) {
+        return Err(format!(
+            "The announced Spend transaction '{}' is not finalized",
+            spend_txid
+        ));
+    }
+
+    let sighash_type = SigHashType::All;
+    let value = unvault_txin.into_txout().into_txout().value;
+    let sighash = SigHashCache::new(spend_tx).signature_hash(
+        input_index,
+        &witness_script,
+        value,
+        sighash_type,
+    );
+    let sighash = secp256k1::Message::from_slice(&sighash).expect("sighash is a 32 bytes hash");
+
+    // All pubkeys use the same derivation index
+    for pubkey in revaultd.managers_pubkeys.iter() {
+        let pubkey = assume_ok!(
+            pubkey
+                .clone()
+                .derive(vault.derivation_index.into())
+                .derive_public_key(&revaultd.secp_ctx),
+            "We just derived a non hardened index"
+        );
+        let signed = witness.iter().any(|elem| match elem.split_last() {
+            Some((given_sighash_type, sig)) if *given_sighash_type == sighash_type as u8 => {
+                Signature::from_der(sig)
+                    .map(|sig| {
+                        revaultd
+                            .secp_ctx
+                            .verify(&sighash, &sig, &pubkey.key)
+                            .is_ok()
+                    })
+                    .unwrap_or(false)
+            }
+            _ => false,
+        });
+        if !signed {
+            return Err(format!(
+                "The announced Spend transaction '{}' is missing a valid signature for manager \
+                 key '{}'",
+                spend_txid, pubkey
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Broadcast the Cancel transaction of this vault, and log the decision along with its outcome.
+fn cancel_unvault(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind_tx: &mpsc::Sender<BitcoindMessageOut>,
+    vault: DbVault,
+    reason: String,
+) -> Result<(), WatchtowerError> {
+    // Don't hold the lock while waiting for the bitcoind thread, it may need it.
+    let (db_path, secp) = {
+        let revaultd = revaultd.read().unwrap();
+        (revaultd.db_file(), revaultd.secp_ctx.clone())
+    };
+
+    log::warn!(
+        "Canceling the Unvault of vault '{}': {}",
+        vault.deposit_outpoint,
+        reason
+    );
+    // FIXME: bump it once we know what feerate to target
+    match bitcoind_broadcast_cancel(bitcoind_tx, &db_path, &secp, vault, None) {
+        Ok(cancel_txid) => db_insert_unvault_audit(
+            &db_path,
+            vault.id,
+            UnvaultDecision::Canceled,
+            Some(&cancel_txid),
+            &reason,
+        )?,
+        Err(e) => {
+            // We'll retry at the next poll, only keep track of the last failure.
+            log::error!(
+                "Error broadcasting the Cancel of vault '{}': '{}'",
+                vault.deposit_outpoint,
+                e
+            );
+            db_cancel_failed_unvault_audit(
+                &db_path,
+                vault.id,
+                &format!("{} (broadcasting Cancel: '{}')", reason, e),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Check the Unvaults we did not take a decision about yet against the Spend transactions
+// announced to the Coordinator, and revault those that were not.
+fn audit_unvaults(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind_tx: &mpsc::Sender<BitcoindMessageOut>,
+) -> Result<(), WatchtowerError> {
+    let db_path = revaultd.read().unwrap().db_file();
+
+    for (vault, unvault_tx) in db_unaudited_unvaults(&db_path)? {
+        // An error with one vault must not prevent us from auditing the others
+        if let Err(e) = audit_unvault(revaultd, bitcoind_tx, vault, unvault_tx) {
+            log::error!(
+                "Error auditing the Unvault of vault '{}': '{}'",
+                vault.deposit_outpoint,
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Check the Unvault of this vault against the Spend transaction announced to the Coordinator,
+// and revault if it was not.
+fn audit_unvault(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind_tx: &mpsc::Sender<BitcoindMessageOut>,
+    vault: DbVault,
+    unvault_tx: UnvaultTransaction,
+) -> Result<(), WatchtowerError> {
+    let db_path = revaultd.read().unwrap().db_file();
+    let unvault_txid = unvault_tx.txid();
+
+    let announced = announced_spend(&revaultd.read().unwrap(), vault.deposit_outpoint);
+    match announced {
+        Ok(Some(spend_tx)) => {
+            let checked =
+                check_announced_spend(&revaultd.read().unwrap(), &vault, &unvault_tx, &spend_tx);
+            match checked {
+                Ok(()) => {
+                    let spend_txid = spend_tx.txid();
+                    log::info!(
+                        "Spend transaction '{}' was announced for vault '{}', not canceling \
+                         its Unvault",
+                        spend_txid,
+                        vault.deposit_outpoint
+                    );
+                    db_insert_unvault_audit(
+                        &db_path,
+                        vault.id,
+                        UnvaultDecision::Allowed,
+                        Some(&spend_txid),
+                        "A Spend transaction was announced to the Coordinator",
+                    )?;
+                }
+                Err(reason) => cancel_unvault(revaultd, bitcoind_tx, vault, reason)?,
+            }
+        }
+        Ok(None) => cancel_unvault(
+            revaultd,
+            bitcoind_tx,
+            vault,
+            "No Spend transaction was announced to the Coordinator".to_string(),
+        )?,
+        Err(e) => {
+            // We can't tell. Retry until the timelock is about to expire, then revault to be
+            // on the safe side.
+            let unvault_height =
+                bitcoind_wallet_tx(bitcoind_tx, unvault_txid)?.and_then(|wtx| wtx.blockheight);
+            let (tip_height, csv) = {
+                let revaultd = revaultd.read().unwrap();
+                (
+                    revaultd.tip.map(|tip| tip.height).unwrap_or(0),
+                    revaultd.unvault_csv,
+                )
+            };
+
+            match unvault_height {
+                Some(height) if tip_height + CSV_SAFETY_MARGIN >= height + csv => cancel_unvault(
+                    revaultd,
+                    bitcoind_tx,
+                    vault,
+                    format!(
+                        "Could not reach the Coordinator ('{}') and the Unvault timelock \
+                     is about to expire",
+                        e
+                    ),
+                )?,
+                _ => log::warn!(
+                    "Could not get the Spend transaction announced for vault '{}': '{}'",
+                    vault.deposit_outpoint,
+                    e
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Revault the vaults whose Unvault we let through but that are being spent by another
+// transaction than the announced Spend.
+fn audit_spends(
+    revaultd: &Arc<RwLock<RevaultD>>,
+    bitcoind_tx: &mpsc::Sender<BitcoindMessageOut>,
+) -> Result<(), WatchtowerError> {
+    let db_path = revaultd.read().unwrap().db_file();
+
+    for (vault, announced_txid) in db_unannounced_spends(&db_path)? {
+        let reason = format!(
+            "The Unvault is being spent by '{}' instead of the announced Spend transaction '{}'",
+            vault.spend_txid.expect("Must be set for 'spending'"),
+            announced_txid
+        );
+        // An error with one vault must not prevent us from auditing the others
+        if let Err(e) = cancel_unvault(revaultd, bitcoind_tx, vault, reason) {
+            log::error!(
+                "Error auditing the Spend of vault '{}': '{}'",
+                vault.deposit_outpoint,
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Poll the database for newly secured vaults to share with our watchtowers, and for new Unvaults
+// to check if we are a watchtower ourselves, indefinitely.
+pub fn watchtowers_loop(
+    rx: mpsc::Receiver<WatchtowersMessageOut>,
+    revaultd: Arc<RwLock<RevaultD>>,
+    bitcoind_tx: mpsc::Sender<BitcoindMessageOut>,
+) -> Result<(), WatchtowerError> {
+    // This is only the polling of our own database, we retry for failed watchtowers at the same
+    // pace as we poll the Coordinator.
+    let mut last_poll = time::Instant::now();
+    let poll_interval = revaultd.read().unwrap().coordinator_poll_interval;
+    // Only set if we are a stakeholder
+    let watchtowers = revaultd.read().unwrap().watchtowers.clone();
+    let local_watchtower = revaultd.read().unwrap().local_watchtower;
+
+    log::info!("Watchtowers thread started.");
+    if local_watchtower {
+        log::info!("Canceling the Unvaults for which no Spend was announced.");
+    }
+
+    loop {
+        // Process any message from master first
+        match rx.try_recv() {
+            Ok(WatchtowersMessageOut::Shutdown) => {
+                log::info!("Watchtowers thread received shutdown. Exiting.");
+                return Ok(());
+            }
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => {
+                return Err(WatchtowerError::ChannelDisconnected);
+            }
+        }
+
+        let elapsed = last_poll.elapsed();
+        // If enough time has elapsed, share the revocation transactions of the new vaults
+        if elapsed >= poll_interval {
+            for (host, noise_key) in watchtowers.iter().flatten() {
+                share_revocation_txs(&revaultd.read().unwrap(), *host, noise_key).unwrap_or_else(
+                    |e| {
+                        log::warn!(
+                            "Error while sharing revocation transactions with watchtower '{}': '{}'",
+                            host,
+                            e
+                        );
+                    },
+                );
+            }
+
+            if local_watchtower {
+                audit_unvaults(&revaultd, &bitcoind_tx).unwrap_or_else(|e| {
+                    log::warn!("Error while checking Unvault transactions: '{}'", e);
+                });
+                audit_spends(&revaultd, &bitcoind_tx).unwrap_or_else(|e| {
+                    log::warn!("Error while checking Spend transactions: '{}'", e);
+                });
+            }
+
+            last_poll = time::Instant::now();
+        }
+
+        // Avoid clogging the CPU by sleeping for a while
+        thread::sleep(time::Duration::from_millis(500));
+    }
+}
@@ -62,6 +62,8 @@ class BitcoinD(TailableProc):
         self.bitcoin_dir = bitcoin_dir
         self.rpcport = rpcport
         self.p2pport = reserve()
+        self.zmqpubhashblock = f"tcp://127.0.0.1:{reserve()}"
+        self.zmqpubrawtx = f"tcp://127.0.0.1:{reserve()}"
         self.prefix = "bitcoind"
 
         regtestdir = os.path.join(bitcoin_dir, "regtest")
@@ -81,6 +83,8 @@ class BitcoinD(TailableProc):
             "rpcport": rpcport,
             "debug": 1,
             "fallbackfee": Decimal(1000) / bitcoin.core.COIN,
+            "zmqpubhashblock": self.zmqpubhashblock,
+            "zmqpubrawtx": self.zmqpubrawtx,
         }
         self.conf_file = os.path.join(bitcoin_dir, "bitcoin.conf")
         with open(self.conf_file, "w") as f:
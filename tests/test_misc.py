@@ -1,8 +1,11 @@
 import copy
+import json
 import logging
 import pytest
 import random
+import shutil
 import os
+import subprocess
 
 from bitcoin.core import COIN
 from fixtures import *
@@ -12,6 +15,7 @@ from test_framework.utils import (
     POSTGRES_IS_SETUP,
     TIMEOUT,
     RpcError,
+    UnixSocket,
     wait_for,
 )
 
@@ -1246,6 +1250,87 @@ def test_getspendtx(revault_network, bitcoind):
         man.rpc.getspendtx(deposits, destinations, feerate)
 
 
+def test_getspendtx_coin_selection(revault_network, bitcoind):
+    """Test getspendtx chooses the vaults to spend itself if we don't give any"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    man = rn.man(0)
+    vaults = [rn.fund(amount) for amount in [1, 2, 5]]
+    deposits = [f"{v['txid']}:{v['vout']}" for v in vaults]
+    for vault in vaults:
+        rn.secure_vault(vault)
+        rn.activate_vault(vault)
+    addr = bitcoind.rpc.getnewaddress()
+    feerate = 2
+
+    # The smallest vault that is enough alone
+    destinations = {addr: 3 * COIN // 2}
+    res = man.rpc.getspendtx([], destinations, feerate)
+    assert res["outpoints"] == [deposits[1]]
+    res = man.rpc.getspendtx([], destinations, feerate, "smallest_sufficient")
+    assert res["outpoints"] == [deposits[1]]
+    psbt = serializations.PSBT()
+    psbt.deserialize(res["spend_tx"])
+    assert len(psbt.inputs) == 1
+
+    # The vaults that were deposited first
+    res = man.rpc.getspendtx([], destinations, feerate, "oldest_first")
+    assert res["outpoints"] == deposits[:2]
+
+    # If the two smallest are exactly enough, they leave no change
+    destinations = {addr: 3 * COIN - rn.compute_spendtx_fees(feerate, 2, 1)}
+    res = man.rpc.getspendtx([], destinations, feerate, "smallest_sufficient")
+    assert res["outpoints"] == [deposits[2]]
+    res = man.rpc.getspendtx([], destinations, feerate, "minimize_change")
+    assert sorted(res["outpoints"]) == sorted(deposits[:2])
+    psbt = serializations.PSBT()
+    psbt.deserialize(res["spend_tx"])
+    assert len(psbt.inputs) == 2 and len(psbt.outputs) == 2
+
+    # Giving the outpoints still works, and they are given back
+    res = man.rpc.getspendtx(deposits[:1], {addr: COIN // 2}, feerate)
+    assert res["outpoints"] == deposits[:1]
+
+    # We can't spend more than what we have
+    with pytest.raises(RpcError, match="Not enough Active vaults"):
+        man.rpc.getspendtx([], {addr: 8 * COIN}, feerate, "minimize_change")
+    with pytest.raises(RpcError, match="Not enough Active vaults"):
+        man.rpc.getspendtx([], {addr: 8 * COIN}, feerate)
+
+
+def test_estimatefees(revault_network, bitcoind):
+    """Test the fees estimation through bitcoind"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    man = rn.man(0)
+
+    # The fees are linear in the feerate, and available to everyone
+    fees = man.rpc.estimatefees(1)
+    assert fees["feerate"] == 1
+    assert fees["unvault"] > fees["emergency"]
+    assert fees["spend"] > fees["cancel"] == fees["unvault_emergency"]
+    fees_10 = rn.stk(0).rpc.estimatefees(10)
+    for tx in ["unvault", "spend", "cancel", "emergency", "unvault_emergency"]:
+        assert fees_10[tx] == 10 * fees[tx]
+    with pytest.raises(RpcError, match="Feerate can't be <1"):
+        man.rpc.estimatefees(0)
+
+    # There is no estimate on a fresh regtest chain
+    for feerate in [None, "economical", {"conf_target": 2}]:
+        with pytest.raises(RpcError, match="No feerate estimate"):
+            man.rpc.estimatefees(feerate)
+    vault = rn.fund(1)
+    rn.secure_vault(vault)
+    rn.activate_vault(vault)
+    destinations = {bitcoind.rpc.getnewaddress(): COIN // 2}
+    with pytest.raises(RpcError, match="No feerate estimate"):
+        man.rpc.getspendtx(
+            [], destinations, {"conf_target": 6, "estimate_mode": "conservative"}
+        )
+    with pytest.raises(RpcError, match="Invalid params"):
+        man.rpc.getspendtx([], destinations, "fast")
+
+
 @pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
 def test_spendtx_management(revault_network, bitcoind):
     CSV = 12
@@ -2189,3 +2274,584 @@ def test_stkman_only(revault_network, bitcoind):
         rn.activate_vault(v)
     rn.unvault_vaults_anyhow(vaults)
     rn.cancel_vault(vaults[0])
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_emergency(revault_network, bitcoind):
+    """Test the broadcast of the Emergency and Unvault Emergency transactions"""
+    rn = revault_network
+    rn.deploy(3, 1, csv=12)
+
+    # One vault still in the deposit, one vault unvaulted, and one not secured
+    deposited = rn.fund(0.6)
+    rn.secure_vault(deposited)
+    unvaulted = rn.fund(0.4)
+    rn.secure_vault(unvaulted)
+    rn.activate_vault(unvaulted)
+    rn.unvault_vaults_anyhow([unvaulted])
+    rn.fund(0.3)
+
+    # Managers can't use it
+    with pytest.raises(RpcError, match="This is a stakeholder command"):
+        rn.man(0).rpc.emergency()
+
+    res = rn.stk(0).rpc.emergency()
+    assert len(res["failed"]) == 0
+    assert len(res["broadcasted"]) == 2
+    txids = {e["vault_outpoint"]: e["txid"] for e in res["broadcasted"]}
+
+    deposit = f"{deposited['txid']}:{deposited['vout']}"
+    emer_tx = rn.stk(0).rpc.listpresignedtransactions([deposit])[
+        "presigned_transactions"
+    ][0]["emergency"]["hex"]
+    assert bitcoind.rpc.decoderawtransaction(emer_tx)["txid"] == txids[deposit]
+    deposit = f"{unvaulted['txid']}:{unvaulted['vout']}"
+    unemer_tx = rn.stk(0).rpc.listpresignedtransactions([deposit])[
+        "presigned_transactions"
+    ][0]["unvault_emergency"]["hex"]
+    assert bitcoind.rpc.decoderawtransaction(unemer_tx)["txid"] == txids[deposit]
+
+    bitcoind.generate_block(1, wait_for_mempool=list(txids.values()))
+    block = bitcoind.rpc.getblock(bitcoind.rpc.getbestblockhash())
+    for txid in txids.values():
+        assert txid in block["tx"]
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_emergency_tracking(revault_network, bitcoind):
+    """Test the poller tracks the Emergency and Unvault Emergency transactions"""
+    rn = revault_network
+    rn.deploy(3, 1, csv=12)
+    stks = rn.stks()
+
+    deposited = rn.fund(0.6)
+    rn.secure_vault(deposited)
+    unvaulted = rn.fund(0.4)
+    rn.secure_vault(unvaulted)
+    rn.activate_vault(unvaulted)
+    rn.unvault_vaults_anyhow([unvaulted])
+    deposit = f"{deposited['txid']}:{deposited['vout']}"
+    unvault_deposit = f"{unvaulted['txid']}:{unvaulted['vout']}"
+
+    res = stks[0].rpc.emergency()
+    assert len(res["broadcasted"]) == 2
+    for w in stks:
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "emergencyvaulting"
+        )
+        wait_for(
+            lambda: w.rpc.listvaults([], [unvault_deposit])["vaults"][0]["status"]
+            == "unvaultemergencyvaulting"
+        )
+
+    bitcoind.generate_block(1, wait_for_mempool=2)
+    for w in stks:
+        w.wait_for_logs(
+            [
+                "Emergency tx .* was confirmed at height .*",
+                "UnvaultEmergency tx .* was confirmed at height .*",
+            ]
+        )
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "emergencyvaulted"
+        )
+        wait_for(
+            lambda: w.rpc.listvaults([], [unvault_deposit])["vaults"][0]["status"]
+            == "unvaultemergencyvaulted"
+        )
+
+    # Unconfirm both emergency transactions, they must be rewound and confirmed again
+    bitcoind.simple_reorg(bitcoind.rpc.getblockcount(), shift=-1)
+    for w in stks:
+        w.wait_for_logs(
+            [
+                "Detected reorg",
+                f"Vault {deposit}'s Emergency transaction .* got unconfirmed",
+                f"Vault {unvault_deposit}'s UnvaultEmergency transaction .* got unconfirmed",
+                "Rescan of all vaults in db done.",
+            ]
+        )
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "emergencyvaulting"
+        )
+        wait_for(
+            lambda: w.rpc.listvaults([], [unvault_deposit])["vaults"][0]["status"]
+            == "unvaultemergencyvaulting"
+        )
+
+    bitcoind.generate_block(1, wait_for_mempool=2)
+    for w in stks:
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "emergencyvaulted"
+        )
+        wait_for(
+            lambda: w.rpc.listvaults([], [unvault_deposit])["vaults"][0]["status"]
+            == "unvaultemergencyvaulted"
+        )
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_local_watchtower(revault_network, bitcoind):
+    """Test a stakeholder acting as its own watchtower cancels unannounced Unvaults"""
+    rn = revault_network
+    rn.deploy(2, 1, csv=12)
+    stk = rn.stk(0)
+
+    # Enable the local watchtower mode
+    stk.stop()
+    with open(stk.conf_file, "a") as f:
+        f.write("local_watchtower = true\n")
+    stk.start()
+    stk.wait_for_log("Canceling the Unvaults for which no Spend was announced.")
+
+    # An Unvault for which a Spend was announced is left alone
+    vault = rn.fund(0.5)
+    rn.secure_vault(vault)
+    rn.activate_vault(vault)
+    rn.unvault_vaults_anyhow([vault])
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    stk.wait_for_log(
+        f"Spend transaction .* was announced for vault '{deposit}', not canceling its Unvault"
+    )
+    assert stk.rpc.listvaults([], [deposit])["vaults"][0]["status"] == "unvaulted"
+
+    # But one for which none was is canceled
+    vault = rn.fund(0.3)
+    rn.secure_vault(vault)
+    rn.activate_vault(vault)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    unvault_psbt = stk.rpc.listpresignedtransactions([deposit])[
+        "presigned_transactions"
+    ][0]["unvault"]["psbt"]
+    unvault_tx = bitcoind.rpc.finalizepsbt(unvault_psbt)["hex"]
+    bitcoind.rpc.sendrawtransaction(unvault_tx)
+    stk.wait_for_log(
+        f"Canceling the Unvault of vault '{deposit}': No Spend transaction was "
+        "announced to the Coordinator"
+    )
+    for w in rn.participants():
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "canceling"
+        )
+    bitcoind.generate_block(1, wait_for_mempool=1)
+    for w in rn.participants():
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "canceled"
+        )
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_bumpfee(revault_network, bitcoind):
+    """Test the managers can CPFP an Unvault transaction through its CPFP output"""
+    rn = revault_network
+    rn.deploy(2, 1, csv=12)
+    man = rn.man(0)
+
+    vault = rn.fund(0.5)
+    rn.secure_vault(vault)
+    rn.activate_vault(vault)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    unvault_psbt = man.rpc.listpresignedtransactions([deposit])[
+        "presigned_transactions"
+    ][0]["unvault"]["psbt"]
+    unvault_tx = bitcoind.rpc.finalizepsbt(unvault_psbt)["hex"]
+    unvault_txid = bitcoind.rpc.sendrawtransaction(unvault_tx)
+    wait_for(
+        lambda: man.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+        == "unvaulting"
+    )
+
+    # Only managers can bump, and not to a null feerate
+    with pytest.raises(RpcError, match="This is a manager command"):
+        rn.stk(0).rpc.bumpfee(unvault_txid, 50)
+    with pytest.raises(RpcError, match="Feerate can't be <1"):
+        man.rpc.bumpfee(unvault_txid, 0)
+
+    # Get the CPFP transaction, sign it and give it back to get it broadcast
+    cpfp_tx = man.rpc.bumpfee(unvault_txid, 50)["cpfp_tx"]
+    signed_cpfp_tx = man.man_keychain.sign_spend_psbt(
+        cpfp_tx, [vault["derivation_index"]]
+    )
+    with pytest.raises(RpcError, match="does not bump this transaction"):
+        man.rpc.bumpfee(unvault_txid, 60, signed_cpfp_tx)
+    cpfp_txid = man.rpc.bumpfee(unvault_txid, 50, signed_cpfp_tx)["txid"]
+    entry = bitcoind.rpc.getmempoolentry(cpfp_txid)
+    assert unvault_txid in entry["depends"]
+    package_feerate = entry["fees"]["ancestor"] * COIN / entry["ancestorsize"]
+    assert package_feerate >= 50
+
+    # Once confirmed, it can't be bumped anymore
+    bitcoind.generate_block(1, wait_for_mempool=[unvault_txid, cpfp_txid])
+    wait_for(
+        lambda: man.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+        == "unvaulted"
+    )
+    with pytest.raises(RpcError, match="is already confirmed"):
+        man.rpc.bumpfee(unvault_txid, 100)
+
+
+@pytest.mark.skipif(not POSTGRES_IS_SETUP, reason="Needs Postgres for servers db")
+def test_feebump_emergency(revault_network, bitcoind):
+    """Test the stakeholders can bump the Emergency transactions with their fee-bumping wallet"""
+    rn = revault_network
+    rn.deploy(3, 1, csv=12)
+    stks = rn.stks()
+
+    vault = rn.fund(0.6)
+    rn.secure_vault(vault)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+
+    # Only stakeholders have a fee-bumping wallet, and it starts empty
+    with pytest.raises(RpcError, match="This is a stakeholder command"):
+        rn.man(0).rpc.getfeebumpinfo(50)
+    with pytest.raises(RpcError, match="Feerate can't be <1"):
+        stks[0].rpc.getfeebumpinfo(0)
+    info = stks[0].rpc.getfeebumpinfo(50)
+    assert info["balance"] == 0 and info["coins"] == 0 and info["bumps"] == 0
+    res = stks[0].rpc.emergency(50)
+    assert len(res["broadcasted"]) == 0
+    assert "No coin of the fee-bumping wallet" in res["failed"][0]["error"]
+
+    # Refill it, now it can bump the Emergency
+    bitcoind.rpc.sendtoaddress(info["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=1)
+    wait_for(lambda: stks[0].rpc.getfeebumpinfo(50)["bumps"] == 1)
+    assert stks[0].rpc.getfeebumpinfo(50)["balance"] == 0.01 * COIN
+
+    res = stks[0].rpc.emergency(50)
+    assert len(res["failed"]) == 0
+    emer_txid = res["broadcasted"][0]["txid"]
+    emer_tx = bitcoind.rpc.getrawtransaction(emer_txid, True)
+    assert len(emer_tx["vin"]) == 2
+    entry = bitcoind.rpc.getmempoolentry(emer_txid)
+    assert entry["fees"]["base"] * COIN / entry["vsize"] >= 50
+
+    # All the stakeholders recognize it even if its txid isn't the presigned one
+    for w in stks:
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "emergencyvaulting"
+        )
+    bitcoind.generate_block(1, wait_for_mempool=[emer_txid])
+    for w in stks:
+        wait_for(
+            lambda: w.rpc.listvaults([], [deposit])["vaults"][0]["status"]
+            == "emergencyvaulted"
+        )
+
+
+def test_listevents(revault_network, bitcoind):
+    """Test we record what happens to the vaults"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    man = rn.man(0)
+
+    vault = rn.fund(0.2)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    rn.secure_vault(vault)
+    rn.activate_vault(vault)
+
+    # Status transitions are recorded, once each
+    events = man.rpc.listevents(["statuschange"], [deposit])["events"]
+    statuses = [ev["status"] for ev in events]
+    assert statuses[:2] == ["unconfirmed", "funded"]
+    assert statuses[-1] == "active"
+    assert all(a != b for a, b in zip(statuses, statuses[1:]))
+    assert all(ev["vault_outpoint"] == deposit for ev in events)
+    assert all(ev["txid"] is None for ev in events)
+    assert all(a["id"] < b["id"] for a, b in zip(events, events[1:]))
+
+    # We can filter by kind, height and paginate
+    sigs = man.rpc.listevents(["signature"])["events"]
+    assert len(sigs) > 0 and all(ev["txid"] is not None for ev in sigs)
+    all_events = man.rpc.listevents()["events"]
+    page = man.rpc.listevents([], [], None, None, None, None, 1, 2)["events"]
+    assert page == all_events[1:3]
+    height = bitcoind.rpc.getblockcount()
+    assert man.rpc.listevents([], [], None, None, height + 1)["events"] == []
+    with pytest.raises(RpcError, match="'unknown' is not a valid event kind"):
+        man.rpc.listevents(["unknown"])
+
+    # Broadcasting the Unvault is recorded along with its txid
+    rn.unvault_vaults_anyhow([vault])
+    broadcasts = man.rpc.listevents(["broadcast"], [deposit])["events"]
+    assert len(broadcasts) == 1
+    unvault_psbt = man.rpc.listpresignedtransactions([deposit])[
+        "presigned_transactions"
+    ][0]["unvault"]
+    unvault_tx = bitcoind.rpc.decodepsbt(unvault_psbt)["tx"]
+    assert broadcasts[0]["txid"] == unvault_tx["txid"]
+
+
+def test_subscribe(revault_network, bitcoind):
+    """Test we stream notifications to the clients that subscribed"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+
+    sock = UnixSocket(stk.rpc.socket_path)
+    sock.sock.settimeout(TIMEOUT)
+    subscribe = {"jsonrpc": "2.0", "id": 0, "method": "subscribe", "params": []}
+    sock.sock.sendall(json.dumps(subscribe).encode())
+    decoder = json.JSONDecoder()
+    buf = ""
+
+    def read_message():
+        nonlocal buf
+        while True:
+            try:
+                msg, end = decoder.raw_decode(buf)
+                buf = buf[end:].lstrip()
+                return msg
+            except json.JSONDecodeError:
+                buf += sock.sock.recv(4096).decode()
+
+    assert read_message()["result"] == {}
+
+    # We get notified of the deposit, then of its confirmation
+    vault = rn.fund(0.5)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    notif = read_message()
+    assert notif["method"] == "deposit"
+    assert notif["params"] == {"vault_outpoint": deposit, "amount": COIN // 2}
+    notif = read_message()
+    assert notif["method"] == "statuschange"
+    assert notif["params"] == {"vault_outpoint": deposit, "status": "funded"}
+
+    # Then of the signatures until it is secured
+    rn.secure_vault(vault)
+    signed_txids = set()
+    while True:
+        notif = read_message()
+        assert notif["params"]["vault_outpoint"] == deposit
+        if notif["method"] == "signature":
+            signed_txids.add(notif["params"]["txid"])
+        elif notif["params"]["status"] == "secured":
+            break
+    assert len(signed_txids) > 0
+
+    # Meanwhile, the other connections are still served as usual
+    assert stk.rpc.listvaults([], [deposit])["vaults"][0]["status"] == "secured"
+    sock.close()
+
+
+def test_notify_hooks(revault_network, bitcoind):
+    """Test the commands configured by the user are run on events"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+
+    log_file = os.path.join(stk.datadir_with_network, "hooks.log")
+    stk.stop()
+    with open(stk.conf_file, "a") as f:
+        f.write("[notify_config]\n")
+        f.write(f"deposit = \"echo 'deposit %o %t %s' >> {log_file}\"\n")
+        f.write(f"secured = \"echo 'secured %o %s' >> {log_file}\"\n")
+        f.write(f"unvault = \"echo 'unvault %o %t' >> {log_file}\"\n")
+    stk.start()
+
+    def hook_lines():
+        if not os.path.exists(log_file):
+            return []
+        with open(log_file, "r") as f:
+            return f.read().splitlines()
+
+    vault = rn.fund(0.5)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    wait_for(lambda: len(hook_lines()) == 1)
+    assert hook_lines()[0] == f"deposit {deposit} {vault['txid']} unconfirmed"
+
+    rn.secure_vault(vault)
+    rn.activate_vault(vault)
+    wait_for(lambda: len(hook_lines()) == 2)
+    assert hook_lines()[1] == f"secured {deposit} secured"
+
+    rn.unvault_vaults_anyhow([vault])
+    wait_for(lambda: len(hook_lines()) == 3)
+    unvault_psbt = stk.rpc.listpresignedtransactions([deposit])[
+        "presigned_transactions"
+    ][0]["unvault"]
+    unvault_txid = bitcoind.rpc.decodepsbt(unvault_psbt)["tx"]["txid"]
+    assert hook_lines()[2] == f"unvault {deposit} {unvault_txid}"
+
+
+def test_backup_restore(revault_network, bitcoind):
+    """Test we can restore a backup of the database, along with the signatures"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+    man = rn.man(0)
+
+    vault = rn.fund(0.5)
+    rn.secure_vault(vault)
+    deposit = f"{vault['txid']}:{vault['vout']}"
+    presigned_txs = stk.rpc.listpresignedtransactions([deposit])
+
+    backup_path = os.path.join(stk.datadir_with_network, "revaultd.bak")
+    stk.rpc.call("backup", [backup_path])
+    assert os.path.isfile(backup_path)
+    with pytest.raises(RpcError, match="already exists"):
+        stk.rpc.call("backup", [backup_path])
+    with pytest.raises(RpcError, match="not an absolute path"):
+        stk.rpc.call("backup", ["revaultd.bak"])
+
+    # We lose the database, and restore it from the backup
+    stk.stop()
+    os.remove(os.path.join(stk.datadir_with_network, "revaultd.sqlite3"))
+    stk.cmd_line += ["--restore", backup_path]
+    stk.start()
+    stk.wait_for_secured_vaults([deposit])
+    assert stk.rpc.listpresignedtransactions([deposit]) == presigned_txs
+
+    # We can't restore it over an existing database, nor for another participant
+    stk.stop()
+    proc = subprocess.run(stk.cmd_line, capture_output=True, text=True)
+    assert proc.returncode == 1
+    assert "There is already a database" in proc.stdout
+    stk.cmd_line = stk.cmd_line[:-2]
+    stk.start()
+
+    man.stop()
+    man_db = os.path.join(man.datadir_with_network, "revaultd.sqlite3")
+    os.rename(man_db, f"{man_db}.old")
+    proc = subprocess.run(
+        man.cmd_line + ["--restore", backup_path], capture_output=True, text=True
+    )
+    assert proc.returncode == 1
+    assert "The backup is for a participant with different keys" in proc.stdout
+    assert not os.path.exists(man_db)
+    os.rename(f"{man_db}.old", man_db)
+    man.start()
+
+
+def test_recovery(revault_network, bitcoind):
+    """Test we can rebuild the vaults of a lost data directory from the block chain and the
+    signatures stored by the Coordinator"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+
+    vaults = rn.fundmany([1, 2, 3, 4])
+    for v in vaults:
+        rn.secure_vault(v)
+    for v in vaults[1:]:
+        rn.activate_vault(v)
+    rn.spend_vaults_anyhow([vaults[2]])
+    rn.unvault_vaults_anyhow([vaults[3]])
+    rn.cancel_vault(vaults[3])
+    statuses = {
+        f"{v['txid']}:{v['vout']}": v["status"] for v in stk.rpc.listvaults()["vaults"]
+    }
+    assert sorted(statuses.values()) == ["active", "canceled", "secured", "spent"]
+
+    # We lose everything but the configuration and the Noise key
+    stk.stop()
+    watchonly_wallet = os.path.join(
+        stk.datadir_with_network, "revaultd-watchonly-wallet-1"
+    )
+    bitcoind.rpc.unloadwallet(watchonly_wallet)
+    shutil.rmtree(watchonly_wallet)
+    os.remove(os.path.join(stk.datadir_with_network, "revaultd.sqlite3"))
+
+    stk.cmd_line += ["--recover", "0"]
+    stk.start()
+    stk.wait_for_log("Vaults recovered, replaying what happened to them since.")
+    wait_for(
+        lambda: {
+            f"{v['txid']}:{v['vout']}": v["status"]
+            for v in stk.rpc.listvaults()["vaults"]
+        }
+        == statuses
+    )
+
+    # It only ever rebuilds a database from scratch
+    stk.stop()
+    proc = subprocess.run(stk.cmd_line, capture_output=True, text=True)
+    assert proc.returncode == 1
+    assert "Can't recover vaults: there is already a database" in proc.stdout
+    stk.cmd_line = stk.cmd_line[:-2]
+    stk.start()
+
+
+def test_emergency_kit(revault_network, bitcoind):
+    """Test the emergency kit is kept up to date and can be exported, and that its
+    transactions are valid"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    stk = rn.stk(0)
+    man = rn.man(0)
+    kit_path = os.path.join(stk.datadir_with_network, "emergency_kit.json")
+
+    vaults = rn.fundmany([1, 2])
+    rn.secure_vault(vaults[0])
+    deposit = f"{vaults[0]['txid']}:{vaults[0]['vout']}"
+    wait_for(lambda: os.path.isfile(kit_path))
+    wait_for(lambda: len(json.load(open(kit_path))["vaults"]) == 1)
+
+    kit = json.load(open(kit_path))
+    assert kit["version"] == 1
+    assert kit["network"] == "regtest"
+    assert len(kit["deposit_descriptor"]) > 0
+    assert len(kit["unvault_descriptor"]) > 0
+    vault = kit["vaults"][0]
+    assert vault["deposit_outpoint"] == deposit
+    assert vault["status"] == "secured"
+    assert bitcoind.rpc.testmempoolaccept([vault["emergency_tx"]])[0]["allowed"]
+    assert len(vault["unvault_emergency_tx"]) > 0
+
+    # It's refreshed as new vaults get secured
+    rn.secure_vault(vaults[1])
+    wait_for(lambda: len(json.load(open(kit_path))["vaults"]) == 2)
+
+    # We can export it elsewhere, but not over an existing file
+    export_path = os.path.join(stk.datadir_with_network, "kit_export.json")
+    res = stk.rpc.call("exportemergencykit", [export_path])
+    assert res == {"path": export_path, "vaults": 2}
+    assert json.load(open(export_path))["vaults"] == json.load(open(kit_path))["vaults"]
+    assert os.stat(export_path).st_mode & 0o777 == 0o600
+    with pytest.raises(RpcError, match="already exists"):
+        stk.rpc.call("exportemergencykit", [export_path])
+    with pytest.raises(RpcError, match="not an absolute path"):
+        stk.rpc.call("exportemergencykit", ["kit.json"])
+    assert stk.rpc.call("exportemergencykit")["path"] == kit_path
+
+    # Managers don't have any
+    with pytest.raises(RpcError, match="This is a stakeholder command"):
+        man.rpc.call("exportemergencykit")
+
+
+def test_zmq_notifications(revault_network, bitcoind):
+    """Test we sync as soon as bitcoind notifies us, without waiting for the next poll"""
+    rn = revault_network
+    rn.deploy(2, 1)
+    man = rn.man(0)
+
+    # Make the manager practically never poll, it can only rely on the notifications
+    man.stop()
+    with open(man.conf_file, "r") as f:
+        conf = f.read()
+    conf = conf.replace(
+        "poll_interval_secs = 3\n",
+        "poll_interval_secs = 3600\n"
+        f'zmqpubhashblock = "{bitcoind.zmqpubhashblock}"\n'
+        f'zmqpubrawtx = "{bitcoind.zmqpubrawtx}"\n',
+    )
+    with open(man.conf_file, "w") as f:
+        f.write(conf)
+    man.start()
+
+    # A new deposit is noticed as soon as it's broadcast, and its confirmation as soon as
+    # it's mined
+    vault = rn.fund(0.5)
+    man.wait_for_log("Got notified of a chain update by bitcoind, syncing now")
+    rn.secure_vault(vault)
+    rn.activate_vault(vault)
+
+    # So is an Unvault, which all participants must see before this returns
+    rn.unvault_vaults_anyhow([vault])